@@ -3,6 +3,7 @@ extern crate serde;
 #[macro_use] extern crate log;
 extern crate simple_logger;
 #[macro_use] extern crate serde_derive;
+extern crate serde_json;
 extern crate fixture_patch;
 extern crate fixture_patch_message;
 extern crate rust_dmx;
@@ -10,21 +11,33 @@ extern crate dataflow;
 extern crate dataflow_message;
 extern crate wiggles_value;
 
+#[cfg(test)]
+mod test;
+
 use std::fmt;
 use std::time::Duration;
 use console_server::*;
 use console_server::clients::{ClientData, ResponseFilter};
 use console_server::reactor::*;
-use fixture_patch::{Patch, UniverseId};
+use console_server::show_library::{ShowFormat, Migration};
+use fixture_patch::{Patch, LegacyPatch, UniverseId, PROFILES, take_reopen_errors};
 use fixture_patch_message::{
     PatchServerRequest,
     PatchServerResponse,
     handle_message as handle_patch_message,
     UnivWithPort};
 use rust_dmx::{DmxPort, OfflineDmxPort, Error as DmxError};
+use serde_json::Value;
 use dataflow::network::OutputId;
-use dataflow::clocks::{ClockKnobAddr, ClockNetwork, ClockCollection};
-use dataflow::wiggles::{WiggleId, WiggleKnobAddr, WiggleNetwork, WiggleCollection, WiggleProvider};
+use dataflow::clocks::{ClockKnobAddr, ClockNetwork, LegacyClockNetwork, ClockCollection};
+use dataflow::wiggles::{
+    WiggleId,
+    WiggleKnobAddr,
+    WiggleNetwork,
+    LegacyWiggleNetwork,
+    WiggleCollection,
+    WiggleProvider,
+};
 use dataflow_message::clock::{
     Command as ClockCommand,
     Response as ClockResponse,
@@ -37,6 +50,7 @@ use dataflow_message::wiggle::{
     ResponseWithKnobs as WiggleResponseWithKnobs,
     handle_message as handle_wiggle_message,
 };
+use dataflow::wiggles::lfo::KIND as LFO_KIND;
 use wiggles_value::knob::{
     Response as KnobResponse,
     Command as KnobCommand,
@@ -54,6 +68,58 @@ struct TestConsole {
     wiggles: WiggleNetwork,
 }
 
+/// The console as stored in autosaves from before show versioning was introduced.
+#[derive(Serialize, Deserialize)]
+struct LegacyConsole {
+    patch: LegacyPatch<ControlSource>,
+    clocks: LegacyClockNetwork,
+    wiggles: LegacyWiggleNetwork,
+}
+
+// Fixture groups were added as a defaulted field, so older shows load with no groups.
+impl ShowFormat for TestConsole {
+    type Legacy = LegacyConsole;
+
+    fn migrations() -> Vec<Migration> {
+        vec!(replace_test_wiggles as Migration)
+    }
+}
+
+/// Version 0 to 1: the "test" wiggle is no longer built into the console.
+/// It rendered a plain sine wave from its clock, so replace it with an LFO using the same clock.
+/// Its duty cycle knob had no effect, so it is dropped.
+fn replace_test_wiggles(mut show: Value) -> Result<Value, String> {
+    let slots = match show.pointer_mut("/wiggles/slots").and_then(Value::as_array_mut) {
+        Some(slots) => slots,
+        None => return Err("Show has no wiggles.".to_string()),
+    };
+    for slot in slots.iter_mut() {
+        let inner = match slot.pointer_mut("/node/inner") {
+            Some(inner) => inner,
+            // Empty slot.
+            None => continue,
+        };
+        if inner["kind"] != "test" {
+            continue;
+        }
+        // Older saves stored each wiggle as a JSON string.
+        let data = match (inner.get("data"), inner.get("serialized").and_then(Value::as_str)) {
+            (Some(data), _) => data.clone(),
+            (None, Some(serialized)) =>
+                serde_json::from_str(serialized).map_err(|e| e.to_string())?,
+            (None, None) => return Err("Test wiggle has no data.".to_string()),
+        };
+        let mut lfo = serde_json::Map::new();
+        lfo.insert("name".to_string(), data["name"].clone());
+        lfo.insert("clock".to_string(), data["clock"].clone());
+        let mut node = serde_json::Map::new();
+        node.insert("kind".to_string(), Value::from(LFO_KIND));
+        node.insert("data".to_string(), Value::Object(lfo));
+        *inner = Value::Object(node);
+    }
+    Ok(show)
+}
+
 impl TestConsole {
     fn handle_patch_message(
         &mut self,
@@ -61,7 +127,16 @@ impl TestConsole {
         client_data: ClientData)
         -> Messages<ResponseWrapper<Response>>
     {
-        let result = handle_patch_message(&mut self.patch, message);
+        let wiggles = &self.wiggles;
+        // Spread a wiggle over a group using its successive outputs.
+        let spread = |&(wiggle, output): &ControlSource, n: usize| {
+            let next = OutputId(output.0 + n as u32);
+            match wiggles.node(wiggle) {
+                Ok(node) if (next.0 as usize) < node.output_count() => Some((wiggle, next)),
+                _ => None,
+            }
+        };
+        let result = handle_patch_message(&mut self.patch, message, spread);
         handle_error(result, client_data, Response::Patcher)
     }
 
@@ -287,9 +362,13 @@ impl Console for TestConsole {
     fn update(&mut self, dt: Duration) -> Messages<ResponseWrapper<Response>> {
         // update the clocks
         let mut clock_msgs = self.clocks.update(dt);
-        let mut wiggle_msgs = self.wiggles.update(dt);
+        let mut wiggle_msgs = self.wiggles.update(dt, &self.clocks);
         let mut messages = Messages::none();
         messages.reserve(clock_msgs.len() + wiggle_msgs.len());
+        // report any DMX ports that couldn't be reopened when the show was loaded
+        for err in take_reopen_errors() {
+            messages.push(Response::Error(err).no_client());
+        }
         for msg in clock_msgs.drain() {
             messages.push(Response::Knob(msg.lift_address(KnobAddress::Clock)).no_client());
         }
@@ -319,6 +398,9 @@ impl Console for TestConsole {
 
 fn main() {
     simple_logger::init_with_level(log::LogLevel::Warn).unwrap();
+
+    // Load fixture profiles up front so any problems with profile files are reported at startup.
+    info!("Loaded {} fixture profiles.", PROFILES.len());
     
     let state: InitialState<TestConsole> = InitialState::default();
 
@@ -0,0 +1,60 @@
+//! Tests for loading shows saved by older versions of the console.
+use std::path::PathBuf;
+use console_server::show_library::{ShowLibrary, LoadSpec};
+use dataflow::network::OutputId;
+use super::TestConsole;
+
+/// Open a show from the library of old shows kept alongside the tests.
+fn open_show(name: &str) -> ShowLibrary {
+    let mut library = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    library.push("test_shows");
+    ShowLibrary::open_existing(&library, name).unwrap()
+}
+
+/// Check the contents of the show saved before show versioning was introduced.
+fn check_baseline_show(console: &TestConsole) {
+    let items = console.patch.items();
+    assert_eq!(2, items.len());
+    assert_eq!("front", items[0].name);
+    assert_eq!(Some((0, 1)), items[0].global_address());
+    assert_eq!(None, items[1].global_address());
+    assert_eq!(1, console.patch.universes().len());
+    assert!(console.patch.groups().is_empty());
+
+    let clocks: Vec<_> = console.clocks.nodes().map(|(_, node)| node.inner().kind()).collect();
+    assert_eq!(vec!("simple", "multiplier"), clocks);
+    let wiggles: Vec<_> = console.wiggles.nodes().collect();
+    assert_eq!(vec!("fanner", "blender"),
+               wiggles.iter().map(|&(_, node)| node.inner().kind()).collect::<Vec<_>>());
+    let (fanner, _) = wiggles[0];
+    assert_eq!(2, wiggles[0].1.output_count());
+    assert_eq!(&[Some((fanner, OutputId(1)))], wiggles[1].1.inputs());
+    assert_eq!(Some((fanner, OutputId(0))), items[0].control_sources()[0]);
+}
+
+#[test]
+fn test_load_baseline_autosave() {
+    let show = open_show("baseline");
+    let console: TestConsole = show.load(LoadSpec::LatestAutosave).unwrap();
+    check_baseline_show(&console);
+}
+
+#[test]
+fn test_load_baseline_save() {
+    let show = open_show("baseline");
+    let console: TestConsole = show.load(LoadSpec::Latest).unwrap();
+    check_baseline_show(&console);
+}
+
+#[test]
+fn test_load_show_with_test_wiggle() {
+    // The test wiggle is no longer built into the console, and loads as an equivalent LFO.
+    let show = open_show("trial");
+    let console: TestConsole = show.load(LoadSpec::Latest).unwrap();
+    let (clock, _) = console.clocks.nodes().next().unwrap();
+    let wiggles: Vec<_> = console.wiggles.nodes().map(|(_, node)| node.inner()).collect();
+    assert_eq!(3, wiggles.len());
+    assert_eq!("lfo", wiggles[2].kind());
+    assert_eq!("wave", wiggles[2].name());
+    assert_eq!(Ok(Some(clock)), wiggles[2].clock_source());
+}
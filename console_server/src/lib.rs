@@ -7,6 +7,7 @@ pub mod show_library;
 pub mod reactor;
 pub mod clients;
 pub mod socket_server;
+pub mod value_tree;
 
 extern crate event_loop;
 extern crate smallvec;
@@ -33,7 +34,7 @@ use chrono::prelude::Local;
 use serde::Serialize;
 
 use reactor::{Console, Reactor};
-use show_library::{ShowLibrary, LoadSpec, LibraryError};
+use show_library::{ShowLibrary, ShowFormat, LoadSpec, LibraryError};
 use clients::ResponseRouter;
 use socket_server::SocketServer;
 
@@ -48,7 +49,7 @@ pub struct InitialState<C> {
     websocket_protocol: String,
 }
 
-impl<C: Default + Serialize> Default for InitialState<C> {
+impl<C: Default + Serialize + ShowFormat> Default for InitialState<C> {
     /// Create a new show with entirely default parameters.
     /// Panics if the show library cannot be found or some other disk-related error occurs.
     /// Probably best to use the builder which can unpack a library error.
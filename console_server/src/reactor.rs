@@ -11,7 +11,7 @@ use smallvec::{SmallVec, self};
 use serde::{Serialize, Deserialize};
 use serde::de::DeserializeOwned;
 
-use super::show_library::{ShowLibrary, LibraryError, LoadShow, LoadSpec, shows};
+use super::show_library::{ShowLibrary, ShowFormat, LibraryError, LoadShow, LoadSpec, shows};
 use super::clients::ClientData;
 
 
@@ -203,7 +203,7 @@ impl<T> FromIterator<T> for Messages<T> {
 /// Note that none of these methods return Result; consoles are expected to be unconditionally
 /// stable as far as the reactor is concerned.  If they need to indicate expected/safe errors, that
 /// should be done in-band as part of the Response type.
-pub trait Console: Serialize + DeserializeOwned {
+pub trait Console: Serialize + DeserializeOwned + ShowFormat {
     /// The native command message type used by this console.
     type Command: 'static + Send + fmt::Debug + DeserializeOwned;
     /// The native response message type used by this console.
@@ -11,16 +11,23 @@
 //! Inside this folder is a folder named "autosave" which stores show snapshots in a more compact
 //! but non-human-readable binary format, probably bincode.  These autosaves are saved with the same
 //! filename as a regular save but with the extension .wiggles_autosave
+//!
+//! Every save and autosave starts with a magic marker and the format version of the show it
+//! contains.  When the format of a show changes, a migration is added that upgrades the previous
+//! version, and older saves are run through every migration since they were written when they are
+//! loaded.  Saves without the marker are from before versioning was introduced, and are treated as
+//! version 0.
 use std::path::{Path, PathBuf};
 use std::error::Error;
-use std::io::Error as IoError;
+use std::io::{Read, Write, Seek, SeekFrom, Error as IoError};
 use std::fmt;
 use std::fs;
 use serde::{Serialize};
 use serde::de::DeserializeOwned;
 use chrono::prelude::*;
-use serde_json;
+use serde_json::{self, Value};
 use bincode;
+use value_tree::{ValueTree, to_value, check_finite};
 
 /// A listing of the shows available in this library.
 pub fn shows(library_path: &Path) -> Result<Vec<String>, IoError> {
@@ -39,6 +46,94 @@ const DATE_FORMAT: &'static str = "%Y-%m-%d_%H:%M:%S_%f";
 const AUTOSAVE_EXTENSION: &'static str = ".wiggles_autosave";
 const SAVE_EXTENSION: &'static str = ".wiggles";
 
+/// Autosaves start with these bytes, followed by the format version.
+/// Autosaves without them predate versioning.  They're long enough that an autosave from before
+/// versioning won't start with them by chance.
+const AUTOSAVE_MAGIC: &'static [u8] = b"WIGGLES AUTOSAVE";
+
+/// Save files are marked as versioned by having this as their magic field, so that a show can't
+/// be mistaken for a save header.
+const SAVE_MAGIC: &'static str = "wiggles show";
+
+/// A step that upgrades a saved show from one format version to the next.
+/// Migrations operate on the show as a JSON value, so old formats don't need to be kept around as
+/// types.  Return a description of the problem if the show can't be upgraded.
+pub type Migration = fn(Value) -> Result<Value, String>;
+
+/// Anything that can be saved in a show library.
+/// The format version of a show is the number of migrations it has accumulated, so changing the
+/// format is just a matter of adding a migration to the end of the chain.
+pub trait ShowFormat {
+    /// The show as it was stored in autosaves from before versioning was introduced.
+    /// Binary autosaves aren't self-describing, so they can only be read using the layout they were
+    /// written with.  Legacy autosaves are read as this type, then converted to JSON and migrated
+    /// from version 0 like any other old save.
+    type Legacy: Serialize + DeserializeOwned;
+
+    /// Every migration for this show format, oldest first.
+    /// Migration n upgrades a version n save to version n + 1.
+    fn migrations() -> Vec<Migration> {
+        Vec::new()
+    }
+}
+
+/// The format version of a show type in this build.
+pub fn format_version<C: ShowFormat>() -> u32 {
+    C::migrations().len() as u32
+}
+
+#[derive(Serialize)]
+/// A save file: a show along with its format version.
+struct SaveFile<'a, C: 'a> {
+    magic: &'a str,
+    version: u32,
+    show: &'a C,
+}
+
+impl<'a, C> SaveFile<'a, C> {
+    fn new(version: u32, show: &'a C) -> Self {
+        SaveFile {
+            magic: SAVE_MAGIC,
+            version: version,
+            show: show,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+/// The header of a save file, as read back.
+struct SaveHeader {
+    version: u32,
+    show: Value,
+}
+
+/// Split the version header off of a saved show.
+/// Saves without a header predate versioning and are version 0.
+fn split_header(save: Value) -> Result<(u32, Value), LibraryError> {
+    if save.get("magic").and_then(Value::as_str) != Some(SAVE_MAGIC) {
+        return Ok((0, save));
+    }
+    let header: SaveHeader = serde_json::from_value(save)?;
+    Ok((header.version, header.show))
+}
+
+/// Upgrade a saved show to the current format version by running it through every migration since
+/// the version it was saved with.
+fn upgrade<C: ShowFormat>(version: u32, mut show: Value) -> Result<Value, LibraryError> {
+    let migrations = C::migrations();
+    let current = migrations.len() as u32;
+    if version > current {
+        return Err(LibraryError::NewerVersion{version: version, supported: current});
+    }
+    for (from, migration) in migrations.iter().enumerate().skip(version as usize) {
+        debug!("Migrating show from format version {} to {}.", from, from + 1);
+        show = migration(show).map_err(|message| {
+            LibraryError::Migration{version: from as u32, message: message}
+        })?;
+    }
+    Ok(show)
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
 pub struct LoadShow {
     pub name: String,
@@ -208,7 +303,7 @@ impl ShowLibrary {
     /// Create a new show with the given name.
     /// The expected folder hierarchy will be created, and an initial saved state will be recorded
     /// as well as an autosave.
-    pub fn create_new<C: Serialize, N: Into<String>>(
+    pub fn create_new<C: Serialize + ShowFormat, N: Into<String>>(
             library_path: &Path,
             name: N,
             console: &C)
@@ -288,23 +383,29 @@ impl ShowLibrary {
 
     /// Save a snapshot of the current state of this show, probably as the result of someone
     /// deciding to hit a save button somewhere.
-    pub fn save<C: Serialize>(&self, console: &C) -> Result<(), LibraryError> {
+    pub fn save<C: Serialize + ShowFormat>(&self, console: &C) -> Result<(), LibraryError> {
         let now = Local::now();
         let filename = format!("{}{}", now.format(DATE_FORMAT), SAVE_EXTENSION);
         let path = extend_path(&self.base_folder, &filename);
         debug!("Saving show '{}' to {:?}.", self.name, path);
+        check_finite(console)?;
         let file = fs::File::create(path)?;
-        serde_json::to_writer_pretty(file, &console).map_err(Into::into)
+        let save = SaveFile::new(format_version::<C>(), console);
+        serde_json::to_writer_pretty(file, &save).map_err(Into::into)
     }
 
     /// Autosave a snapshot of the current state of this show.
-    pub fn autosave<C: Serialize>(&self, console: &C) -> Result<(), LibraryError> {
+    /// The show is stored as a value tree rather than directly so that migrations can get at it.
+    pub fn autosave<C: Serialize + ShowFormat>(&self, console: &C) -> Result<(), LibraryError> {
         let now = Local::now();
         let filename = format!("{}{}", now.format(DATE_FORMAT), AUTOSAVE_EXTENSION);
         let path = extend_path(&self.autosave_dir(), &filename);
         debug!("Autosaving show '{}' to {:?}.", self.name, path);
+        let show = ValueTree(to_value(console)?);
         let mut file = fs::File::create(path)?;
-        bincode::serialize_into(&mut file, console, bincode::Infinite).map_err(Into::into)
+        file.write_all(AUTOSAVE_MAGIC)?;
+        bincode::serialize_into(&mut file, &(format_version::<C>(), show))
+            .map_err(Into::into)
     }
 
     /// Return a listing of the names of available saves in this dir, trimming off extension.
@@ -326,7 +427,9 @@ impl ShowLibrary {
     }
 
     /// Load a saved version of this show.
-    pub fn load<C: DeserializeOwned>(&self, spec: LoadSpec) -> Result<C, LibraryError> {
+    pub fn load<C>(&self, spec: LoadSpec) -> Result<C, LibraryError>
+        where C: DeserializeOwned + ShowFormat
+    {
         debug!("Loading state for show '{}' from {}.", self.name, spec);        
         match spec {
             LoadSpec::Latest => self.load_latest(),
@@ -375,13 +478,13 @@ impl ShowLibrary {
     }
 
     /// Load the latest save file we have for this show.
-    fn load_latest<C: DeserializeOwned>(&self) -> Result<C, LibraryError> {
+    fn load_latest<C: DeserializeOwned + ShowFormat>(&self) -> Result<C, LibraryError> {
         let filename = self.latest_filename(&self.base_folder)?;
         self.load_from_save_file(&filename)
     }
     
     /// Load the lastest autosave file we have for this show.
-    fn load_latest_autosave<C: DeserializeOwned>(&self) -> Result<C, LibraryError> {
+    fn load_latest_autosave<C: DeserializeOwned + ShowFormat>(&self) -> Result<C, LibraryError> {
         let filename = self.latest_filename(&self.autosave_dir())?;
         self.load_from_autosave_file(&filename)
     }
@@ -401,15 +504,35 @@ impl ShowLibrary {
     }
 
     /// Try to load console state from this file name.
-    fn load_from_save_file<C: DeserializeOwned>(&self, filename: &str) -> Result<C, LibraryError> {
+    fn load_from_save_file<C>(&self, filename: &str) -> Result<C, LibraryError>
+        where C: DeserializeOwned + ShowFormat
+    {
         let file = self.open_file(filename, &self.base_folder)?;
-        serde_json::from_reader(file).map_err(Into::into)
+        let (version, show) = split_header(serde_json::from_reader(file)?)?;
+        serde_json::from_value(upgrade::<C>(version, show)?).map_err(Into::into)
     }
 
     /// Try to load console state from this autosave file name.
-    fn load_from_autosave_file<C: DeserializeOwned>(&self, filename: &str) -> Result<C, LibraryError> {
+    fn load_from_autosave_file<C>(&self, filename: &str) -> Result<C, LibraryError>
+        where C: DeserializeOwned + ShowFormat
+    {
         let mut file = self.open_file(filename, &self.autosave_dir())?;
-        bincode::deserialize_from(&mut file, bincode::Infinite).map_err(Into::into)
+        let mut magic = vec!(0; AUTOSAVE_MAGIC.len());
+        let has_header = match file.read_exact(&mut magic) {
+            Ok(()) => &magic[..] == AUTOSAVE_MAGIC,
+            Err(_) => false,
+        };
+        let (version, show) = if has_header {
+            let (version, show): (u32, ValueTree) =
+                bincode::deserialize_from(&mut file)?;
+            (version, show.0)
+        } else {
+            // This autosave predates versioning, so read it using the layout of the time.
+            file.seek(SeekFrom::Start(0))?;
+            let legacy: C::Legacy = bincode::deserialize_from(&mut file)?;
+            (0, serde_json::to_value(legacy)?)
+        };
+        serde_json::from_value(upgrade::<C>(version, show)?).map_err(Into::into)
     }
 }
 
@@ -428,6 +551,10 @@ pub enum LibraryError {
     DuplicateName(String),
     /// A save or load operation failed due to a file system error.
     Io(IoError),
+    /// The save was made by a newer version of the console than this one.
+    NewerVersion{version: u32, supported: u32},
+    /// The save could not be upgraded from this format version.
+    Migration{version: u32, message: String},
 }
 
 impl From<serde_json::Error> for LibraryError {
@@ -459,6 +586,18 @@ impl fmt::Display for LibraryError {
             JsonError(ref e) => write!(f, "Show load error: {}", e),
             Bincode(ref e) => write!(f, "Autosave load error: {}", e),
             Io(ref e) => write!(f, "An IO error occurred: {}", e),
+            NewerVersion{version, supported} =>
+                write!(
+                    f,
+                    "The save has format version {} but this console only supports up to {}.",
+                    version,
+                    supported),
+            Migration{version, ref message} =>
+                write!(
+                    f,
+                    "Could not upgrade the save from format version {}: {}",
+                    version,
+                    message),
         }
     }
 }
@@ -473,6 +612,8 @@ impl Error for LibraryError {
             JsonError(_) => "Show could not be loaded.",
             Bincode(_) => "Autosave could not be loaded.",
             Io(_) => "IO error occurred.",
+            NewerVersion{..} => "Save was made by a newer version of the console.",
+            Migration{..} => "Save could not be upgraded.",
         }
     }
 
@@ -485,6 +626,8 @@ impl Error for LibraryError {
             JsonError(ref e) => Some(e),
             Bincode(ref e) => Some(e),
             Io(ref e) => Some(e),
+            NewerVersion{..} => None,
+            Migration{..} => None,
         }
     }
 }
@@ -554,6 +697,62 @@ mod test {
         }
     }
 
+    impl ShowFormat for MockConsole {
+        type Legacy = MockConsole;
+    }
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    /// The mock console after a couple of format changes.
+    struct MockConsoleV2 {
+        title: String,
+        data: Vec<u64>,
+        scale: u64,
+    }
+
+    /// Version 0 to 1: the name field was renamed.
+    fn rename_name(mut show: Value) -> Result<Value, String> {
+        let name = show.as_object_mut()
+            .and_then(|fields| fields.remove("name"))
+            .ok_or("Show has no name.".to_string())?;
+        show["title"] = name;
+        Ok(show)
+    }
+
+    /// Version 1 to 2: a scale field was added.
+    fn add_scale(mut show: Value) -> Result<Value, String> {
+        show["scale"] = Value::from(1);
+        Ok(show)
+    }
+
+    impl ShowFormat for MockConsoleV2 {
+        type Legacy = MockConsole;
+
+        fn migrations() -> Vec<Migration> {
+            vec!(rename_name as Migration, add_scale)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    /// A console whose shows look just like the header of a versioned save.
+    struct HeaderLikeConsole {
+        version: u32,
+        show: Vec<u64>,
+    }
+
+    impl ShowFormat for HeaderLikeConsole {
+        type Legacy = HeaderLikeConsole;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    /// A console with some floating-point state.
+    struct FloatConsole {
+        levels: Vec<f64>,
+    }
+
+    impl ShowFormat for FloatConsole {
+        type Legacy = FloatConsole;
+    }
+
     struct TestLibrary {
         lib_path: PathBuf,
     }
@@ -669,4 +868,83 @@ mod test {
             _ => panic!("No duplicate check did not fire."),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_non_finite() {
+        let lib = TestLibrary::new("test_non_finite");
+        let mut d = FloatConsole { levels: vec!(0.5, -1.0) };
+        let show_lib = ShowLibrary::create_new(&lib.lib_path, "test show", &d).unwrap();
+        assert_eq!(d, show_lib.load(LoadSpec::Latest).unwrap());
+        assert_eq!(d, show_lib.load(LoadSpec::LatestAutosave).unwrap());
+
+        // Non-finite values can't be stored as JSON, so saving them fails rather than writing null.
+        for &bad in &[::std::f64::NAN, ::std::f64::INFINITY, ::std::f64::NEG_INFINITY] {
+            d.levels[1] = bad;
+            match show_lib.save(&d) {
+                Err(LibraryError::JsonError(_)) => (),
+                x => panic!("Expected a JSON error saving {}, got {:?}", bad, x),
+            }
+            match show_lib.autosave(&d) {
+                Err(LibraryError::JsonError(_)) => (),
+                x => panic!("Expected a JSON error autosaving {}, got {:?}", bad, x),
+            }
+        }
+        assert_eq!(1, show_lib.saves().unwrap().len());
+        assert_eq!(1, show_lib.autosaves().unwrap().len());
+    }
+
+    /// Return a path for a new file in this directory with a timestamp for a name.
+    fn new_file_path(dir: &Path, ext: &str) -> PathBuf {
+        extend_path(dir, &format!("{}{}", Local::now().format(DATE_FORMAT), ext))
+    }
+
+    #[test]
+    fn test_migrate() {
+        let lib = TestLibrary::new("test_migrate");
+        let d = MockConsole::new();
+        let show_lib = ShowLibrary::create_new(&lib.lib_path, "test show", &d).unwrap();
+        let upgraded = MockConsoleV2 { title: d.name.clone(), data: d.data.clone(), scale: 1 };
+        assert_eq!(0, format_version::<MockConsole>());
+        assert_eq!(2, format_version::<MockConsoleV2>());
+
+        // Older saves and autosaves are upgraded on load.
+        assert_eq!(upgraded, show_lib.load(LoadSpec::Latest).unwrap());
+        assert_eq!(upgraded, show_lib.load(LoadSpec::LatestAutosave).unwrap());
+
+        // Saves from a newer version can't be loaded.
+        show_lib.save(&upgraded).unwrap();
+        show_lib.autosave(&upgraded).unwrap();
+        for spec in vec!(LoadSpec::Latest, LoadSpec::LatestAutosave) {
+            match show_lib.load::<MockConsole>(spec) {
+                Err(LibraryError::NewerVersion{version: 2, supported: 0}) => (),
+                x => panic!("Expected a newer version error, got {:?}", x),
+            }
+        }
+
+        // Saves from before versioning are version 0.
+        let file = fs::File::create(new_file_path(&show_lib.base_folder, SAVE_EXTENSION)).unwrap();
+        serde_json::to_writer(file, &d).unwrap();
+        assert_eq!(upgraded, show_lib.load(LoadSpec::Latest).unwrap());
+        let mut file =
+            fs::File::create(new_file_path(&show_lib.autosave_dir(), AUTOSAVE_EXTENSION)).unwrap();
+        bincode::serialize_into(&mut file, &d).unwrap();
+        assert_eq!(d, show_lib.load(LoadSpec::LatestAutosave).unwrap());
+        assert_eq!(upgraded, show_lib.load(LoadSpec::LatestAutosave).unwrap());
+
+        // Headerless saves are never mistaken for versioned ones, whatever the show looks like.
+        let header_like = HeaderLikeConsole { version: 7, show: vec!(1, 2, 3) };
+        let file = fs::File::create(new_file_path(&show_lib.base_folder, SAVE_EXTENSION)).unwrap();
+        serde_json::to_writer(file, &header_like).unwrap();
+        assert_eq!(header_like, show_lib.load(LoadSpec::Latest).unwrap());
+        show_lib.save(&header_like).unwrap();
+        assert_eq!(header_like, show_lib.load(LoadSpec::Latest).unwrap());
+
+        // Migration failures are reported with the version that couldn't be upgraded.
+        let file = fs::File::create(new_file_path(&show_lib.base_folder, SAVE_EXTENSION)).unwrap();
+        serde_json::to_writer(file, &SaveFile::new(0, &vec!(1, 2, 3))).unwrap();
+        match show_lib.load::<MockConsoleV2>(LoadSpec::Latest) {
+            Err(LibraryError::Migration{version: 0, ..}) => (),
+            x => panic!("Expected a migration error, got {:?}", x),
+        }
+    }
+}
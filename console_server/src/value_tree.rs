@@ -0,0 +1,326 @@
+//! Serialization of arbitrary JSON value trees in formats that aren't self-describing.
+//! Human-readable formats get the value tree as-is, so it reads like any other JSON.  Binary
+//! formats like bincode can't deserialize a value tree without knowing its shape up front, so they
+//! get a tagged copy instead.  Formats are told apart using is_human_readable, which bincode only
+//! reports correctly from 1.0, so that is the version we depend on.
+//!
+//! JSON has no representation for non-finite numbers, and serde_json quietly writes them as null,
+//! so anything headed for a value tree should go through to_value, which rejects them instead.
+use std::fmt::Display;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::ser::{self, Error as SerError};
+use serde_json::{self, Value, Number, Map};
+
+/// Convert anything serializable into a JSON value tree.
+/// Fail if it contains a NaN or infinite float, rather than silently turning it into null.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, serde_json::Error> {
+    check_finite(value)?;
+    serde_json::to_value(value)
+}
+
+/// Return an error if this value contains a NaN or infinite float anywhere.
+pub fn check_finite<T: Serialize + ?Sized>(value: &T) -> Result<(), serde_json::Error> {
+    value.serialize(FiniteCheck)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A JSON value tree that can be serialized in any format.
+pub struct ValueTree(pub Value);
+
+impl Serialize for ValueTree {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        if serializer.is_human_readable() {
+            self.0.serialize(serializer)
+        } else {
+            Tagged::from(&self.0).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueTree {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        if deserializer.is_human_readable() {
+            Value::deserialize(deserializer).map(ValueTree)
+        } else {
+            Tagged::deserialize(deserializer).map(|t| ValueTree(t.into()))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+/// A JSON value tree that says what it is, for formats that aren't self-describing.
+enum Tagged {
+    Null,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    F64(f64),
+    String(String),
+    Array(Vec<Tagged>),
+    Object(Vec<(String, Tagged)>),
+}
+
+impl<'a> From<&'a Value> for Tagged {
+    fn from(value: &'a Value) -> Self {
+        match *value {
+            Value::Null => Tagged::Null,
+            Value::Bool(b) => Tagged::Bool(b),
+            Value::Number(ref n) => {
+                if let Some(u) = n.as_u64() {
+                    Tagged::U64(u)
+                } else if let Some(i) = n.as_i64() {
+                    Tagged::I64(i)
+                } else {
+                    Tagged::F64(n.as_f64().unwrap_or(0.0))
+                }
+            }
+            Value::String(ref s) => Tagged::String(s.clone()),
+            Value::Array(ref a) => Tagged::Array(a.iter().map(Into::into).collect()),
+            Value::Object(ref o) =>
+                Tagged::Object(o.iter().map(|(k, v)| (k.clone(), v.into())).collect()),
+        }
+    }
+}
+
+impl From<Tagged> for Value {
+    fn from(tagged: Tagged) -> Self {
+        match tagged {
+            Tagged::Null => Value::Null,
+            Tagged::Bool(b) => Value::Bool(b),
+            Tagged::U64(u) => Value::Number(u.into()),
+            Tagged::I64(i) => Value::Number(i.into()),
+            // JSON has no representation for non-finite numbers.
+            Tagged::F64(f) => Number::from_f64(f).map(Value::Number).unwrap_or(Value::Null),
+            Tagged::String(s) => Value::String(s),
+            Tagged::Array(a) => Value::Array(a.into_iter().map(Into::into).collect()),
+            Tagged::Object(o) =>
+                Value::Object(o.into_iter().map(|(k, v)| (k, v.into())).collect::<Map<_, _>>()),
+        }
+    }
+}
+
+/// A serializer that produces nothing, and only checks that every float is finite.
+struct FiniteCheck;
+
+impl FiniteCheck {
+    fn float(self, v: f64) -> Result<(), serde_json::Error> {
+        if v.is_finite() {
+            Ok(())
+        } else {
+            Err(serde_json::Error::custom(format!("{} cannot be represented in JSON", v)))
+        }
+    }
+}
+
+// Everything other than floats is fine as-is.
+macro_rules! accept {
+    ($($method:ident($ty:ty)),*) => {
+        $(fn $method(self, _: $ty) -> Result<(), serde_json::Error> { Ok(()) })*
+    }
+}
+
+impl Serializer for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    accept!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_unit_struct(&'static str)
+    );
+
+    fn serialize_f32(self, v: f32) -> Result<(), serde_json::Error> {
+        self.float(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), serde_json::Error> {
+        self.float(v)
+    }
+
+    fn serialize_none(self) -> Result<(), serde_json::Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, v: &T) -> Result<(), serde_json::Error> {
+        v.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), serde_json::Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, _: &'static str)
+        -> Result<(), serde_json::Error>
+    {
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, v: &T)
+        -> Result<(), serde_json::Error>
+    {
+        v.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self, _: &'static str, _: u32, _: &'static str, v: &T)
+        -> Result<(), serde_json::Error>
+    {
+        v.serialize(self)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self, serde_json::Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self, serde_json::Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self, serde_json::Error> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, _: &'static str, _: usize)
+        -> Result<Self, serde_json::Error>
+    {
+        Ok(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self, serde_json::Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, serde_json::Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize)
+        -> Result<Self, serde_json::Error>
+    {
+        Ok(self)
+    }
+
+    fn collect_str<T: Display + ?Sized>(self, _: &T) -> Result<(), serde_json::Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Self::Error> {
+        v.serialize(FiniteCheck)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Self::Error> {
+        v.serialize(FiniteCheck)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Self::Error> {
+        v.serialize(FiniteCheck)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Self::Error> {
+        v.serialize(FiniteCheck)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, k: &T) -> Result<(), Self::Error> {
+        k.serialize(FiniteCheck)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), Self::Error> {
+        v.serialize(FiniteCheck)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &'static str, v: &T)
+        -> Result<(), Self::Error>
+    {
+        v.serialize(FiniteCheck)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for FiniteCheck {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &'static str, v: &T)
+        -> Result<(), Self::Error>
+    {
+        v.serialize(FiniteCheck)
+    }
+
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+//! A clock that follows the beat of live audio.
+//! Audio is pulled from a PcmSource on every update and chopped into short frames.  An onset
+//! detection function is computed from the rise in energy between frames, and the tempo is
+//! estimated from the autocorrelation of its recent history.  The beat phase is found by
+//! sliding a comb at the estimated tempo across the same history, and the clock is gently pulled
+//! towards it so that it runs smoothly even if the audio is messy.
+//!
+//! The audio input is chosen by name using the input knob; see pcm::open for what a name can
+//! refer to.  The input is opened on the next update, and reopened after a delay if it can't be
+//! opened or its stream ends, so a clock loaded from a save picks its input back up by itself.
+//!
+//! If no audio source is attached, or the audio falls silent, the clock free-runs at the last
+//! tempo it detected.
+use std::sync::Arc;
+use std::time::Duration;
+use std::collections::VecDeque;
+use std::fmt;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use ::util::{secs, min_included_angle, modulo_one};
+use super::clock::{Clock, ClockValue, ClockId, ClockProvider, KnobAddr};
+use super::pcm::{self, PcmSource};
+use ::network::{Inputs, OutputId};
+use wiggles_value::knob::{
+    Knobs, Datatype, Data, KnobDescription, Error as KnobError, badaddr, Response as KnobResponse};
+use wiggles_value::knob_types::Rate;
+use serde_json::{Error as SerdeJsonError, self};
+
+fn init_clock_val() -> ClockValue {
+    ClockValue::from_float_value(0.0, true)
+}
+// Assume 120 bpm until we've heard something.
+pub const INIT_RATE: f64 = 2.0;
+
+/// Rate at which the onset detection function is computed, in Hz.
+const FRAME_RATE: f64 = 100.0;
+/// Length of onset history used for tempo and phase estimation, in seconds.
+const HISTORY_DURATION: f64 = 8.0;
+/// Don't try to estimate tempo until we have this much history, in seconds.
+const MIN_HISTORY_DURATION: f64 = 3.0;
+/// Interval between tempo and phase estimates, in seconds.
+const ESTIMATE_INTERVAL: f64 = 0.5;
+/// Range of tempos we'll consider, in beats per minute.
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 180.0;
+/// Tempo we're biased towards when deciding between multiples of the same pulse.
+const PREFERRED_BPM: f64 = 120.0;
+/// Width of the tempo preference, in octaves.
+const PREFERENCE_WIDTH: f64 = 1.0;
+/// Fraction of each new tempo estimate blended into the running estimate.
+const TEMPO_SMOOTHING: f64 = 0.5;
+/// Fraction of the phase error corrected on each estimate.
+const PHASE_GAIN: f64 = 0.5;
+/// Floor added to frame energy to keep the log finite during silence.
+const ENERGY_FLOOR: f64 = 1e-6;
+/// Time to wait before trying to reopen an input that failed, in seconds.
+const REOPEN_INTERVAL: f64 = 5.0;
+
+/// The audio analysis state of a beat clock.
+/// None of this is persisted; it is rebuilt from the audio.
+#[derive(Debug, Default)]
+struct BeatTracker {
+    /// Samples that haven't yet filled a complete frame.
+    pending: Vec<f64>,
+    /// Recent history of the onset detection function, oldest first.
+    onsets: VecDeque<f64>,
+    /// Energy of the previous frame.
+    prev_energy: f64,
+    /// Number of frames since the last estimate.
+    frames_since_estimate: usize,
+    /// True once we've produced at least one tempo estimate.
+    locked: bool,
+}
+
+impl BeatTracker {
+    /// Chop newly-arrived samples into frames and update the onset history.
+    /// Return true if it is time for a new estimate.
+    fn process(&mut self, samples: &[f64], sample_rate: u32) -> bool {
+        let frame_len = (sample_rate as f64 / FRAME_RATE).round().max(1.0) as usize;
+        let max_history = (HISTORY_DURATION * FRAME_RATE) as usize;
+        let estimate_frames = (ESTIMATE_INTERVAL * FRAME_RATE) as usize;
+        self.pending.extend_from_slice(samples);
+
+        let mut estimate = false;
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= frame_len {
+            let frame = &self.pending[consumed..consumed+frame_len];
+            consumed += frame_len;
+            let energy = frame.iter().map(|s| s * s).sum::<f64>() / frame_len as f64;
+            // Half-wave rectified log energy difference, so only rising energy counts.
+            let onset = ((energy + ENERGY_FLOOR).ln() - (self.prev_energy + ENERGY_FLOOR).ln())
+                .max(0.0);
+            self.prev_energy = energy;
+            self.onsets.push_back(onset);
+            if self.onsets.len() > max_history {
+                self.onsets.pop_front();
+            }
+            self.frames_since_estimate += 1;
+            if self.frames_since_estimate >= estimate_frames {
+                self.frames_since_estimate = 0;
+                estimate = true;
+            }
+        }
+        self.pending.drain(..consumed);
+        estimate
+    }
+
+    /// Estimate the beat period from the onset history, in frames.
+    /// Return None if there isn't enough history or no clear periodicity.
+    fn estimate_period(&self) -> Option<f64> {
+        let n = self.onsets.len();
+        if (n as f64) < MIN_HISTORY_DURATION * FRAME_RATE {
+            return None;
+        }
+        let mean = self.onsets.iter().sum::<f64>() / n as f64;
+        let centered: Vec<f64> = self.onsets.iter().map(|o| o - mean).collect();
+
+        let min_lag = (60.0 * FRAME_RATE / MAX_BPM).floor() as usize;
+        let max_lag = (60.0 * FRAME_RATE / MIN_BPM).ceil() as usize;
+        if max_lag + 1 >= n {
+            return None;
+        }
+        // Compute the autocorrelation one lag either side of the range for interpolation.
+        let autocorr: Vec<f64> = (min_lag-1..max_lag+2)
+            .map(|lag| {
+                let sum: f64 = (lag..n).map(|i| centered[i] * centered[i-lag]).sum();
+                sum / (n - lag) as f64
+            })
+            .collect();
+        let weighted = |i: usize| {
+            let bpm = 60.0 * FRAME_RATE / (min_lag + i - 1) as f64;
+            let octaves = (bpm / PREFERRED_BPM).log2() / PREFERENCE_WIDTH;
+            autocorr[i] * (-0.5 * octaves * octaves).exp()
+        };
+        let best = (1..autocorr.len()-1)
+            .max_by(|a, b| weighted(*a).partial_cmp(&weighted(*b)).unwrap())
+            .unwrap();
+        if autocorr[best] <= 0.0 {
+            return None;
+        }
+        // Refine the peak location with a parabolic fit.
+        let (a, b, c) = (autocorr[best-1], autocorr[best], autocorr[best+1]);
+        let denom = a - 2.0 * b + c;
+        let shift = if denom < 0.0 { (0.5 * (a - c) / denom).max(-0.5).min(0.5) } else { 0.0 };
+        Some((min_lag + best - 1) as f64 + shift)
+    }
+
+    /// Find how many frames ago the most recent beat occurred, given the beat period in frames.
+    /// The beat comb is aligned to the onset history to find the best fit.
+    fn frames_since_beat(&self, period: f64) -> Option<f64> {
+        let n = self.onsets.len();
+        let mut best = None;
+        let mut best_score = 0.0;
+        for offset in 0..(period.ceil() as usize) {
+            let mut score = 0.0;
+            let mut k = 0;
+            loop {
+                let back = (offset as f64 + k as f64 * period).round() as usize;
+                if back >= n {
+                    break;
+                }
+                score += self.onsets[n - 1 - back];
+                k += 1;
+            }
+            if score > best_score {
+                best_score = score;
+                best = Some(offset);
+            }
+        }
+        // The onset is registered somewhere within its frame; split the difference.
+        best.map(|offset| offset as f64 + 0.5)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BeatClock {
+    name: String,
+    value: ClockValue,
+    /// Current tempo estimate in Hz.
+    rate: f64,
+    /// The name of the audio input to listen to, or empty for none.
+    #[serde(default)]
+    input: String,
+    #[serde(skip_serializing, skip_deserializing)]
+    source: Option<Box<PcmSource>>,
+    /// Seconds until we next try to open the input.
+    #[serde(skip_serializing, skip_deserializing)]
+    reopen_in: f64,
+    #[serde(skip_serializing, skip_deserializing)]
+    tracker: BeatTracker,
+    /// Reusable buffer for incoming samples.
+    #[serde(skip_serializing, skip_deserializing)]
+    buffer: Vec<f64>,
+}
+
+impl BeatClock {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        BeatClock {
+            name: name.into(),
+            value: init_clock_val(),
+            rate: INIT_RATE,
+            input: String::new(),
+            source: None,
+            reopen_in: 0.0,
+            tracker: BeatTracker::default(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Create a beat clock that listens to the provided audio source.
+    pub fn with_source<N: Into<String>>(name: N, source: Box<PcmSource>) -> Self {
+        let mut clock = BeatClock::new(name);
+        clock.set_source(Some(source));
+        clock
+    }
+
+    /// Attach a new audio source to this clock, or detach the current one.
+    /// Any audio analysis performed so far is discarded, but the tempo estimate is retained.
+    pub fn set_source(&mut self, source: Option<Box<PcmSource>>) {
+        self.source = source;
+        self.tracker = BeatTracker::default();
+    }
+
+    /// Return true if an audio source is attached to this clock.
+    pub fn has_source(&self) -> bool {
+        self.source.is_some()
+    }
+
+    /// Open the input if we aren't listening to anything, or drop the source if it has closed.
+    /// Wait a while between attempts so that a missing input doesn't flood the log.
+    fn connect(&mut self, dt: Duration) {
+        if self.source.as_ref().map(|source| source.is_closed()).unwrap_or(false) {
+            warn!("{} lost its audio input {}.", self.name, self.input);
+            self.set_source(None);
+            self.reopen_in = REOPEN_INTERVAL;
+        }
+        if self.source.is_some() || self.input.is_empty() {
+            return;
+        }
+        self.reopen_in -= secs(dt);
+        if self.reopen_in > 0.0 {
+            return;
+        }
+        match pcm::open(&self.input) {
+            Ok(source) => self.set_source(Some(source)),
+            Err(e) => {
+                warn!("{} could not open audio input {}: {}", self.name, self.input, e);
+                self.reopen_in = REOPEN_INTERVAL;
+            }
+        }
+    }
+
+    /// Pull the next block of audio and run the analysis.
+    /// Return a new tempo estimate in Hz, and the phase we should currently be at, if one is ready.
+    fn listen(&mut self, dt: Duration) -> Option<(f64, f64)> {
+        let sample_rate = match self.source {
+            Some(ref mut source) => {
+                self.buffer.clear();
+                source.read(dt, &mut self.buffer);
+                source.sample_rate()
+            }
+            None => return None,
+        };
+        if !self.tracker.process(&self.buffer, sample_rate) {
+            return None;
+        }
+        let period = self.tracker.estimate_period()?;
+        let rate = if self.tracker.locked {
+            self.rate + TEMPO_SMOOTHING * (FRAME_RATE / period - self.rate)
+        }
+        else {
+            FRAME_RATE / period
+        };
+        self.tracker.locked = true;
+        // Account for the audio that hasn't yet made it into a frame.
+        let pending_frames =
+            self.tracker.pending.len() as f64 * FRAME_RATE / sample_rate as f64;
+        let since_beat = self.tracker.frames_since_beat(FRAME_RATE / rate)? + pending_frames;
+        Some((rate, modulo_one(since_beat * rate / FRAME_RATE)))
+    }
+}
+
+impl fmt::Debug for BeatClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BeatClock")
+            .field("name", &self.name)
+            .field("value", &self.value)
+            .field("rate", &self.rate)
+            .field("input", &self.input)
+            .field("has_source", &self.has_source())
+            .field("tracker", &self.tracker)
+            .finish()
+    }
+}
+
+// Only the tempo estimate and the input are persisted, and only they are compared.
+impl PartialEq for BeatClock {
+    fn eq(&self, other: &BeatClock) -> bool {
+        self.name == other.name
+            && self.value == other.value
+            && self.rate == other.rate
+            && self.input == other.input
+    }
+}
+
+pub const KIND: &'static str = "beat";
+
+impl<M, I> Inputs<M, I> for BeatClock {
+    /// Beat clock listens to audio, not other clocks.
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+const RATE_KNOB_ADDR: u32 = 0;
+const INPUT_KNOB_ADDR: u32 = 1;
+
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let rate_desc = KnobDescription {
+            name: Arc::new("rate".to_string()),
+            datatype: Datatype::Rate,
+        };
+        let input_desc = KnobDescription {
+            name: Arc::new("input".to_string()),
+            datatype: Datatype::Text,
+        };
+        vec!((RATE_KNOB_ADDR, rate_desc), (INPUT_KNOB_ADDR, input_desc))
+    };
+}
+
+impl Knobs<KnobAddr> for BeatClock {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<Datatype, KnobError<KnobAddr>> {
+        match addr {
+            RATE_KNOB_ADDR => Ok(Datatype::Rate),
+            INPUT_KNOB_ADDR => Ok(Datatype::Text),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<Data, KnobError<KnobAddr>> {
+        match addr {
+            RATE_KNOB_ADDR => Ok(Data::Rate(Rate::Hz(self.rate))),
+            INPUT_KNOB_ADDR => Ok(Data::Text(self.input.clone())),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// The rate knob reports the detected tempo.  Setting it gives the tracker a starting point,
+    /// which will be overridden as soon as the audio provides a better one.
+    /// Changing the input closes the current one and opens the new one on the next update.
+    fn set_knob(&mut self, addr: KnobAddr, value: Data) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            RATE_KNOB_ADDR => {
+                self.rate = value.as_rate()?.in_hz();
+                Ok(())
+            }
+            INPUT_KNOB_ADDR => {
+                self.input = value.as_text()?;
+                self.set_source(None);
+                self.reopen_in = 0.0;
+                Ok(())
+            }
+            _ => {
+                Err(badaddr(addr))
+            }
+        }
+    }
+}
+
+impl Clock for BeatClock {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Listen to the next block of audio, then advance the clock, pulling it towards the beat.
+    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<KnobAddr>> {
+        self.connect(dt);
+        let mut messages = Messages::none();
+        let mut elapsed_phase = self.rate * secs(dt);
+
+        if let Some((rate, beat_phase)) = self.listen(dt) {
+            self.rate = rate;
+            elapsed_phase = rate * secs(dt);
+            let error = min_included_angle(*self.value.phase() + elapsed_phase, beat_phase);
+            // Never run backwards, or we could tick twice on the same beat.
+            elapsed_phase = (elapsed_phase + PHASE_GAIN * error).max(0.0);
+            messages.push(KnobResponse::ValueChange(RATE_KNOB_ADDR, Data::Rate(Rate::Hz(rate))));
+        }
+
+        let phase_unwrapped = *self.value.phase() + elapsed_phase;
+        let accumulated_ticks = phase_unwrapped.floor() as i64;
+        self.value.ticked = accumulated_ticks > 0;
+        self.value.tick_count += accumulated_ticks;
+        self.value.set_phase(phase_unwrapped);
+        messages
+    }
+
+    fn render(&self, _: &[Option<(ClockId, OutputId)>], _: &ClockProvider) -> ClockValue {
+        self.value
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -13,8 +13,9 @@ use std::fmt;
 use std::any::Any;
 use serde::{Serialize, Serializer};
 use serde::de::DeserializeOwned;
-use serde_json::{Error as SerdeJsonError, self};
+use serde_json::{Error as SerdeJsonError, Value, self};
 use super::serde::SerializableClock;
+use node_data::{NodeData, LegacyNode};
 
 // Individual clocks just use numeric indices for their knobs.
 pub type KnobAddr = u32;
@@ -122,15 +123,16 @@ pub trait Clock {
     /// function that can be used to retrieve the current value of one of those inputs.
     fn render(&self, inputs: &[Option<(ClockId, OutputId)>], network: &ClockProvider) -> ClockValue;
 
-    /// Serialize yourself into JSON.
+    /// Serialize yourself into a JSON value.
     /// Every clock must implement this separately until an erased_serde solution is back in
-    /// action.
-    fn as_json(&self) -> Result<String, SerdeJsonError>;
+    /// action.  Use value_tree::to_value rather than serde_json's, so that NaN and infinite
+    /// values are rejected rather than saved as null.
+    fn as_json(&self) -> Result<Value, SerdeJsonError>;
 
     fn serializable(&self) -> Result<SerializableClock, SerdeJsonError> {
         Ok(SerializableClock {
             kind: self.kind().to_string(),
-            serialized: self.as_json()?,
+            data: NodeData(self.as_json()?),
         })
     }
 }
@@ -160,6 +162,9 @@ impl NodeId for ClockId {
 /// Type alias for a network of clocks.
 pub type ClockNetwork = Network<Box<CompleteClock>, ClockId, KnobResponse<ClockKnobAddr>>;
 
+/// A network of clocks as stored in binary autosaves from before show versioning.
+pub type LegacyClockNetwork = Network<LegacyNode, ClockId, KnobResponse<ClockKnobAddr>>;
+
 impl ClockProvider for ClockNetwork {
     /// Get the value of the requested clock.
     /// If it is missing, log an error and return a default.
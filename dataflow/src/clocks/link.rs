@@ -0,0 +1,484 @@
+//! A clock that shares tempo and beat phase with peers on the network, in the style of Ableton
+//! Link.
+//!
+//! Every peer keeps a session timeline mapping its host time onto beats, and periodically
+//! broadcasts it.  Peers measure the offset between their host clocks by exchanging pings, so a
+//! timeline received from a peer can be translated into local host time.  When a peer changes the
+//! tempo it bumps the timeline version, and every peer adopts the timeline with the newest
+//! version, so the session converges on the most recent change.
+//!
+//! Beat counts aren't shared directly: each peer keeps its own beat count and only aligns it with
+//! the session modulo the quantum, so joining a session never moves the clock by more than half a
+//! bar.  The quantum is shared along with the tempo.
+//!
+//! Host time is the accumulated update interval, as for every other clock, so the beat stays in
+//! step with the rest of the show.  Peers measure the offset between their host times the same way
+//! they would between wall clocks, and keep measuring, so any drift between them is taken up.
+//!
+//! Unless given a transport of its own, the clock joins the default multicast group, and keeps
+//! trying to rejoin if it can't.
+use std::sync::Arc;
+use std::time::Duration;
+use std::collections::HashMap;
+use std::fmt;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use super::clock::{Clock, ClockValue, ClockId, ClockProvider, KnobAddr};
+use super::peer::{
+    PeerTransport, UdpTransport, PeerMessage, PeerId, Timeline, DEFAULT_GROUP, DEFAULT_PORT};
+use ::util::{secs, random_seed};
+use ::network::{Inputs, OutputId};
+use wiggles_value::knob::{
+    Knobs, Datatype, Data, KnobDescription, Error as KnobError, badaddr, Response as KnobResponse};
+use wiggles_value::knob_types::Rate;
+use serde_json::{Error as SerdeJsonError, self};
+
+// Run at 120 bpm in 4/4 until told otherwise.
+pub const INIT_RATE: f64 = 2.0;
+pub const INIT_QUANTUM: f64 = 4.0;
+
+/// Interval between timeline broadcasts, in microseconds.
+const BROADCAST_INTERVAL: i64 = 200_000;
+/// Interval between pings to each peer, in microseconds.
+const PING_INTERVAL: i64 = 1_000_000;
+/// Forget about peers we haven't heard from in this long, in microseconds.
+const PEER_TIMEOUT: i64 = 5_000_000;
+/// The round trip time of the best offset measurement grows by this fraction every ping, so that
+/// old measurements are eventually replaced.
+const RTT_AGING: f64 = 0.05;
+/// Time to wait before trying to rejoin the multicast group after failing to, in seconds.
+const REJOIN_INTERVAL: f64 = 5.0;
+
+/// What we know about another peer.
+#[derive(Debug)]
+struct Peer {
+    /// Their host time minus ours, in microseconds, once measured.
+    offset: Option<i64>,
+    /// Round trip time of the measurement that produced the offset.
+    rtt: f64,
+    last_heard: i64,
+    last_ping: i64,
+}
+
+/// Session state; none of this is persisted.
+struct Session {
+    id: PeerId,
+    /// Host time, in microseconds since this session was created.
+    now: i64,
+    /// True if we should join the default multicast group ourselves, as we haven't been given a
+    /// transport.
+    auto_join: bool,
+    /// Seconds until we next try to join the multicast group.
+    rejoin_in: f64,
+    /// The session timeline, in our host time.
+    timeline: Option<Timeline>,
+    /// Our beat count minus the session beat count; always a multiple of the quantum.
+    beat_offset: f64,
+    peers: HashMap<PeerId, Peer>,
+    last_broadcast: Option<i64>,
+    /// True if we changed the timeline and should tell everyone right away.
+    changed: bool,
+    /// Reusable buffer for incoming datagrams.
+    inbox: Vec<Vec<u8>>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            id: random_seed(),
+            now: 0,
+            auto_join: true,
+            rejoin_in: 0.0,
+            timeline: None,
+            beat_offset: 0.0,
+            peers: HashMap::new(),
+            last_broadcast: None,
+            changed: false,
+            inbox: Vec::new(),
+        }
+    }
+}
+
+impl Session {
+    /// Advance host time by the update interval, and return the new time.
+    fn advance(&mut self, dt: Duration) -> i64 {
+        self.now += dt.as_secs() as i64 * 1_000_000 + (dt.subsec_nanos() / 1000) as i64;
+        self.now
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LinkClock {
+    name: String,
+    value: ClockValue,
+    /// Session tempo, in Hz.
+    rate: f64,
+    /// Session quantum, in beats.
+    quantum: f64,
+    #[serde(skip_serializing, skip_deserializing)]
+    transport: Option<Box<PeerTransport>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    session: Session,
+}
+
+impl LinkClock {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        LinkClock {
+            name: name.into(),
+            value: ClockValue::default(),
+            rate: INIT_RATE,
+            quantum: INIT_QUANTUM,
+            transport: None,
+            session: Session::default(),
+        }
+    }
+
+    /// Create a clock that joins the session on the provided transport.
+    pub fn with_transport<N: Into<String>>(name: N, transport: Box<PeerTransport>) -> Self {
+        let mut clock = LinkClock::new(name);
+        clock.set_transport(Some(transport));
+        clock
+    }
+
+    /// Join a session on a new transport, or leave the network entirely.
+    /// Our own timeline carries over into the new session.  Once given a transport, or told to
+    /// leave, the clock no longer joins the default multicast group by itself.
+    pub fn set_transport(&mut self, transport: Option<Box<PeerTransport>>) {
+        self.say_goodbye();
+        self.transport = transport;
+        self.session.auto_join = false;
+        self.session.peers.clear();
+        self.session.changed = true;
+    }
+
+    /// Join the default multicast group if we should and haven't yet.
+    /// Wait a while between attempts so that a missing network doesn't flood the log.
+    fn join(&mut self, dt: Duration) {
+        if !self.session.auto_join || self.transport.is_some() {
+            return;
+        }
+        self.session.rejoin_in -= secs(dt);
+        if self.session.rejoin_in > 0.0 {
+            return;
+        }
+        match UdpTransport::multicast(DEFAULT_GROUP, DEFAULT_PORT) {
+            Ok(transport) => {
+                self.transport = Some(Box::new(transport));
+                self.session.changed = true;
+            }
+            Err(e) => {
+                warn!("{} could not join the tempo sync group: {}", self.name, e);
+                self.session.rejoin_in = REJOIN_INTERVAL;
+            }
+        }
+    }
+
+    /// Return true if this clock is on the network.
+    pub fn has_transport(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// The number of peers whose timelines we can hear.
+    pub fn peer_count(&self) -> usize {
+        self.session.peers.values().filter(|p| p.offset.is_some()).count()
+    }
+
+    fn send(&mut self, msg: &PeerMessage) {
+        if let Some(ref mut transport) = self.transport {
+            transport.send(&msg.encode());
+        }
+    }
+
+    fn say_goodbye(&mut self) {
+        let bye = PeerMessage::Bye{from: self.session.id};
+        self.send(&bye);
+    }
+
+    /// Our current beat, according to the session timeline.
+    fn local_beat(&self, now: i64) -> Option<f64> {
+        self.session.timeline.map(|t| t.beat_at(now) + self.session.beat_offset)
+    }
+
+    /// Start a new version of the timeline using our current tempo and quantum, keeping the beat
+    /// continuous.
+    fn change_timeline(&mut self) {
+        let now = self.session.now;
+        let id = self.session.id;
+        let (rate, quantum) = (self.rate, self.quantum);
+        if let Some(ref mut timeline) = self.session.timeline {
+            *timeline = Timeline {
+                author: id,
+                version: timeline.version + 1,
+                tempo: rate * 60.0,
+                quantum: quantum,
+                beat: timeline.beat_at(now),
+                time: now,
+            };
+            self.session.changed = true;
+        }
+    }
+
+    /// Switch to a timeline, keeping our beat count aligned with it modulo the quantum.
+    /// Return knob change messages if the tempo or quantum changed.
+    fn adopt(&mut self, timeline: Timeline, now: i64) -> Messages<KnobResponse<KnobAddr>> {
+        let session_beat = timeline.beat_at(now);
+        let local_beat = self.local_beat(now).unwrap_or(session_beat);
+        let quantum = if timeline.quantum > 0.0 { timeline.quantum } else { 1.0 };
+        self.session.beat_offset = ((local_beat - session_beat) / quantum).round() * quantum;
+        self.session.timeline = Some(timeline);
+
+        let mut messages = Messages::none();
+        let rate = timeline.tempo / 60.0;
+        if rate != self.rate {
+            self.rate = rate;
+            messages.push(KnobResponse::ValueChange(RATE_KNOB_ADDR, Data::Rate(Rate::Hz(rate))));
+        }
+        if timeline.quantum != self.quantum {
+            self.quantum = timeline.quantum;
+            messages.push(KnobResponse::ValueChange(QUANTUM_KNOB_ADDR, Data::UFloat(self.quantum)));
+        }
+        messages
+    }
+
+    /// Act on a message from another peer.
+    fn handle(&mut self, msg: PeerMessage, now: i64) -> Messages<KnobResponse<KnobAddr>> {
+        let id = self.session.id;
+        let from = msg.from();
+        if from == id {
+            return Messages::none();
+        }
+        let mut new_peer = false;
+        self.session.peers.entry(from)
+            .or_insert_with(|| {
+                new_peer = true;
+                Peer { offset: None, rtt: 0.0, last_heard: now, last_ping: now }
+            })
+            .last_heard = now;
+        if new_peer {
+            debug!("Tempo sync peer {} joined.", from);
+            self.send(&PeerMessage::Ping{from: id, to: from, time: now});
+        }
+
+        match msg {
+            PeerMessage::Ping{to, time, ..} if to == id => {
+                self.send(&PeerMessage::Pong{from: id, to: from, ping_time: time, time: now});
+            }
+            PeerMessage::Pong{to, ping_time, time, ..} if to == id => {
+                let rtt = (now - ping_time) as f64;
+                let peer = self.session.peers.get_mut(&from).unwrap();
+                // Trust the measurement with the shortest round trip, as it has the least
+                // opportunity for asymmetric delay.
+                if peer.offset.is_none() || rtt <= peer.rtt {
+                    peer.offset = Some(time - (ping_time + now) / 2);
+                    peer.rtt = rtt;
+                }
+            }
+            PeerMessage::Timeline{timeline, ..} => {
+                let offset = self.session.peers.get(&from).and_then(|p| p.offset);
+                if let (Some(offset), Some(current)) = (offset, self.session.timeline) {
+                    let local = Timeline { time: timeline.time - offset, ..timeline };
+                    let same = (local.version, local.author) == (current.version, current.author);
+                    // The author is the authority on its own timeline; everyone else just
+                    // relays it, so only use relays to hear about newer timelines.
+                    if local.supersedes(&current) || (same && from == local.author) {
+                        return self.adopt(local, now);
+                    }
+                }
+            }
+            PeerMessage::Bye{..} => {
+                debug!("Tempo sync peer {} left.", from);
+                self.session.peers.remove(&from);
+            }
+            _ => (),
+        }
+        Messages::none()
+    }
+
+    /// Exchange messages with the network.
+    fn communicate(&mut self, now: i64) -> Messages<KnobResponse<KnobAddr>> {
+        let mut messages = Messages::none();
+        let mut inbox = ::std::mem::replace(&mut self.session.inbox, Vec::new());
+        match self.transport {
+            Some(ref mut transport) => transport.receive(&mut inbox),
+            None => return messages,
+        }
+        for datagram in inbox.drain(..) {
+            match PeerMessage::decode(&datagram) {
+                Some(msg) => messages.extend(self.handle(msg, now)),
+                None => warn!("Received an unreadable tempo sync datagram."),
+            }
+        }
+        self.session.inbox = inbox;
+
+        // Keep our offset measurements fresh, and forget peers that have gone away.
+        let id = self.session.id;
+        let mut pings = Vec::new();
+        self.session.peers.retain(|_, peer| now - peer.last_heard < PEER_TIMEOUT);
+        for (peer_id, peer) in self.session.peers.iter_mut() {
+            if now - peer.last_ping >= PING_INTERVAL {
+                peer.last_ping = now;
+                peer.rtt *= 1.0 + RTT_AGING;
+                pings.push(PeerMessage::Ping{from: id, to: *peer_id, time: now});
+            }
+        }
+        for ping in &pings {
+            self.send(ping);
+        }
+
+        let due = match self.session.last_broadcast {
+            Some(last) => now - last >= BROADCAST_INTERVAL,
+            None => true,
+        };
+        if due || self.session.changed {
+            if let Some(timeline) = self.session.timeline {
+                self.send(&PeerMessage::Timeline{from: id, timeline: timeline.at(now)});
+            }
+            self.session.last_broadcast = Some(now);
+            self.session.changed = false;
+        }
+        messages
+    }
+}
+
+impl Drop for LinkClock {
+    fn drop(&mut self) {
+        self.say_goodbye();
+    }
+}
+
+impl fmt::Debug for LinkClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LinkClock")
+            .field("name", &self.name)
+            .field("value", &self.value)
+            .field("rate", &self.rate)
+            .field("quantum", &self.quantum)
+            .field("has_transport", &self.has_transport())
+            .field("peer_id", &self.session.id)
+            .field("timeline", &self.session.timeline)
+            .field("peers", &self.session.peers)
+            .finish()
+    }
+}
+
+// Session state isn't persisted, so it isn't compared either.
+impl PartialEq for LinkClock {
+    fn eq(&self, other: &LinkClock) -> bool {
+        self.name == other.name
+            && self.value == other.value
+            && self.rate == other.rate
+            && self.quantum == other.quantum
+    }
+}
+
+pub const KIND: &'static str = "link";
+
+impl<M, I> Inputs<M, I> for LinkClock {
+    /// Link clock listens to the network, not other clocks.
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+const RATE_KNOB_ADDR: u32 = 0;
+const QUANTUM_KNOB_ADDR: u32 = 1;
+
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let rate_desc = KnobDescription {
+            name: Arc::new("rate".to_string()),
+            datatype: Datatype::Rate,
+        };
+        let quantum_desc = KnobDescription {
+            name: Arc::new("quantum".to_string()),
+            datatype: Datatype::UFloat,
+        };
+        vec!((RATE_KNOB_ADDR, rate_desc), (QUANTUM_KNOB_ADDR, quantum_desc))
+    };
+}
+
+impl Knobs<KnobAddr> for LinkClock {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<Datatype, KnobError<KnobAddr>> {
+        match addr {
+            RATE_KNOB_ADDR => Ok(Datatype::Rate),
+            QUANTUM_KNOB_ADDR => Ok(Datatype::UFloat),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<Data, KnobError<KnobAddr>> {
+        match addr {
+            RATE_KNOB_ADDR => Ok(Data::Rate(Rate::Hz(self.rate))),
+            QUANTUM_KNOB_ADDR => Ok(Data::UFloat(self.quantum)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Changing the tempo or quantum changes it for the whole session.
+    fn set_knob(&mut self, addr: KnobAddr, value: Data) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            RATE_KNOB_ADDR => {
+                self.rate = value.as_rate()?.in_hz();
+            }
+            QUANTUM_KNOB_ADDR => {
+                self.quantum = value.as_ufloat()?;
+            }
+            _ => {
+                return Err(badaddr(addr));
+            }
+        }
+        self.change_timeline();
+        Ok(())
+    }
+}
+
+impl Clock for LinkClock {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Talk to our peers, then read the session timeline at the current time.
+    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<KnobAddr>> {
+        let now = self.session.advance(dt);
+        self.join(dt);
+        if self.session.timeline.is_none() {
+            // Start our own timeline, picking up from wherever we were when last saved.
+            self.session.timeline = Some(Timeline {
+                author: self.session.id,
+                version: 0,
+                tempo: self.rate * 60.0,
+                quantum: self.quantum,
+                beat: self.value.float_value(),
+                time: now,
+            });
+        }
+        let messages = self.communicate(now);
+
+        if let Some(beat) = self.local_beat(now) {
+            let ticked = beat.floor() > self.value.float_value().floor();
+            self.value = ClockValue::from_float_value(beat, ticked);
+        }
+        messages
+    }
+
+    fn render(&self, _: &[Option<(ClockId, OutputId)>], _: &ClockProvider) -> ClockValue {
+        self.value
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,503 @@
+//! MIDI transport and the subset of the MIDI protocol we need for clock sync.
+//! Bytes are moved through the MidiInput and MidiOutput traits so that clocks don't care if they
+//! are talking to a hardware port, a network bridge, or a test harness.  MidiQueue is a simple
+//! shared buffer that implements both, and can be fed from any byte stream.
+//!
+//! Hardware ports are the raw MIDI devices that ALSA provides in /dev/snd, and are named by path.
+//!
+//! Also provides MidiClockOutput, which renders a clock value as MIDI beat clock.
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::VecDeque;
+use std::io::{Read, Write, Error as IoError};
+use std::fs::{self, File, OpenOptions};
+use std::thread;
+use super::clock::ClockValue;
+
+/// MIDI clock runs at 24 pulses per quarter note.
+pub const PULSES_PER_BEAT: i64 = 24;
+/// Song position pointer counts in sixteenth notes, which are six pulses.
+pub const PULSES_PER_SIXTEENTH: i64 = 6;
+
+// System real-time status bytes.
+pub const CLOCK: u8 = 0xF8;
+pub const START: u8 = 0xFA;
+pub const CONTINUE: u8 = 0xFB;
+pub const STOP: u8 = 0xFC;
+// System common status bytes.
+pub const QUARTER_FRAME: u8 = 0xF1;
+pub const SONG_POSITION: u8 = 0xF2;
+pub const SYSEX_START: u8 = 0xF0;
+pub const SYSEX_END: u8 = 0xF7;
+
+/// A source of incoming MIDI bytes.
+pub trait MidiInput: Send {
+    /// Append every byte that has arrived since the last read to the buffer.
+    fn read(&mut self, buffer: &mut Vec<u8>);
+
+    /// Return true if this input has failed or its stream has ended, and it will never produce
+    /// any more bytes.  A clock listening to a closed input will try to reopen its port.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// A destination for outgoing MIDI bytes.
+pub trait MidiOutput: Send {
+    fn write(&mut self, bytes: &[u8]);
+
+    /// Return true if this output has failed and will never accept any more bytes.
+    /// A clock sending to a closed output will try to reopen its port.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// Directory containing the raw MIDI devices.
+const PORT_DIR: &'static str = "/dev/snd";
+
+/// List the hardware MIDI ports currently present, by path.
+pub fn available_ports() -> Vec<String> {
+    let mut ports: Vec<String> = match fs::read_dir(PORT_DIR) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("midi"))
+            .map(|entry| entry.path().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    ports.sort();
+    ports
+}
+
+/// Open a MIDI port by path and start reading from it.
+pub fn open_port(port: &str) -> Result<MidiQueue, IoError> {
+    Ok(MidiQueue::from_reader(File::open(port)?))
+}
+
+/// Open a MIDI port by path for writing.
+pub fn open_output_port(port: &str) -> Result<MidiOutputPort, IoError> {
+    let file = OpenOptions::new().write(true).open(port)?;
+    Ok(MidiOutputPort { file: file, closed: false })
+}
+
+/// A hardware MIDI port opened for writing.
+pub struct MidiOutputPort {
+    file: File,
+    /// Set once a write has failed.
+    closed: bool,
+}
+
+impl MidiOutput for MidiOutputPort {
+    fn write(&mut self, bytes: &[u8]) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.file.write_all(bytes) {
+            error!("MIDI output port failed: {}", e);
+            self.closed = true;
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+#[derive(Clone, Default)]
+/// A shared queue of MIDI bytes.
+/// Clones share the same queue, so one handle can be given to a clock while another is used to
+/// feed it, or to collect the output of a MidiClockOutput.
+pub struct MidiQueue {
+    bytes: Arc<Mutex<VecDeque<u8>>>,
+    /// Set once the stream feeding this queue has ended.
+    closed: Arc<AtomicBool>,
+}
+
+impl MidiQueue {
+    pub fn new() -> Self {
+        MidiQueue::default()
+    }
+
+    /// Add bytes to the end of the queue.
+    pub fn push(&self, bytes: &[u8]) {
+        self.bytes.lock().unwrap().extend(bytes.iter().cloned());
+    }
+
+    /// Remove and return everything in the queue.
+    pub fn take(&self) -> Vec<u8> {
+        self.bytes.lock().unwrap().drain(..).collect()
+    }
+
+    /// Spawn a thread that copies everything from a byte stream into a new queue.
+    /// The thread exits when the stream ends or produces an error, and the queue is closed once
+    /// it has been emptied.
+    pub fn from_reader<R: Read + Send + 'static>(mut reader: R) -> Self {
+        let queue = MidiQueue::new();
+        let feed = queue.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => feed.push(&buf[..n]),
+                    Err(e) => {
+                        error!("MIDI input stream failed: {}", e);
+                        break;
+                    }
+                }
+            }
+            feed.closed.store(true, Ordering::SeqCst);
+        });
+        queue
+    }
+}
+
+impl MidiInput for MidiQueue {
+    fn read(&mut self, buffer: &mut Vec<u8>) {
+        buffer.extend(self.take());
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst) && self.bytes.lock().unwrap().is_empty()
+    }
+}
+
+impl MidiOutput for MidiQueue {
+    fn write(&mut self, bytes: &[u8]) {
+        self.push(bytes);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtcRate {
+    Fps24,
+    Fps25,
+    Fps2997Drop,
+    Fps30,
+}
+
+impl MtcRate {
+    /// Decode the two-bit rate code used in MTC messages.
+    fn from_code(code: u8) -> Self {
+        match code & 0x3 {
+            0 => MtcRate::Fps24,
+            1 => MtcRate::Fps25,
+            2 => MtcRate::Fps2997Drop,
+            _ => MtcRate::Fps30,
+        }
+    }
+
+    /// The number of frame labels per second.
+    /// Drop-frame timecode is labeled at 30 fps, and frame numbers are skipped to keep up.
+    fn label_rate(&self) -> f64 {
+        match *self {
+            MtcRate::Fps24 => 24.0,
+            MtcRate::Fps25 => 25.0,
+            MtcRate::Fps2997Drop | MtcRate::Fps30 => 30.0,
+        }
+    }
+
+    /// The actual number of frames per second.
+    pub fn fps(&self) -> f64 {
+        match *self {
+            MtcRate::Fps2997Drop => 30000.0 / 1001.0,
+            _ => self.label_rate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A complete SMPTE timecode.
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: MtcRate,
+}
+
+impl Timecode {
+    /// The position of this timecode, in seconds.
+    pub fn as_secs(&self) -> f64 {
+        let mut frames = (self.hours as i64 * 3600 + self.minutes as i64 * 60 + self.seconds as i64)
+            * self.rate.label_rate() as i64
+            + self.frames as i64;
+        if self.rate == MtcRate::Fps2997Drop {
+            // Two frame numbers are dropped every minute, except every tenth minute.
+            let total_minutes = self.hours as i64 * 60 + self.minutes as i64;
+            frames -= 2 * (total_minutes - total_minutes / 10);
+        }
+        frames as f64 / self.rate.fps()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The MIDI messages relevant to clock sync.
+pub enum MidiMessage {
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    /// Song position, in sixteenth notes.
+    SongPosition(u16),
+    /// An MTC quarter frame message: piece number and data nibble.
+    QuarterFrame(u8, u8),
+    /// An MTC full frame message, used when locating.
+    FullFrame(Timecode),
+}
+
+#[derive(Debug, Default)]
+/// Incremental MIDI parser.
+/// Handles running status and real-time bytes interleaved with other messages, and skips any
+/// message we don't care about.
+pub struct MidiParser {
+    /// The current running status, if any.
+    status: Option<u8>,
+    /// Data bytes received for the current message.
+    data: Vec<u8>,
+    /// True if we're inside a sysex message.
+    in_sysex: bool,
+}
+
+/// The number of data bytes following a channel or system common status byte.
+fn data_len(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        0xF0 => match status {
+            QUARTER_FRAME | 0xF3 => 1,
+            SONG_POSITION => 2,
+            _ => 0,
+        },
+        _ => 2,
+    }
+}
+
+impl MidiParser {
+    pub fn new() -> Self {
+        MidiParser::default()
+    }
+
+    /// Parse a single byte, returning a message if this byte completed one.
+    pub fn parse(&mut self, byte: u8) -> Option<MidiMessage> {
+        // Real-time messages can appear anywhere, even in the middle of other messages.
+        match byte {
+            CLOCK => return Some(MidiMessage::Clock),
+            START => return Some(MidiMessage::Start),
+            CONTINUE => return Some(MidiMessage::Continue),
+            STOP => return Some(MidiMessage::Stop),
+            0xF9 | 0xFD | 0xFE | 0xFF => return None,
+            _ => (),
+        }
+        if byte == SYSEX_START {
+            self.in_sysex = true;
+            self.status = None;
+            self.data.clear();
+            return None;
+        }
+        if self.in_sysex {
+            if byte == SYSEX_END {
+                self.in_sysex = false;
+                let msg = self.parse_sysex();
+                self.data.clear();
+                return msg;
+            }
+            else if byte & 0x80 == 0 {
+                self.data.push(byte);
+                return None;
+            }
+            // Any other status byte aborts the sysex, and is handled below.
+            self.in_sysex = false;
+        }
+        if byte & 0x80 != 0 {
+            self.status = Some(byte);
+            self.data.clear();
+            return if data_len(byte) == 0 { self.complete(byte) } else { None };
+        }
+        // A data byte; only meaningful if we have a status.
+        match self.status {
+            Some(status) => {
+                self.data.push(byte);
+                if self.data.len() >= data_len(status) {
+                    let msg = self.complete(status);
+                    self.data.clear();
+                    msg
+                }
+                else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+
+    /// Interpret a completed message with the current data bytes.
+    fn complete(&mut self, status: u8) -> Option<MidiMessage> {
+        // System common messages cancel running status.
+        if status >= 0xF0 {
+            self.status = None;
+        }
+        match status {
+            QUARTER_FRAME => Some(MidiMessage::QuarterFrame(self.data[0] >> 4, self.data[0] & 0xF)),
+            SONG_POSITION =>
+                Some(MidiMessage::SongPosition(self.data[0] as u16 | (self.data[1] as u16) << 7)),
+            _ => None,
+        }
+    }
+
+    /// Interpret the body of a sysex message.
+    /// We only care about MTC full frame: 7F <device> 01 01 hr mn sc fr
+    fn parse_sysex(&self) -> Option<MidiMessage> {
+        let d = &self.data;
+        if d.len() == 8 && d[0] == 0x7F && d[2] == 0x01 && d[3] == 0x01 {
+            Some(MidiMessage::FullFrame(Timecode {
+                hours: d[4] & 0x1F,
+                minutes: d[5],
+                seconds: d[6],
+                frames: d[7],
+                rate: MtcRate::from_code(d[4] >> 5),
+            }))
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// Assembles MTC quarter frame messages into a running timecode position.
+#[derive(Debug, Default)]
+pub struct MtcDecoder {
+    /// Data nibbles for each of the eight pieces.
+    nibbles: [u8; 8],
+    /// Bit mask of the pieces received since the last piece 0.
+    received: u8,
+    /// Current position in seconds, once we've seen a complete timecode.
+    position: Option<f64>,
+    /// Frame rate of the last complete timecode.
+    rate: Option<MtcRate>,
+}
+
+impl MtcDecoder {
+    /// Process a quarter frame message, returning the current position in seconds if known.
+    pub fn quarter_frame(&mut self, piece: u8, nibble: u8) -> Option<f64> {
+        let piece = (piece & 0x7) as usize;
+        if piece == 0 {
+            self.received = 0;
+        }
+        self.nibbles[piece] = nibble & 0xF;
+        self.received |= 1 << piece;
+
+        // Every quarter frame moves time along by a quarter of a frame.
+        if let (Some(pos), Some(rate)) = (self.position, self.rate) {
+            self.position = Some(pos + 0.25 / rate.fps());
+        }
+        if piece == 7 && self.received == 0xFF {
+            let n = &self.nibbles;
+            let tc = Timecode {
+                frames: n[0] | n[1] << 4,
+                seconds: n[2] | n[3] << 4,
+                minutes: n[4] | n[5] << 4,
+                hours: n[6] | (n[7] & 0x1) << 4,
+                rate: MtcRate::from_code(n[7] >> 1),
+            };
+            // The timecode was valid when piece 0 arrived, seven quarter frames ago.
+            self.position = Some(tc.as_secs() + 1.75 / tc.rate.fps());
+            self.rate = Some(tc.rate);
+        }
+        self.position
+    }
+
+    /// The frame rate of the incoming timecode, once we've seen a complete timecode.
+    pub fn rate(&self) -> Option<MtcRate> {
+        self.rate
+    }
+
+    /// Jump directly to a timecode, as instructed by a full frame message.
+    pub fn locate(&mut self, tc: Timecode) -> f64 {
+        let pos = tc.as_secs();
+        self.position = Some(pos);
+        self.rate = Some(tc.rate);
+        self.received = 0;
+        pos
+    }
+}
+
+/// The largest forward jump we'll cover by sending clock pulses rather than relocating.
+const MAX_CATCH_UP_PULSES: i64 = PULSES_PER_BEAT;
+/// Song position pointer is a 14-bit count of sixteenth notes.
+const SONG_POSITION_RANGE: i64 = 0x4000;
+
+/// Sends MIDI beat clock that follows a clock value.
+/// Call send with a clock's value every update; pulses are emitted as the clock passes them.
+/// Starting from the top of the song sends Start, otherwise the receiver is located using song
+/// position pointer and sent Continue.  Jumps backwards, and large jumps forwards, stop the
+/// receiver and relocate it the same way.
+pub struct MidiClockOutput {
+    sink: Box<MidiOutput>,
+    /// The last pulse that was sent, if we're running.
+    last_pulse: Option<i64>,
+}
+
+impl MidiClockOutput {
+    pub fn new(sink: Box<MidiOutput>) -> Self {
+        MidiClockOutput {
+            sink: sink,
+            last_pulse: None,
+        }
+    }
+
+    /// Return true if the receiver has been started and not since stopped.
+    pub fn is_running(&self) -> bool {
+        self.last_pulse.is_some()
+    }
+
+    /// Return true if the output we're sending to has failed.
+    pub fn is_closed(&self) -> bool {
+        self.sink.is_closed()
+    }
+
+    /// Emit whatever messages are needed to bring the receiver up to this clock value.
+    pub fn send(&mut self, value: ClockValue) {
+        let pulse = (value.float_value() * PULSES_PER_BEAT as f64).floor().max(0.0) as i64;
+        let mut bytes = Vec::new();
+        let last = match self.last_pulse {
+            Some(last) if pulse >= last && pulse - last <= MAX_CATCH_UP_PULSES => last,
+            running => {
+                if running.is_some() {
+                    bytes.push(STOP);
+                }
+                // Locate to the sixteenth note at or before this pulse and continue from there.
+                // Song position only counts up to 0x3FFF, so past that we wrap around, which
+                // still lands on the same beat.
+                let sixteenth = (pulse / PULSES_PER_SIXTEENTH) % SONG_POSITION_RANGE;
+                if sixteenth == 0 {
+                    // Start puts the receiver at the top of the song, and the first pulse after
+                    // it marks beat zero.
+                    bytes.push(START);
+                    bytes.push(CLOCK);
+                }
+                else {
+                    bytes.push(SONG_POSITION);
+                    bytes.push((sixteenth & 0x7F) as u8);
+                    bytes.push((sixteenth >> 7) as u8);
+                    bytes.push(CONTINUE);
+                }
+                pulse - pulse % PULSES_PER_SIXTEENTH
+            }
+        };
+        for _ in last..pulse {
+            bytes.push(CLOCK);
+        }
+        self.last_pulse = Some(pulse);
+        if !bytes.is_empty() {
+            self.sink.write(&bytes);
+        }
+    }
+
+    /// Stop the receiver.  The next send will start or relocate it again.
+    pub fn stop(&mut self) {
+        if self.last_pulse.take().is_some() {
+            self.sink.write(&[STOP]);
+        }
+    }
+}
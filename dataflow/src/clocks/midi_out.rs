@@ -0,0 +1,236 @@
+//! A clock that passes its input through unchanged, and sends it out as MIDI beat clock so that
+//! other gear can follow the show.
+//!
+//! The MIDI output is chosen from the hardware ports using the port knob.  As with the MIDI sync
+//! clock, the port is opened on the next update and reopened after a delay if it can't be opened
+//! or fails.  Disconnecting the input stops the receiver.
+use std::sync::Arc;
+use std::time::Duration;
+use std::cell::RefCell;
+use std::fmt;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use ::util::secs;
+use super::clock::{Clock, ClockValue, ClockId, ClockProvider, KnobAddr};
+use super::midi::{self, MidiOutput, MidiClockOutput};
+use ::network::{Inputs, OutputId};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype,
+    Data,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+
+/// Time to wait before trying to reopen a port that failed, in seconds.
+const REOPEN_INTERVAL: f64 = 5.0;
+/// The port knob choice for not sending to any port.
+const NO_PORT: &'static str = "none";
+
+#[derive(Serialize, Deserialize)]
+pub struct MidiOutClock {
+    name: String,
+    /// The MIDI port to send to, or empty for none.
+    #[serde(default)]
+    port: String,
+    // Implementation note: the output is a RefCell because beat clock is sent as the input is
+    // rendered, which is the only time the upstream value is available.
+    #[serde(skip_serializing, skip_deserializing)]
+    output: RefCell<Option<MidiClockOutput>>,
+    /// Seconds until we next try to open the port.
+    #[serde(skip_serializing, skip_deserializing)]
+    reopen_in: f64,
+}
+
+impl MidiOutClock {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        MidiOutClock {
+            name: name.into(),
+            port: String::new(),
+            output: RefCell::new(None),
+            reopen_in: 0.0,
+        }
+    }
+
+    /// Create a clock that sends to the provided MIDI output.
+    pub fn with_output<N: Into<String>>(name: N, output: Box<MidiOutput>) -> Self {
+        let mut clock = MidiOutClock::new(name);
+        clock.set_output(Some(output));
+        clock
+    }
+
+    /// Attach a new MIDI output to this clock, or detach the current one.
+    /// The current receiver is stopped, and the new one will be started on the next render.
+    pub fn set_output(&mut self, output: Option<Box<MidiOutput>>) {
+        let mut current = self.output.borrow_mut();
+        if let Some(ref mut current) = *current {
+            current.stop();
+        }
+        *current = output.map(MidiClockOutput::new);
+    }
+
+    /// Return true if a MIDI output is attached to this clock.
+    pub fn has_output(&self) -> bool {
+        self.output.borrow().is_some()
+    }
+
+    /// Open the port if we aren't sending anywhere, or drop the output if it has failed.
+    /// Wait a while between attempts so that a missing port doesn't flood the log.
+    fn connect(&mut self, dt: f64) {
+        let closed = self.output.borrow().as_ref().map(|out| out.is_closed()).unwrap_or(false);
+        if closed {
+            warn!("{} lost its MIDI port {}.", self.name, self.port);
+            *self.output.borrow_mut() = None;
+            self.reopen_in = REOPEN_INTERVAL;
+        }
+        if self.has_output() || self.port.is_empty() {
+            return;
+        }
+        self.reopen_in -= dt;
+        if self.reopen_in > 0.0 {
+            return;
+        }
+        match midi::open_output_port(&self.port) {
+            Ok(output) => self.set_output(Some(Box::new(output))),
+            Err(e) => {
+                warn!("{} could not open MIDI port {}: {}", self.name, self.port, e);
+                self.reopen_in = REOPEN_INTERVAL;
+            }
+        }
+    }
+
+    /// The ports that are present, plus the one we're set to even if it has gone away.
+    fn port_knob_datatype(&self) -> Datatype {
+        let mut ports = vec!(NO_PORT.to_string());
+        ports.extend(midi::available_ports());
+        if !self.port.is_empty() && !ports.contains(&self.port) {
+            ports.push(self.port.clone());
+        }
+        Datatype::Picker(ports)
+    }
+}
+
+impl fmt::Debug for MidiOutClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MidiOutClock")
+            .field("name", &self.name)
+            .field("port", &self.port)
+            .field("has_output", &self.has_output())
+            .finish()
+    }
+}
+
+// The output isn't persisted, so it isn't compared either.
+impl PartialEq for MidiOutClock {
+    fn eq(&self, other: &MidiOutClock) -> bool {
+        self.name == other.name && self.port == other.port
+    }
+}
+
+pub const KIND: &'static str = "midi out";
+
+// MIDI out always has a single input.
+impl<M, I> Inputs<M, I> for MidiOutClock {}
+
+const PORT_KNOB_ADDR: u32 = 0;
+
+impl Knobs<KnobAddr> for MidiOutClock {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        let port_desc = KnobDescription {
+            name: Arc::new("port".to_string()),
+            datatype: self.port_knob_datatype(),
+        };
+        vec!((PORT_KNOB_ADDR, port_desc))
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<Datatype, KnobError<KnobAddr>> {
+        match addr {
+            PORT_KNOB_ADDR => Ok(self.port_knob_datatype()),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<Data, KnobError<KnobAddr>> {
+        match addr {
+            PORT_KNOB_ADDR if self.port.is_empty() => Ok(Data::Picker(NO_PORT.to_string())),
+            PORT_KNOB_ADDR => Ok(Data::Picker(self.port.clone())),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Changing the port stops and closes the current one, and opens the new one on the next
+    /// update.
+    fn set_knob(&mut self, addr: KnobAddr, value: Data) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            PORT_KNOB_ADDR => {
+                let port = match value.as_picker() {
+                    Ok(NO_PORT) => String::new(),
+                    Ok(p) if p == self.port || midi::available_ports().iter().any(|a| a == p) =>
+                        p.to_string(),
+                    _ => return Err(badtype(self.port_knob_datatype(), value)),
+                };
+                self.port = port;
+                self.set_output(None);
+                self.reopen_in = 0.0;
+                Ok(())
+            }
+            _ => {
+                Err(badaddr(addr))
+            }
+        }
+    }
+}
+
+impl Clock for MidiOutClock {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<KnobAddr>> {
+        self.connect(secs(dt));
+        Messages::none()
+    }
+
+    /// Pass the upstream value through, sending whatever beat clock it calls for.
+    fn render(&self, inputs: &[Option<(ClockId, OutputId)>], clock_network: &ClockProvider)
+              -> ClockValue {
+        let mut output = self.output.borrow_mut();
+        match inputs.get(0) {
+            Some(&Some((id, _))) => {
+                let value = clock_network.get_value(id);
+                if let Some(ref mut output) = *output {
+                    output.send(value);
+                }
+                value
+            }
+            Some(&None) => {
+                // Input is disconnected, so stop the receiver and return 0.
+                if let Some(ref mut output) = *output {
+                    output.stop();
+                }
+                ClockValue::default()
+            }
+            None => {
+                error!("MIDI out {} was passed 0 inputs.", self.name);
+                ClockValue::default()
+            }
+        }
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,516 @@
+//! A clock that slaves to incoming MIDI beat clock or MIDI time code.
+//! In beat clock mode, every 24 pulses is one beat, Start/Stop/Continue control the transport and
+//! song position pointer relocates.  In timecode mode, the timecode position is converted into
+//! beats using the rate knob.
+//!
+//! The MIDI input is chosen from the hardware ports using the port knob.  The port is opened on
+//! the next update, and reopened after a delay if it can't be opened or goes away, so a clock
+//! loaded from a save picks its port back up by itself.
+//!
+//! Incoming bytes are only timestamped to the nearest update, so rather than jumping to every
+//! pulse we run at a smoothed estimate of the incoming rate and continuously pull the clock
+//! towards the received position.  The clock never runs backwards, except when relocated, and
+//! never runs more than a pulse ahead of what it has received, so it stops promptly if the
+//! transport goes quiet.
+use std::sync::Arc;
+use std::time::Duration;
+use std::collections::VecDeque;
+use std::fmt;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use ::util::secs;
+use super::clock::{Clock, ClockValue, ClockId, ClockProvider, KnobAddr};
+use super::midi::{
+    self, MidiInput, MidiParser, MidiMessage, MtcDecoder, PULSES_PER_BEAT, PULSES_PER_SIXTEENTH};
+use ::network::{Inputs, OutputId};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype,
+    Data,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use wiggles_value::knob_types::Rate;
+use serde_json::{Error as SerdeJsonError, self};
+
+// Assume 120 bpm until we've heard something.
+pub const INIT_RATE: f64 = 2.0;
+
+/// Window over which the incoming pulse rate is averaged, in seconds.
+const RATE_WINDOW: f64 = 1.0;
+/// Fraction of the position error corrected on each update.
+const POSITION_GAIN: f64 = 0.2;
+/// Timecode is considered stopped if no quarter frames arrive for this long, in seconds.
+const MTC_TIMEOUT: f64 = 0.1;
+/// If the received position is further than this from ours, in beats, jump straight to it.
+const RELOCATE_THRESHOLD: f64 = 1.0;
+/// Only report a new rate to clients if it has changed by more than this fraction.
+const RATE_REPORT_THRESHOLD: f64 = 0.01;
+/// Time to wait before trying to reopen a port that failed, in seconds.
+const REOPEN_INTERVAL: f64 = 5.0;
+/// The port knob choice for not listening to any port.
+const NO_PORT: &'static str = "none";
+
+lazy_static! {
+    static ref MODES: Vec<String> = vec!("midi clock".to_string(), "timecode".to_string());
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+enum SyncMode {
+    BeatClock,
+    Timecode,
+}
+
+impl SyncMode {
+    fn to_picker(&self) -> &'static str {
+        match *self {
+            SyncMode::BeatClock => "midi clock",
+            SyncMode::Timecode => "timecode",
+        }
+    }
+
+    fn from_picker(s: &str) -> Result<Self, ()> {
+        match s {
+            "midi clock" => Ok(SyncMode::BeatClock),
+            "timecode" => Ok(SyncMode::Timecode),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Transport state rebuilt from incoming messages; none of this is persisted.
+#[derive(Debug, Default)]
+struct SyncState {
+    parser: MidiParser,
+    mtc: MtcDecoder,
+    /// True if the transport is playing.
+    running: bool,
+    /// True if the next clock pulse is the first after a Start, and marks beat zero.
+    awaiting_first_pulse: bool,
+    /// Received song position, in pulses.
+    pulses: i64,
+    /// Total pulses ever received, used for rate estimation as it is never relocated.
+    pulse_total: u64,
+    /// Recent (time, pulse total) pairs, for rate estimation.
+    pulse_history: VecDeque<(f64, u64)>,
+    /// Received timecode position in seconds, if we have one.
+    timecode: Option<f64>,
+    /// When we last received a quarter frame.
+    last_quarter_frame: f64,
+    /// Total time this clock has been running, in seconds.
+    elapsed: f64,
+    /// Last rate reported to clients.
+    reported_rate: Option<f64>,
+    /// True if the clock was relocated during this update.
+    relocated: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MidiSyncClock {
+    name: String,
+    value: ClockValue,
+    mode: SyncMode,
+    /// In beat clock mode, the estimated incoming rate.  In timecode mode, the rate used to
+    /// convert timecode into beats.  In Hz.
+    rate: f64,
+    /// The smoothed position of this clock, in beats.
+    position: f64,
+    /// The MIDI port to listen to, or empty for none.
+    #[serde(default)]
+    port: String,
+    #[serde(skip_serializing, skip_deserializing)]
+    input: Option<Box<MidiInput>>,
+    /// Seconds until we next try to open the port.
+    #[serde(skip_serializing, skip_deserializing)]
+    reopen_in: f64,
+    #[serde(skip_serializing, skip_deserializing)]
+    state: SyncState,
+    /// Reusable buffer for incoming bytes.
+    #[serde(skip_serializing, skip_deserializing)]
+    buffer: Vec<u8>,
+}
+
+impl MidiSyncClock {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        MidiSyncClock {
+            name: name.into(),
+            value: ClockValue::default(),
+            mode: SyncMode::BeatClock,
+            rate: INIT_RATE,
+            position: 0.0,
+            port: String::new(),
+            input: None,
+            reopen_in: 0.0,
+            state: SyncState::default(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Create a clock that listens to the provided MIDI input.
+    pub fn with_input<N: Into<String>>(name: N, input: Box<MidiInput>) -> Self {
+        let mut clock = MidiSyncClock::new(name);
+        clock.set_input(Some(input));
+        clock
+    }
+
+    /// Attach a new MIDI input to this clock, or detach the current one.
+    /// The transport is considered stopped until we hear otherwise.
+    pub fn set_input(&mut self, input: Option<Box<MidiInput>>) {
+        self.input = input;
+        self.state = SyncState::default();
+    }
+
+    /// Return true if a MIDI input is attached to this clock.
+    pub fn has_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    /// Open the port if we aren't listening to anything, or drop the input if it has closed.
+    /// Wait a while between attempts so that a missing port doesn't flood the log.
+    fn connect(&mut self, dt: f64) {
+        if self.input.as_ref().map(|input| input.is_closed()).unwrap_or(false) {
+            warn!("{} lost its MIDI port {}.", self.name, self.port);
+            self.set_input(None);
+            self.reopen_in = REOPEN_INTERVAL;
+        }
+        if self.input.is_some() || self.port.is_empty() {
+            return;
+        }
+        self.reopen_in -= dt;
+        if self.reopen_in > 0.0 {
+            return;
+        }
+        match midi::open_port(&self.port) {
+            Ok(input) => self.set_input(Some(Box::new(input))),
+            Err(e) => {
+                warn!("{} could not open MIDI port {}: {}", self.name, self.port, e);
+                self.reopen_in = REOPEN_INTERVAL;
+            }
+        }
+    }
+
+    /// Move the clock directly to a new position, in beats.
+    fn relocate(&mut self, position: f64) {
+        self.position = position;
+        self.value = ClockValue::from_float_value(position, true);
+        self.state.relocated = true;
+    }
+
+    /// Act on a single incoming message.
+    fn handle(&mut self, msg: MidiMessage) {
+        let beat_clock = self.mode == SyncMode::BeatClock;
+        match msg {
+            MidiMessage::Clock if beat_clock && self.state.running => {
+                if self.state.awaiting_first_pulse {
+                    self.state.awaiting_first_pulse = false;
+                }
+                else {
+                    self.state.pulses += 1;
+                }
+                self.state.pulse_total += 1;
+            }
+            MidiMessage::Start if beat_clock => {
+                self.state.running = true;
+                self.state.awaiting_first_pulse = true;
+                self.state.pulses = 0;
+                self.relocate(0.0);
+            }
+            MidiMessage::Continue if beat_clock => {
+                self.state.running = true;
+            }
+            MidiMessage::Stop if beat_clock => {
+                self.state.running = false;
+                self.state.pulse_history.clear();
+            }
+            MidiMessage::SongPosition(sixteenths) if beat_clock => {
+                self.state.pulses = sixteenths as i64 * PULSES_PER_SIXTEENTH;
+                let position = self.state.pulses as f64 / PULSES_PER_BEAT as f64;
+                self.relocate(position);
+            }
+            MidiMessage::QuarterFrame(piece, nibble) if !beat_clock => {
+                self.state.last_quarter_frame = self.state.elapsed;
+                let had_timecode = self.state.timecode.is_some();
+                self.state.timecode = self.state.mtc.quarter_frame(piece, nibble);
+                if let (false, Some(tc)) = (had_timecode, self.state.timecode) {
+                    let position = tc * self.rate;
+                    self.relocate(position);
+                }
+            }
+            MidiMessage::FullFrame(tc) if !beat_clock => {
+                let secs = self.state.mtc.locate(tc);
+                self.state.timecode = Some(secs);
+                let position = secs * self.rate;
+                self.relocate(position);
+            }
+            _ => (),
+        }
+    }
+
+    /// Read and act on everything that has arrived from the MIDI input.
+    fn listen(&mut self) {
+        match self.input {
+            Some(ref mut input) => {
+                self.buffer.clear();
+                input.read(&mut self.buffer);
+            }
+            None => return,
+        }
+        let bytes = ::std::mem::replace(&mut self.buffer, Vec::new());
+        let pulses_before = self.state.pulse_total;
+        for byte in &bytes {
+            if let Some(msg) = self.state.parser.parse(*byte) {
+                self.handle(msg);
+            }
+        }
+        self.buffer = bytes;
+
+        if self.state.pulse_total != pulses_before {
+            let now = self.state.elapsed;
+            let history = &mut self.state.pulse_history;
+            history.push_back((now, self.state.pulse_total));
+            while history.len() > 2 && now - history[1].0 >= RATE_WINDOW {
+                history.pop_front();
+            }
+            let (t0, p0) = history[0];
+            if now > t0 {
+                self.rate = (self.state.pulse_total - p0) as f64
+                    / (now - t0)
+                    / PULSES_PER_BEAT as f64;
+            }
+        }
+    }
+
+    /// The position we've received, and whether the transport is running.
+    fn received_position(&self) -> Option<f64> {
+        match self.mode {
+            SyncMode::BeatClock if self.state.running =>
+                Some(self.state.pulses as f64 / PULSES_PER_BEAT as f64),
+            SyncMode::Timecode if self.state.elapsed - self.state.last_quarter_frame < MTC_TIMEOUT =>
+                self.state.timecode.map(|tc| tc * self.rate),
+            _ => None,
+        }
+    }
+
+    /// How far ahead of the received position we're allowed to run, in beats.
+    fn max_lead(&self) -> f64 {
+        match self.mode {
+            SyncMode::BeatClock => 1.0 / PULSES_PER_BEAT as f64,
+            // A quarter frame's worth of time at the incoming frame rate.
+            SyncMode::Timecode =>
+                self.state.mtc.rate().map(|rate| 0.25 / rate.fps()).unwrap_or(0.0) * self.rate,
+        }
+    }
+}
+
+impl fmt::Debug for MidiSyncClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MidiSyncClock")
+            .field("name", &self.name)
+            .field("value", &self.value)
+            .field("mode", &self.mode)
+            .field("rate", &self.rate)
+            .field("position", &self.position)
+            .field("port", &self.port)
+            .field("has_input", &self.has_input())
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+// Transport state isn't persisted, so it isn't compared either.
+impl PartialEq for MidiSyncClock {
+    fn eq(&self, other: &MidiSyncClock) -> bool {
+        self.name == other.name
+            && self.value == other.value
+            && self.mode == other.mode
+            && self.rate == other.rate
+            && self.position == other.position
+            && self.port == other.port
+    }
+}
+
+pub const KIND: &'static str = "midi";
+
+impl<M, I> Inputs<M, I> for MidiSyncClock {
+    /// MIDI sync clock listens to MIDI, not other clocks.
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+const MODE_KNOB_ADDR: u32 = 0;
+const RATE_KNOB_ADDR: u32 = 1;
+const PORT_KNOB_ADDR: u32 = 2;
+
+fn mode_knob_datatype() -> Datatype {
+    Datatype::Picker(MODES.clone())
+}
+
+impl MidiSyncClock {
+    /// The ports that are present, plus the one we're set to even if it has gone away.
+    fn port_knob_datatype(&self) -> Datatype {
+        let mut ports = vec!(NO_PORT.to_string());
+        ports.extend(midi::available_ports());
+        if !self.port.is_empty() && !ports.contains(&self.port) {
+            ports.push(self.port.clone());
+        }
+        Datatype::Picker(ports)
+    }
+}
+
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let mode_desc = KnobDescription {
+            name: Arc::new("sync mode".to_string()),
+            datatype: mode_knob_datatype(),
+        };
+        let rate_desc = KnobDescription {
+            name: Arc::new("rate".to_string()),
+            datatype: Datatype::Rate,
+        };
+        vec!((MODE_KNOB_ADDR, mode_desc), (RATE_KNOB_ADDR, rate_desc))
+    };
+}
+
+impl Knobs<KnobAddr> for MidiSyncClock {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        let mut knobs = KNOB_DESC.clone();
+        let port_desc = KnobDescription {
+            name: Arc::new("port".to_string()),
+            datatype: self.port_knob_datatype(),
+        };
+        knobs.push((PORT_KNOB_ADDR, port_desc));
+        knobs
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<Datatype, KnobError<KnobAddr>> {
+        match addr {
+            MODE_KNOB_ADDR => Ok(mode_knob_datatype()),
+            RATE_KNOB_ADDR => Ok(Datatype::Rate),
+            PORT_KNOB_ADDR => Ok(self.port_knob_datatype()),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<Data, KnobError<KnobAddr>> {
+        match addr {
+            MODE_KNOB_ADDR => Ok(Data::Picker(self.mode.to_picker().to_string())),
+            RATE_KNOB_ADDR => Ok(Data::Rate(Rate::Hz(self.rate))),
+            PORT_KNOB_ADDR if self.port.is_empty() => Ok(Data::Picker(NO_PORT.to_string())),
+            PORT_KNOB_ADDR => Ok(Data::Picker(self.port.clone())),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// In beat clock mode the rate knob reports the incoming rate, and setting it only provides
+    /// a starting estimate.  In timecode mode it sets the tempo of the timecode.
+    /// Changing the port closes the current one and opens the new one on the next update.
+    fn set_knob(&mut self, addr: KnobAddr, value: Data) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            MODE_KNOB_ADDR => {
+                let mode =
+                    value.as_picker()
+                        .and_then(|p| SyncMode::from_picker(&p))
+                        .map_err(|()| badtype(mode_knob_datatype(), value))?;
+                if mode != self.mode {
+                    self.mode = mode;
+                    // Start over, as positions in the two modes are unrelated.
+                    self.state = SyncState::default();
+                }
+                Ok(())
+            }
+            RATE_KNOB_ADDR => {
+                self.rate = value.as_rate()?.in_hz();
+                self.state.reported_rate = Some(self.rate);
+                // Changing the tempo of timecode moves us to a different beat.
+                if let (SyncMode::Timecode, Some(tc)) = (self.mode, self.state.timecode) {
+                    let position = tc * self.rate;
+                    self.relocate(position);
+                }
+                Ok(())
+            }
+            PORT_KNOB_ADDR => {
+                let port = match value.as_picker() {
+                    Ok(NO_PORT) => String::new(),
+                    Ok(p) if p == self.port || midi::available_ports().iter().any(|a| a == p) =>
+                        p.to_string(),
+                    _ => return Err(badtype(self.port_knob_datatype(), value)),
+                };
+                self.port = port;
+                self.set_input(None);
+                self.reopen_in = 0.0;
+                Ok(())
+            }
+            _ => {
+                Err(badaddr(addr))
+            }
+        }
+    }
+}
+
+impl Clock for MidiSyncClock {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Read incoming MIDI, then advance towards the received position.
+    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<KnobAddr>> {
+        let dt = secs(dt);
+        self.state.elapsed += dt;
+        self.value.ticked = false;
+        self.state.relocated = false;
+        self.connect(dt);
+        self.listen();
+
+        // Relocating sets the value directly, so there's nothing more to do.
+        if !self.state.relocated {
+            match self.received_position() {
+                Some(target) if (target - self.position).abs() > RELOCATE_THRESHOLD => {
+                    self.relocate(target);
+                }
+                Some(target) => {
+                    let predicted = self.position + self.rate * dt;
+                    let corrected = predicted + POSITION_GAIN * (target - predicted);
+                    let position = corrected.min(target + self.max_lead()).max(self.position);
+                    let ticked = position.floor() > self.position.floor();
+                    self.position = position;
+                    self.value = ClockValue::from_float_value(position, ticked);
+                }
+                None => (),
+            }
+        }
+
+        let rate = self.rate;
+        match self.state.reported_rate {
+            Some(reported) if ((rate - reported) / reported).abs() > RATE_REPORT_THRESHOLD => {
+                self.state.reported_rate = Some(rate);
+                Messages::one(KnobResponse::ValueChange(RATE_KNOB_ADDR, Data::Rate(Rate::Hz(rate))))
+            }
+            Some(_) => Messages::none(),
+            None => {
+                self.state.reported_rate = Some(rate);
+                Messages::none()
+            }
+        }
+    }
+
+    fn render(&self, _: &[Option<(ClockId, OutputId)>], _: &ClockProvider) -> ClockValue {
+        self.value
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
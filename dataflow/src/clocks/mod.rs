@@ -1,12 +1,20 @@
-use std::collections::HashMap;
-use serde::Deserializer;
+use std::sync::RwLock;
+use serde::de::DeserializeOwned;
 use serde_json::{self, Error as SerdeJsonError};
 use self::serde::SerializableClock;
-use serde::de::Error;
+use registry::{Registry, RegistryError};
 
 pub mod clock;
 pub mod simple;
 pub mod multiplier;
+pub mod tap;
+pub mod beat;
+pub mod pcm;
+pub mod midi;
+pub mod midi_sync;
+pub mod midi_out;
+pub mod peer;
+pub mod link;
 mod serde;
 
 pub use self::clock::{
@@ -16,56 +24,68 @@ pub use self::clock::{
     ClockKnobAddr,
     ClockValue,
     ClockNetwork,
+    LegacyClockNetwork,
     KnobAddr,
     ClockCollection,
 };
 
 // Gather every clock declaration up here.
-// We could potentially make this mutable and provide a registration function if we want to be able
-// to load clock defintions after compile time.  For now, we'll just keep it static.
-// This collection serves as both a registry to every kind of clock and how it is created, and
-// enables serialization and deserialization of those clocks once they are hidden behind trait
-// objects.
+// This registry knows about every kind of clock and how it is created, and enables serialization
+// and deserialization of those clocks once they are hidden behind trait objects.
+// The built-in clocks are registered here; other crates can add their own using register.
 
 lazy_static! {
-    pub static ref CLOCKS: Vec<&'static str> = vec!(
-        simple::KIND,
-        multiplier::KIND,
-    );
+    static ref REGISTRY: RwLock<Registry<CompleteClock>> = {
+        let mut registry = Registry::new("clock");
+        add(&mut registry, simple::KIND, simple::SimpleClock::new).unwrap();
+        add(&mut registry, multiplier::KIND, multiplier::ClockMultiplier::new).unwrap();
+        add(&mut registry, tap::KIND, tap::TapClock::new).unwrap();
+        add(&mut registry, beat::KIND, beat::BeatClock::new).unwrap();
+        add(&mut registry, midi_sync::KIND, midi_sync::MidiSyncClock::new).unwrap();
+        add(&mut registry, midi_out::KIND, midi_out::MidiOutClock::new).unwrap();
+        add(&mut registry, link::KIND, link::LinkClock::new).unwrap();
+        RwLock::new(registry)
+    };
+}
+
+fn add<C, F>(
+    registry: &mut Registry<CompleteClock>,
+    kind: &'static str,
+    constructor: F)
+    -> Result<(), RegistryError>
+    where C: 'static + CompleteClock + DeserializeOwned,
+          F: 'static + Fn(String) -> C + Send + Sync
+{
+    registry.register(
+        kind,
+        Box::new(move |name| Box::new(constructor(name))),
+        Box::new(|data| {
+            let clock: C = serde_json::from_value(data)?;
+            Ok(Box::new(clock))
+        }))
+}
+
+/// Register a new kind of clock, using a function that creates a new clock given a name.
+/// The kind must match the one the clock reports, as that is how it will be deserialized.
+pub fn register<C, F>(kind: &'static str, constructor: F) -> Result<(), RegistryError>
+    where C: 'static + CompleteClock + DeserializeOwned,
+          F: 'static + Fn(String) -> C + Send + Sync
+{
+    add(&mut REGISTRY.write().unwrap(), kind, constructor)
+}
+
+/// List every registered kind of clock.
+pub fn kinds() -> Vec<&'static str> {
+    REGISTRY.read().unwrap().kinds().to_vec()
 }
 
 /// Return an initialized clock with the provided name, if the kind matches a registered one.
 /// Return None if the kind is unknown.
 pub fn new_clock<N: Into<String>>(kind: &str, name: N) -> Option<Box<CompleteClock>> {
-    match kind {
-        simple::KIND => Some(Box::new(simple::SimpleClock::new(name))),
-        multiplier::KIND => Some(Box::new(multiplier::ClockMultiplier::new(name))),
-        _ => None,
-    }
+    REGISTRY.read().unwrap().create(kind, name.into())
 }
 
-/// Deserialize a clock that has been serialized using our janky mechanism.
-pub fn deserialize(clock: SerializableClock) -> Result<Box<CompleteClock>, SerdeJsonError>
-{
-    match clock.kind.as_str() {
-        simple::KIND => {
-            let result: Result<simple::SimpleClock, _> = serde_json::from_str(&clock.serialized); 
-            handle_deserialize_result(result)
-        }
-        multiplier::KIND => {
-            let result: Result<multiplier::ClockMultiplier, _> = serde_json::from_str(&clock.serialized); 
-            handle_deserialize_result(result)
-        }
-        _ => Err(SerdeJsonError::custom(format!("Unknown clock kind: '{}'.", clock.kind))),
-    }
+/// Deserialize a clock from its kind and data, using whatever was registered for that kind.
+pub fn deserialize(clock: SerializableClock) -> Result<Box<CompleteClock>, SerdeJsonError> {
+    REGISTRY.read().unwrap().deserialize(&clock.kind, clock.data.0)
 }
-
-fn handle_deserialize_result<T>(
-    result: Result<T, SerdeJsonError>) -> Result<Box<CompleteClock>, SerdeJsonError>
-    where T: 'static + CompleteClock
-{
-    match result {
-        Ok(deserialized) => Ok(Box::new(deserialized)),
-        Err(e) => Err(SerdeJsonError::custom(e)),
-    }
-}
\ No newline at end of file
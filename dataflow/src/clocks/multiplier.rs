@@ -6,6 +6,7 @@ use std::time::Duration;
 use std::cell::Cell;
 use std::cmp::max;
 use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
 use ::util::{modulo_one, almost_eq};
 use super::clock::{Clock, ClockValue, ClockId, ClockProvider, KnobAddr};
 use ::network::{Inputs, OutputId};
@@ -219,7 +220,7 @@ impl Clock for ClockMultiplier {
 
     }
 
-    fn as_json(&self) -> Result<String, SerdeJsonError> {
-        serde_json::to_string(self)
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
     }
 }
\ No newline at end of file
@@ -0,0 +1,369 @@
+//! Sources of PCM audio used to drive audio-reactive clocks.
+//! Audio arrives through the PcmSource trait so that a clock doesn't care if it is listening to a
+//! sound card or reading a file from disk.  A simple WAV file reader is provided here, which is
+//! mostly useful for testing, along with a reader for live streams of raw samples.
+use std::path::Path;
+use std::time::Duration;
+use std::io::{Read, Error as IoError, ErrorKind};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use ::util::secs;
+
+/// A stream of audio samples.
+pub trait PcmSource: Send {
+    /// The sample rate of this source, in Hz.
+    fn sample_rate(&self) -> u32;
+
+    /// Append mono samples on the range [-1.0, 1.0] to the buffer.
+    /// Sources that play back recorded audio should append the samples covering the provided
+    /// interval.  Live sources should append whatever has arrived since the last read, regardless
+    /// of the interval.
+    fn read(&mut self, interval: Duration, buffer: &mut Vec<f64>);
+
+    /// Return true if this source has failed or its stream has ended, and it will never produce
+    /// any more audio.  A clock listening to a closed source will try to reopen its input.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// Open an audio input by name.
+/// Names ending in ".wav" are played back as WAV files.  Anything else is read as a live stream of
+/// raw samples, such as a named pipe or a device.
+pub fn open(input: &str) -> Result<Box<PcmSource>, WavError> {
+    if input.to_lowercase().ends_with(".wav") {
+        Ok(Box::new(WavSource::open(input)?))
+    }
+    else {
+        Ok(Box::new(StreamSource::open(input, STREAM_SAMPLE_RATE)?))
+    }
+}
+
+/// WAV format tag for integer PCM.
+const FORMAT_PCM: u16 = 1;
+/// WAV format tag for IEEE floating point.
+const FORMAT_FLOAT: u16 = 3;
+/// WAV format tag indicating that the real format is in the extension block.
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Plays back the contents of a WAV file in real time, mixed down to mono.
+/// Once the file has been exhausted, no more samples are produced.
+pub struct WavSource {
+    sample_rate: u32,
+    samples: Vec<f64>,
+    /// Index of the next sample to play.
+    position: usize,
+    /// Fractional sample left over from the last read, so we don't drift over time.
+    remainder: f64,
+}
+
+impl WavSource {
+    /// Open and decode a WAV file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WavError> {
+        let file = File::open(path)?;
+        WavSource::from_reader(file)
+    }
+
+    /// Decode a WAV file from a reader.
+    /// Supports 8, 16, 24, and 32-bit integer PCM and 32 and 64-bit float, with any number of
+    /// channels.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, WavError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(WavError::NotWav);
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos+4];
+            let len = le_u32(&bytes[pos+4..pos+8]) as usize;
+            let start = pos + 8;
+            // Tolerate a truncated final chunk, which is common with streamed recordings.
+            let end = (start + len).min(bytes.len());
+            match id {
+                b"fmt " => format = Some(WavFormat::parse(&bytes[start..end])?),
+                b"data" => data = Some(&bytes[start..end]),
+                _ => (),
+            }
+            // Chunks are padded to an even length.
+            pos = start + len + (len & 1);
+        }
+        let format = format.ok_or(WavError::MissingChunk("fmt "))?;
+        let data = data.ok_or(WavError::MissingChunk("data"))?;
+
+        Ok(WavSource {
+            sample_rate: format.sample_rate,
+            samples: format.decode(data)?,
+            position: 0,
+            remainder: 0.0,
+        })
+    }
+
+    /// Construct a source from mono samples that have already been decoded.
+    pub fn from_samples(sample_rate: u32, samples: Vec<f64>) -> Self {
+        WavSource {
+            sample_rate: sample_rate,
+            samples: samples,
+            position: 0,
+            remainder: 0.0,
+        }
+    }
+
+    /// The total duration of this file.
+    pub fn duration(&self) -> Duration {
+        let nanos = self.samples.len() as u64 * 1_000_000_000 / self.sample_rate as u64;
+        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+    }
+
+    /// Return true if every sample has been played.
+    pub fn finished(&self) -> bool {
+        self.position >= self.samples.len()
+    }
+}
+
+impl PcmSource for WavSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, interval: Duration, buffer: &mut Vec<f64>) {
+        let wanted = secs(interval) * self.sample_rate as f64 + self.remainder;
+        let count = wanted.floor();
+        self.remainder = wanted - count;
+        let end = (self.position + count as usize).min(self.samples.len());
+        buffer.extend_from_slice(&self.samples[self.position..end]);
+        self.position = end;
+    }
+}
+
+/// Sample rate assumed for raw audio streams, in Hz.
+pub const STREAM_SAMPLE_RATE: u32 = 44100;
+/// The most audio a stream holds on to between reads, in seconds.
+const MAX_STREAM_BUFFER: f64 = 1.0;
+
+#[derive(Default)]
+struct StreamState {
+    samples: VecDeque<f64>,
+    closed: bool,
+}
+
+/// Live audio arriving as raw signed 16-bit little-endian mono samples, such as from a named pipe
+/// fed by `arecord -t raw -f S16_LE -c 1 -r 44100`.
+/// The stream is read on its own thread, and each read hands over everything that has arrived.
+/// If nobody reads for a while, the oldest audio is thrown away.
+pub struct StreamSource {
+    sample_rate: u32,
+    state: Arc<Mutex<StreamState>>,
+}
+
+impl StreamSource {
+    /// Start reading a stream from a file, named pipe, or device.
+    /// Opening a named pipe blocks until something starts writing to it, so the stream is opened
+    /// on the reader thread; we only check here that it exists.
+    pub fn open<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<Self, WavError> {
+        let path = path.as_ref().to_path_buf();
+        path.metadata()?;
+        Ok(StreamSource::spawn(sample_rate, move || File::open(path)))
+    }
+
+    /// Start reading a stream from a reader.
+    pub fn from_reader<R: Read + Send + 'static>(reader: R, sample_rate: u32) -> Self {
+        StreamSource::spawn(sample_rate, move || Ok(reader))
+    }
+
+    fn spawn<R, F>(sample_rate: u32, open: F) -> Self
+        where R: Read, F: FnOnce() -> Result<R, IoError> + Send + 'static
+    {
+        let state = Arc::new(Mutex::new(StreamState::default()));
+        let shared = state.clone();
+        let max_len = (MAX_STREAM_BUFFER * sample_rate as f64) as usize;
+        thread::spawn(move || {
+            if let Err(e) = open().and_then(|reader| read_stream(reader, &shared, max_len)) {
+                error!("Audio stream failed: {}", e);
+            }
+            shared.lock().unwrap().closed = true;
+        });
+        StreamSource {
+            sample_rate: sample_rate,
+            state: state,
+        }
+    }
+}
+
+/// Read samples from a stream into the shared state until it ends, or until the source that owns
+/// the state is dropped.
+fn read_stream<R: Read>(
+    mut reader: R, state: &Arc<Mutex<StreamState>>, max_len: usize) -> Result<(), IoError>
+{
+    let mut bytes = [0u8; 4096];
+    // The first byte of a sample that was split between reads.
+    let mut split = None;
+    loop {
+        let count = match reader.read(&mut bytes) {
+            Ok(0) => return Ok(()),
+            Ok(count) => count,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if Arc::strong_count(state) == 1 {
+            return Ok(());
+        }
+        let mut data = &bytes[..count];
+        let mut state = state.lock().unwrap();
+        if let Some(first) = split.take() {
+            state.samples.push_back(s16_sample(&[first, data[0]]));
+            data = &data[1..];
+        }
+        for sample in data.chunks(2) {
+            if sample.len() == 2 {
+                state.samples.push_back(s16_sample(sample));
+            }
+            else {
+                split = Some(sample[0]);
+            }
+        }
+        while state.samples.len() > max_len {
+            state.samples.pop_front();
+        }
+    }
+}
+
+impl PcmSource for StreamSource {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, _: Duration, buffer: &mut Vec<f64>) {
+        buffer.extend(self.state.lock().unwrap().samples.drain(..));
+    }
+
+    /// The stream is closed once it has ended and everything it produced has been read.
+    fn is_closed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.closed && state.samples.is_empty()
+    }
+}
+
+/// The relevant contents of a WAV fmt chunk.
+struct WavFormat {
+    format: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+impl WavFormat {
+    fn parse(chunk: &[u8]) -> Result<Self, WavError> {
+        if chunk.len() < 16 {
+            return Err(WavError::MissingChunk("fmt "));
+        }
+        let mut format = le_u16(&chunk[0..2]);
+        // The extensible format stores the real format tag at the start of the sub-format GUID.
+        if format == FORMAT_EXTENSIBLE && chunk.len() >= 26 {
+            format = le_u16(&chunk[24..26]);
+        }
+        Ok(WavFormat {
+            format: format,
+            channels: le_u16(&chunk[2..4]),
+            sample_rate: le_u32(&chunk[4..8]),
+            bits: le_u16(&chunk[14..16]),
+        })
+    }
+
+    /// Decode interleaved sample data, mixing all channels down to mono.
+    fn decode(&self, data: &[u8]) -> Result<Vec<f64>, WavError> {
+        let decode_sample: fn(&[u8]) -> f64 = match (self.format, self.bits) {
+            (FORMAT_PCM, 8) => |b| (b[0] as f64 - 128.0) / 128.0,
+            (FORMAT_PCM, 16) => s16_sample,
+            (FORMAT_PCM, 24) =>
+                |b| ((le_u32(&[0, b[0], b[1], b[2]]) as i32) >> 8) as f64 / 8388608.0,
+            (FORMAT_PCM, 32) => |b| le_u32(b) as i32 as f64 / 2147483648.0,
+            (FORMAT_FLOAT, 32) => |b| f32::from_bits(le_u32(b)) as f64,
+            (FORMAT_FLOAT, 64) =>
+                |b| f64::from_bits(le_u32(&b[0..4]) as u64 | (le_u32(&b[4..8]) as u64) << 32),
+            (format, bits) => return Err(WavError::UnsupportedFormat{format: format, bits: bits}),
+        };
+        if self.channels == 0 || self.sample_rate == 0 {
+            return Err(WavError::UnsupportedFormat{format: self.format, bits: self.bits});
+        }
+        let sample_bytes = (self.bits / 8) as usize;
+        let frame_bytes = sample_bytes * self.channels as usize;
+        let channels = self.channels as f64;
+        Ok(data.chunks(frame_bytes)
+            // drop any incomplete trailing frame
+            .filter(|frame| frame.len() == frame_bytes)
+            .map(|frame| frame.chunks(sample_bytes).map(decode_sample).sum::<f64>() / channels)
+            .collect())
+    }
+}
+
+fn s16_sample(b: &[u8]) -> f64 {
+    le_u16(b) as i16 as f64 / 32768.0
+}
+
+fn le_u16(b: &[u8]) -> u16 {
+    b[0] as u16 | (b[1] as u16) << 8
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+}
+
+#[derive(Debug)]
+/// The errors that could occur while reading a WAV file or opening an audio stream.
+pub enum WavError {
+    /// The file could not be read.
+    Io(IoError),
+    /// The file doesn't have a RIFF WAVE header.
+    NotWav,
+    /// A chunk we need was missing or malformed.
+    MissingChunk(&'static str),
+    /// We don't know how to decode this sample format.
+    UnsupportedFormat{format: u16, bits: u16},
+}
+
+impl From<IoError> for WavError {
+    fn from(e: IoError) -> Self {
+        WavError::Io(e)
+    }
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::WavError::*;
+        match *self {
+            Io(ref e) => write!(f, "Could not read WAV file: {}", e),
+            NotWav => write!(f, "Not a WAV file."),
+            MissingChunk(id) => write!(f, "WAV file has a missing or malformed '{}' chunk.", id),
+            UnsupportedFormat{format, bits} =>
+                write!(f, "Unsupported WAV sample format {} with {} bits per sample.", format, bits),
+        }
+    }
+}
+
+impl Error for WavError {
+    fn description(&self) -> &str {
+        use self::WavError::*;
+        match *self {
+            Io(_) => "IO error occurred.",
+            NotWav => "Not a WAV file.",
+            MissingChunk(_) => "Missing WAV chunk.",
+            UnsupportedFormat{..} => "Unsupported WAV sample format.",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            WavError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Datagram transport and wire format for sharing a tempo session with peers on the network.
+//! Peers are found by sending to a UDP multicast group; every peer listening on the group hears
+//! every message.  Peers can also be given an explicit list of unicast addresses, which is handy
+//! for testing on loopback or crossing networks that don't pass multicast.
+use std::net::{UdpSocket, SocketAddr, SocketAddrV4, Ipv4Addr};
+use std::io::{Error as IoError, ErrorKind};
+use serde_json;
+
+/// Default multicast group and port used to find peers.
+pub const DEFAULT_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 77, 77);
+pub const DEFAULT_PORT: u16 = 20909;
+
+/// Largest datagram we expect to receive.
+const MAX_DATAGRAM: usize = 1024;
+
+/// Unique identifier of a peer.
+pub type PeerId = u64;
+
+/// A way to send and receive datagrams to every peer in the session.
+pub trait PeerTransport: Send {
+    /// Send a datagram to every peer.
+    fn send(&mut self, datagram: &[u8]);
+
+    /// Append every datagram that has arrived since the last call to the buffer.
+    fn receive(&mut self, buffer: &mut Vec<Vec<u8>>);
+}
+
+/// Peer transport over UDP.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    destinations: Vec<SocketAddr>,
+}
+
+impl UdpTransport {
+    /// Join a multicast group and send to it.
+    /// Only one process on a host can bind the port, so multiple peers on one machine should use
+    /// unicast instead.
+    pub fn multicast(group: Ipv4Addr, port: u16) -> Result<Self, IoError> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port))?;
+        socket.join_multicast_v4(&group, &Ipv4Addr::new(0, 0, 0, 0))?;
+        socket.set_multicast_loop_v4(true)?;
+        UdpTransport::new(socket, vec!(SocketAddr::V4(SocketAddrV4::new(group, port))))
+    }
+
+    /// Bind to a local address and send to a fixed list of peers.
+    pub fn unicast(bind: SocketAddr, peers: Vec<SocketAddr>) -> Result<Self, IoError> {
+        UdpTransport::new(UdpSocket::bind(bind)?, peers)
+    }
+
+    fn new(socket: UdpSocket, destinations: Vec<SocketAddr>) -> Result<Self, IoError> {
+        socket.set_nonblocking(true)?;
+        Ok(UdpTransport {
+            socket: socket,
+            destinations: destinations,
+        })
+    }
+
+    /// Add another destination for our datagrams.
+    pub fn add_destination(&mut self, addr: SocketAddr) {
+        self.destinations.push(addr);
+    }
+
+    /// The local address this transport is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
+        self.socket.local_addr()
+    }
+}
+
+impl PeerTransport for UdpTransport {
+    fn send(&mut self, datagram: &[u8]) {
+        for dest in &self.destinations {
+            if let Err(e) = self.socket.send_to(datagram, dest) {
+                warn!("Could not send tempo sync datagram to {}: {}", dest, e);
+            }
+        }
+    }
+
+    fn receive(&mut self, buffer: &mut Vec<Vec<u8>>) {
+        let mut buf = [0u8; MAX_DATAGRAM];
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, _)) => buffer.push(buf[..len].to_vec()),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Error receiving tempo sync datagram: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// A mapping from a peer's host time onto beats.
+pub struct Timeline {
+    /// The peer that last changed the tempo.
+    /// Ties between timelines of the same version go to the higher author.
+    pub author: PeerId,
+    /// Incremented every time the tempo is changed, so the newest change wins.
+    pub version: u64,
+    /// Tempo, in beats per minute.
+    pub tempo: f64,
+    /// Length of a bar in beats.  Peers keep their beats aligned modulo the quantum.
+    pub quantum: f64,
+    /// The beat at the reference time.
+    pub beat: f64,
+    /// Reference time in microseconds, in the host time of the peer sending the message.
+    pub time: i64,
+}
+
+impl Timeline {
+    /// The beat at a particular host time.
+    pub fn beat_at(&self, time: i64) -> f64 {
+        self.beat + (time - self.time) as f64 * self.tempo / 60e6
+    }
+
+    /// Return true if this timeline should replace the other.
+    pub fn supersedes(&self, other: &Timeline) -> bool {
+        (self.version, self.author) > (other.version, other.author)
+    }
+
+    /// Re-express this timeline with a new reference time.
+    pub fn at(&self, time: i64) -> Timeline {
+        Timeline {
+            beat: self.beat_at(time),
+            time: time,
+            ..*self
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Everything that peers say to each other.
+pub enum PeerMessage {
+    /// Our view of the session timeline.
+    Timeline{from: PeerId, timeline: Timeline},
+    /// Ask a peer for its host time, so we can measure our clock offset.
+    Ping{from: PeerId, to: PeerId, time: i64},
+    /// Reply to a ping with our host time.
+    Pong{from: PeerId, to: PeerId, ping_time: i64, time: i64},
+    /// We're leaving the session.
+    Bye{from: PeerId},
+}
+
+impl PeerMessage {
+    pub fn from(&self) -> PeerId {
+        match *self {
+            PeerMessage::Timeline{from, ..} => from,
+            PeerMessage::Ping{from, ..} => from,
+            PeerMessage::Pong{from, ..} => from,
+            PeerMessage::Bye{from} => from,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // Serializing this type can't fail.
+        serde_json::to_vec(self).unwrap()
+    }
+
+    pub fn decode(datagram: &[u8]) -> Option<Self> {
+        serde_json::from_slice(datagram).ok()
+    }
+}
@@ -1,21 +1,17 @@
 //! Implement serialization and deserialization of clocks hidden behind trait objects.
 //! Unfortunately, since erased_serde has not been ported to serde 1.0 yet, there is no general-
 //! purpose mechanism for serializing a trait object, as Serialize is not object-safe :(
-//! As a shitty, horrible hack, we use the JSON serializer to serialize the object to a string,
-//! and then serialize that string inside whatever other serialization format we're using.
-//! Using JSON here ensures that all quasi-human-readable saves are still quasi-human-readable.
+//! Instead, each clock serializes itself into a JSON value, which is tagged with the clock's kind
+//! and serialized inside whatever other serialization format we're using.  See node_data.
 use super::clock::{CompleteClock, KnobAddr};
 use super::{deserialize as deserialize_clock};
+use node_data::SerializedNode;
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde::ser::{Error as SerError};
 use serde::de::{Error as DeError};
 
-#[derive(Debug, Serialize, Deserialize)]
-/// A clock in JSON-serialized form.
-pub struct SerializableClock {
-    pub kind: String,
-    pub serialized: String,
-}
+/// A clock in serialized form.
+pub type SerializableClock = SerializedNode;
 
 impl Serialize for Box<CompleteClock> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -3,6 +3,7 @@
 use std::sync::Arc;
 use std::time::Duration;
 use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
 use ::util::{secs, modulo_one};
 use super::clock::{Clock, ClockValue, ClockId, ClockProvider, KnobAddr};
 use ::network::{Inputs, OutputId};
@@ -160,7 +161,7 @@ impl Clock for SimpleClock {
         self.value
     }
 
-    fn as_json(&self) -> Result<String, SerdeJsonError> {
-        serde_json::to_string(self)
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
     }
 }
\ No newline at end of file
@@ -0,0 +1,240 @@
+//! A clock that learns its rate from a series of taps on a button knob.
+//! The intervals between recent taps are averaged, ignoring any that are wildly out of line with
+//! the rest, and every tap resets the phase so that the beat lines up with the tap.
+//! Also provides a nudge knob which temporarily speeds up or slows down the clock, for making
+//! small phase corrections by ear.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use ::util::secs;
+use super::clock::{Clock, ClockValue, ClockId, ClockProvider, KnobAddr};
+use ::network::{Inputs, OutputId};
+use wiggles_value::knob::{
+    Knobs, Datatype, Data, KnobDescription, Error as KnobError, badaddr, Response as KnobResponse};
+use wiggles_value::knob_types::Rate;
+use wiggles_value::{Datatype as WiggleDatatype, Data as WiggleData, Bipolar};
+use serde_json::{Error as SerdeJsonError, self};
+
+fn init_clock_val() -> ClockValue {
+    ClockValue::from_float_value(0.0, true)
+}
+// Run at 1 Hz until we've been tapped.
+pub const INIT_RATE: f64 = 1.0;
+/// The number of most recent taps used to estimate the rate.
+pub const MAX_TAPS: usize = 8;
+/// A gap between taps longer than this many seconds starts a new tap sequence.
+pub const MAX_TAP_INTERVAL: f64 = 2.0;
+/// Intervals that differ from the median interval by more than this fraction are ignored.
+pub const OUTLIER_TOLERANCE: f64 = 0.25;
+/// The fractional change in rate produced by a fully-deflected nudge knob.
+pub const MAX_NUDGE: f64 = 0.1;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TapClock {
+    name: String,
+    value: ClockValue,
+    /// Floating-point rate in units of Hz, as estimated from the taps.
+    rate: f64,
+    /// Temporary rate adjustment; the clock runs at rate * (1 + MAX_NUDGE * nudge).
+    nudge: Bipolar,
+    /// Total time this clock has been running, in seconds.
+    /// Taps are timestamped using this clock, so they are only as precise as the update interval.
+    elapsed: f64,
+    /// Timestamps of the taps in the current sequence, oldest first.
+    taps: Vec<f64>,
+    /// If True, the clock was tapped since the last update and should reset its phase.
+    tapped: bool,
+}
+
+impl TapClock {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        TapClock {
+            name: name.into(),
+            value: init_clock_val(),
+            rate: INIT_RATE,
+            nudge: Bipolar(0.0),
+            elapsed: 0.0,
+            taps: Vec::new(),
+            tapped: false,
+        }
+    }
+
+    /// Register a tap at the current time and re-estimate the rate.
+    fn tap(&mut self) {
+        let now = self.elapsed;
+        let stale = match self.taps.last() {
+            Some(last) => now - last > MAX_TAP_INTERVAL,
+            None => false,
+        };
+        if stale {
+            self.taps.clear();
+        }
+        self.taps.push(now);
+        if self.taps.len() > MAX_TAPS {
+            self.taps.remove(0);
+        }
+        if let Some(interval) = estimate_interval(&self.taps) {
+            self.rate = 1.0 / interval;
+        }
+        self.tapped = true;
+    }
+}
+
+/// Estimate the beat interval from a sequence of tap timestamps.
+/// Intervals too far from the median are rejected as outliers and the remainder are averaged.
+/// Return None if there aren't enough taps to produce an estimate.
+fn estimate_interval(taps: &[f64]) -> Option<f64> {
+    let mut intervals: Vec<f64> = taps.windows(2).map(|w| w[1] - w[0]).collect();
+    if intervals.is_empty() {
+        return None;
+    }
+    let mut sorted = intervals.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = sorted[sorted.len() / 2];
+    if median <= 0.0 {
+        return None;
+    }
+    intervals.retain(|i| ((i - median) / median).abs() <= OUTLIER_TOLERANCE);
+    // The median itself always survives, so this is never empty.
+    Some(intervals.iter().sum::<f64>() / intervals.len() as f64)
+}
+
+pub const KIND: &'static str = "tap";
+
+impl<M, I> Inputs<M, I> for TapClock {
+    /// Tap clock always has no inputs.
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+const TAP_KNOB_ADDR: u32 = 0;
+const RATE_KNOB_ADDR: u32 = 1;
+const NUDGE_KNOB_ADDR: u32 = 2;
+
+// Since TapClock always has the same number of knobs, use a static for its knob descriptions.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let tap_desc = KnobDescription {
+            name: Arc::new("tap".to_string()),
+            datatype: Datatype::Button,
+        };
+        let rate_desc = KnobDescription {
+            name: Arc::new("rate".to_string()),
+            datatype: Datatype::Rate,
+        };
+        let nudge_desc = KnobDescription {
+            name: Arc::new("nudge".to_string()),
+            datatype: Datatype::Wiggle(WiggleDatatype::Bipolar),
+        };
+        vec!((TAP_KNOB_ADDR, tap_desc), (RATE_KNOB_ADDR, rate_desc), (NUDGE_KNOB_ADDR, nudge_desc))
+    };
+}
+
+impl Knobs<KnobAddr> for TapClock {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<Datatype, KnobError<KnobAddr>> {
+        match addr {
+            TAP_KNOB_ADDR => Ok(Datatype::Button),
+            RATE_KNOB_ADDR => Ok(Datatype::Rate),
+            NUDGE_KNOB_ADDR => Ok(Datatype::Wiggle(WiggleDatatype::Bipolar)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<Data, KnobError<KnobAddr>> {
+        match addr {
+            TAP_KNOB_ADDR => Ok(Data::Button(self.tapped)),
+            RATE_KNOB_ADDR => Ok(Data::Rate(Rate::Hz(self.rate))),
+            NUDGE_KNOB_ADDR => Ok(Data::Wiggle(WiggleData::Bipolar(self.nudge))),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: Data) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            TAP_KNOB_ADDR => {
+                // We only pay attention to a "button down" message for a tap.
+                if value.as_button()? {
+                    self.tap();
+                }
+                else {
+                    debug!("Tap clock ignored a button-up knob message.");
+                }
+                Ok(())
+            }
+            RATE_KNOB_ADDR => {
+                // Setting the rate by hand abandons the current tap sequence.
+                self.rate = value.as_rate()?.in_hz();
+                self.taps.clear();
+                Ok(())
+            }
+            NUDGE_KNOB_ADDR => {
+                self.nudge = value.as_bipolar()?;
+                Ok(())
+            }
+            _ => {
+                Err(badaddr(addr))
+            }
+        }
+    }
+}
+
+impl Clock for TapClock {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Update the state of this clock using the provided update interval.
+    /// If we were tapped, the beat starts over from the beginning of this interval.
+    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<KnobAddr>> {
+        let dt = secs(dt);
+        self.elapsed += dt;
+        let elapsed_phase = self.rate * (1.0 + MAX_NUDGE * self.nudge.0) * dt;
+
+        if self.tapped {
+            self.tapped = false;
+            self.value.set_phase(elapsed_phase);
+            self.value.ticked = true;
+            self.value.tick_count += 1;
+            // Let the world know we've reset the button and what rate we've learned.
+            let mut msgs = Messages::one(
+                KnobResponse::ValueChange(TAP_KNOB_ADDR, Data::Button(false)));
+            msgs.push(KnobResponse::ValueChange(RATE_KNOB_ADDR, Data::Rate(Rate::Hz(self.rate))));
+            msgs
+        }
+        else {
+            let phase_unwrapped = *self.value.phase() + elapsed_phase;
+
+            // Determine how many ticks have actually elapsed.  It may be more than 1.
+            let accumulated_ticks = phase_unwrapped.floor() as i64;
+
+            self.value.ticked = accumulated_ticks.abs() > 0;
+            self.value.tick_count += accumulated_ticks;
+
+            self.value.set_phase(phase_unwrapped);
+            Messages::none()
+        }
+    }
+
+    fn render(&self, _: &[Option<(ClockId, OutputId)>], _: &ClockProvider) -> ClockValue {
+        self.value
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
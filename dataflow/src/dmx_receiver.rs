@@ -0,0 +1,265 @@
+//! Receive DMX from the network, so that other consoles and fader wings can drive wiggles.
+//! Art-Net and sACN are each received by a background thread, which keeps the most recent frame
+//! heard from every source of every universe.  Anything that wants DMX input asks for a universe
+//! to be listened to, then picks up its latest frame whenever it likes; it is up to the reader to
+//! decide when a frame is too old to trust.
+//!
+//! sACN sources are told apart by their CID, and the highest-priority source that is still
+//! sending wins.  Art-Net has no notion of a source, so every Art-Net sender counts as the same
+//! one, and the last packet received wins.
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{UdpSocket, Ipv4Addr};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use dmx_protocol::{artnet, sacn};
+
+/// Identifies the sender of a frame.
+pub type SourceId = [u8; 16];
+/// The source of every Art-Net frame.
+const ARTNET_SOURCE: SourceId = [0; 16];
+
+/// The largest universe we accept.
+const UNIVERSE_SIZE: usize = 512;
+
+/// An sACN source that has been silent this long gives way to lower-priority ones.
+const SACN_SOURCE_TIMEOUT_MS: u64 = 2500;
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub enum Protocol {
+    ArtNet,
+    Sacn,
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Protocol::ArtNet => "Art-Net",
+            Protocol::Sacn => "sACN",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, ()> {
+        match name {
+            "Art-Net" => Ok(Protocol::ArtNet),
+            "sACN" => Ok(Protocol::Sacn),
+            _ => Err(()),
+        }
+    }
+
+    /// The universe to use if none has been chosen.
+    pub fn default_universe(&self) -> u16 {
+        match *self {
+            Protocol::ArtNet => 0,
+            Protocol::Sacn => sacn::MIN_UNIVERSE,
+        }
+    }
+
+    pub fn universe_is_valid(&self, universe: u16) -> bool {
+        match *self {
+            Protocol::ArtNet => universe <= artnet::MAX_PORT_ADDRESS,
+            Protocol::Sacn => universe >= sacn::MIN_UNIVERSE && universe <= sacn::MAX_UNIVERSE,
+        }
+    }
+
+    /// Parse a universe as written by a user.
+    /// Art-Net universes may be written either as a single port address or as
+    /// "net:subnet:universe".
+    pub fn parse_universe(&self, universe: &str) -> Result<u16, ()> {
+        let parts = universe.trim().split(':')
+            .map(|part| part.trim().parse::<u16>().map_err(|_| ()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let parsed = match (*self, parts.as_slice()) {
+            (_, &[universe]) => universe,
+            (Protocol::ArtNet, &[net, subnet, universe]) if subnet < 0x10 && universe < 0x10 =>
+                net << 8 | subnet << 4 | universe,
+            _ => return Err(()),
+        };
+        if self.universe_is_valid(parsed) { Ok(parsed) } else { Err(()) }
+    }
+
+    /// Write a universe the way a user would.
+    pub fn format_universe(&self, universe: u16) -> String {
+        match *self {
+            Protocol::ArtNet =>
+                format!("{}:{}:{}", universe >> 8, (universe >> 4) & 0xf, universe & 0xf),
+            Protocol::Sacn => universe.to_string(),
+        }
+    }
+
+    fn port(&self) -> u16 {
+        match *self {
+            Protocol::ArtNet => artnet::ARTNET_PORT,
+            Protocol::Sacn => sacn::SACN_PORT,
+        }
+    }
+}
+
+/// The most recent levels received for a universe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Always a full universe; channels the sender didn't include are zero.
+    levels: Vec<u8>,
+    received: Instant,
+    priority: u8,
+}
+
+impl Frame {
+    fn new(data: &[u8], priority: u8) -> Self {
+        let mut levels = data[..data.len().min(UNIVERSE_SIZE)].to_vec();
+        levels.resize(UNIVERSE_SIZE, 0);
+        Frame {
+            levels: levels,
+            received: Instant::now(),
+            priority: priority,
+        }
+    }
+
+    /// Return the level of a channel, numbered from 1.  Channels outside the universe are zero.
+    pub fn level(&self, channel: usize) -> u8 {
+        if channel == 0 {
+            return 0;
+        }
+        self.levels.get(channel - 1).cloned().unwrap_or(0)
+    }
+
+    /// How long ago this frame arrived.
+    pub fn age(&self) -> Duration {
+        self.received.elapsed()
+    }
+}
+
+/// A frame of DMX pulled out of a packet.
+#[derive(Debug, PartialEq)]
+pub struct Received<'a> {
+    pub source: SourceId,
+    pub universe: u16,
+    pub levels: &'a [u8],
+    pub priority: u8,
+    /// The sender has stopped sending this universe.
+    pub terminated: bool,
+}
+
+/// Parse an ArtDmx packet, returning None for any other kind of packet.
+pub fn parse_artnet<'a>(packet: &'a [u8]) -> Option<Received<'a>> {
+    artnet::parse_dmx(packet).map(|dmx| Received {
+        source: ARTNET_SOURCE,
+        universe: dmx.universe,
+        levels: dmx.levels,
+        priority: 0,
+        terminated: false,
+    })
+}
+
+/// Parse an sACN data packet, returning None for any other kind of packet.
+/// Preview data and alternate start codes are ignored.
+pub fn parse_sacn<'a>(packet: &'a [u8]) -> Option<Received<'a>> {
+    match sacn::parse_data(packet) {
+        Some(ref data) if data.preview() => None,
+        Some(data) => Some(Received {
+            source: data.cid,
+            universe: data.universe,
+            levels: data.levels,
+            priority: data.priority,
+            terminated: data.terminated(),
+        }),
+        None => None,
+    }
+}
+
+lazy_static! {
+    /// The most recent frame from every source of every universe.
+    static ref FRAMES: Mutex<HashMap<(Protocol, u16), HashMap<SourceId, Frame>>> =
+        Mutex::new(HashMap::new());
+    /// The socket each protocol is being received on, if we've started listening.
+    static ref LISTENERS: Mutex<HashMap<Protocol, UdpSocket>> = Mutex::new(HashMap::new());
+}
+
+/// Handle a packet received for this protocol, keeping its levels if it carried any.
+/// A source that ends its stream is forgotten straight away; only the source itself can do so.
+pub fn receive(protocol: Protocol, packet: &[u8]) {
+    let received = match protocol {
+        Protocol::ArtNet => parse_artnet(packet),
+        Protocol::Sacn => parse_sacn(packet),
+    };
+    let received = match received {
+        Some(r) => r,
+        None => return,
+    };
+    let mut frames = FRAMES.lock().unwrap();
+    let key = (protocol, received.universe);
+    if received.terminated {
+        let empty = match frames.get_mut(&key) {
+            Some(sources) => {
+                sources.remove(&received.source);
+                sources.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            frames.remove(&key);
+        }
+    }
+    else {
+        let sources = frames.entry(key).or_insert_with(HashMap::new);
+        // Forget sources that have gone quiet, so they don't pile up.
+        sources.retain(|_, frame| frame.age() < Duration::from_millis(SACN_SOURCE_TIMEOUT_MS));
+        sources.insert(received.source, Frame::new(received.levels, received.priority));
+    }
+}
+
+/// Return the frame for this universe from the highest-priority source that is still sending.
+/// Sources of equal priority take turns, packet by packet.  If every source has gone quiet, return
+/// the most recent frame.
+pub fn frame(protocol: Protocol, universe: u16) -> Option<Frame> {
+    let frames = FRAMES.lock().unwrap();
+    let timeout = Duration::from_millis(SACN_SOURCE_TIMEOUT_MS);
+    frames.get(&(protocol, universe))?
+        .values()
+        .max_by_key(|frame| (frame.age() < timeout, frame.priority, frame.received))
+        .cloned()
+}
+
+/// Receive a protocol on this socket, on a new thread.
+/// Normally the socket is bound to the protocol's port by listen, but any socket will do.
+pub fn listen_on(protocol: Protocol, socket: UdpSocket) -> io::Result<()> {
+    LISTENERS.lock().unwrap().insert(protocol, socket.try_clone()?);
+    thread::Builder::new()
+        .name(format!("{} receiver", protocol.name()))
+        .spawn(move || {
+            let mut buf = [0; 1024];
+            loop {
+                match socket.recv(&mut buf) {
+                    Ok(len) => receive(protocol, &buf[..len]),
+                    Err(e) => {
+                        error!("{} receiver quit: {}", protocol.name(), e);
+                        LISTENERS.lock().unwrap().remove(&protocol);
+                        return;
+                    }
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Make sure we're receiving this universe, starting to listen for the protocol if need be.
+/// sACN universes are multicast, so we join the universe's group as well.
+pub fn listen(protocol: Protocol, universe: u16) -> io::Result<()> {
+    let listening = LISTENERS.lock().unwrap().contains_key(&protocol);
+    if !listening {
+        listen_on(protocol, UdpSocket::bind(("0.0.0.0", protocol.port()))?)?;
+    }
+    if protocol == Protocol::Sacn {
+        let listeners = LISTENERS.lock().unwrap();
+        if let Some(socket) = listeners.get(&protocol) {
+            let group = sacn::multicast_group(universe);
+            match socket.join_multicast_v4(&group, &Ipv4Addr::new(0, 0, 0, 0)) {
+                // We've already joined this group for another reader.
+                Err(ref e) if e.kind() == ErrorKind::AddrInUse => (),
+                result => result?,
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,469 @@
+//! A tiny arithmetic expression language, used to let users write their own wiggles.
+//! Expressions are compiled once into a syntax tree which can then be cheaply evaluated as
+//! many times as we like.
+//!
+//! Expressions may use:
+//! - numbers, parentheses, and the operators + - * / % ^ (power, right-associative)
+//! - phase: the phase of the wiggle's clock, including any phase offset
+//! - in1, in2, ...: the values of the wiggle's inputs; unconnected inputs are 0
+//! - pi
+//! - min(a, ...), max(a, ...), clamp(x, lo, hi), abs(x), pow(x, y)
+//! - the waveforms sine, triangle, sawtooth, square, exp_ramp_up and exp_ramp_down, which take an
+//!   angle and an optional duty cycle, and smooth_square, which takes an angle, a smoothing, and
+//!   an optional duty cycle.  Angles wrap around, so phase * 2 runs twice as fast.
+use std::f64::consts::PI;
+use std::fmt;
+use std::error;
+use std::iter::Peekable;
+use std::str::CharIndices;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error as DeError;
+use wiggles_value::{Unipolar, Datatype, Data};
+use waveforms;
+use util::{modulo, modulo_one};
+
+/// The values an expression can refer to when it is evaluated.
+pub struct Env<'a> {
+    /// Clock phase, on [0.0, 1.0).
+    pub phase: f64,
+    /// Values of the inputs; in1 is the first.
+    pub inputs: &'a [f64],
+    /// The datatype the waveform functions should produce.
+    pub type_hint: Option<Datatype>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A problem with the text of an expression.
+pub struct ParseError {
+    /// Character offset into the expression where the problem was found.
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}.", self.message, self.position)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        "Could not parse expression."
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+fn parse_error<T, M: Into<String>>(position: usize, message: M) -> Result<T, ParseError> {
+    Err(ParseError { position: position, message: message.into() })
+}
+
+#[derive(Debug, Clone)]
+/// A compiled expression.
+/// Serializes as its source text, and is recompiled when deserialized.
+pub struct Program {
+    source: String,
+    root: Expr,
+}
+
+impl Program {
+    /// Compile an expression, or explain why it can't be.
+    pub fn compile<S: Into<String>>(source: S) -> Result<Self, ParseError> {
+        let source = source.into();
+        let root = Parser::new(&source)?.parse()?;
+        Ok(Program { source: source, root: root })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate this expression.
+    /// Any input that isn't present in the environment evaluates to 0.
+    pub fn eval(&self, env: &Env) -> f64 {
+        self.root.eval(env)
+    }
+}
+
+// Two programs are the same if they were compiled from the same text.
+impl PartialEq for Program {
+    fn eq(&self, other: &Program) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for Program {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        self.source.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Program {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let source = String::deserialize(deserializer)?;
+        Program::compile(source).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Function {
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    Pow,
+    Sine,
+    Triangle,
+    Sawtooth,
+    Square,
+    SmoothSquare,
+    ExpRampUp,
+    ExpRampDown,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "clamp" => Some(Function::Clamp),
+            "abs" => Some(Function::Abs),
+            "pow" => Some(Function::Pow),
+            "sine" => Some(Function::Sine),
+            "triangle" => Some(Function::Triangle),
+            "sawtooth" => Some(Function::Sawtooth),
+            "square" => Some(Function::Square),
+            "smooth_square" => Some(Function::SmoothSquare),
+            "exp_ramp_up" => Some(Function::ExpRampUp),
+            "exp_ramp_down" => Some(Function::ExpRampDown),
+            _ => None,
+        }
+    }
+
+    /// The smallest and largest number of arguments this function takes.
+    fn arity(&self) -> (usize, Option<usize>) {
+        match *self {
+            Function::Min | Function::Max => (1, None),
+            Function::Clamp => (3, Some(3)),
+            Function::Abs => (1, Some(1)),
+            Function::Pow => (2, Some(2)),
+            Function::SmoothSquare => (2, Some(3)),
+            Function::Sine |
+            Function::Triangle |
+            Function::Sawtooth |
+            Function::Square |
+            Function::ExpRampUp |
+            Function::ExpRampDown => (1, Some(2)),
+        }
+    }
+
+    fn call(&self, args: &[f64], type_hint: Option<Datatype>) -> f64 {
+        // Waveform arguments, with the duty cycle defaulting to 1.
+        let angle = || Unipolar(modulo_one(args[0]));
+        let duty = |i: usize| Unipolar(args.get(i).cloned().unwrap_or(1.0).max(0.0).min(1.0));
+        let data = match *self {
+            Function::Min => return args.iter().cloned().fold(args[0], f64::min),
+            Function::Max => return args.iter().cloned().fold(args[0], f64::max),
+            Function::Clamp => return args[0].max(args[1]).min(args[2]),
+            Function::Abs => return args[0].abs(),
+            Function::Pow => return args[0].powf(args[1]),
+            Function::Sine => waveforms::sine(angle(), duty(1), false, type_hint),
+            Function::Triangle => waveforms::triangle(angle(), duty(1), false, type_hint),
+            Function::Sawtooth => waveforms::sawtooth(angle(), duty(1), false, type_hint),
+            Function::Square => waveforms::square(angle(), duty(1), false, type_hint),
+            Function::SmoothSquare => {
+                let smoothing = Unipolar(args[1].max(0.0).min(1.0));
+                waveforms::smooth_square(angle(), smoothing, duty(2), false, type_hint)
+            }
+            Function::ExpRampUp => waveforms::exp_ramp_up(angle(), duty(1), false, type_hint),
+            Function::ExpRampDown => waveforms::exp_ramp_down(angle(), duty(1), false, type_hint),
+        };
+        match data {
+            Data::Unipolar(Unipolar(v)) => v,
+            Data::Bipolar(b) => b.0,
+            other => Unipolar::from(other).0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Const(f64),
+    Phase,
+    /// Index of an input, starting at 0.
+    Input(usize),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, env: &Env) -> f64 {
+        match *self {
+            Expr::Const(v) => v,
+            Expr::Phase => env.phase,
+            Expr::Input(i) => env.inputs.get(i).cloned().unwrap_or(0.0),
+            Expr::Negate(ref e) => -e.eval(env),
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let (a, b) = (lhs.eval(env), rhs.eval(env));
+                match op {
+                    BinaryOp::Add => a + b,
+                    BinaryOp::Subtract => a - b,
+                    BinaryOp::Multiply => a * b,
+                    BinaryOp::Divide => a / b,
+                    BinaryOp::Modulo => modulo(a, b),
+                    BinaryOp::Power => a.powf(b),
+                }
+            }
+            Expr::Call(func, ref args) => {
+                let args: Vec<f64> = args.iter().map(|a| a.eval(env)).collect();
+                func.call(&args, env.type_hint)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    LeftParen,
+    RightParen,
+    Comma,
+    End,
+}
+
+/// Split an expression into tokens, each tagged with its position.
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<CharIndices> = source.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '+' | '-' | '*' | '/' | '%' | '^' => Token::Op(c),
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            ',' => Token::Comma,
+            c if c.is_digit(10) || c == '.' => {
+                let mut end = pos + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_digit(10) || c == '.') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                match source[pos..end].parse() {
+                    Ok(v) => Token::Number(v),
+                    Err(_) => return parse_error(pos, format!("Bad number '{}'", &source[pos..end])),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut end = pos + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                Token::Ident(source[pos..end].to_string())
+            }
+            c => return parse_error(pos, format!("Unexpected character '{}'", c)),
+        };
+        tokens.push((pos, token));
+    }
+    tokens.push((source.len(), Token::End));
+    Ok(tokens)
+}
+
+/// Recursive-descent parser.
+///
+/// expr  := term (('+' | '-') term)*
+/// term  := unary (('*' | '/' | '%') unary)*
+/// unary := '-' unary | power
+/// power := atom ('^' unary)?
+/// atom  := number | name | name '(' expr (',' expr)* ')' | '(' expr ')'
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, ParseError> {
+        Ok(Parser { tokens: tokenize(source)?, next: 0 })
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next].1
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.next].0
+    }
+
+    /// Consume the next token, leaving the end marker in place.
+    fn advance(&mut self) -> (usize, Token) {
+        let token = self.tokens[self.next].clone();
+        if self.next < self.tokens.len() - 1 {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), ParseError> {
+        if *self.peek() == expected {
+            self.advance();
+            Ok(())
+        }
+        else {
+            parse_error(self.position(), format!("Expected {}", what))
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, ParseError> {
+        if *self.peek() == Token::End {
+            return parse_error(0, "Empty expression");
+        }
+        let expr = self.expr()?;
+        match *self.peek() {
+            Token::End => Ok(expr),
+            Token::RightParen => parse_error(self.position(), "Unmatched ')'"),
+            _ => parse_error(self.position(), "Expected an operator"),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match *self.peek() {
+                Token::Op('+') => BinaryOp::Add,
+                Token::Op('-') => BinaryOp::Subtract,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match *self.peek() {
+                Token::Op('*') => BinaryOp::Multiply,
+                Token::Op('/') => BinaryOp::Divide,
+                Token::Op('%') => BinaryOp::Modulo,
+                _ => return Ok(lhs),
+            };
+            self.advance();
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if *self.peek() == Token::Op('-') {
+            self.advance();
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let base = self.atom()?;
+        if *self.peek() == Token::Op('^') {
+            self.advance();
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(BinaryOp::Power, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        let (pos, token) = self.advance();
+        match token {
+            Token::Number(v) => Ok(Expr::Const(v)),
+            Token::LeftParen => {
+                let expr = self.expr()?;
+                self.expect(Token::RightParen, "')'")?;
+                Ok(expr)
+            }
+            Token::Ident(name) => {
+                if *self.peek() == Token::LeftParen {
+                    self.advance();
+                    self.call(pos, &name)
+                }
+                else {
+                    variable(pos, &name)
+                }
+            }
+            Token::End => parse_error(pos, "Unexpected end of expression"),
+            _ => parse_error(pos, "Expected a number, name, or '('"),
+        }
+    }
+
+    /// Parse the arguments of a function call, having already consumed the opening paren.
+    fn call(&mut self, pos: usize, name: &str) -> Result<Expr, ParseError> {
+        let func = match Function::from_name(name) {
+            Some(f) => f,
+            None => return parse_error(pos, format!("Unknown function '{}'", name)),
+        };
+        let mut args = Vec::new();
+        if *self.peek() != Token::RightParen {
+            loop {
+                args.push(self.expr()?);
+                if *self.peek() != Token::Comma {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.expect(Token::RightParen, "',' or ')'")?;
+
+        let (min, max) = func.arity();
+        if args.len() < min || max.map(|max| args.len() > max).unwrap_or(false) {
+            let expected = match max {
+                Some(max) if max == min => format!("{}", min),
+                Some(max) => format!("{} to {}", min, max),
+                None => format!("at least {}", min),
+            };
+            return parse_error(
+                pos,
+                format!("'{}' takes {} arguments but was given {}", name, expected, args.len()));
+        }
+        Ok(Expr::Call(func, args))
+    }
+}
+
+fn variable(pos: usize, name: &str) -> Result<Expr, ParseError> {
+    match name {
+        "phase" => return Ok(Expr::Phase),
+        "pi" => return Ok(Expr::Const(PI)),
+        _ => (),
+    }
+    if name.starts_with("in") {
+        if let Ok(n) = name[2..].parse::<usize>() {
+            if n > 0 {
+                return Ok(Expr::Input(n - 1));
+            }
+            return parse_error(pos, "Inputs are numbered starting from in1");
+        }
+    }
+    parse_error(pos, format!("Unknown name '{}'", name))
+}
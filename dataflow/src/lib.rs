@@ -7,10 +7,16 @@ extern crate console_server;
 #[macro_use] extern crate lazy_static;
 extern crate serde_json;
 extern crate waveforms;
+extern crate dmx_protocol;
 #[cfg(test)] extern crate simple_logger;
+#[cfg(test)] extern crate bincode;
 
 pub mod network;
 pub mod clocks;
 pub mod wiggles;
+pub mod registry;
+pub mod node_data;
+pub mod expression;
+pub mod dmx_receiver;
 mod util;
 mod test;
\ No newline at end of file
@@ -0,0 +1,68 @@
+//! Serialized form of a node hidden behind a trait object.
+//! Serialize is not object-safe, so nodes serialize themselves into a JSON value tree, which
+//! we can then serialize with whatever serializer we were handed; see console_server::value_tree.
+//!
+//! Older saves stored each node as a JSON string inside the outer document; these still load.
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
+use serde_json::{self, Value};
+use network::{Inputs, Outputs};
+pub use console_server::value_tree::ValueTree as NodeData;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+/// A node in serialized form, tagged with its kind so we know how to deserialize it.
+pub struct SerializedNode {
+    pub kind: String,
+    pub data: NodeData,
+}
+
+impl<'de> Deserialize<'de> for SerializedNode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        struct Binary {
+            kind: String,
+            data: NodeData,
+        }
+
+        #[derive(Deserialize)]
+        struct HumanReadable {
+            kind: String,
+            #[serde(default)]
+            data: Option<Value>,
+            /// The node as a JSON string, as written by older versions.
+            #[serde(default)]
+            serialized: Option<String>,
+        }
+
+        if !deserializer.is_human_readable() {
+            let node = Binary::deserialize(deserializer)?;
+            return Ok(SerializedNode { kind: node.kind, data: node.data });
+        }
+        let node = HumanReadable::deserialize(deserializer)?;
+        let data = match (node.data, node.serialized) {
+            (Some(data), _) => data,
+            (None, Some(serialized)) =>
+                serde_json::from_str(&serialized).map_err(D::Error::custom)?,
+            (None, None) => return Err(D::Error::missing_field("data")),
+        };
+        Ok(SerializedNode { kind: node.kind, data: NodeData(data) })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// A node as stored in binary autosaves from before show versioning, with the node as a JSON
+/// string.  Binary formats aren't self-describing, so these can't be told apart from the current
+/// layout; legacy autosaves are read into networks of these instead, which serialize to JSON that
+/// SerializedNode still understands.
+pub struct LegacyNode {
+    pub kind: String,
+    pub serialized: String,
+}
+
+// Legacy nodes only pass through a network on their way to being upgraded, so they don't need any
+// routing of their own.
+impl<M, I> Inputs<M, I> for LegacyNode {}
+
+impl<M, I> Outputs<M, I> for LegacyNode {}
@@ -0,0 +1,97 @@
+//! A registry of every kind of node that can be created in a network.
+//! Each kind is registered with a function that creates a fresh node and a function that
+//! deserializes one from a JSON value, so that nodes can be created by name and restored from
+//! behind trait objects.  Crates using dataflow can register their own kinds alongside the built-in ones.
+use std::collections::HashMap;
+use std::fmt;
+use std::error;
+use serde::de::Error as DeError;
+use serde_json::{Error as SerdeJsonError, Value};
+
+type Constructor<T> = Box<Fn(String) -> Box<T> + Send + Sync>;
+type Deserializer<T> = Box<Fn(Value) -> Result<Box<T>, SerdeJsonError> + Send + Sync>;
+
+struct Entry<T: ?Sized> {
+    create: Constructor<T>,
+    deserialize: Deserializer<T>,
+}
+
+pub struct Registry<T: ?Sized> {
+    /// What sort of node this registry holds, for error messages.
+    noun: &'static str,
+    /// Every registered kind, in the order it was registered.
+    kinds: Vec<&'static str>,
+    entries: HashMap<&'static str, Entry<T>>,
+}
+
+impl<T: ?Sized> Registry<T> {
+    pub fn new(noun: &'static str) -> Self {
+        Registry {
+            noun: noun,
+            kinds: Vec::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Register a new kind of node.
+    /// Kinds are global names, so registering a kind twice is an error.
+    pub fn register(
+        &mut self,
+        kind: &'static str,
+        create: Constructor<T>,
+        deserialize: Deserializer<T>)
+        -> Result<(), RegistryError>
+    {
+        if self.entries.contains_key(kind) {
+            return Err(RegistryError::DuplicateKind(kind));
+        }
+        self.kinds.push(kind);
+        self.entries.insert(kind, Entry { create: create, deserialize: deserialize });
+        Ok(())
+    }
+
+    /// List every registered kind, in the order they were registered.
+    pub fn kinds(&self) -> &[&'static str] {
+        &self.kinds
+    }
+
+    /// Return a fresh node of this kind, or None if the kind is unknown.
+    pub fn create(&self, kind: &str, name: String) -> Option<Box<T>> {
+        self.entries.get(kind).map(|entry| (entry.create)(name))
+    }
+
+    /// Deserialize a node of this kind from a JSON value.
+    pub fn deserialize(&self, kind: &str, data: Value) -> Result<Box<T>, SerdeJsonError> {
+        match self.entries.get(kind) {
+            Some(entry) => (entry.deserialize)(data),
+            None => Err(SerdeJsonError::custom(format!("Unknown {} kind: '{}'.", self.noun, kind))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryError {
+    /// A kind with this name has already been registered.
+    DuplicateKind(&'static str),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegistryError::DuplicateKind(kind) =>
+                write!(f, "Kind '{}' has already been registered.", kind),
+        }
+    }
+}
+
+impl error::Error for RegistryError {
+    fn description(&self) -> &str {
+        match *self {
+            RegistryError::DuplicateKind(_) => "Kind has already been registered.",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
@@ -2,4 +2,48 @@
 #[cfg(test)]
 mod test_clock_network;
 #[cfg(test)]
-mod test_wiggle_network;
\ No newline at end of file
+mod test_wiggle_network;
+#[cfg(test)]
+mod test_beat_clock;
+#[cfg(test)]
+mod test_midi_clock;
+#[cfg(test)]
+mod test_link_clock;
+#[cfg(test)]
+mod test_expression;
+#[cfg(test)]
+mod test_envelope;
+#[cfg(test)]
+mod test_sequencer;
+#[cfg(test)]
+mod test_random;
+#[cfg(test)]
+mod test_lag;
+#[cfg(test)]
+mod test_color;
+#[cfg(test)]
+mod test_dmx_input;
+
+#[cfg(test)]
+use clocks::clock::{ClockId, ClockProvider, ClockValue};
+
+/// A clock provider that reports the same value for every clock.
+#[cfg(test)]
+pub struct TestClockProvider {
+    pub value: ClockValue,
+}
+
+#[cfg(test)]
+impl TestClockProvider {
+    /// Report this phase, without a tick, for every clock.
+    pub fn at(phase: f64) -> Self {
+        TestClockProvider { value: ClockValue::from_float_value(phase, false) }
+    }
+}
+
+#[cfg(test)]
+impl ClockProvider for TestClockProvider {
+    fn get_value(&self, _: ClockId) -> ClockValue {
+        self.value
+    }
+}
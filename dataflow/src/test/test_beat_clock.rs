@@ -0,0 +1,228 @@
+//! Tests for the audio-reactive beat clock, fed from WAV files written to disk.
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write, Result as IoResult};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::thread;
+use std::f64::consts::PI;
+use network::Network;
+use clocks::beat::BeatClock;
+use clocks::pcm::{PcmSource, WavSource, WavError, StreamSource};
+use clocks::clock::{Clock, ClockNetwork};
+use clocks::{new_clock, deserialize};
+use wiggles_value::knob::{Knobs, Data};
+use serde_json;
+use util::{min_included_angle, assert_almost_eq};
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// Write 16-bit stereo PCM to a WAV file in the temp directory, returning its path.
+/// Each channel gets the same signal, scaled differently so mixdown is exercised.
+fn write_wav(name: &str, samples: &[f64]) -> PathBuf {
+    let path = env::temp_dir().join(format!("wiggles_{}_{}.wav", name, ::std::process::id()));
+    let channels = 2u16;
+    let data_len = (samples.len() * 2 * channels as usize) as u32;
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&u32_le(36 + data_len));
+    bytes.extend_from_slice(b"WAVE");
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&u32_le(16));
+    bytes.extend_from_slice(&u16_le(1));
+    bytes.extend_from_slice(&u16_le(channels));
+    bytes.extend_from_slice(&u32_le(SAMPLE_RATE));
+    bytes.extend_from_slice(&u32_le(SAMPLE_RATE * 2 * channels as u32));
+    bytes.extend_from_slice(&u16_le(2 * channels));
+    bytes.extend_from_slice(&u16_le(16));
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&u32_le(data_len));
+    for s in samples {
+        // left at 3/4 scale, right at 1/4 scale, so the mix is half scale
+        bytes.extend_from_slice(&u16_le((s * 0.75 * 32767.0).round() as i16 as u16));
+        bytes.extend_from_slice(&u16_le((s * 0.25 * 32767.0).round() as i16 as u16));
+    }
+    File::create(&path).unwrap().write_all(&bytes).unwrap();
+    path
+}
+
+fn u16_le(v: u16) -> [u8; 2] {
+    [v as u8, (v >> 8) as u8]
+}
+
+fn u32_le(v: u32) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+/// Generate a click track: a short decaying tone burst on every beat, starting at first_beat.
+fn click_track(bpm: f64, first_beat: f64, duration: f64) -> Vec<f64> {
+    let period = 60.0 / bpm;
+    let click_len = 0.03;
+    (0..(duration * SAMPLE_RATE as f64) as usize)
+        .map(|i| {
+            let t = i as f64 / SAMPLE_RATE as f64;
+            if t < first_beat {
+                return 0.0;
+            }
+            let since_beat = (t - first_beat) % period;
+            if since_beat < click_len {
+                (2.0 * PI * 1000.0 * since_beat).sin() * (-since_beat / 0.01).exp()
+            }
+            else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_wav_source() {
+    let samples: Vec<f64> = (0..1000).map(|i| (i as f64 / 1000.0) - 0.5).collect();
+    let path = write_wav("source", &samples);
+    let mut source = WavSource::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(SAMPLE_RATE, source.sample_rate());
+    assert_eq!(Duration::new(0, 22675736), source.duration());
+
+    // 1/3 of a millisecond is 14.7 samples; the fraction should carry over between reads.
+    let mut buffer = Vec::new();
+    for _ in 0..3 {
+        source.read(Duration::new(0, 333334), &mut buffer);
+    }
+    assert_eq!(44, buffer.len());
+    for (read, original) in buffer.iter().zip(samples.iter()) {
+        // stereo was mixed down to mono at half scale
+        assert!((read - original / 2.0).abs() < 1e-4, "{} != {}", read, original / 2.0);
+    }
+
+    // Reading past the end just stops producing samples.
+    source.read(Duration::from_secs(1), &mut buffer);
+    assert_eq!(1000, buffer.len());
+    assert!(source.finished());
+    source.read(Duration::from_secs(1), &mut buffer);
+    assert_eq!(1000, buffer.len());
+
+    match WavSource::from_reader(&b"RIFX\x00\x00\x00\x00WAVE"[..]) {
+        Err(WavError::NotWav) => (),
+        x => panic!("Expected a format error, got {:?}", x.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_beat_clock_follows_click_track() {
+    let bpm = 128.0;
+    let first_beat = 0.25;
+    let duration = 16.0;
+    let path = write_wav("click", &click_track(bpm, first_beat, duration));
+    let source = WavSource::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let mut clock = BeatClock::with_source("test", Box::new(source));
+    let provider: ClockNetwork = Network::new();
+    let dt = 0.02;
+    let rate = bpm / 60.0;
+    let mut prev_tick_count = clock.render(&[], &provider).tick_count;
+    let mut ticks = 0;
+    let steps = (duration / dt) as usize;
+    for step in 1..steps+1 {
+        clock.update(Duration::from_millis(20));
+        let val = clock.render(&[], &provider);
+        // ticked and tick_count should always agree
+        assert_eq!(val.ticked, val.tick_count > prev_tick_count);
+        assert!(val.tick_count >= prev_tick_count, "Clock ran backwards.");
+        prev_tick_count = val.tick_count;
+
+        let t = step as f64 * dt;
+        // Give the tracker a few seconds to lock on.
+        if t > 8.0 {
+            if val.ticked {
+                ticks += 1;
+            }
+            let expected_phase = (t - first_beat) * rate;
+            let error = min_included_angle(*val.phase(), expected_phase);
+            assert!(
+                error.abs() < 0.05,
+                "Clock out of phase with the music at {} s by {}.", t, error);
+        }
+    }
+    // Over the last 8 seconds, we should have ticked once per beat.
+    let expected_ticks = 8.0 * rate;
+    assert!((ticks as f64 - expected_ticks).abs() <= 1.0, "{} ticks", ticks);
+
+    match clock.knob_value(0).unwrap() {
+        Data::Rate(r) => assert!((r.in_hz() - rate).abs() / rate < 0.01, "Wrong tempo: {:?}", r),
+        x => panic!("Unexpected rate knob value: {:?}", x),
+    }
+}
+
+#[test]
+fn test_beat_clock_without_source() {
+    let mut clock = new_clock("beat", "test").unwrap();
+    let provider: ClockNetwork = Network::new();
+    // Free-run at 120 bpm.
+    for _ in 0..6 {
+        assert_eq!(0, clock.update(Duration::from_millis(100)).len());
+    }
+    let val = clock.render(&[], &provider);
+    assert_eq!(1, val.tick_count);
+    assert_almost_eq(0.2, *val.phase());
+
+    let de = deserialize(clock.serializable().unwrap()).unwrap();
+    assert!(*de == *clock);
+}
+
+/// A reader that hands out a few bytes at a time, so samples get split between reads.
+struct Trickle(Vec<u8>);
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let count = self.0.len().min(buf.len()).min(3);
+        buf[..count].copy_from_slice(&self.0[..count]);
+        self.0.drain(..count);
+        Ok(count)
+    }
+}
+
+#[test]
+fn test_stream_source() {
+    let samples = [0i16, 16384, -16384, 32767, -32768];
+    let bytes = samples.iter().flat_map(|s| u16_le(*s as u16).to_vec()).collect();
+    let mut source = StreamSource::from_reader(Trickle(bytes), SAMPLE_RATE);
+    let start = Instant::now();
+    let mut buffer = Vec::new();
+    while !source.is_closed() {
+        assert!(start.elapsed() < Duration::from_secs(5), "Stream never closed.");
+        source.read(Duration::from_millis(10), &mut buffer);
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(vec!(0.0, 0.5, -0.5, 32767.0 / 32768.0, -1.0), buffer);
+}
+
+#[test]
+fn test_beat_clock_input_knob() {
+    let path = write_wav("input", &click_track(120.0, 0.0, 1.0));
+    let input = path.to_str().unwrap().to_string();
+    let mut clock = BeatClock::new("test");
+    clock.set_knob(1, Data::Text(input.clone())).unwrap();
+    assert!(!clock.has_source());
+    clock.update(Duration::from_millis(20));
+    assert!(clock.has_source());
+
+    // The input is reopened after loading.
+    let mut loaded: BeatClock =
+        serde_json::from_value(serde_json::to_value(&clock).unwrap()).unwrap();
+    assert_eq!(clock, loaded);
+    assert_eq!(Data::Text(input), loaded.knob_value(1).unwrap());
+    loaded.update(Duration::from_millis(20));
+    assert!(loaded.has_source());
+    fs::remove_file(&path).unwrap();
+
+    // An input that doesn't exist leaves the clock free-running.
+    clock.set_knob(1, Data::Text(path.to_str().unwrap().to_string())).unwrap();
+    clock.update(Duration::from_millis(20));
+    assert!(!clock.has_source());
+    clock.set_knob(1, Data::Text(String::new())).unwrap();
+    clock.update(Duration::from_millis(20));
+    assert!(!clock.has_source());
+}
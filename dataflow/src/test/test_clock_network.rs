@@ -5,8 +5,10 @@ use std::time::Duration;
 use network::Network;
 use clocks::simple::SimpleClock;
 use clocks::multiplier::ClockMultiplier;
+use clocks::tap::TapClock;
 use clocks::clock::{
     ClockNetwork,
+    Clock,
     CompleteClock,
     ClockId,
     KnobAddr,
@@ -14,8 +16,19 @@ use clocks::clock::{
     ClockValue,
     ClockProvider,
     ClockCollection,
+    LegacyClockNetwork,
 };
+use node_data::LegacyNode;
+use clocks::{register, kinds, new_clock};
+use console_server::reactor::Messages;
+use network::{Inputs, OutputId};
+use registry::RegistryError;
+use wiggles_value::knob::{
+    Knobs, Data, Datatype, KnobDescription, Error as KnobError, badaddr, Response as KnobResponse};
+use wiggles_value::knob_types::Rate;
+use util::assert_almost_eq;
 use serde_json;
+use bincode;
 
 fn box_clock<T: 'static + CompleteClock>(t: T) -> Box<CompleteClock> {
     Box::new(t)
@@ -59,4 +72,192 @@ fn test_construct_network() {
     let ser_net = serde_json::to_string(&network).unwrap();
     let de_net: ClockNetwork = serde_json::from_str(&ser_net).unwrap();
     assert_eq!(network, de_net);
+    let ser_net = bincode::serialize(&network).unwrap();
+    let de_net: ClockNetwork = bincode::deserialize(&ser_net).unwrap();
+    assert_eq!(network, de_net);
+}
+
+#[test]
+fn test_clock_serialization_format() {
+    let mut clock = SimpleClock::new("test");
+    clock.set_knob(0, Data::Rate(Rate::Hz(2.0))).unwrap();
+    let boxed = box_clock(clock);
+
+    // Clocks are saved as structured values, tagged with their kind.
+    let value = serde_json::to_value(&boxed).unwrap();
+    assert_eq!(Some("simple"), value["kind"].as_str());
+    assert_eq!(Some("test"), value["data"]["name"].as_str());
+
+    // Older saves embedded each clock as a JSON string, and should still load.
+    let legacy = format!(
+        r#"{{"kind": "simple", "serialized": {}}}"#,
+        serde_json::to_string(&value["data"].to_string()).unwrap());
+    let de: Box<CompleteClock> = serde_json::from_str(&legacy).unwrap();
+    assert!(*de == *boxed);
+
+    let bad = r#"{"kind": "no such clock", "data": {}}"#;
+    assert!(serde_json::from_str::<Box<CompleteClock>>(bad).is_err());
+}
+
+#[test]
+fn test_clock_bincode_format() {
+    let mut clock = SimpleClock::new("test");
+    clock.set_knob(0, Data::Rate(Rate::Hz(2.0))).unwrap();
+    let boxed = box_clock(clock);
+
+    // Clocks round-trip through bincode using the tagged value tree.
+    let ser = bincode::serialize(&boxed).unwrap();
+    let de: Box<CompleteClock> = bincode::deserialize(&ser).unwrap();
+    assert!(*de == *boxed);
+
+    // Binary autosaves from before versioning stored each clock as a JSON string.  They're read
+    // as legacy nodes, and converted to JSON on their way to being upgraded.
+    let mut legacy_network: LegacyClockNetwork = Network::new();
+    let (legacy_id, _) = legacy_network.add(LegacyNode {
+        kind: "simple".to_string(),
+        serialized: boxed.as_json().unwrap().to_string(),
+    });
+    let ser = bincode::serialize(&legacy_network).unwrap();
+    let legacy_network: LegacyClockNetwork = bincode::deserialize(&ser).unwrap();
+    let network: ClockNetwork =
+        serde_json::from_value(serde_json::to_value(&legacy_network).unwrap()).unwrap();
+    assert!(**network.node_inner(legacy_id).unwrap() == *boxed);
+
+    // Once upgraded, they're saved in the current layout.
+    let ser = bincode::serialize(&network).unwrap();
+    let de: ClockNetwork = bincode::deserialize(&ser).unwrap();
+    assert_eq!(network, de);
+}
+
+fn tap_rate(clock: &TapClock) -> f64 {
+    match clock.knob_value(1).unwrap() {
+        Data::Rate(r) => r.in_hz(),
+        x => panic!("Unexpected rate knob value: {:?}", x),
+    }
+}
+
+#[test]
+fn test_tap_clock() {
+    let mut clock = TapClock::new("test");
+    let provider: ClockNetwork = Network::new();
+    let step = Duration::from_millis(100);
+    let tap = |clock: &mut TapClock| clock.set_knob(0, Data::Button(true)).unwrap();
+    let run = |clock: &mut TapClock, steps: usize| {
+        for _ in 0..steps {
+            clock.update(step);
+        }
+    };
+
+    // A single tap doesn't tell us anything about the rate, but does reset the phase.
+    run(&mut clock, 3);
+    tap(&mut clock);
+    let messages = clock.update(step);
+    assert_eq!(2, messages.len());
+    assert_almost_eq(1.0, tap_rate(&clock));
+    let val = clock.render(&[], &provider);
+    assert_eq!(ClockValue::from_float_value(1.1, true), val);
+
+    // Tap every half second, with one early tap that should be ignored as an outlier.
+    for &steps in &[4, 5, 3, 5] {
+        run(&mut clock, steps);
+        tap(&mut clock);
+    }
+    assert_almost_eq(2.0, tap_rate(&clock));
+    clock.update(step);
+    let val = clock.render(&[], &provider);
+    assert_almost_eq(0.2, val.phase().0);
+    assert!(val.ticked);
+
+    // The tap button itself ignores button up messages.
+    clock.set_knob(0, Data::Button(false)).unwrap();
+    assert_eq!(0, clock.update(step).len());
+
+    // Nudging speeds the clock up temporarily.
+    clock.set_knob(2, Data::Wiggle(::wiggles_value::Data::bipolar(1.0))).unwrap();
+    clock.update(step);
+    let val = clock.render(&[], &provider);
+    assert_almost_eq(0.4 + 0.22, val.phase().0);
+    clock.set_knob(2, Data::Wiggle(::wiggles_value::Data::bipolar(0.0))).unwrap();
+
+    // A long pause starts a fresh sequence, so a new single tap leaves the rate alone.
+    run(&mut clock, 30);
+    tap(&mut clock);
+    assert_almost_eq(2.0, tap_rate(&clock));
+    run(&mut clock, 3);
+    tap(&mut clock);
+    assert_almost_eq(1.0 / 0.3, tap_rate(&clock));
+
+    match clock.update(step).drain().last() {
+        Some(KnobResponse::ValueChange(1, Data::Rate(r))) => assert_almost_eq(1.0 / 0.3, r.in_hz()),
+        x => panic!("Expected a rate change message, got {:?}", x),
+    }
+
+    // Setting the rate by hand overrides the taps.
+    clock.set_knob(1, Data::Rate(Rate::Hz(0.5))).unwrap();
+    run(&mut clock, 2);
+    tap(&mut clock);
+    assert_almost_eq(0.5, tap_rate(&clock));
+
+    let boxed = box_clock(clock);
+    let de = ::clocks::deserialize(boxed.serializable().unwrap()).unwrap();
+    assert_eq!(boxed.as_json().unwrap(), de.as_json().unwrap());
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// A clock defined outside of the clocks module, as a downstream crate might.
+struct StoppedClock {
+    name: String,
+}
+
+impl StoppedClock {
+    fn new(name: String) -> Self {
+        StoppedClock { name: name }
+    }
+}
+
+impl Clock for StoppedClock {
+    fn kind(&self) -> &'static str { "stopped" }
+    fn name(&self) -> &str { &self.name }
+    fn set_name(&mut self, name: String) { self.name = name; }
+    fn update(&mut self, _: Duration) -> Messages<KnobResponse<KnobAddr>> { Messages::none() }
+    fn render(&self, _: &[Option<(ClockId, OutputId)>], _: &ClockProvider) -> ClockValue {
+        ClockValue::default()
+    }
+    fn as_json(&self) -> Result<serde_json::Value, serde_json::Error> { serde_json::to_value(self) }
+}
+
+impl<M, I> Inputs<M, I> for StoppedClock {
+    fn default_input_count(&self) -> u32 { 0 }
+}
+
+impl Knobs<KnobAddr> for StoppedClock {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> { Vec::new() }
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<Datatype, KnobError<KnobAddr>> {
+        Err(badaddr(addr))
+    }
+    fn knob_value(&self, addr: KnobAddr) -> Result<Data, KnobError<KnobAddr>> {
+        Err(badaddr(addr))
+    }
+    fn set_knob(&mut self, addr: KnobAddr, _: Data) -> Result<(), KnobError<KnobAddr>> {
+        Err(badaddr(addr))
+    }
+}
+
+#[test]
+fn test_register_clock() {
+    assert!(new_clock("stopped", "test").is_none());
+    register("stopped", StoppedClock::new).unwrap();
+    assert_eq!(Err(RegistryError::DuplicateKind("stopped")), register("stopped", StoppedClock::new));
+    assert_eq!(Err(RegistryError::DuplicateKind("simple")), register("simple", StoppedClock::new));
+
+    let kinds = kinds();
+    assert_eq!(Some(&"simple"), kinds.first());
+    assert_eq!(Some(&"stopped"), kinds.last());
+
+    // Registered clocks can be added to networks and saved like any other.
+    let mut network: ClockNetwork = Network::new();
+    network.add(new_clock("stopped", "test").unwrap());
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: ClockNetwork = serde_json::from_str(&ser_net).unwrap();
+    assert_eq!(network, de_net);
 }
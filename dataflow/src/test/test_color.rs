@@ -0,0 +1,151 @@
+//! Tests for the color wiggles.
+use network::Network;
+use clocks::clock::ClockNetwork;
+use clocks::simple::SimpleClock;
+use wiggles_value::{Datatype, Data, Color};
+use wiggles_value::knob::{Knobs, Data as KnobData, Response as KnobResponse};
+use wiggles::hue_rotate::HueRotate;
+use wiggles::gradient::Gradient;
+use wiggles::palette::{Palette, KIND as PALETTE_KIND};
+use wiggles::lfo::KIND as LFO_KIND;
+use wiggles::new_wiggle;
+use wiggles::wiggle::{Wiggle, WiggleNetwork, WiggleProvider};
+use std::time::Duration;
+use serde_json;
+use super::TestClockProvider;
+
+/// Render a wiggle with no inputs at this clock value, expecting a color.
+fn color_at<W: Wiggle>(wiggle: &W, clock: f64) -> Color {
+    let network: WiggleNetwork = Network::new();
+    let clocks = TestClockProvider::at(clock);
+    match wiggle.render(0.0, None, &[], 0u32.into(), &network, &clocks) {
+        Data::Color(c) => c,
+        x => panic!("Unexpected output: {:?}", x),
+    }
+}
+
+fn set_color<W: Knobs<u32>>(wiggle: &mut W, addr: u32, color: Color) {
+    wiggle.set_knob(addr, KnobData::Wiggle(Data::Color(color))).unwrap();
+}
+
+fn set_picker<W: Knobs<u32>>(wiggle: &mut W, addr: u32, value: &str) {
+    wiggle.set_knob(addr, KnobData::Picker(value.to_string())).unwrap();
+}
+
+fn pending<W: Wiggle>(wiggle: &mut W) -> Vec<KnobResponse<u32>> {
+    let mut messages = wiggle.update(Duration::from_millis(10), &TestClockProvider::at(0.0));
+    messages.drain().collect()
+}
+
+#[test]
+fn test_hue_rotate() {
+    let mut clocks: ClockNetwork = Network::new();
+    let (cid, _) = clocks.add(Box::new(SimpleClock::new("test clock")));
+    let mut rotate = HueRotate::new("test");
+    rotate.set_clock(Some(cid)).unwrap();
+    let red = Color::rgb(1.0, 0.0, 0.0);
+    assert_eq!(red, color_at(&rotate, 0.0));
+    assert_eq!(Color::rgb(0.0, 1.0, 0.0), color_at(&rotate, 1.0 / 3.0));
+    // A full turn of the clock comes back around.
+    assert_eq!(red, color_at(&rotate, 3.0));
+
+    rotate.set_knob(1, KnobData::Wiggle(Data::unipolar(1.0 / 3.0))).unwrap();
+    assert_eq!(Color::rgb(0.0, 0.0, 1.0), color_at(&rotate, 1.0 / 3.0));
+
+    // Saturation and value are left alone.
+    rotate.set_knob(1, KnobData::Wiggle(Data::unipolar(0.0))).unwrap();
+    set_color(&mut rotate, 0, Color::rgb(0.5, 0.25, 0.25));
+    assert_eq!(Color::rgb(0.25, 0.5, 0.25), color_at(&rotate, 1.0 / 3.0));
+    assert!(rotate.set_knob(0, KnobData::Picker("red".to_string())).is_err());
+}
+
+#[test]
+fn test_gradient() {
+    let mut gradient = Gradient::new("test");
+    assert_eq!(Color::rgb(0.0, 0.0, 0.0), color_at(&gradient, 0.0));
+    gradient.set_knob(1, KnobData::Wiggle(Data::unipolar(0.5))).unwrap();
+    assert_eq!(Color::rgb(0.5, 0.5, 0.5), color_at(&gradient, 0.0));
+
+    // Add a red stop in the middle, declared last.
+    set_picker(&mut gradient, 0, "3");
+    assert_eq!(2, pending(&mut gradient).len());
+    assert_eq!(8, gradient.knobs().len());
+    set_color(&mut gradient, 6, Color::rgb(1.0, 0.0, 0.0));
+    gradient.set_knob(7, KnobData::Wiggle(Data::unipolar(0.5))).unwrap();
+    let at = |gradient: &mut Gradient, position: f64| {
+        gradient.set_knob(1, KnobData::Wiggle(Data::unipolar(position))).unwrap();
+        color_at(gradient, 0.0)
+    };
+    assert_eq!(Color::rgb(0.5, 0.0, 0.0), at(&mut gradient, 0.25));
+    assert_eq!(Color::rgb(1.0, 0.0, 0.0), at(&mut gradient, 0.5));
+    assert_eq!(Color::rgb(1.0, 0.5, 0.5), at(&mut gradient, 0.75));
+
+    // Stops inside the range leave the ends flat.
+    gradient.set_knob(3, KnobData::Wiggle(Data::unipolar(0.25))).unwrap();
+    assert_eq!(Color::rgb(0.0, 0.0, 0.0), at(&mut gradient, 0.1));
+
+    assert!(gradient.set_knob(0, KnobData::Picker("1".to_string())).is_err());
+    assert!(gradient.set_knob(8, KnobData::Wiggle(Data::unipolar(0.5))).is_err());
+    set_picker(&mut gradient, 0, "2");
+    assert_eq!(2, pending(&mut gradient).len());
+    assert!(gradient.knob_value(6).is_err());
+
+    // Saved gradients with too few stops don't load.
+    let mut saved = serde_json::to_value(&Gradient::new("test")).unwrap();
+    saved["stops"].as_array_mut().unwrap().pop();
+    assert!(serde_json::from_value::<Gradient>(saved).is_err());
+}
+
+#[test]
+fn test_palette() {
+    let mut palette = Palette::new("test");
+    assert_eq!(Color::rgb(1.0, 0.0, 0.0), color_at(&palette, 0.0));
+    set_picker(&mut palette, 1, "2");
+    assert_eq!(Color::rgb(0.0, 1.0, 0.0), color_at(&palette, 0.0));
+    set_picker(&mut palette, 1, "4");
+    assert!(palette.set_knob(1, KnobData::Picker("5".to_string())).is_err());
+
+    // Shrinking the palette moves the selection onto the last remaining color.
+    set_picker(&mut palette, 0, "2");
+    assert_eq!(
+        vec!(
+            KnobResponse::Removed(5),
+            KnobResponse::Removed(4),
+            KnobResponse::ValueChange(1, KnobData::Picker("2".to_string()))),
+        pending(&mut palette));
+    assert_eq!(Color::rgb(0.0, 1.0, 0.0), color_at(&palette, 0.0));
+
+    // Saved palettes need colors, and a saved selection past the end picks the last one.
+    let mut saved = serde_json::to_value(&Palette::new("test")).unwrap();
+    saved["selected"] = serde_json::Value::from(9);
+    let loaded: Palette = serde_json::from_value(saved.clone()).unwrap();
+    assert_eq!(Color::rgb(1.0, 1.0, 1.0), color_at(&loaded, 0.0));
+    saved["colors"] = serde_json::Value::Array(Vec::new());
+    assert!(serde_json::from_value::<Palette>(saved).is_err());
+}
+
+#[test]
+fn test_palette_input() {
+    let clocks = TestClockProvider::at(0.0);
+    let mut network: WiggleNetwork = Network::new();
+    let (palette, _) = network.add(new_wiggle(PALETTE_KIND, "palette").unwrap());
+    let (source, _) = network.add(new_wiggle(LFO_KIND, "source").unwrap());
+    {
+        let source = network.node_inner_mut(source).unwrap();
+        source.set_knob(3, KnobData::Wiggle(Data::unipolar(0.0))).unwrap();
+        source.set_knob(4, KnobData::Wiggle(Data::bipolar(0.6))).unwrap();
+    }
+    network.swap_input(palette, 0u32.into(), Some((source, 0u32.into()))).unwrap();
+    // The input sweeps across the palette, overriding the selected knob.
+    assert_eq!(
+        Data::Color(Color::rgb(0.0, 0.0, 1.0)),
+        network.get_value(palette, 0u32.into(), 0.0, None, &clocks));
+    // Asking for a scalar gets the brightness.
+    assert_eq!(
+        Data::unipolar(1.0),
+        network.get_value(palette, 0u32.into(), 0.0, Some(Datatype::Unipolar), &clocks));
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_str(&ser_net).unwrap();
+    assert_eq!(network, de_net);
+}
@@ -0,0 +1,179 @@
+//! Tests for receiving DMX as wiggle data.
+use std::net::UdpSocket;
+use std::thread;
+use std::time::{Duration, Instant};
+use network::{Network, Outputs};
+use clocks::clock::{ClockValue, ClockProvider, ClockId};
+use wiggles_value::{Data, Unipolar};
+use wiggles_value::knob::{Knobs, Data as KnobData, Response as KnobResponse};
+use wiggles::dmx_input::DmxInput;
+use wiggles::wiggle::{Wiggle, WiggleNetwork};
+use dmx_receiver::{self, Protocol};
+
+struct NoClocks;
+
+impl ClockProvider for NoClocks {
+    fn get_value(&self, _: ClockId) -> ClockValue {
+        ClockValue::default()
+    }
+}
+
+fn update(input: &mut DmxInput) -> Vec<KnobResponse<u32>> {
+    input.update(Duration::from_millis(10), &NoClocks).drain().collect()
+}
+
+fn level(input: &DmxInput, output: u32) -> f64 {
+    let network: WiggleNetwork = Network::new();
+    match input.render(0.0, None, &[], output.into(), &network, &NoClocks) {
+        Data::Unipolar(Unipolar(v)) => v,
+        x => panic!("Unexpected output: {:?}", x),
+    }
+}
+
+/// Start receiving a protocol on a loopback socket, returning a socket that sends to it.
+fn loopback_receiver(protocol: Protocol) -> UdpSocket {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.connect(receiver.local_addr().unwrap()).unwrap();
+    dmx_receiver::listen_on(protocol, receiver).unwrap();
+    sender
+}
+
+/// Send a packet, and wait for the receiver to pick it up.
+fn send(sender: &UdpSocket, protocol: Protocol, universe: u16, packet: &[u8]) {
+    let previous = dmx_receiver::frame(protocol, universe);
+    sender.send(packet).unwrap();
+    let start = Instant::now();
+    while dmx_receiver::frame(protocol, universe) == previous {
+        assert!(start.elapsed() < Duration::from_secs(5), "Packet never arrived.");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn artdmx(net: u8, sub_uni: u8, levels: &[u8]) -> Vec<u8> {
+    let mut packet = b"Art-Net\0\x00\x50\x00\x0e\x00\x00".to_vec();
+    packet.extend_from_slice(&[sub_uni, net, (levels.len() >> 8) as u8, levels.len() as u8]);
+    packet.extend_from_slice(levels);
+    packet
+}
+
+/// An sACN data packet from the source whose CID is filled with the byte cid.
+fn sacn(universe: u16, cid: u8, priority: u8, options: u8, levels: &[u8]) -> Vec<u8> {
+    let mut packet = vec!(0; 126);
+    packet[..16].copy_from_slice(b"\x00\x10\x00\x00ASC-E1.17\x00\x00\x00");
+    packet[21] = 0x4;
+    packet[22..38].copy_from_slice(&[cid; 16]);
+    packet[43] = 0x2;
+    packet[108] = priority;
+    packet[112] = options;
+    packet[113..115].copy_from_slice(&[(universe >> 8) as u8, universe as u8]);
+    packet[117] = 0x2;
+    let count = levels.len() + 1;
+    packet[123..125].copy_from_slice(&[(count >> 8) as u8, count as u8]);
+    packet.extend_from_slice(levels);
+    packet
+}
+
+#[test]
+fn test_parse_universes() {
+    assert_eq!(Ok(0x123), Protocol::ArtNet.parse_universe("1:2:3"));
+    assert_eq!(Ok(0x123), Protocol::ArtNet.parse_universe("291"));
+    assert_eq!("1:2:3", Protocol::ArtNet.format_universe(0x123));
+    assert!(Protocol::ArtNet.parse_universe("1:16:0").is_err());
+    assert!(Protocol::ArtNet.parse_universe("32768").is_err());
+    assert_eq!(Ok(7), Protocol::Sacn.parse_universe("7"));
+    assert!(Protocol::Sacn.parse_universe("0").is_err());
+    assert!(Protocol::Sacn.parse_universe("0:0:7").is_err());
+    assert!(Protocol::Sacn.parse_universe("64000").is_err());
+}
+
+#[test]
+fn test_artnet_input() {
+    let sender = loopback_receiver(Protocol::ArtNet);
+    let mut input = DmxInput::new("test");
+    input.set_knob(1, KnobData::Text("0:1:2".to_string())).unwrap();
+    input.set_knob(3, KnobData::Wiggle(Data::Unipolar(Unipolar(0.5)))).unwrap();
+    // Follow channels 2 and 3 as a 16-bit value on a second output.
+    Outputs::<(), ()>::try_push_output(&mut input, ()).unwrap();
+    assert_eq!(2, update(&mut input).len());
+    input.set_knob(6, KnobData::UInt(2)).unwrap();
+    input.set_knob(7, KnobData::Button(true)).unwrap();
+    assert!(input.set_knob(6, KnobData::UInt(513)).is_err());
+    assert!(input.set_knob(6, KnobData::UInt(0)).is_err());
+    assert!(input.set_knob(6, KnobData::UFloat(2.0)).is_err());
+    assert_eq!(KnobData::UInt(2), input.knob_value(6).unwrap());
+
+    // Nothing received yet.
+    update(&mut input);
+    assert_eq!(0.5, level(&input, 0));
+    assert_eq!(0.5, level(&input, 1));
+
+    // Data for another universe is ignored.
+    send(&sender, Protocol::ArtNet, 0x13, &artdmx(0, 0x13, &[255, 255, 255, 255]));
+    update(&mut input);
+    assert_eq!(0.5, level(&input, 0));
+
+    send(&sender, Protocol::ArtNet, 0x12, &artdmx(0, 0x12, &[51, 0x12, 0x34, 0]));
+    update(&mut input);
+    assert_eq!(0.2, level(&input, 0));
+    assert_eq!(0x1234 as f64 / 65535.0, level(&input, 1));
+
+    // Go stale.
+    input.set_knob(2, KnobData::UFloat(0.05)).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    update(&mut input);
+    assert_eq!(0.5, level(&input, 0));
+    assert_eq!(0.5, level(&input, 1));
+
+    // Remove the 16-bit output.
+    Outputs::<(), ()>::try_pop_output(&mut input, ()).unwrap();
+    assert!(Outputs::<(), ()>::try_pop_output(&mut input, ()).is_err());
+    assert_eq!(vec!(KnobResponse::Removed(6), KnobResponse::Removed(7)), update(&mut input));
+}
+
+#[test]
+fn test_sacn_input() {
+    let sender = loopback_receiver(Protocol::Sacn);
+    let mut input = DmxInput::new("test");
+    input.set_knob(0, KnobData::Picker("sACN".to_string())).unwrap();
+    // Art-Net universe 0 doesn't exist in sACN.
+    assert_eq!(vec!(KnobResponse::ValueChange(1, KnobData::Text("1".to_string()))),
+               update(&mut input));
+    input.set_knob(1, KnobData::Text("300".to_string())).unwrap();
+    input.set_knob(4, KnobData::UInt(3)).unwrap();
+
+    send(&sender, Protocol::Sacn, 300, &sacn(300, 1, 100, 0, &[0, 0, 255]));
+    update(&mut input);
+    assert_eq!(1.0, level(&input, 0));
+
+    // A lower-priority source doesn't take over, a higher one does.
+    sender.send(&sacn(300, 2, 50, 0, &[0, 0, 0])).unwrap();
+    send(&sender, Protocol::Sacn, 300, &sacn(300, 3, 150, 0, &[0, 0, 102]));
+    update(&mut input);
+    assert_eq!(0.4, level(&input, 0));
+
+    // Preview data is ignored, and other sources can't end the stream of the one in control.
+    sender.send(&sacn(300, 4, 200, 0x80, &[0, 0, 0])).unwrap();
+    sender.send(&sacn(300, 2, 50, 0x40, &[0, 0, 0])).unwrap();
+    sender.send(&sacn(300, 4, 200, 0x40, &[0, 0, 0])).unwrap();
+    send(&sender, Protocol::Sacn, 300, &sacn(300, 3, 150, 0, &[0, 0, 51]));
+    update(&mut input);
+    assert_eq!(0.2, level(&input, 0));
+
+    // A source lowering its priority gives way straight away.
+    send(&sender, Protocol::Sacn, 300, &sacn(300, 3, 80, 0, &[0, 0, 0]));
+    update(&mut input);
+    assert_eq!(1.0, level(&input, 0));
+
+    // Terminating every stream falls back straight away.
+    for cid in 1..4 {
+        sender.send(&sacn(300, cid, 100, 0x40, &[0, 0, 102])).unwrap();
+    }
+    let start = Instant::now();
+    while dmx_receiver::frame(Protocol::Sacn, 300).is_some() {
+        assert!(start.elapsed() < Duration::from_secs(5), "Stream never terminated.");
+        thread::sleep(Duration::from_millis(1));
+    }
+    update(&mut input);
+    assert_eq!(0.0, level(&input, 0));
+}
@@ -0,0 +1,141 @@
+//! Tests for the envelope generator.
+use std::time::Duration;
+use network::Network;
+use clocks::clock::{ClockValue, ClockNetwork};
+use clocks::simple::SimpleClock;
+use wiggles_value::{Datatype, Data};
+use wiggles_value::knob::{Knobs, Data as KnobData};
+use wiggles::envelope::Envelope;
+use wiggles::wiggle::{Wiggle, WiggleNetwork};
+use wiggles::{new_wiggle, deserialize};
+use super::TestClockProvider;
+
+fn clock(val: f64, ticked: bool) -> TestClockProvider {
+    TestClockProvider { value: ClockValue::from_float_value(val, ticked) }
+}
+
+fn update(env: &mut Envelope, millis: u64) {
+    env.update(Duration::from_millis(millis), &clock(0.0, false));
+}
+
+fn value(env: &Envelope) -> Data {
+    let network: WiggleNetwork = Network::new();
+    env.render(0.0, None, &[], 0u32.into(), &network, &clock(0.0, false))
+}
+
+/// Attack and decay of 100 ms, sustain at half, release of 200 ms.
+fn envelope(mode: &str) -> Envelope {
+    let mut env = Envelope::new("test");
+    env.set_knob(0, KnobData::UFloat(0.1)).unwrap();
+    env.set_knob(1, KnobData::UFloat(0.1)).unwrap();
+    env.set_knob(2, KnobData::Wiggle(Data::unipolar(0.5))).unwrap();
+    env.set_knob(3, KnobData::UFloat(0.2)).unwrap();
+    env.set_knob(4, KnobData::Picker(mode.to_string())).unwrap();
+    env
+}
+
+#[test]
+fn test_button_trigger() {
+    let mut env = envelope("retrigger");
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(0.0), value(&env));
+
+    env.set_knob(6, KnobData::Button(true)).unwrap();
+    assert_eq!(KnobData::Button(true), env.knob_value(6).unwrap());
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(0.5), value(&env));
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(1.0), value(&env));
+    update(&mut env, 25);
+    assert_eq!(Data::unipolar(0.75), value(&env));
+    // Stages carry over inside a single update.
+    update(&mut env, 1000);
+    assert_eq!(Data::unipolar(0.5), value(&env));
+    // The envelope is positive for bipolar output too.
+    let network: WiggleNetwork = Network::new();
+    assert_eq!(
+        Data::bipolar(0.5),
+        env.render(0.0, Some(Datatype::Bipolar), &[], 0u32.into(), &network, &clock(0.0, false)));
+
+    env.set_knob(6, KnobData::Button(false)).unwrap();
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(0.25), value(&env));
+    update(&mut env, 100);
+    assert_eq!(Data::unipolar(0.0), value(&env));
+
+    // Releasing the gate during the attack goes straight to the release.
+    env.set_knob(6, KnobData::Button(true)).unwrap();
+    env.set_knob(6, KnobData::Button(false)).unwrap();
+    update(&mut env, 0);
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(0.0), value(&env));
+}
+
+#[test]
+fn test_clock_trigger() {
+    let mut clocks: ClockNetwork = Network::new();
+    let (cid, _) = clocks.add(Box::new(SimpleClock::new("test clock")));
+    let mut env = envelope("retrigger");
+    env.set_clock(Some(cid)).unwrap();
+
+    env.update(Duration::from_millis(50), &clock(0.0, true));
+    assert_eq!(Data::unipolar(0.5), value(&env));
+    env.update(Duration::from_millis(150), &clock(0.3, false));
+    assert_eq!(Data::unipolar(0.5), value(&env));
+    // The gate closes halfway through the clock's period.
+    env.update(Duration::from_millis(50), &clock(0.6, false));
+    assert_eq!(Data::unipolar(0.25), value(&env));
+    // The next tick retriggers.
+    env.update(Duration::from_millis(25), &clock(1.0, true));
+    assert_eq!(Data::unipolar(0.5), value(&env));
+}
+
+#[test]
+fn test_modes() {
+    // Retrigger restarts the attack from the current level.
+    let mut env = envelope("retrigger");
+    env.set_knob(6, KnobData::Button(true)).unwrap();
+    update(&mut env, 150);
+    assert_eq!(Data::unipolar(0.5), value(&env));
+    env.set_knob(6, KnobData::Button(false)).unwrap();
+    env.set_knob(6, KnobData::Button(true)).unwrap();
+    update(&mut env, 25);
+    assert_eq!(Data::unipolar(0.75), value(&env));
+
+    // Legato ignores triggers while the envelope is running, and returns to the sustain level if
+    // triggered during the release.
+    let mut env = envelope("legato");
+    env.set_knob(6, KnobData::Button(true)).unwrap();
+    update(&mut env, 200);
+    env.set_knob(6, KnobData::Button(false)).unwrap();
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(0.25), value(&env));
+    env.set_knob(6, KnobData::Button(true)).unwrap();
+    update(&mut env, 10);
+    assert_eq!(Data::unipolar(0.35), value(&env));
+    update(&mut env, 100);
+    assert_eq!(Data::unipolar(0.5), value(&env));
+
+    // One shot runs through the whole envelope, even if the gate closes immediately.
+    let mut env = envelope("one shot");
+    env.set_knob(6, KnobData::Button(true)).unwrap();
+    env.set_knob(6, KnobData::Button(false)).unwrap();
+    update(&mut env, 150);
+    assert_eq!(Data::unipolar(0.5), value(&env));
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(0.25), value(&env));
+    update(&mut env, 50);
+    assert_eq!(Data::unipolar(0.0), value(&env));
+
+    assert!(env.set_knob(4, KnobData::Picker("foo".to_string())).is_err());
+    assert_eq!(KnobData::Picker("one shot".to_string()), env.knob_value(4).unwrap());
+}
+
+#[test]
+fn test_envelope_serialization() {
+    let mut env = new_wiggle("envelope", "test").unwrap();
+    env.set_knob(0, KnobData::UFloat(1.5)).unwrap();
+    env.set_knob(4, KnobData::Picker("legato".to_string())).unwrap();
+    let de = deserialize(env.serializable().unwrap()).unwrap();
+    assert!(*de == *env);
+}
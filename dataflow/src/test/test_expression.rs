@@ -0,0 +1,142 @@
+//! Tests for the expression language and the expression wiggle.
+use network::Network;
+use clocks::clock::ClockNetwork;
+use clocks::simple::SimpleClock;
+use expression::{Program, Env};
+use wiggles_value::{Datatype, Data};
+use wiggles_value::knob::{Knobs, Data as KnobData, Error as KnobError};
+use wiggles::lfo::KIND as LFO_KIND;
+use wiggles::expression::KIND as EXPRESSION_KIND;
+use wiggles::new_wiggle;
+use wiggles::wiggle::{WiggleProvider, WiggleNetwork};
+use util::assert_almost_eq;
+use serde_json;
+use bincode;
+use super::TestClockProvider;
+
+fn eval(source: &str, phase: f64, inputs: &[f64]) -> f64 {
+    let env = Env { phase: phase, inputs: inputs, type_hint: None };
+    Program::compile(source).unwrap().eval(&env)
+}
+
+#[test]
+fn test_eval() {
+    assert_almost_eq(7.0, eval("1 + 2 * 3", 0.0, &[]));
+    assert_almost_eq(9.0, eval("(1 + 2) * 3", 0.0, &[]));
+    assert_almost_eq(-4.0, eval("-2 ^ 2", 0.0, &[]));
+    assert_almost_eq(512.0, eval("2 ^ 3 ^ 2", 0.0, &[]));
+    assert_almost_eq(0.5, eval("2 ^ -1", 0.0, &[]));
+    assert_almost_eq(1.0, eval("10 - 4 - 5", 0.0, &[]));
+    assert_almost_eq(0.5, eval("-1.5 % 1", 0.0, &[]));
+    assert_almost_eq(0.75, eval("phase * 3", 0.25, &[]));
+    assert_almost_eq(0.5, eval("in1 * in2", 0.0, &[0.25, 2.0]));
+    // Missing inputs are 0.
+    assert_almost_eq(0.25, eval("in1 + in3", 0.0, &[0.25]));
+    assert_almost_eq(1.0, eval("min(3, 1, 2)", 0.0, &[]));
+    assert_almost_eq(3.0, eval("max(3, 1, 2)", 0.0, &[]));
+    assert_almost_eq(1.0, eval("clamp(5, 0, 1)", 0.0, &[]));
+    assert_almost_eq(2.0, eval("abs(-2)", 0.0, &[]));
+    assert_almost_eq(8.0, eval("pow(2, 3)", 0.0, &[]));
+    assert_almost_eq(1.0, eval("pi / 3.14159265358979", 0.0, &[]));
+}
+
+#[test]
+fn test_waveform_functions() {
+    // Unipolar sine peaks in the middle of the period.
+    assert_almost_eq(1.0, eval("sine(phase)", 0.5, &[]));
+    // Angles wrap around.
+    assert_almost_eq(1.0, eval("sine(phase + 1)", 0.5, &[]));
+    assert_almost_eq(1.0, eval("sine(phase * 2)", 0.25, &[]));
+    // Duty cycle compresses the waveform.
+    assert_almost_eq(1.0, eval("triangle(phase, 0.5)", 0.25, &[]));
+    assert_almost_eq(0.0, eval("triangle(phase, 0.5)", 0.75, &[]));
+    assert_almost_eq(1.0, eval("smooth_square(phase, 0.5)", 0.5, &[]));
+
+    let program = Program::compile("sine(phase)").unwrap();
+    let env = Env { phase: 0.25, inputs: &[], type_hint: Some(Datatype::Bipolar) };
+    assert_almost_eq(1.0, program.eval(&env));
+}
+
+#[test]
+fn test_parse_errors() {
+    let error = |source: &str| Program::compile(source).unwrap_err();
+    assert_eq!(0, error("").position);
+    assert_eq!(4, error("1 + ").position);
+    assert_eq!(2, error("1 $ 2").position);
+    assert_eq!(6, error("(1 + 2").position);
+    assert_eq!(1, error("1) + 2").position);
+    assert_eq!(2, error("1 2").position);
+    assert_eq!(0, error("foo").position);
+    assert_eq!(0, error("in0").position);
+    assert_eq!(0, error("foo(1)").position);
+    assert_eq!(4, error("1 + clamp(1, 2)").position);
+    assert_eq!(0, error("min()").position);
+    assert_eq!(0, error("1.2.3").position);
+    assert!(error("abs(1, 2)").message.contains("takes 1 arguments but was given 2"));
+}
+
+#[test]
+fn test_expression_wiggle() {
+    let mut clocks: ClockNetwork = Network::new();
+    let (cid, _) = clocks.add(Box::new(SimpleClock::new("test clock")));
+    let provider = TestClockProvider::at(0.3);
+
+    let mut network: WiggleNetwork = Network::new();
+    let (lfo, _) = network.add(new_wiggle(LFO_KIND, "test lfo").unwrap());
+    {
+        let lfo = network.node_inner_mut(lfo).unwrap();
+        lfo.set_clock(Some(cid)).unwrap();
+        // Phase offset of 0.2 puts the 0.3 test clock value at the sine peak.
+        lfo.set_knob(5, KnobData::Wiggle(Data::unipolar(0.2))).unwrap();
+    }
+    let (wid, _) = network.add(new_wiggle(EXPRESSION_KIND, "test expression").unwrap());
+    assert_eq!(1, network.node(wid).unwrap().inputs().len());
+
+    // By default the expression passes its first input through.
+    assert_eq!(Data::unipolar(0.0), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+    network.swap_input(wid, 0u32.into(), Some((lfo, 0u32.into()))).unwrap();
+    assert_eq!(Data::unipolar(1.0), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+
+    {
+        let expr = network.node_inner_mut(wid).unwrap();
+        expr.set_clock(Some(cid)).unwrap();
+        expr.set_knob(0, KnobData::Text("in1 * 0.5 + phase - in2".to_string())).unwrap();
+        assert_eq!(
+            KnobData::Text("in1 * 0.5 + phase - in2".to_string()),
+            expr.knob_value(0).unwrap());
+
+        // Bad expressions are reported as knob errors and leave the old expression in place.
+        match expr.set_knob(0, KnobData::Text("in1 +".to_string())) {
+            Err(KnobError::InvalidValue{addr: 0, ..}) => (),
+            x => panic!("Unexpected result of setting a bad expression: {:?}", x),
+        }
+        assert!(expr.set_knob(0, KnobData::Button(true)).is_err());
+        assert_eq!(
+            KnobData::Text("in1 * 0.5 + phase - in2".to_string()),
+            expr.knob_value(0).unwrap());
+    }
+    assert_eq!(Data::unipolar(0.8), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+
+    // Results are clipped to the requested type, and nonsense is 0.
+    network.node_inner_mut(wid).unwrap()
+        .set_knob(0, KnobData::Text("-in1 - 2".to_string())).unwrap();
+    assert_eq!(
+        Data::bipolar(-1.0),
+        network.get_value(wid, 0u32.into(), 0.0, Some(Datatype::Bipolar), &provider));
+    network.node_inner_mut(wid).unwrap()
+        .set_knob(0, KnobData::Text("1 / 0".to_string())).unwrap();
+    assert_eq!(Data::unipolar(0.0), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+
+    network.node_inner_mut(wid).unwrap()
+        .set_knob(0, KnobData::Text("in1 + in2".to_string())).unwrap();
+    network.push_input(wid).unwrap();
+    network.swap_input(wid, 1u32.into(), Some((lfo, 0u32.into()))).unwrap();
+    assert_eq!(Data::unipolar(1.0), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_reader(ser_net.as_bytes()).unwrap();
+    assert_eq!(network, de_net);
+    let ser_net = bincode::serialize(&network).unwrap();
+    let de_net: WiggleNetwork = bincode::deserialize(&ser_net).unwrap();
+    assert_eq!(network, de_net);
+}
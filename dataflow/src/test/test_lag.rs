@@ -0,0 +1,105 @@
+//! Tests for the lag wiggle.
+use std::time::Duration;
+use network::Network;
+use wiggles_value::{Datatype, Data};
+use wiggles_value::knob::{Knobs, Data as KnobData};
+use wiggles::lfo::KIND as LFO_KIND;
+use wiggles::lag::KIND as LAG_KIND;
+use wiggles::new_wiggle;
+use wiggles::wiggle::{WiggleId, WiggleNetwork, WiggleProvider, WiggleCollection};
+use util::assert_almost_eq;
+use serde_json;
+use super::TestClockProvider;
+
+/// Make a network with a constant source feeding a lag.
+/// Return the ids of the source and the lag.
+fn network() -> (WiggleNetwork, WiggleId, WiggleId) {
+    let mut network: WiggleNetwork = Network::new();
+    let (source, _) = network.add(new_wiggle(LFO_KIND, "source").unwrap());
+    // An LFO with no amplitude is just its offset.
+    network.node_inner_mut(source).unwrap()
+        .set_knob(3, KnobData::Wiggle(Data::unipolar(0.0))).unwrap();
+    let (lag, _) = network.add(new_wiggle(LAG_KIND, "lag").unwrap());
+    network.swap_input(lag, 0u32.into(), Some((source, 0u32.into()))).unwrap();
+    (network, source, lag)
+}
+
+fn set_source(network: &mut WiggleNetwork, source: WiggleId, value: f64) {
+    network.node_inner_mut(source).unwrap()
+        .set_knob(4, KnobData::Wiggle(Data::bipolar(value))).unwrap();
+}
+
+fn run(network: &mut WiggleNetwork, millis: u64, steps: usize) {
+    for _ in 0..steps {
+        network.update(Duration::from_millis(millis), &TestClockProvider::at(0.0));
+    }
+}
+
+fn value(network: &WiggleNetwork, lag: WiggleId, type_hint: Option<Datatype>) -> f64 {
+    match network.get_value(lag, 0u32.into(), 0.0, type_hint, &TestClockProvider::at(0.0)) {
+        Data::Unipolar(u) => u.0,
+        Data::Bipolar(b) => b.0,
+        x => panic!("Unexpected output: {:?}", x),
+    }
+}
+
+#[test]
+fn test_slew() {
+    let (mut network, source, lag) = network();
+    {
+        let lag = network.node_inner_mut(lag).unwrap();
+        lag.set_knob(1, KnobData::UFloat(1.0)).unwrap();
+        lag.set_knob(2, KnobData::UFloat(0.5)).unwrap();
+    }
+    set_source(&mut network, source, 1.0);
+    run(&mut network, 100, 5);
+    // Rise covers the full range in one second; bipolar has twice as far to go.
+    assert_almost_eq(0.5, value(&network, lag, None));
+    assert_almost_eq(1.0, value(&network, lag, Some(Datatype::Bipolar)));
+    run(&mut network, 100, 10);
+    assert_almost_eq(1.0, value(&network, lag, None));
+
+    // Falling is faster, and the output stops at the input.
+    set_source(&mut network, source, 0.3);
+    run(&mut network, 100, 1);
+    assert_almost_eq(0.8, value(&network, lag, None));
+    run(&mut network, 100, 10);
+    assert_almost_eq(0.3, value(&network, lag, None));
+
+    // Disconnecting the input lets the output fall to 0.
+    network.swap_input(lag, 0u32.into(), None).unwrap();
+    run(&mut network, 100, 10);
+    assert_almost_eq(0.0, value(&network, lag, None));
+}
+
+#[test]
+fn test_low_pass() {
+    let (mut network, source, lag) = network();
+    {
+        let lag = network.node_inner_mut(lag).unwrap();
+        lag.set_knob(0, KnobData::Picker("low pass".to_string())).unwrap();
+        lag.set_knob(1, KnobData::UFloat(1.0)).unwrap();
+        lag.set_knob(2, KnobData::UFloat(0.0)).unwrap();
+        assert!(lag.set_knob(0, KnobData::Picker("foo".to_string())).is_err());
+    }
+    set_source(&mut network, source, 1.0);
+    // After one time constant we're most of the way there, however finely we step.
+    run(&mut network, 10, 100);
+    assert_almost_eq(1.0 - (-1.0f64).exp(), value(&network, lag, None));
+    let (mut coarse, source, lag) = self::network();
+    coarse.node_inner_mut(lag).unwrap()
+        .set_knob(0, KnobData::Picker("low pass".to_string())).unwrap();
+    coarse.node_inner_mut(lag).unwrap().set_knob(1, KnobData::UFloat(1.0)).unwrap();
+    set_source(&mut coarse, source, 1.0);
+    run(&mut coarse, 250, 4);
+    assert_almost_eq(1.0 - (-1.0f64).exp(), value(&coarse, lag, None));
+
+    // A zero fall time jumps straight down.
+    set_source(&mut network, source, 0.0);
+    run(&mut network, 10, 1);
+    assert_almost_eq(0.0, value(&network, lag, None));
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_str(&ser_net).unwrap();
+    assert_eq!(network, de_net);
+}
@@ -0,0 +1,154 @@
+//! Tests for network tempo sync, using two peers talking over loopback.
+use std::thread;
+use std::time::Duration;
+use std::net::SocketAddr;
+use network::Network;
+use clocks::clock::{Clock, ClockValue, ClockNetwork};
+use clocks::link::LinkClock;
+use clocks::peer::{UdpTransport, PeerMessage, Timeline};
+use clocks::{new_clock, deserialize};
+use wiggles_value::knob::{Knobs, Data};
+use wiggles_value::knob_types::Rate;
+use util::{min_included_angle, assert_almost_eq};
+
+fn value(clock: &LinkClock) -> ClockValue {
+    let provider: ClockNetwork = Network::new();
+    clock.render(&[], &provider)
+}
+
+fn bpm(clock: &LinkClock) -> f64 {
+    match clock.knob_value(0).unwrap() {
+        Data::Rate(r) => r.in_hz() * 60.0,
+        x => panic!("Unexpected rate knob value: {:?}", x),
+    }
+}
+
+/// Make a pair of transports that send to each other.
+fn transport_pair() -> (UdpTransport, UdpTransport) {
+    let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut a = UdpTransport::unicast(loopback, Vec::new()).unwrap();
+    let mut b = UdpTransport::unicast(loopback, Vec::new()).unwrap();
+    a.add_destination(b.local_addr().unwrap());
+    b.add_destination(a.local_addr().unwrap());
+    (a, b)
+}
+
+/// Update both clocks for a while.
+fn run(a: &mut LinkClock, b: &mut LinkClock, millis: u64) {
+    for _ in 0..millis / 5 {
+        a.update(Duration::from_millis(5));
+        b.update(Duration::from_millis(5));
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Assert that two clocks agree on the beat modulo the quantum.
+fn assert_in_sync(a: &LinkClock, b: &LinkClock, quantum: f64) {
+    let (a, b) = (value(a).float_value(), value(b).float_value());
+    let error = min_included_angle(a / quantum, b / quantum) * quantum;
+    assert!(error.abs() < 0.05, "Peers out of sync: {} vs {}", a, b);
+}
+
+#[test]
+fn test_timeline() {
+    let timeline = Timeline {
+        author: 1, version: 2, tempo: 120.0, quantum: 4.0, beat: 10.0, time: 1_000_000};
+    assert_almost_eq(12.0, timeline.beat_at(2_000_000));
+    assert_almost_eq(9.0, timeline.beat_at(500_000));
+    let moved = timeline.at(3_000_000);
+    assert_almost_eq(14.0, moved.beat);
+    assert_almost_eq(timeline.beat_at(0), moved.beat_at(0));
+
+    assert!(Timeline { version: 3, author: 0, ..timeline }.supersedes(&timeline));
+    assert!(Timeline { author: 2, ..timeline }.supersedes(&timeline));
+    assert!(!timeline.supersedes(&timeline));
+
+    let msg = PeerMessage::Timeline{from: 1, timeline: timeline};
+    assert_eq!(Some(msg.clone()), PeerMessage::decode(&msg.encode()));
+    assert_eq!(None, PeerMessage::decode(b"garbage"));
+}
+
+#[test]
+fn test_link_peers_sync() {
+    let (ta, tb) = transport_pair();
+    let mut a = LinkClock::with_transport("a", Box::new(ta));
+    let mut b = LinkClock::with_transport("b", Box::new(tb));
+    a.set_knob(0, Data::Rate(Rate::Bpm(100.0))).unwrap();
+    b.set_knob(0, Data::Rate(Rate::Bpm(140.0))).unwrap();
+    b.set_knob(1, Data::UFloat(3.0)).unwrap();
+
+    // Start one peer a little before the other so their beats differ.
+    for _ in 0..20 {
+        a.update(Duration::from_millis(5));
+        thread::sleep(Duration::from_millis(5));
+    }
+    run(&mut a, &mut b, 500);
+    assert_eq!(1, a.peer_count());
+    assert_eq!(1, b.peer_count());
+
+    // The peers settle on one of their timelines.
+    let tempo = bpm(&a);
+    assert!(tempo == 100.0 || tempo == 140.0, "Unexpected tempo {}", tempo);
+    assert_almost_eq(tempo, bpm(&b));
+    let quantum = match a.knob_value(1).unwrap() {
+        Data::UFloat(q) => q,
+        x => panic!("Unexpected quantum knob value: {:?}", x),
+    };
+    assert_eq!(Data::UFloat(quantum), b.knob_value(1).unwrap());
+    assert_in_sync(&a, &b, quantum);
+
+    // The clocks keep running at the session tempo.
+    let before = value(&a).float_value();
+    run(&mut a, &mut b, 500);
+    let advanced = value(&a).float_value() - before;
+    assert!(advanced > 0.0 && advanced < tempo / 60.0, "Advanced {} beats", advanced);
+
+    // A tempo change on either peer takes over the session.
+    b.set_knob(0, Data::Rate(Rate::Bpm(90.0))).unwrap();
+    b.set_knob(1, Data::UFloat(4.0)).unwrap();
+    run(&mut a, &mut b, 200);
+    assert_almost_eq(90.0, bpm(&a));
+    assert_eq!(Data::UFloat(4.0), a.knob_value(1).unwrap());
+    assert_in_sync(&a, &b, 4.0);
+
+    // Peers notice when others leave.
+    drop(b);
+    thread::sleep(Duration::from_millis(5));
+    a.update(Duration::from_millis(5));
+    assert_eq!(0, a.peer_count());
+}
+
+#[test]
+fn test_link_clock_alone() {
+    let mut clock = LinkClock::new("test");
+    // Stay off the network, so we don't join a session with anyone else.
+    clock.set_transport(None);
+    clock.set_knob(0, Data::Rate(Rate::Bpm(600.0))).unwrap();
+    let mut prev = value(&clock);
+    let mut ticks = 0;
+    for _ in 0..100 {
+        clock.update(Duration::from_millis(5));
+        let val = value(&clock);
+        assert!(val.float_value() >= prev.float_value(), "Clock ran backwards.");
+        if val.ticked {
+            ticks += 1;
+        }
+        prev = val;
+    }
+    assert!(!clock.has_transport());
+    // The clock follows the update interval: the timeline starts on the first update, and the
+    // next 99 take us through 495 ms at 10 beats per second.
+    assert_almost_eq(4.95, prev.float_value());
+    assert_eq!(4, ticks);
+    assert_eq!(ticks, prev.tick_count);
+}
+
+#[test]
+fn test_link_clock_serialization() {
+    let mut clock = new_clock("link", "test").unwrap();
+    clock.set_knob(0, Data::Rate(Rate::Bpm(90.0))).unwrap();
+    clock.set_knob(1, Data::UFloat(3.0)).unwrap();
+    clock.update(Duration::from_millis(10));
+    let de = deserialize(clock.serializable().unwrap()).unwrap();
+    assert!(*de == *clock);
+}
@@ -0,0 +1,403 @@
+//! Tests for MIDI parsing, the MIDI sync clock, and MIDI clock output.
+use std::env;
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::thread;
+use std::time::Duration;
+use network::Network;
+use clocks::clock::{
+    Clock, ClockValue, ClockNetwork, ClockProvider, ClockCollection, CompleteClock};
+use clocks::simple::SimpleClock;
+use clocks::midi::*;
+use clocks::midi_sync::MidiSyncClock;
+use clocks::midi_out::MidiOutClock;
+use clocks::{new_clock, deserialize};
+use wiggles_value::knob::{Knobs, Data};
+use wiggles_value::knob_types::Rate;
+use util::{min_included_angle, assert_almost_eq};
+use serde_json;
+
+fn value(clock: &MidiSyncClock) -> ClockValue {
+    let provider: ClockNetwork = Network::new();
+    clock.render(&[], &provider)
+}
+
+fn parse_all(bytes: &[u8]) -> Vec<MidiMessage> {
+    let mut parser = MidiParser::new();
+    bytes.iter().filter_map(|b| parser.parse(*b)).collect()
+}
+
+/// Deterministic timing jitter of up to 3 ms either way.
+fn jitter(i: usize) -> f64 {
+    ((i * 7919) % 7) as f64 * 0.001 - 0.003
+}
+
+#[test]
+fn test_midi_parser() {
+    let bytes = [
+        // note on with running status, with a clock pulse in the middle
+        0x90, 0x40, 0x7F, 0x41, CLOCK, 0x7F,
+        START,
+        // song position 0x0101 sixteenths
+        SONG_POSITION, 0x01, 0x02,
+        // a stray data byte with no running status after system common
+        0x12,
+        // MTC quarter frame, piece 3, nibble 0xA
+        QUARTER_FRAME, 0x3A,
+        // full frame: 25 fps, 01:02:03:04
+        SYSEX_START, 0x7F, 0x7F, 0x01, 0x01, 0x21, 0x02, 0x03, 0x04, SYSEX_END,
+        // some other sysex, with an active sensing byte inside
+        SYSEX_START, 0x43, 0xFE, 0x12, SYSEX_END,
+        STOP, CONTINUE,
+    ];
+    assert_eq!(
+        vec!(
+            MidiMessage::Clock,
+            MidiMessage::Start,
+            MidiMessage::SongPosition(0x101),
+            MidiMessage::QuarterFrame(3, 0xA),
+            MidiMessage::FullFrame(Timecode {
+                hours: 1, minutes: 2, seconds: 3, frames: 4, rate: MtcRate::Fps25}),
+            MidiMessage::Stop,
+            MidiMessage::Continue,
+        ),
+        parse_all(&bytes));
+}
+
+#[test]
+fn test_timecode() {
+    let tc = Timecode {hours: 1, minutes: 2, seconds: 3, frames: 5, rate: MtcRate::Fps25};
+    assert_almost_eq(3723.2, tc.as_secs());
+    // Drop frame skips frame labels 0 and 1 at the start of the minute.
+    let tc = Timecode {hours: 0, minutes: 1, seconds: 0, frames: 2, rate: MtcRate::Fps2997Drop};
+    assert_almost_eq(1800.0 * 1001.0 / 30000.0, tc.as_secs());
+
+    let mut decoder = MtcDecoder::default();
+    // 00:00:10:00 at 25 fps, rate code 1
+    let nibbles = [0, 0, 10, 0, 0, 0, 0, 1 << 1];
+    for piece in 0..7 {
+        assert_eq!(None, decoder.quarter_frame(piece, nibbles[piece as usize]));
+    }
+    assert_eq!(None, decoder.rate());
+    assert_almost_eq(10.0 + 1.75 / 25.0, decoder.quarter_frame(7, nibbles[7]).unwrap());
+    assert_eq!(Some(MtcRate::Fps25), decoder.rate());
+    assert_almost_eq(10.0 + 2.0 / 25.0, decoder.quarter_frame(0, 2).unwrap());
+}
+
+#[test]
+fn test_midi_clock_follows_beat_clock() {
+    let queue = MidiQueue::new();
+    let mut clock = MidiSyncClock::with_input("test", Box::new(queue.clone()));
+    let dt = 0.01;
+    let bpm = 126.0;
+    let pulse_interval = 60.0 / bpm / PULSES_PER_BEAT as f64;
+
+    // Clock pulses flow even while stopped; they should be ignored.
+    queue.push(&[CLOCK, CLOCK, CLOCK]);
+    clock.update(Duration::from_millis(10));
+    assert_eq!(ClockValue::default(), value(&clock));
+
+    queue.push(&[START]);
+    clock.update(Duration::from_millis(10));
+    assert_eq!(ClockValue::from_float_value(0.0, true), value(&clock));
+
+    // Run for 4 seconds, with the first pulse marking the downbeat.
+    let mut next_pulse = 0;
+    let mut prev = value(&clock);
+    let mut ticks = 0;
+    for step in 1..401 {
+        let t = step as f64 * dt;
+        while next_pulse as f64 * pulse_interval + jitter(next_pulse) <= t {
+            queue.push(&[CLOCK]);
+            next_pulse += 1;
+        }
+        clock.update(Duration::from_millis(10));
+        let val = value(&clock);
+        assert_eq!(val.ticked, val.tick_count > prev.tick_count);
+        assert!(val.float_value() >= prev.float_value(), "Clock ran backwards.");
+        if val.ticked {
+            ticks += 1;
+        }
+        if t > 2.0 {
+            let expected = t * bpm / 60.0;
+            let error = min_included_angle(*val.phase(), expected);
+            assert!(error.abs() < 0.05, "Clock out of sync at {} s by {}.", t, error);
+        }
+        prev = val;
+    }
+    assert_eq!(8, ticks);
+    match clock.knob_value(1).unwrap() {
+        Data::Rate(r) => assert!((r.in_hz() * 60.0 - bpm).abs() < 1.0, "Wrong tempo: {:?}", r),
+        x => panic!("Unexpected rate knob value: {:?}", x),
+    }
+
+    // Stopping freezes the clock, even though pulses keep coming.
+    queue.push(&[STOP]);
+    clock.update(Duration::from_millis(10));
+    let stopped = value(&clock);
+    for _ in 0..50 {
+        queue.push(&[CLOCK]);
+        clock.update(Duration::from_millis(10));
+    }
+    assert_eq!(stopped.tick_count, value(&clock).tick_count);
+    assert_almost_eq(*stopped.phase(), *value(&clock).phase());
+
+    // Song position pointer relocates, and continue carries on from there.
+    queue.push(&[SONG_POSITION, 18, 0]);
+    clock.update(Duration::from_millis(10));
+    assert_eq!(ClockValue::from_float_value(4.5, true), value(&clock));
+    queue.push(&[CONTINUE]);
+    for _ in 0..12 {
+        queue.push(&[CLOCK]);
+        clock.update(Duration::from_millis(10));
+    }
+
+    // If the pulses stop arriving, we catch up and then don't run away.
+    for _ in 0..100 {
+        clock.update(Duration::from_millis(10));
+    }
+    assert_eq!(5, value(&clock).tick_count);
+    assert!(value(&clock).float_value() <= 5.0 + 1.0 / PULSES_PER_BEAT as f64 + 1e-9);
+}
+
+#[test]
+fn test_midi_clock_follows_timecode() {
+    let queue = MidiQueue::new();
+    let mut clock = MidiSyncClock::with_input("test", Box::new(queue.clone()));
+    clock.set_knob(0, Data::Picker("timecode".to_string())).unwrap();
+    clock.set_knob(1, Data::Rate(Rate::Bpm(120.0))).unwrap();
+
+    // Send quarter frames at 25 fps starting from 00:00:10:00.
+    let qf_interval = 0.01;
+    let mut frames = 250;
+    let mut piece = 0;
+    let mut next_qf = 0.0;
+    for step in 1..301 {
+        let t = step as f64 * 0.01;
+        while next_qf <= t + 1e-9 {
+            let nibble = match piece {
+                0 => frames % 25 & 0xF,
+                1 => frames % 25 >> 4,
+                2 => frames / 25 % 60 & 0xF,
+                3 => frames / 25 % 60 >> 4,
+                7 => 1 << 1,
+                _ => 0,
+            };
+            queue.push(&[QUARTER_FRAME, (piece << 4 | nibble) as u8]);
+            piece = (piece + 1) % 8;
+            if piece == 0 {
+                frames += 2;
+            }
+            next_qf += qf_interval;
+        }
+        clock.update(Duration::from_millis(10));
+        if t > 0.5 {
+            // The first quarter frame was sent at 10 s.
+            let expected = (10.0 + t - qf_interval) * 2.0;
+            let val = value(&clock).float_value();
+            assert!((val - expected).abs() < 0.05, "At {} s clock at {} not {}", t, val, expected);
+        }
+    }
+
+    // Full frame messages locate immediately.
+    queue.push(&[SYSEX_START, 0x7F, 0x7F, 0x01, 0x01, 0x20, 0x01, 0x00, 0x00, SYSEX_END]);
+    clock.update(Duration::from_millis(10));
+    assert_eq!(ClockValue::from_float_value(120.0, true), value(&clock));
+
+    // Without quarter frames, timecode is stopped.
+    for _ in 0..20 {
+        clock.update(Duration::from_millis(10));
+    }
+    let stopped = value(&clock);
+    assert_eq!(120, stopped.tick_count);
+    assert!(*stopped.phase() < 0.03);
+    clock.update(Duration::from_millis(10));
+    assert_eq!(stopped, value(&clock));
+}
+
+#[test]
+fn test_midi_clock_output_messages() {
+    let queue = MidiQueue::new();
+    let mut output = MidiClockOutput::new(Box::new(queue.clone()));
+    let at = |beats: f64| ClockValue::from_float_value(beats, false);
+
+    // Starting at the top of the song sends Start, then the pulse marking beat zero.
+    output.send(at(0.0));
+    assert!(output.is_running());
+    assert_eq!(vec!(START, CLOCK), queue.take());
+    // Sending the same value again sends nothing.
+    output.send(at(0.0));
+    assert_eq!(Vec::<u8>::new(), queue.take());
+    output.send(at(3.5 / 24.0));
+    assert_eq!(vec!(CLOCK, CLOCK, CLOCK), queue.take());
+
+    // Jumping ahead by more than a beat stops, relocates to the sixteenth and continues.
+    output.send(at(10.1));
+    assert_eq!(vec!(STOP, SONG_POSITION, 40, 0, CONTINUE, CLOCK, CLOCK), queue.take());
+
+    // Jumping backwards relocates too, using both bytes of the song position.
+    output.send(at(40.0));
+    queue.take();
+    output.send(at(33.0));
+    assert_eq!(vec!(STOP, SONG_POSITION, 4, 1, CONTINUE), queue.take());
+
+    // Positions past the range of song position pointer wrap around onto the same beat.
+    output.send(at(4096.0 + 2.0));
+    assert_eq!(vec!(STOP, SONG_POSITION, 8, 0, CONTINUE), queue.take());
+
+    // Stopping only sends Stop once, and the next send starts again.
+    output.stop();
+    output.stop();
+    assert!(!output.is_running());
+    assert_eq!(vec!(STOP), queue.take());
+    output.send(at(0.0));
+    assert_eq!(vec!(START, CLOCK), queue.take());
+}
+
+#[test]
+fn test_midi_clock_output() {
+    let queue = MidiQueue::new();
+    let mut output = MidiClockOutput::new(Box::new(queue.clone()));
+    let mut follower = MidiSyncClock::with_input("follower", Box::new(queue.clone()));
+    let mut leader = SimpleClock::new("leader");
+    let provider: ClockNetwork = Network::new();
+    leader.set_knob(0, Data::Rate(Rate::Bpm(100.0))).unwrap();
+
+    for _ in 0..300 {
+        leader.update(Duration::from_millis(10));
+        output.send(leader.render(&[], &provider));
+        follower.update(Duration::from_millis(10));
+    }
+    let lead = leader.render(&[], &provider);
+    let follow = value(&follower);
+    assert_eq!(lead.tick_count, follow.tick_count);
+    assert!((lead.float_value() - follow.float_value()).abs() < 0.1);
+
+    // Resetting the leader restarts the follower.
+    leader.set_knob(1, Data::Button(true)).unwrap();
+    leader.update(Duration::from_millis(10));
+    output.send(leader.render(&[], &provider));
+    follower.update(Duration::from_millis(10));
+    assert_eq!(ClockValue::from_float_value(0.0, true), value(&follower));
+
+    output.stop();
+    assert_eq!(vec!(STOP), queue.take());
+}
+
+#[test]
+fn test_midi_out_clock() {
+    let queue = MidiQueue::new();
+    let mut network: ClockNetwork = Network::new();
+    let leader: Box<CompleteClock> = Box::new(SimpleClock::new("leader"));
+    let (leader_id, _) = network.add(leader);
+    let out: Box<CompleteClock> =
+        Box::new(MidiOutClock::with_output("out", Box::new(queue.clone())));
+    let (out_id, _) = network.add(out);
+
+    // Nothing is sent until the input is connected.
+    network.update(Duration::from_millis(10));
+    assert_eq!(ClockValue::default(), network.get_value(out_id));
+    assert_eq!(Vec::<u8>::new(), queue.take());
+
+    network.swap_input(out_id, 0u32.into(), Some((leader_id, 0u32.into()))).unwrap();
+    network.update(Duration::from_millis(10));
+    assert_eq!(network.get_value(leader_id), network.get_value(out_id));
+    assert_eq!(vec!(START, CLOCK), queue.take());
+    // At the default rate of 1 Hz, a quarter second is six pulses.
+    network.update(Duration::from_millis(250));
+    network.get_value(out_id);
+    assert_eq!(vec![CLOCK; 6], queue.take());
+
+    // Disconnecting the input stops the receiver.
+    network.swap_input(out_id, 0u32.into(), None).unwrap();
+    assert_eq!(ClockValue::default(), network.get_value(out_id));
+    assert_eq!(vec!(STOP), queue.take());
+}
+
+#[test]
+fn test_midi_out_clock_port() {
+    let mut clock = new_clock("midi out", "test").unwrap();
+    assert_eq!(Data::Picker("none".to_string()), clock.knob_value(0).unwrap());
+    assert!(clock.set_knob(0, Data::Picker("not a port".to_string())).is_err());
+    let de = deserialize(clock.serializable().unwrap()).unwrap();
+    assert!(*de == *clock);
+
+    // A clock loaded from a save opens its port on the next update and sends to it.
+    let path = env::temp_dir().join(format!("wiggles_midi_out_{}", ::std::process::id()));
+    File::create(&path).unwrap();
+    let port = path.to_str().unwrap().to_string();
+    let mut json = serde_json::to_value(&MidiOutClock::new("test")).unwrap();
+    json["port"] = serde_json::Value::String(port.clone());
+    let mut clock: MidiOutClock = serde_json::from_value(json).unwrap();
+    assert_eq!(Data::Picker(port), clock.knob_value(0).unwrap());
+    assert!(!clock.has_output());
+    clock.update(Duration::from_millis(10));
+    assert!(clock.has_output());
+
+    let mut provider: ClockNetwork = Network::new();
+    let leader: Box<CompleteClock> = Box::new(SimpleClock::new("leader"));
+    let (leader_id, _) = provider.add(leader);
+    clock.render(&[Some((leader_id, 0u32.into()))], &provider);
+    clock.set_knob(0, Data::Picker("none".to_string())).unwrap();
+    assert!(!clock.has_output());
+    assert_eq!(vec!(START, CLOCK, STOP), fs::read(&path).unwrap());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_midi_queue_from_reader() {
+    let queue = MidiQueue::from_reader(Cursor::new(vec!(START, CLOCK, CLOCK)));
+    let mut received = Vec::new();
+    for _ in 0..100 {
+        received.extend(queue.take());
+        if received.len() == 3 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(vec!(START, CLOCK, CLOCK), received);
+}
+
+#[test]
+fn test_midi_clock_serialization() {
+    let mut clock = new_clock("midi", "test").unwrap();
+    clock.set_knob(0, Data::Picker("timecode".to_string())).unwrap();
+    assert!(clock.set_knob(0, Data::Picker("foo".to_string())).is_err());
+    clock.update(Duration::from_millis(10));
+    let de = deserialize(clock.serializable().unwrap()).unwrap();
+    assert!(*de == *clock);
+}
+
+#[test]
+fn test_midi_clock_port() {
+    let mut clock = MidiSyncClock::new("test");
+    assert_eq!(Data::Picker("none".to_string()), clock.knob_value(2).unwrap());
+    assert!(clock.set_knob(2, Data::Picker("not a port".to_string())).is_err());
+    clock.set_knob(2, Data::Picker("none".to_string())).unwrap();
+
+    // A clock loaded from a save opens its port, and starts over if the port goes away.
+    let path = env::temp_dir().join(format!("wiggles_midi_port_{}", ::std::process::id()));
+    File::create(&path).unwrap().write_all(&[SONG_POSITION, 8, 0]).unwrap();
+    let port = path.to_str().unwrap().to_string();
+    let mut json = serde_json::to_value(&clock).unwrap();
+    json["port"] = serde_json::Value::String(port.clone());
+    let mut clock: MidiSyncClock = serde_json::from_value(json).unwrap();
+    assert_eq!(Data::Picker(port.clone()), clock.knob_value(2).unwrap());
+    assert!(clock.set_knob(2, Data::Picker(port)).is_ok());
+    for _ in 0..100 {
+        clock.update(Duration::from_millis(10));
+        if value(&clock).float_value() > 0.0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(ClockValue::from_float_value(2.0, true), value(&clock));
+    fs::remove_file(&path).unwrap();
+    // The file has ended, so the port closes and can't be reopened.
+    clock.update(Duration::from_millis(10));
+    assert!(!clock.has_input());
+    for _ in 0..600 {
+        clock.update(Duration::from_millis(10));
+    }
+    assert!(!clock.has_input());
+}
@@ -0,0 +1,140 @@
+//! Tests for the random and sample and hold wiggles.
+use network::Network;
+use clocks::clock::{ClockId, ClockNetwork};
+use clocks::simple::SimpleClock;
+use wiggles_value::{Datatype, Data};
+use wiggles_value::knob::{Knobs, Data as KnobData};
+use wiggles::noise::Noise;
+use wiggles::lfo::KIND as LFO_KIND;
+use wiggles::sample_hold::KIND as SAMPLE_HOLD_KIND;
+use wiggles::wiggle::{Wiggle, WiggleNetwork, WiggleProvider};
+use wiggles::{new_wiggle, deserialize};
+use super::TestClockProvider;
+
+fn clock_id() -> ClockId {
+    let mut clocks: ClockNetwork = Network::new();
+    let (cid, _) = clocks.add(Box::new(SimpleClock::new("test clock")));
+    cid
+}
+
+fn noise(smooth: bool, seed: u64) -> Noise {
+    let mut noise = Noise::with_seed("test", smooth, seed);
+    noise.set_clock(Some(clock_id())).unwrap();
+    noise
+}
+
+fn value_at(wiggle: &Wiggle, clock: f64, phase_offset: f64, type_hint: Option<Datatype>) -> Data {
+    let network: WiggleNetwork = Network::new();
+    let clocks = TestClockProvider::at(clock);
+    wiggle.render(phase_offset, type_hint, &[], 0u32.into(), &network, &clocks)
+}
+
+fn unipolar_at(wiggle: &Wiggle, clock: f64) -> f64 {
+    match value_at(wiggle, clock, 0.0, None) {
+        Data::Unipolar(u) => u.0,
+        x => panic!("Unexpected output: {:?}", x),
+    }
+}
+
+#[test]
+fn test_stepped_random() {
+    let random = noise(false, 1234);
+    // Values hold for a whole tick.
+    assert_eq!(unipolar_at(&random, 2.0), unipolar_at(&random, 2.9));
+    assert!(unipolar_at(&random, 2.0) != unipolar_at(&random, 3.0));
+    // The same seed always gives the same values; a different one doesn't.
+    let values: Vec<f64> = (0..16).map(|t| unipolar_at(&random, t as f64)).collect();
+    let again: Vec<f64> = (0..16).map(|t| unipolar_at(&noise(false, 1234), t as f64)).collect();
+    let other: Vec<f64> = (0..16).map(|t| unipolar_at(&noise(false, 4321), t as f64)).collect();
+    assert_eq!(values, again);
+    assert!(values != other);
+    for v in &values {
+        assert!(*v >= 0.0 && *v < 1.0, "Random value {} out of range.", v);
+    }
+    // A phase offset looks ahead along the clock.
+    assert_eq!(value_at(&random, 3.3, 0.0, None), value_at(&random, 2.3, 1.0, None));
+
+    // Bipolar values cover the whole bipolar range.
+    let mut negative = false;
+    for t in 0..16 {
+        match value_at(&random, t as f64, 0.0, Some(Datatype::Bipolar)) {
+            Data::Bipolar(b) => negative |= b.0 < 0.0,
+            x => panic!("Unexpected output: {:?}", x),
+        }
+    }
+    assert!(negative);
+}
+
+#[test]
+fn test_smooth_noise() {
+    let stepped = noise(false, 99);
+    let smooth = noise(true, 99);
+    // Smooth noise passes through the random values on the ticks.
+    for t in 0..8 {
+        assert_eq!(unipolar_at(&stepped, t as f64), unipolar_at(&smooth, t as f64));
+    }
+    // In between, it glides without jumping.
+    let mut prev = unipolar_at(&smooth, 0.0);
+    for i in 1..400 {
+        let v = unipolar_at(&smooth, i as f64 / 100.0);
+        assert!((v - prev).abs() < 0.05, "Noise jumped from {} to {}", prev, v);
+        prev = v;
+    }
+    assert_eq!(value_at(&smooth, 3.3, 0.0, None), value_at(&smooth, 2.8, 0.5, None));
+}
+
+#[test]
+fn test_reseed() {
+    let mut random = new_wiggle("random", "test").unwrap();
+    random.set_clock(Some(clock_id())).unwrap();
+    let values: Vec<f64> = (0..16).map(|t| unipolar_at(&*random, t as f64)).collect();
+
+    let de = deserialize(random.serializable().unwrap()).unwrap();
+    assert!(*de == *random);
+    assert_eq!("random", de.kind());
+    assert_eq!("noise", new_wiggle("noise", "test").unwrap().kind());
+
+    random.set_knob(0, KnobData::Button(true)).unwrap();
+    assert_eq!(KnobData::Button(false), random.knob_value(0).unwrap());
+    let reseeded: Vec<f64> = (0..16).map(|t| unipolar_at(&*random, t as f64)).collect();
+    assert!(values != reseeded);
+}
+
+#[test]
+fn test_sample_and_hold() {
+    let cid = clock_id();
+    let mut network: WiggleNetwork = Network::new();
+    let (lfo, _) = network.add(new_wiggle(LFO_KIND, "test lfo").unwrap());
+    {
+        let lfo = network.node_inner_mut(lfo).unwrap();
+        lfo.set_clock(Some(cid)).unwrap();
+        // Offset the sine so that it is at 0.5 on the ticks.
+        lfo.set_knob(5, KnobData::Wiggle(Data::unipolar(0.25))).unwrap();
+    }
+    let (sh, _) = network.add(new_wiggle(SAMPLE_HOLD_KIND, "test s&h").unwrap());
+    let clocks = TestClockProvider::at;
+
+    // Nothing connected.
+    assert_eq!(Data::unipolar(0.0), network.get_value(sh, 0u32.into(), 0.0, None, &clocks(2.3)));
+
+    // Without a clock, the input passes straight through.
+    network.swap_input(sh, 0u32.into(), Some((lfo, 0u32.into()))).unwrap();
+    assert_eq!(
+        network.get_value(lfo, 0u32.into(), 0.0, None, &clocks(2.3)),
+        network.get_value(sh, 0u32.into(), 0.0, None, &clocks(2.3)));
+
+    // With a clock, the value at the last tick is held.
+    network.node_inner_mut(sh).unwrap().set_clock(Some(cid)).unwrap();
+    for &t in &[2.0, 2.3, 2.9, 7.5] {
+        assert_eq!(Data::unipolar(0.5), network.get_value(sh, 0u32.into(), 0.0, None, &clocks(t)));
+    }
+    // Phase offsets move the sample point along with the clock.
+    network.node_inner_mut(lfo).unwrap()
+        .set_knob(5, KnobData::Wiggle(Data::unipolar(0.0))).unwrap();
+    assert_eq!(
+        network.get_value(lfo, 0u32.into(), 0.0, None, &clocks(2.0)),
+        network.get_value(sh, 0u32.into(), 0.0, None, &clocks(2.3)));
+    assert_eq!(
+        Data::unipolar(0.0),
+        network.get_value(sh, 0u32.into(), 0.25, None, &clocks(2.3)));
+}
@@ -0,0 +1,134 @@
+//! Tests for the step sequencer.
+use std::time::Duration;
+use network::Network;
+use clocks::clock::ClockNetwork;
+use clocks::simple::SimpleClock;
+use wiggles_value::{Datatype, Data};
+use wiggles_value::knob::{Knobs, Data as KnobData, Response as KnobResponse};
+use wiggles::sequencer::{Sequencer, KIND as SEQUENCER_KIND};
+use wiggles::wiggle::{Wiggle, WiggleNetwork, WiggleProvider, WiggleCollection};
+use wiggles::new_wiggle;
+use serde_json;
+use bincode;
+use super::TestClockProvider;
+
+/// Make a sequencer running from a clock, with steps 0.0, 0.1, 0.2, 0.3.
+fn sequencer() -> Sequencer {
+    let mut clocks: ClockNetwork = Network::new();
+    let (cid, _) = clocks.add(Box::new(SimpleClock::new("test clock")));
+    let mut seq = Sequencer::new("test");
+    seq.set_clock(Some(cid)).unwrap();
+    for step in 0..4 {
+        seq.set_knob(3 + step, KnobData::Wiggle(Data::unipolar(step as f64 / 10.0))).unwrap();
+    }
+    seq
+}
+
+fn value_at(seq: &Sequencer, clock: f64) -> f64 {
+    let network: WiggleNetwork = Network::new();
+    let clocks = TestClockProvider::at(clock);
+    match seq.render(0.0, None, &[], 0u32.into(), &network, &clocks) {
+        Data::Unipolar(u) => u.0,
+        x => panic!("Unexpected sequencer output: {:?}", x),
+    }
+}
+
+/// Return the step that was playing on each of the first few ticks.
+fn steps(seq: &Sequencer, ticks: i64) -> Vec<usize> {
+    (0..ticks).map(|tick| (value_at(seq, tick as f64 + 0.5) * 10.0).round() as usize).collect()
+}
+
+#[test]
+fn test_playback() {
+    let mut seq = sequencer();
+    assert_eq!(vec!(0, 1, 2, 3, 0, 1), steps(&seq, 6));
+    // The step changes only on the tick.
+    assert_eq!(0.1, value_at(&seq, 1.99));
+
+    seq.set_knob(0, KnobData::Picker("reverse".to_string())).unwrap();
+    assert_eq!(vec!(3, 2, 1, 0, 3, 2), steps(&seq, 6));
+
+    seq.set_knob(0, KnobData::Picker("ping pong".to_string())).unwrap();
+    assert_eq!(vec!(0, 1, 2, 3, 2, 1, 0, 1), steps(&seq, 8));
+
+    // Random playback is repeatable.
+    seq.set_knob(0, KnobData::Picker("random".to_string())).unwrap();
+    let random = steps(&seq, 32);
+    assert_eq!(random, steps(&seq, 32));
+    for step in 0..4 {
+        assert!(random.contains(&step), "Step {} never played in {:?}", step, random);
+    }
+
+    assert!(seq.set_knob(0, KnobData::Picker("foo".to_string())).is_err());
+    assert_eq!(KnobData::Picker("random".to_string()), seq.knob_value(0).unwrap());
+}
+
+#[test]
+fn test_glide() {
+    let mut seq = sequencer();
+    seq.set_knob(1, KnobData::Wiggle(Data::unipolar(0.5))).unwrap();
+    assert_eq!(Data::unipolar(0.1), Data::unipolar(value_at(&seq, 1.25)));
+    assert_eq!(Data::unipolar(0.1), Data::unipolar(value_at(&seq, 1.5)));
+    assert_eq!(Data::unipolar(0.15), Data::unipolar(value_at(&seq, 1.75)));
+    // Glide wraps around to the first step.
+    assert_eq!(Data::unipolar(0.15), Data::unipolar(value_at(&seq, 3.75)));
+
+    // A phase offset looks ahead in the sequence.
+    let network: WiggleNetwork = Network::new();
+    let clocks = TestClockProvider::at(1.0);
+    assert_eq!(
+        Data::unipolar(0.2),
+        seq.render(1.0, Some(Datatype::Unipolar), &[], 0u32.into(), &network, &clocks));
+}
+
+#[test]
+fn test_step_count() {
+    let mut seq = sequencer();
+    assert_eq!(7, seq.knobs().len());
+    seq.set_knob(2, KnobData::Picker("6".to_string())).unwrap();
+    assert_eq!(9, seq.knobs().len());
+    assert_eq!(KnobData::Picker("6".to_string()), seq.knob_value(2).unwrap());
+    assert_eq!(KnobData::Wiggle(Data::unipolar(0.0)), seq.knob_value(8).unwrap());
+    assert!(seq.knob_value(9).is_err());
+    assert_eq!(vec!(0, 1, 2, 3, 0, 0, 0), steps(&seq, 7));
+
+    // The new knobs are announced on the next update.
+    let mut messages = seq.update(Duration::from_millis(10), &TestClockProvider::at(0.0));
+    let messages: Vec<_> = messages.drain().collect();
+    assert_eq!(2, messages.len());
+    match messages[1] {
+        KnobResponse::Added(8, ref desc) => assert_eq!("step 6", desc.name.as_str()),
+        ref x => panic!("Unexpected message: {:?}", x),
+    }
+
+    seq.set_knob(2, KnobData::Picker("2".to_string())).unwrap();
+    assert!(seq.set_knob(4, KnobData::Wiggle(Data::unipolar(0.5))).is_ok());
+    assert!(seq.set_knob(5, KnobData::Wiggle(Data::unipolar(0.5))).is_err());
+    let mut messages = seq.update(Duration::from_millis(10), &TestClockProvider::at(0.0));
+    let messages: Vec<_> = messages.drain().collect();
+    assert_eq!(KnobResponse::Removed(5), messages[3]);
+
+    assert!(seq.set_knob(2, KnobData::Picker("0".to_string())).is_err());
+    assert!(seq.set_knob(2, KnobData::Picker("33".to_string())).is_err());
+}
+
+#[test]
+fn test_sequencer_network() {
+    let mut network: WiggleNetwork = Network::new();
+    let (wid, _) = network.add(new_wiggle(SEQUENCER_KIND, "test seq").unwrap());
+    network.node_inner_mut(wid).unwrap()
+        .set_knob(2, KnobData::Picker("3".to_string())).unwrap();
+    let clocks = TestClockProvider::at(0.0);
+    assert_eq!(Data::unipolar(1.0), network.get_value(wid, 0u32.into(), 0.0, None, &clocks));
+
+    // The network lifts the sequencer's knob announcements into its own address space.
+    let mut messages = network.update(Duration::from_millis(10), &clocks);
+    assert_eq!(vec!(KnobResponse::Removed((wid, 6))), messages.drain().collect::<Vec<_>>());
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_reader(ser_net.as_bytes()).unwrap();
+    assert_eq!(network, de_net);
+    let ser_net = bincode::serialize(&network).unwrap();
+    let de_net: WiggleNetwork = bincode::deserialize(&ser_net).unwrap();
+    assert_eq!(network, de_net);
+}
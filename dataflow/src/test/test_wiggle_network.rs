@@ -1,21 +1,31 @@
 use network::Network;
-use clocks::clock::{ClockValue, ClockProvider, ClockId};
-use wiggles_value::Unipolar;
+use clocks::clock::ClockNetwork;
+use clocks::simple::SimpleClock;
+use wiggles_value::{Unipolar, Bipolar, Color, Position, Datatype, Data};
+use wiggles_value::knob::{Knobs, Data as KnobData};
 use wiggles::trial::{TestWiggle, KIND as TEST_KIND};
-use wiggles::new_wiggle;
-use wiggles::wiggle::{WiggleProvider, WiggleNetwork};
+use wiggles::lfo::KIND as LFO_KIND;
+use wiggles::crossfader::KIND as CROSSFADER_KIND;
+use wiggles::blender::{Blender, KIND as BLENDER_KIND};
+use wiggles::convert::KIND as CONVERT_KIND;
+use wiggles::{new_wiggle, register};
+use wiggles::wiggle::{WiggleProvider, WiggleNetwork, WiggleId};
+use registry::RegistryError;
 use serde_json;
+use bincode;
+use super::TestClockProvider;
 
-struct TestClockProvider {}
-
-impl ClockProvider for TestClockProvider {
-    fn get_value(&self, _: ClockId) -> ClockValue {
-        ClockValue::from_float_value(0.3, false)
+/// Register the test wiggle, which isn't built outside of tests.
+/// The registry is shared by every test, so another one may already have registered it.
+fn register_test_wiggle() {
+    match register(TEST_KIND, TestWiggle::new) {
+        Ok(()) | Err(RegistryError::DuplicateKind(_)) => (),
     }
 }
 
 #[test]
 fn test_wiggle_network() {
+    register_test_wiggle();
     let mut network: WiggleNetwork = Network::new();
     let wiggle = new_wiggle(TEST_KIND, "test wiggle").unwrap();
     let wid = {
@@ -23,10 +33,238 @@ fn test_wiggle_network() {
         wid
     };
 
-    let val = network.get_value(wid, 0u32.into(), 0.0, None, &TestClockProvider{});
+    let val = network.get_value(wid, 0u32.into(), 0.0, None, &TestClockProvider::at(0.3));
 
     // check serialization/deserialization mechanism
     let ser_net = serde_json::to_string(&network).unwrap();
     let de_net: WiggleNetwork = serde_json::from_reader(ser_net.as_bytes()).unwrap();
     assert_eq!(network, de_net);
-}
\ No newline at end of file
+    let ser_net = bincode::serialize(&network).unwrap();
+    let de_net: WiggleNetwork = bincode::deserialize(&ser_net).unwrap();
+    assert_eq!(network, de_net);
+}
+
+#[test]
+fn test_lfo() {
+    // Get a real clock id to assign to the LFO; the test provider ignores it anyway.
+    let mut clocks: ClockNetwork = Network::new();
+    let (cid, _) = clocks.add(Box::new(SimpleClock::new("test clock")));
+
+    let mut network: WiggleNetwork = Network::new();
+    let (wid, _) = network.add(new_wiggle(LFO_KIND, "test lfo").unwrap());
+    let provider = TestClockProvider::at(0.3);
+
+    // Without a clock, the LFO sits at the start of its waveform.
+    assert_eq!(Data::unipolar(0.0), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+
+    {
+        let lfo = network.node_inner_mut(wid).unwrap();
+        lfo.set_clock(Some(cid)).unwrap();
+        // Phase offset of 0.2 puts the 0.3 test clock value at the sine peak.
+        lfo.set_knob(5, KnobData::Wiggle(Data::unipolar(0.2))).unwrap();
+    }
+    assert_eq!(Data::unipolar(1.0), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+
+    {
+        let lfo = network.node_inner_mut(wid).unwrap();
+        // amplitude
+        lfo.set_knob(3, KnobData::Wiggle(Data::unipolar(0.5))).unwrap();
+        // offset
+        lfo.set_knob(4, KnobData::Wiggle(Data::bipolar(0.25))).unwrap();
+    }
+    assert_eq!(Data::unipolar(0.75), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+    // Offset is applied after scaling and the result is clipped.
+    network.node_inner_mut(wid).unwrap()
+        .set_knob(4, KnobData::Wiggle(Data::Bipolar(Bipolar(1.0)))).unwrap();
+    assert_eq!(Data::unipolar(1.0), network.get_value(wid, 0u32.into(), 0.0, None, &provider));
+
+    {
+        let lfo = network.node_inner_mut(wid).unwrap();
+        lfo.set_knob(3, KnobData::Wiggle(Data::unipolar(1.0))).unwrap();
+        lfo.set_knob(4, KnobData::Wiggle(Data::bipolar(0.0))).unwrap();
+        lfo.set_knob(0, KnobData::Picker("square".to_string())).unwrap();
+        // Unknown waveforms are rejected.
+        assert!(lfo.set_knob(0, KnobData::Picker("foo".to_string())).is_err());
+        assert_eq!(KnobData::Picker("square".to_string()), lfo.knob_value(0).unwrap());
+    }
+    // The bipolar square is low during the second half of the period.
+    assert_eq!(
+        Data::bipolar(-1.0),
+        network.get_value(wid, 0u32.into(), 0.0, Some(Datatype::Bipolar), &provider));
+    // Pulse mode keeps the waveform positive.
+    network.node_inner_mut(wid).unwrap().set_knob(6, KnobData::Button(true)).unwrap();
+    assert_eq!(
+        Data::bipolar(1.0),
+        network.get_value(wid, 0u32.into(), 0.0, Some(Datatype::Bipolar), &provider));
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_reader(ser_net.as_bytes()).unwrap();
+    assert_eq!(network, de_net);
+    let ser_net = bincode::serialize(&network).unwrap();
+    let de_net: WiggleNetwork = bincode::deserialize(&ser_net).unwrap();
+    assert_eq!(network, de_net);
+}
+
+/// Add an LFO with no amplitude to the network, which outputs a constant.
+fn constant(network: &mut WiggleNetwork, value: f64) -> WiggleId {
+    let (id, _) = network.add(new_wiggle(LFO_KIND, "constant").unwrap());
+    let lfo = network.node_inner_mut(id).unwrap();
+    lfo.set_knob(3, KnobData::Wiggle(Data::unipolar(0.0))).unwrap();
+    lfo.set_knob(4, KnobData::Wiggle(Data::bipolar(value))).unwrap();
+    id
+}
+
+#[test]
+fn test_crossfader() {
+    let mut network: WiggleNetwork = Network::new();
+    let a = constant(&mut network, 0.2);
+    let b = constant(&mut network, -0.8);
+    let (xf, _) = network.add(new_wiggle(CROSSFADER_KIND, "test xfade").unwrap());
+    assert_eq!(3, network.node(xf).unwrap().inputs().len());
+    network.swap_input(xf, 0u32.into(), Some((a, 0u32.into()))).unwrap();
+    network.swap_input(xf, 1u32.into(), Some((b, 0u32.into()))).unwrap();
+    let provider = TestClockProvider::at(0.3);
+    let value = |network: &WiggleNetwork| {
+        network.get_value(xf, 0u32.into(), 0.0, Some(Datatype::Bipolar), &provider)
+    };
+
+    assert_eq!(Data::bipolar(0.2), value(&network));
+    network.node_inner_mut(xf).unwrap()
+        .set_knob(1, KnobData::Wiggle(Data::unipolar(0.25))).unwrap();
+    assert_eq!(Data::bipolar(0.75 * 0.2 - 0.25 * 0.8), value(&network));
+
+    // Equal power keeps both sides louder in the middle.
+    network.node_inner_mut(xf).unwrap()
+        .set_knob(1, KnobData::Wiggle(Data::unipolar(0.5))).unwrap();
+    network.node_inner_mut(xf).unwrap()
+        .set_knob(0, KnobData::Picker("equal power".to_string())).unwrap();
+    let half = (0.5f64).sqrt();
+    assert_eq!(Data::bipolar(half * 0.2 - half * 0.8), value(&network));
+
+    network.node_inner_mut(xf).unwrap()
+        .set_knob(0, KnobData::Picker("cut".to_string())).unwrap();
+    assert_eq!(Data::bipolar(-0.8), value(&network));
+    assert!(network.node_inner_mut(xf).unwrap()
+        .set_knob(0, KnobData::Picker("foo".to_string())).is_err());
+
+    // The position input overrides the knob.
+    let position = constant(&mut network, 0.3);
+    network.swap_input(xf, 2u32.into(), Some((position, 0u32.into()))).unwrap();
+    assert_eq!(Data::bipolar(0.2), value(&network));
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_reader(ser_net.as_bytes()).unwrap();
+    assert_eq!(network, de_net);
+}
+
+#[test]
+fn test_blender_modes() {
+    let mut network: WiggleNetwork = Network::new();
+    let a = constant(&mut network, 0.6);
+    let b = constant(&mut network, 0.2);
+    let (blender, _) = network.add(new_wiggle(BLENDER_KIND, "test blender").unwrap());
+    let (_, msgs) = network.push_input(blender).unwrap();
+    // Each new channel brings a level and a priority knob.
+    assert_eq!(2, msgs.len());
+    network.swap_input(blender, 0u32.into(), Some((a, 0u32.into()))).unwrap();
+    network.swap_input(blender, 1u32.into(), Some((b, 0u32.into()))).unwrap();
+    let provider = TestClockProvider::at(0.3);
+    let value = |network: &mut WiggleNetwork, mode: &str| {
+        network.node_inner_mut(blender).unwrap()
+            .set_knob(0, KnobData::Picker(mode.to_string())).unwrap();
+        network.get_value(blender, 0u32.into(), 0.0, Some(Datatype::Unipolar), &provider)
+    };
+
+    assert_eq!(Data::unipolar(0.8), value(&mut network, "add"));
+    assert_eq!(Data::unipolar(0.2), value(&mut network, "min"));
+    assert_eq!(Data::unipolar(0.4), value(&mut network, "subtract"));
+    assert_eq!(Data::unipolar(0.4), value(&mut network, "difference"));
+    assert_eq!(Data::unipolar(1.0 - 0.4 * 0.8), value(&mut network, "screen"));
+    assert_eq!(Data::unipolar(0.4), value(&mut network, "average"));
+    assert_eq!(Data::unipolar(0.2), value(&mut network, "last"));
+    assert_eq!(Data::unipolar(0.6), value(&mut network, "htp"));
+
+    // Give the second channel a higher priority; it now wins even though it is lower.
+    let priority_addr = (1 << 16) + 2;
+    network.node_inner_mut(blender).unwrap()
+        .set_knob(priority_addr, KnobData::UFloat(1.0)).unwrap();
+    assert_eq!(Data::unipolar(0.2), value(&mut network, "htp"));
+    assert_eq!(
+        KnobData::UFloat(1.0),
+        network.node_inner_mut(blender).unwrap().knob_value(priority_addr).unwrap());
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_reader(ser_net.as_bytes()).unwrap();
+    assert_eq!(network, de_net);
+
+    // Shows saved before the last mode was renamed still load.
+    let old: Blender =
+        serde_json::from_str(r#"{"name":"old","levels":[1.0],"blend_mode":"Ltp"}"#).unwrap();
+    assert_eq!(KnobData::Picker("last".to_string()), old.knob_value(0).unwrap());
+
+    let msgs = network.pop_input(blender).unwrap();
+    assert_eq!(2, msgs.len());
+    assert!(network.node_inner_mut(blender).unwrap().knob_value(priority_addr).is_err());
+}
+
+#[test]
+fn test_convert() {
+    let mut network: WiggleNetwork = Network::new();
+    let source = constant(&mut network, -0.5);
+    let (convert, _) = network.add(new_wiggle(CONVERT_KIND, "test convert").unwrap());
+    let provider = TestClockProvider::at(0.3);
+    let value = |network: &mut WiggleNetwork, conversion: &str| {
+        network.node_inner_mut(convert).unwrap()
+            .set_knob(0, KnobData::Picker(conversion.to_string())).unwrap();
+        network.get_value(convert, 0u32.into(), 0.0, Some(Datatype::Unipolar), &provider)
+    };
+    assert_eq!(Data::unipolar(0.0), value(&mut network, "rescale"));
+
+    network.swap_input(convert, 0u32.into(), Some((source, 0u32.into()))).unwrap();
+    assert_eq!(Data::unipolar(0.25), value(&mut network, "rescale"));
+    assert_eq!(Data::unipolar(0.5), value(&mut network, "abs"));
+    assert_eq!(Data::unipolar(0.0), value(&mut network, "clip"));
+    assert_eq!(Data::unipolar(0.0), value(&mut network, "half wave"));
+    assert!(network.node_inner_mut(convert).unwrap()
+        .set_knob(0, KnobData::Picker("foo".to_string())).is_err());
+
+    let ser_net = serde_json::to_string(&network).unwrap();
+    let de_net: WiggleNetwork = serde_json::from_reader(ser_net.as_bytes()).unwrap();
+    assert_eq!(network, de_net);
+}
+
+#[test]
+fn test_structured_types() {
+    let mut network: WiggleNetwork = Network::new();
+    let a = constant(&mut network, 0.6);
+    let b = constant(&mut network, -0.2);
+    let provider = TestClockProvider::at(0.3);
+    // Scalar wiggles are converted into whatever type we ask for.
+    assert_eq!(
+        Data::Color(Color::rgb(0.6, 0.6, 0.6)),
+        network.get_value(a, 0u32.into(), 0.0, Some(Datatype::Color), &provider));
+    assert_eq!(
+        Data::Position(Position::new(-0.2, 0.0)),
+        network.get_value(b, 0u32.into(), 0.0, Some(Datatype::Position), &provider));
+
+    // Blending and crossfading work on whole positions.
+    let (blender, _) = network.add(new_wiggle(BLENDER_KIND, "test blender").unwrap());
+    network.push_input(blender).unwrap();
+    network.swap_input(blender, 0u32.into(), Some((a, 0u32.into()))).unwrap();
+    network.swap_input(blender, 1u32.into(), Some((b, 0u32.into()))).unwrap();
+    assert_eq!(
+        Data::Position(Position::new(0.4, 0.0)),
+        network.get_value(blender, 0u32.into(), 0.0, Some(Datatype::Position), &provider));
+
+    let (xf, _) = network.add(new_wiggle(CROSSFADER_KIND, "test xfade").unwrap());
+    network.swap_input(xf, 0u32.into(), Some((a, 0u32.into()))).unwrap();
+    network.swap_input(xf, 1u32.into(), Some((b, 0u32.into()))).unwrap();
+    network.node_inner_mut(xf).unwrap()
+        .set_knob(1, KnobData::Wiggle(Data::unipolar(0.5))).unwrap();
+    assert_eq!(
+        Data::Position(Position::new(0.2, 0.0)),
+        network.get_value(xf, 0u32.into(), 0.0, Some(Datatype::Position), &provider));
+    assert_eq!(
+        Datatype::Index(4),
+        network.get_value(xf, 0u32.into(), 0.0, Some(Datatype::Index(4)), &provider).datatype());
+}
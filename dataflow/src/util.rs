@@ -1,5 +1,7 @@
 //! Utility functions.
 use std::time::Duration;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 
 /// Convert a duration into a floating-point number of seconds.
 pub fn secs(d: Duration) -> f64 {
@@ -41,4 +43,24 @@ pub fn angle_almost_eq(a: f64, b: f64) -> bool {
 #[inline(always)]
 pub fn assert_almost_eq(a: f64, b: f64) {
     assert!(almost_eq(a, b), "{} != {}", a, b);
-}
\ No newline at end of file
+}
+
+/// Scramble an integer into a pseudo-random one (the splitmix64 finalizer).
+/// The same input always produces the same output, on every machine.
+pub fn hash(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// A pseudo-random number on [0.0, 1.0), determined entirely by a seed and an index.
+pub fn random_unit(seed: u64, index: i64) -> f64 {
+    // Keep the top 53 bits, which is all a float can hold.
+    (hash(seed ^ hash(index as u64)) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Pick a fresh seed, different every time.
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
@@ -6,6 +6,7 @@ use std::ops::{Add, Mul};
 use std::cmp::max;
 use std::fmt;
 use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
 use ::network::{OutputId, Inputs, Outputs};
 use wiggles_value::knob::{
     Knobs,
@@ -20,13 +21,22 @@ use wiggles_value::knob::{
 use serde_json::{Error as SerdeJsonError, self};
 use clocks::clock::{ClockId, ClockProvider, ClockValue};
 use super::wiggle::{Wiggle, CompleteWiggle, WiggleId, KnobAddr, WiggleKnobAddr, WiggleProvider};
-use wiggles_value::{Unipolar, Bipolar, Datatype, Data};
-use wiggles_value::blend::Blend;
+use wiggles_value::{Unipolar, Bipolar, Color, Position, Index, Datatype, Data};
+use wiggles_value::blend::{Blend, Prioritized};
 use waveforms::sine;
 
 lazy_static! {
     static ref BLEND_MODES: Vec<String> = vec!(
-        "add".to_string(), "mult".to_string(), "max".to_string());
+        "add".to_string(),
+        "mult".to_string(),
+        "max".to_string(),
+        "min".to_string(),
+        "subtract".to_string(),
+        "difference".to_string(),
+        "screen".to_string(),
+        "average".to_string(),
+        "htp".to_string(),
+        "last".to_string());
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
@@ -34,6 +44,19 @@ enum BlendMode {
     Add,
     Multiply,
     Max,
+    Min,
+    /// Subtract every channel after the first from the first.
+    Subtract,
+    Difference,
+    Screen,
+    /// The mean of all of the channels.
+    Average,
+    /// Highest takes precedence, using the channel priorities.
+    Htp,
+    /// The last channel replaces all of the others, like the top layer of a stack.
+    /// Shows saved before this was renamed call it Ltp.
+    #[serde(alias="Ltp")]
+    Last,
 }
 
 impl BlendMode {
@@ -42,6 +65,13 @@ impl BlendMode {
             BlendMode::Add => "add",
             BlendMode::Multiply => "mult",
             BlendMode::Max => "max",
+            BlendMode::Min => "min",
+            BlendMode::Subtract => "subtract",
+            BlendMode::Difference => "difference",
+            BlendMode::Screen => "screen",
+            BlendMode::Average => "average",
+            BlendMode::Htp => "htp",
+            BlendMode::Last => "last",
         }
     }
 
@@ -50,9 +80,33 @@ impl BlendMode {
             "add" => Ok(BlendMode::Add),
             "mult" => Ok(BlendMode::Multiply),
             "max" => Ok(BlendMode::Max),
+            "min" => Ok(BlendMode::Min),
+            "subtract" => Ok(BlendMode::Subtract),
+            "difference" => Ok(BlendMode::Difference),
+            "screen" => Ok(BlendMode::Screen),
+            "average" => Ok(BlendMode::Average),
+            "htp" => Ok(BlendMode::Htp),
+            "last" => Ok(BlendMode::Last),
             _ => Err(()),
         }
     }
+
+    /// The function that blends two layers in this mode, for this type.
+    /// Average adds up inputs that have already been scaled down by the number of inputs.
+    /// HTP needs the channel priorities, so it has no such function.
+    fn blend_func<B: Blend>(&self) -> Option<fn(Data, Data) -> Data> {
+        match *self {
+            BlendMode::Add | BlendMode::Average => Some(B::add),
+            BlendMode::Multiply => Some(B::mult),
+            BlendMode::Max => Some(B::max),
+            BlendMode::Min => Some(B::min),
+            BlendMode::Subtract => Some(B::subtract),
+            BlendMode::Difference => Some(B::difference),
+            BlendMode::Screen => Some(B::screen),
+            BlendMode::Htp => None,
+            BlendMode::Last => Some(B::ltp),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -61,6 +115,11 @@ pub struct Blender {
     /// Channel fader levels, each controlled by a knob.
     /// Knob addresses start from 1, as blend mode is 0.
     levels: Vec<Unipolar>,
+    /// Channel priorities, used by HTP blending.
+    /// Knob addresses start from FIRST_PRIORITY_KNOB_ADDR.
+    /// Shows saved before priorities existed have none; missing priorities are 0.
+    #[serde(default)]
+    priorities: Vec<f64>,
     /// What blend mode is currently active?
     /// This is controlled by knob 0.
     blend_mode: BlendMode,
@@ -72,14 +131,24 @@ impl Blender {
             name: name.into(),
             // start with one channel all the way up
             levels: vec!(Unipolar(1.0)),
+            priorities: vec!(0.0),
             // run in Add mode by default
             blend_mode: BlendMode::Add,
         }
     }
+
+    /// Get the priority of a channel, counting from 0.
+    fn priority(&self, chan: usize) -> f64 {
+        self.priorities.get(chan).cloned().unwrap_or(0.0)
+    }
 }
 
 pub const KIND: &'static str = "blender";
 
+/// Priority knobs are numbered well clear of the level knobs, so the level knobs keep the
+/// addresses they have always had.
+const FIRST_PRIORITY_KNOB_ADDR: KnobAddr = 1 << 16;
+
 fn level_knob_desc(chan: KnobAddr) -> KnobDescription {
     KnobDescription {
         name: Arc::new(format!("channel {} level", chan)),
@@ -87,6 +156,14 @@ fn level_knob_desc(chan: KnobAddr) -> KnobDescription {
     }
 }
 
+/// Describe the priority knob for a channel, counting from 1 like the levels.
+fn priority_knob_desc(chan: KnobAddr) -> KnobDescription {
+    KnobDescription {
+        name: Arc::new(format!("channel {} priority", chan)),
+        datatype: KnobDatatype::UFloat,
+    }
+}
+
 // Blender has at least one input, and up to an unlimited number of them.
 impl Inputs<KnobResponse<WiggleKnobAddr>, WiggleId> for Blender {
     fn default_input_count(&self) -> u32 {
@@ -94,12 +171,18 @@ impl Inputs<KnobResponse<WiggleKnobAddr>, WiggleId> for Blender {
     }
     fn try_push_input(
             &mut self, node_id: WiggleId) -> Result<Messages<KnobResponse<WiggleKnobAddr>>, ()> {
-        // add a fresh input, set to 1
+        // add a fresh input, set to 1, with no priority
+        let chan = self.levels.len();
         self.levels.push(Unipolar(1.0));
-        // tell the world that there's a new knob available
+        self.priorities.resize(chan, 0.0);
+        self.priorities.push(0.0);
+        // tell the world that there are new knobs available
         // level addresses start at 1
         let addr = self.levels.len() as KnobAddr;
-        Ok(Messages::one(KnobResponse::Added((node_id, addr), level_knob_desc(addr))))
+        let mut msgs = Messages::one(KnobResponse::Added((node_id, addr), level_knob_desc(addr)));
+        msgs.push(KnobResponse::Added(
+            (node_id, FIRST_PRIORITY_KNOB_ADDR + addr), priority_knob_desc(addr)));
+        Ok(msgs)
     }
     fn try_pop_input(
             &mut self, node_id: WiggleId) -> Result<Messages<KnobResponse<WiggleKnobAddr>>, ()> {
@@ -108,7 +191,11 @@ impl Inputs<KnobResponse<WiggleKnobAddr>, WiggleId> for Blender {
             return Err(());
         }
         self.levels.pop();
-        Ok(Messages::one(KnobResponse::Removed((node_id, (self.levels.len() + 1) as KnobAddr))))
+        self.priorities.truncate(self.levels.len());
+        let addr = (self.levels.len() + 1) as KnobAddr;
+        let mut msgs = Messages::one(KnobResponse::Removed((node_id, addr)));
+        msgs.push(KnobResponse::Removed((node_id, FIRST_PRIORITY_KNOB_ADDR + addr)));
+        Ok(msgs)
     }
 }
 
@@ -135,6 +222,10 @@ impl Knobs<KnobAddr> for Blender {
         for chan in 1..self.levels.len()+1 {
             descs.push((chan as KnobAddr, level_knob_desc(chan as KnobAddr)));
         }
+        for chan in 1..self.levels.len()+1 {
+            let chan = chan as KnobAddr;
+            descs.push((FIRST_PRIORITY_KNOB_ADDR + chan, priority_knob_desc(chan)));
+        }
         descs
     }
 
@@ -145,6 +236,10 @@ impl Knobs<KnobAddr> for Blender {
         else if addr <= (self.levels.len() as KnobAddr) {
             Ok(KnobDatatype::Wiggle(Datatype::Unipolar))
         }
+        else if addr > FIRST_PRIORITY_KNOB_ADDR
+                && addr <= FIRST_PRIORITY_KNOB_ADDR + (self.levels.len() as KnobAddr) {
+            Ok(KnobDatatype::UFloat)
+        }
         else {
             Err(badaddr(addr))
         }
@@ -155,6 +250,13 @@ impl Knobs<KnobAddr> for Blender {
         if addr == BLEND_MODE_KNOB_ADDR {
             return Ok(KnobData::Picker(self.blend_mode.to_picker().to_string()));
         }
+        if addr > FIRST_PRIORITY_KNOB_ADDR {
+            let chan = (addr - FIRST_PRIORITY_KNOB_ADDR - 1) as usize;
+            if chan < self.levels.len() {
+                return Ok(KnobData::UFloat(self.priority(chan)));
+            }
+            return Err(badaddr(addr));
+        }
         match self.levels.get((addr - 1) as usize) {
             Some(level) => Ok(KnobData::Wiggle(Data::Unipolar(*level))),
             None => Err(badaddr(addr)),
@@ -169,6 +271,17 @@ impl Knobs<KnobAddr> for Blender {
                     .map_err(|()| badtype(blend_knob_datatype(), value))?;
             return Ok(());
         }
+        if addr > FIRST_PRIORITY_KNOB_ADDR {
+            let chan = (addr - FIRST_PRIORITY_KNOB_ADDR - 1) as usize;
+            if chan >= self.levels.len() {
+                return Err(badaddr(addr));
+            }
+            let priority = value.as_ufloat()?;
+            let channels = self.levels.len();
+            self.priorities.resize(channels, 0.0);
+            self.priorities[chan] = priority;
+            return Ok(());
+        }
         // not the blend knob, should be a level knob
         match self.levels.get_mut((addr - 1) as usize) {
             Some(level) => {
@@ -194,7 +307,7 @@ impl Wiggle for Blender {
     }
 
     /// Blender is stateless, update does nothing.
-    fn update(&mut self, _: Duration) -> Messages<KnobResponse<KnobAddr>> {
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
         Messages::none()
     }
 
@@ -209,28 +322,6 @@ impl Wiggle for Blender {
         clocks: &ClockProvider)
         -> Data
     {
-        let blender = match type_hint {
-            Some(Datatype::Unipolar) | None => {
-                match self.blend_mode {
-                    BlendMode::Add => <Unipolar as Blend>::add,
-                    BlendMode::Multiply => <Unipolar as Blend>::mult,
-                    BlendMode::Max => <Unipolar as Blend>::max,
-                }
-            }
-            Some(Datatype::Bipolar) => {
-                match self.blend_mode {
-                    BlendMode::Add => <Bipolar as Blend>::add,
-                    BlendMode::Multiply => <Bipolar as Blend>::mult,
-                    BlendMode::Max => <Bipolar as Blend>::max,
-                }
-            }
-        };
-        let base_layer = match self.blend_mode {
-            BlendMode::Add => Data::unipolar(0.0),
-            BlendMode::Multiply => Data::unipolar(1.0),
-            BlendMode::Max => Data::unipolar(0.0),
-        };
-
         // log an error if we didn't get the right number of inputs, but don't panic
         if inputs.len() != self.levels.len() {
             error!(
@@ -239,18 +330,59 @@ impl Wiggle for Blender {
                 self.levels.len(),
                 inputs.len());
         }
-        // Use the selected blend function to fold over the inputs.
-        inputs.iter()
+        let datatype = type_hint.unwrap_or(Datatype::Unipolar);
+        // Scale every input value by its level.
+        // For the average, also scale it down by the number of inputs, so we can just add them.
+        let scale = if self.blend_mode == BlendMode::Average {
+            Unipolar(1.0 / inputs.len() as f64)
+        }
+        else {
+            Unipolar(1.0)
+        };
+        let values: Vec<Data> = inputs.iter()
             .zip(self.levels.iter())
             .map(|(input_id_opt, level)| {
                 let input_val = match *input_id_opt {
                     Some((id, output)) => wiggles.get_value(id, output, phase_offset, type_hint, clocks),
                     None => Data::default_with_type_hint(type_hint),
                 };
-                // scale the input value by its level
-                input_val * (*level)
+                (input_val * (*level) * scale).as_type(datatype)
             })
-            .fold(base_layer, blender)
+            .collect();
+
+        let blender = match datatype {
+            Datatype::Unipolar => self.blend_mode.blend_func::<Unipolar>(),
+            Datatype::Bipolar => self.blend_mode.blend_func::<Bipolar>(),
+            Datatype::Color => self.blend_mode.blend_func::<Color>(),
+            Datatype::Position => self.blend_mode.blend_func::<Position>(),
+            Datatype::Index(_) => self.blend_mode.blend_func::<Index>(),
+        };
+        if let Some(blender) = blender {
+            // Use the selected blend function to fold the rest of the inputs onto the first.
+            let mut values = values.into_iter();
+            return match values.next() {
+                Some(first) => values.fold(first, blender),
+                None => Data::default_with_type_hint(type_hint),
+            };
+        }
+
+        // HTP needs the channel priorities, so it doesn't fit the plain fold above.
+        let htp = match datatype {
+            Datatype::Unipolar => <Unipolar as Blend>::htp,
+            Datatype::Bipolar => <Bipolar as Blend>::htp,
+            Datatype::Color => <Color as Blend>::htp,
+            Datatype::Position => <Position as Blend>::htp,
+            Datatype::Index(_) => <Index as Blend>::htp,
+        };
+        values.into_iter()
+            .enumerate()
+            .map(|(chan, value)| Prioritized::new(value, self.priority(chan)))
+            .fold(None, |base, top| Some(match base {
+                Some(base) => htp(base, top),
+                None => top,
+            }))
+            .map(|blended| blended.data)
+            .unwrap_or(Data::default_with_type_hint(type_hint))
     }
 
     fn clock_source(&self) -> Result<Option<ClockId>, ()> {
@@ -261,8 +393,8 @@ impl Wiggle for Blender {
         Err(())
     }
 
-    fn as_json(&self) -> Result<String, SerdeJsonError> {
-        serde_json::to_string(self)
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
     }
 }
 
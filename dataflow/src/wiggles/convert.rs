@@ -0,0 +1,179 @@
+//! A wiggle that converts its input between unipolar and bipolar using a selected strategy.
+//! Patch it in front of any wiggle input to change how that input sees its source.  The input is
+//! read as the opposite of the type we're asked for and then converted, so for example a bipolar
+//! LFO can drive a unipolar input with rescaling rather than abs, which would double its
+//! frequency.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Datatype, Data, Conversion};
+
+lazy_static! {
+    static ref CONVERSIONS: Vec<String> = vec!(
+        "abs".to_string(),
+        "rescale".to_string(),
+        "clip".to_string(),
+        "half wave".to_string());
+}
+
+fn to_picker(conversion: Conversion) -> &'static str {
+    match conversion {
+        Conversion::Abs => "abs",
+        Conversion::Rescale => "rescale",
+        Conversion::Clip => "clip",
+        Conversion::HalfWave => "half wave",
+    }
+}
+
+fn from_picker(s: &str) -> Result<Conversion, ()> {
+    match s {
+        "abs" => Ok(Conversion::Abs),
+        "rescale" => Ok(Conversion::Rescale),
+        "clip" => Ok(Conversion::Clip),
+        "half wave" => Ok(Conversion::HalfWave),
+        _ => Err(()),
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Convert {
+    name: String,
+    conversion: Conversion,
+}
+
+impl Convert {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Convert {
+            name: name.into(),
+            conversion: Conversion::Rescale,
+        }
+    }
+}
+
+pub const KIND: &'static str = "convert";
+
+// Convert has a single input, the default.
+impl<M, I> Inputs<M, I> for Convert {}
+
+// Convert has one output.
+impl<M, I> Outputs<M, I> for Convert {}
+
+const CONVERSION_KNOB_ADDR: KnobAddr = 0;
+
+fn conversion_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(CONVERSIONS.clone())
+}
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = vec!(
+        (CONVERSION_KNOB_ADDR, KnobDescription {
+            name: Arc::new("conversion".to_string()),
+            datatype: conversion_knob_datatype(),
+        }),
+    );
+}
+
+impl Knobs<KnobAddr> for Convert {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            CONVERSION_KNOB_ADDR => Ok(conversion_knob_datatype()),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            CONVERSION_KNOB_ADDR => Ok(KnobData::Picker(to_picker(self.conversion).to_string())),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            CONVERSION_KNOB_ADDR => {
+                self.conversion =
+                    value.as_picker()
+                        .and_then(|p| from_picker(&p))
+                        .map_err(|()| badtype(conversion_knob_datatype(), value))?;
+            }
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Convert {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Convert is stateless, update does nothing.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        Messages::none()
+    }
+
+    /// Read the input as the opposite type to the one we're asked for, and convert it.
+    /// Provide unipolar if no type hint is provided.
+    fn render(
+        &self,
+        phase_offset: f64,
+        type_hint: Option<Datatype>,
+        inputs: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        wiggles: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let (datatype, source_type) = match type_hint {
+            Some(t) if t.is_centered() => (Datatype::Bipolar, Datatype::Unipolar),
+            _ => (Datatype::Unipolar, Datatype::Bipolar),
+        };
+        let input = match inputs.first() {
+            Some(&Some((id, output))) =>
+                wiggles.get_value(id, output, phase_offset, Some(source_type), clocks),
+            _ => return Data::default_with_type_hint(Some(datatype)),
+        };
+        self.conversion.convert(input, datatype)
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Err(())
+    }
+
+    fn set_clock(&mut self, _: Option<ClockId>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,224 @@
+//! A wiggles node that crossfades between two inputs, A and B, using a selected curve.
+//! The position can be set by its knob, or patched from another wiggle through the third input
+//! so that transitions can be automated.
+use std::sync::Arc;
+use std::time::Duration;
+use std::f64::consts::PI;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Datatype, Data};
+
+lazy_static! {
+    static ref CURVES: Vec<String> = vec!(
+        "linear".to_string(), "equal power".to_string(), "cut".to_string());
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+enum Curve {
+    Linear,
+    /// Keep the total power constant through the fade, so the middle doesn't dip.
+    EqualPower,
+    /// Switch from A to B halfway across.
+    Cut,
+}
+
+impl Curve {
+    fn to_picker(&self) -> &'static str {
+        match *self {
+            Curve::Linear => "linear",
+            Curve::EqualPower => "equal power",
+            Curve::Cut => "cut",
+        }
+    }
+
+    fn from_picker(s: &str) -> Result<Self, ()> {
+        match s {
+            "linear" => Ok(Curve::Linear),
+            "equal power" => Ok(Curve::EqualPower),
+            "cut" => Ok(Curve::Cut),
+            _ => Err(()),
+        }
+    }
+
+    /// The levels of A and B at this position.
+    fn levels(&self, position: f64) -> (f64, f64) {
+        match *self {
+            Curve::Linear => (1.0 - position, position),
+            Curve::EqualPower => ((position * PI / 2.0).cos(), (position * PI / 2.0).sin()),
+            Curve::Cut => if position < 0.5 { (1.0, 0.0) } else { (0.0, 1.0) },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Crossfader {
+    name: String,
+    curve: Curve,
+    /// Position of the fade, from all A at 0 to all B at 1.
+    /// Only used if the position input is not connected.
+    position: Unipolar,
+}
+
+impl Crossfader {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Crossfader {
+            name: name.into(),
+            curve: Curve::Linear,
+            position: Unipolar(0.0),
+        }
+    }
+}
+
+pub const KIND: &'static str = "crossfader";
+
+// Inputs are A, B, and the position.
+const A_INPUT: usize = 0;
+const B_INPUT: usize = 1;
+const POSITION_INPUT: usize = 2;
+
+// Crossfader has three fixed inputs.
+impl<M, I> Inputs<M, I> for Crossfader {
+    fn default_input_count(&self) -> u32 {
+        3
+    }
+}
+
+// Crossfader has a single, fixed output.
+impl<M, I> Outputs<M, I> for Crossfader {}
+
+const CURVE_KNOB_ADDR: KnobAddr = 0;
+const POSITION_KNOB_ADDR: KnobAddr = 1;
+
+fn curve_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(CURVES.clone())
+}
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        vec!(
+            (CURVE_KNOB_ADDR, desc("curve", curve_knob_datatype())),
+            (POSITION_KNOB_ADDR, desc("position", KnobDatatype::Wiggle(Datatype::Unipolar))),
+        )
+    };
+}
+
+impl Knobs<KnobAddr> for Crossfader {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            CURVE_KNOB_ADDR => Ok(curve_knob_datatype()),
+            POSITION_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            CURVE_KNOB_ADDR => Ok(KnobData::Picker(self.curve.to_picker().to_string())),
+            POSITION_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.position))),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            CURVE_KNOB_ADDR => {
+                self.curve =
+                    value.as_picker()
+                        .and_then(|p| Curve::from_picker(&p))
+                        .map_err(|()| badtype(curve_knob_datatype(), value))?;
+            }
+            POSITION_KNOB_ADDR => self.position = value.as_unipolar()?,
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Crossfader {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Crossfader is stateless, update does nothing.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        Messages::none()
+    }
+
+    /// Scale A and B by the levels the curve gives for the current position, and add them.
+    /// Structured types such as colors are mixed as a whole.
+    fn render(
+        &self,
+        phase_offset: f64,
+        type_hint: Option<Datatype>,
+        inputs: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        wiggles: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let input_value = |index: usize, type_hint| {
+            match inputs.get(index) {
+                Some(&Some((id, output))) =>
+                    Some(wiggles.get_value(id, output, phase_offset, type_hint, clocks)),
+                _ => None,
+            }
+        };
+        let datatype = type_hint.unwrap_or(Datatype::Unipolar);
+        let value = |index: usize| {
+            input_value(index, type_hint)
+                .unwrap_or(Data::default_with_type_hint(type_hint))
+                .as_type(datatype)
+        };
+        let position = match input_value(POSITION_INPUT, Some(Datatype::Unipolar)) {
+            Some(data) => Unipolar::from(data).coerce(),
+            None => self.position,
+        };
+        let (a_level, b_level) = self.curve.levels(position.0);
+        let mixed = value(A_INPUT) * Unipolar(a_level) + value(B_INPUT) * Unipolar(b_level);
+        mixed.coerce()
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Err(())
+    }
+
+    fn set_clock(&mut self, _: Option<ClockId>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,349 @@
+//! A wiggle that turns DMX received from the network into wiggle data.
+//! Each output follows one channel of a received Art-Net or sACN universe, or a pair of channels
+//! for 16-bit values, with the coarse channel first.  Outputs can be added and removed like a
+//! fanner's.
+//! If nothing has been received for the universe within the timeout, every output falls back to
+//! the fallback level, so that a console going away doesn't leave things stuck where it left them.
+//! If we can't listen to the universe, we try again after a delay.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use util::secs;
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Datatype, Data};
+use dmx_receiver::{self, Protocol, Frame};
+
+const MAX_CHANNEL: usize = 512;
+
+/// How long to wait for data before falling back, in seconds.
+const DEFAULT_TIMEOUT: f64 = 2.5;
+/// Time to wait before trying to listen again after failing to, in seconds.
+const RELISTEN_INTERVAL: f64 = 5.0;
+
+lazy_static! {
+    static ref PROTOCOLS: Vec<String> = vec!(
+        Protocol::ArtNet.name().to_string(),
+        Protocol::Sacn.name().to_string());
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+struct InputChannel {
+    /// DMX address, numbered from 1.
+    channel: usize,
+    /// If true, this channel is the coarse half of a 16-bit value and the next is the fine half.
+    fine: bool,
+}
+
+impl InputChannel {
+    fn level(&self, frame: &Frame) -> Unipolar {
+        if self.fine {
+            let coarse = frame.level(self.channel) as u32;
+            let fine = frame.level(self.channel + 1) as u32;
+            Unipolar((coarse << 8 | fine) as f64 / 65535.0)
+        }
+        else {
+            Unipolar(frame.level(self.channel) as f64 / 255.0)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DmxInput {
+    name: String,
+    protocol: Protocol,
+    universe: u16,
+    /// Seconds without data before the outputs fall back.
+    timeout: f64,
+    fallback: Unipolar,
+    /// The channel for each output.
+    channels: Vec<InputChannel>,
+    /// The frame to render, if it's fresh enough.
+    #[serde(skip)]
+    frame: Option<Frame>,
+    /// The universe we've asked the receiver to listen to.
+    #[serde(skip)]
+    listening: Option<(Protocol, u16)>,
+    /// Seconds until we next try to listen, if we failed to.
+    #[serde(skip)]
+    relisten_in: f64,
+    /// Knob changes caused by adding or removing outputs or changing protocol, to be sent out on
+    /// the next update.
+    #[serde(skip)]
+    pending: Vec<KnobResponse<KnobAddr>>,
+}
+
+impl DmxInput {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        DmxInput {
+            name: name.into(),
+            protocol: Protocol::ArtNet,
+            universe: Protocol::ArtNet.default_universe(),
+            timeout: DEFAULT_TIMEOUT,
+            fallback: Unipolar(0.0),
+            channels: vec!(InputChannel { channel: 1, fine: false }),
+            frame: None,
+            listening: None,
+            relisten_in: 0.0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Change protocols, moving to the default universe if the current one doesn't exist in the
+    /// new protocol.  The universe is written differently between protocols, so always announce it.
+    fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+        self.relisten_in = 0.0;
+        if !protocol.universe_is_valid(self.universe) {
+            self.universe = protocol.default_universe();
+        }
+        self.pending.push(KnobResponse::ValueChange(
+            UNIVERSE_KNOB_ADDR, KnobData::Text(protocol.format_universe(self.universe))));
+    }
+}
+
+pub const KIND: &'static str = "dmx input";
+
+// DmxInput has no inputs.
+impl<M, I> Inputs<M, I> for DmxInput {
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+// DmxInput has an output for every channel it follows.
+impl<M, I> Outputs<M, I> for DmxInput {
+    fn default_output_count(&self) -> u32 {
+        self.channels.len() as u32
+    }
+
+    /// New outputs follow the channel after the last one.
+    fn try_push_output(&mut self, _: I) -> Result<Messages<M>, ()> {
+        let channel = match self.channels.last() {
+            Some(last) if last.fine => last.channel + 2,
+            Some(last) => last.channel + 1,
+            None => 1,
+        };
+        self.channels.push(InputChannel { channel: channel.min(MAX_CHANNEL), fine: false });
+        for (addr, desc) in output_knob_descs(self.channels.len() - 1) {
+            self.pending.push(KnobResponse::Added(addr, desc));
+        }
+        Ok(Messages::none())
+    }
+
+    fn try_pop_output(&mut self, _: I) -> Result<Messages<M>, ()> {
+        if self.channels.len() == 1 {
+            return Err(());
+        }
+        self.channels.pop();
+        for (addr, _) in output_knob_descs(self.channels.len()) {
+            self.pending.push(KnobResponse::Removed(addr));
+        }
+        Ok(Messages::none())
+    }
+}
+
+const PROTOCOL_KNOB_ADDR: KnobAddr = 0;
+const UNIVERSE_KNOB_ADDR: KnobAddr = 1;
+const TIMEOUT_KNOB_ADDR: KnobAddr = 2;
+const FALLBACK_KNOB_ADDR: KnobAddr = 3;
+const FIRST_OUTPUT_KNOB_ADDR: KnobAddr = 4;
+
+/// Every output has a channel knob followed by a 16-bit knob.
+fn output_channel_knob_addr(output: usize) -> KnobAddr {
+    FIRST_OUTPUT_KNOB_ADDR + 2 * output as KnobAddr
+}
+
+fn output_fine_knob_addr(output: usize) -> KnobAddr {
+    output_channel_knob_addr(output) + 1
+}
+
+fn output_knob_descs(output: usize) -> Vec<(KnobAddr, KnobDescription)> {
+    let desc = |param: &str, datatype| KnobDescription {
+        name: Arc::new(format!("output {} {}", output + 1, param)),
+        datatype: datatype,
+    };
+    vec!(
+        (output_channel_knob_addr(output), desc("channel", KnobDatatype::UInt)),
+        (output_fine_knob_addr(output), desc("16 bit", KnobDatatype::Button)),
+    )
+}
+
+fn protocol_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(PROTOCOLS.clone())
+}
+
+impl Knobs<KnobAddr> for DmxInput {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        let mut descs = vec!(
+            (PROTOCOL_KNOB_ADDR, desc("protocol", protocol_knob_datatype())),
+            (UNIVERSE_KNOB_ADDR, desc("universe", KnobDatatype::Text)),
+            (TIMEOUT_KNOB_ADDR, desc("timeout", KnobDatatype::UFloat)),
+            (FALLBACK_KNOB_ADDR, desc("fallback", KnobDatatype::Wiggle(Datatype::Unipolar))),
+        );
+        for output in 0..self.channels.len() {
+            descs.extend(output_knob_descs(output));
+        }
+        descs
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            PROTOCOL_KNOB_ADDR => Ok(protocol_knob_datatype()),
+            UNIVERSE_KNOB_ADDR => Ok(KnobDatatype::Text),
+            TIMEOUT_KNOB_ADDR => Ok(KnobDatatype::UFloat),
+            FALLBACK_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            a if a < output_channel_knob_addr(self.channels.len()) => {
+                if (a - FIRST_OUTPUT_KNOB_ADDR) % 2 == 0 {
+                    Ok(KnobDatatype::UInt)
+                }
+                else {
+                    Ok(KnobDatatype::Button)
+                }
+            }
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            PROTOCOL_KNOB_ADDR => Ok(KnobData::Picker(self.protocol.name().to_string())),
+            UNIVERSE_KNOB_ADDR => Ok(KnobData::Text(self.protocol.format_universe(self.universe))),
+            TIMEOUT_KNOB_ADDR => Ok(KnobData::UFloat(self.timeout)),
+            FALLBACK_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.fallback))),
+            _ => {
+                let offset = (addr - FIRST_OUTPUT_KNOB_ADDR) as usize;
+                match self.channels.get(offset / 2) {
+                    Some(c) if offset % 2 == 0 => Ok(KnobData::UInt(c.channel as u32)),
+                    Some(c) => Ok(KnobData::Button(c.fine)),
+                    None => Err(badaddr(addr)),
+                }
+            }
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            PROTOCOL_KNOB_ADDR => {
+                let protocol =
+                    value.as_picker()
+                        .and_then(Protocol::from_name)
+                        .map_err(|()| badtype(protocol_knob_datatype(), value))?;
+                self.set_protocol(protocol);
+            }
+            UNIVERSE_KNOB_ADDR => {
+                let universe = value.clone().as_text()?;
+                self.universe =
+                    self.protocol.parse_universe(&universe)
+                        .map_err(|()| badtype(KnobDatatype::Text, value))?;
+                self.relisten_in = 0.0;
+            }
+            TIMEOUT_KNOB_ADDR => self.timeout = value.as_ufloat()?,
+            FALLBACK_KNOB_ADDR => self.fallback = value.as_unipolar()?,
+            _ => {
+                let offset = (addr - FIRST_OUTPUT_KNOB_ADDR) as usize;
+                match self.channels.get_mut(offset / 2) {
+                    Some(c) if offset % 2 == 0 => {
+                        let channel = value.clone().as_uint()? as usize;
+                        if channel < 1 || channel > MAX_CHANNEL {
+                            return Err(badtype(KnobDatatype::UInt, value));
+                        }
+                        c.channel = channel;
+                    }
+                    Some(c) => c.fine = value.as_button()?,
+                    None => return Err(badaddr(addr)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for DmxInput {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Pick up the latest frame for our universe, if it hasn't gone stale.
+    fn update(&mut self, dt: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        let universe = (self.protocol, self.universe);
+        if self.listening != Some(universe) {
+            self.relisten_in -= secs(dt);
+        }
+        if self.listening != Some(universe) && self.relisten_in <= 0.0 {
+            match dmx_receiver::listen(self.protocol, self.universe) {
+                Ok(()) => self.listening = Some(universe),
+                Err(e) => {
+                    error!(
+                        "{} could not listen to {} universe {}: {}",
+                        self.name,
+                        self.protocol.name(),
+                        self.protocol.format_universe(self.universe),
+                        e);
+                    self.relisten_in = RELISTEN_INTERVAL;
+                }
+            }
+        }
+        let timeout = Duration::from_millis((self.timeout * 1000.0) as u64);
+        self.frame = dmx_receiver::frame(self.protocol, self.universe)
+            .and_then(|frame| if frame.age() <= timeout { Some(frame) } else { None });
+        self.pending.drain(..).collect()
+    }
+
+    /// Render the level of the channel for this output.
+    /// The network converts it if another type was asked for.
+    fn render(
+        &self,
+        _: f64,
+        _: Option<Datatype>,
+        _: &[Option<(WiggleId, OutputId)>],
+        output: OutputId,
+        _: &WiggleProvider,
+        _: &ClockProvider)
+        -> Data
+    {
+        let level = match (&self.frame, self.channels.get(output.0 as usize)) {
+            (&Some(ref frame), Some(channel)) => channel.level(frame),
+            _ => self.fallback,
+        };
+        Data::Unipolar(level)
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Err(())
+    }
+
+    fn set_clock(&mut self, _: Option<ClockId>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
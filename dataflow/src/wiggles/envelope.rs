@@ -0,0 +1,355 @@
+//! An attack/decay/sustain/release envelope generator.
+//! The envelope is triggered by ticks of its assigned clock or by pressing its trigger button.
+//! A button trigger holds the gate open until the button is released; a clock trigger holds the
+//! gate open for a fraction of the clock's period.
+//!
+//! Attack, decay and release are given as the time in seconds to sweep across the full range,
+//! so an envelope that is interrupted partway through a stage continues smoothly from its
+//! current level.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Datatype, Data};
+use util::secs;
+
+lazy_static! {
+    static ref MODES: Vec<String> = vec!(
+        "retrigger".to_string(),
+        "legato".to_string(),
+        "one shot".to_string());
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+enum Mode {
+    /// Every trigger restarts the attack from the current level.
+    Retrigger,
+    /// Triggers while the envelope is running do not restart the attack; a trigger during the
+    /// release returns to the sustain level.
+    Legato,
+    /// Ignore the gate, and run straight through attack, decay and release on every trigger.
+    OneShot,
+}
+
+impl Mode {
+    fn to_picker(&self) -> &'static str {
+        match *self {
+            Mode::Retrigger => "retrigger",
+            Mode::Legato => "legato",
+            Mode::OneShot => "one shot",
+        }
+    }
+
+    fn from_picker(s: &str) -> Result<Self, ()> {
+        match s {
+            "retrigger" => Ok(Mode::Retrigger),
+            "legato" => Ok(Mode::Legato),
+            "one shot" => Ok(Mode::OneShot),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+impl Default for Stage {
+    fn default() -> Self {
+        Stage::Idle
+    }
+}
+
+#[derive(Debug, PartialEq, Default)]
+/// The running state of the envelope, which is not saved with the show.
+struct State {
+    stage: Stage,
+    level: f64,
+    /// Is the gate being held open by the trigger button?
+    button_gate: bool,
+    /// Is the gate being held open by the clock?
+    clock_gate: bool,
+    /// Has the trigger button been pressed since the last update?
+    button_pressed: bool,
+}
+
+impl State {
+    fn gate(&self) -> bool {
+        self.button_gate || self.clock_gate
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    name: String,
+    clock: Option<ClockId>,
+    /// Stage times, in seconds.
+    attack: f64,
+    decay: f64,
+    sustain: Unipolar,
+    release: f64,
+    mode: Mode,
+    /// Fraction of the clock period the gate stays open after a clock tick.
+    gate_length: Unipolar,
+    #[serde(skip)]
+    state: State,
+}
+
+impl Envelope {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Envelope {
+            name: name.into(),
+            clock: None,
+            attack: 0.05,
+            decay: 0.2,
+            sustain: Unipolar(0.7),
+            release: 0.5,
+            mode: Mode::Retrigger,
+            gate_length: Unipolar(0.5),
+            state: State::default(),
+        }
+    }
+
+    fn trigger(&mut self) {
+        let stage = self.state.stage;
+        self.state.stage = match self.mode {
+            Mode::Retrigger | Mode::OneShot => Stage::Attack,
+            Mode::Legato => match stage {
+                Stage::Idle => Stage::Attack,
+                Stage::Release => Stage::Decay,
+                running => running,
+            },
+        };
+    }
+
+    /// Run the envelope forward in time, possibly across several stages.
+    fn advance(&mut self, dt: f64) {
+        let mut remaining = dt;
+        loop {
+            // Which level are we heading for, how long does a full-scale sweep take, and which
+            // stage comes next?
+            let (target, time, next) = match self.state.stage {
+                Stage::Idle => return,
+                Stage::Attack => (1.0, self.attack, Stage::Decay),
+                Stage::Decay => (self.sustain.0, self.decay, Stage::Sustain),
+                Stage::Sustain => {
+                    if self.mode == Mode::OneShot || !self.state.gate() {
+                        self.state.stage = Stage::Release;
+                        continue;
+                    }
+                    self.state.level = self.sustain.0;
+                    return;
+                }
+                Stage::Release => (0.0, self.release, Stage::Idle),
+            };
+            let distance = (target - self.state.level).abs();
+            let needed = distance * time;
+            if remaining >= needed {
+                remaining -= needed;
+                self.state.level = target;
+                self.state.stage = next;
+            }
+            else {
+                let step = remaining / time;
+                if target > self.state.level {
+                    self.state.level += step;
+                }
+                else {
+                    self.state.level -= step;
+                }
+                return;
+            }
+        }
+    }
+}
+
+pub const KIND: &'static str = "envelope";
+
+// Envelope has no inputs.
+impl<M, I> Inputs<M, I> for Envelope {
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+// Envelope has one output.
+impl<M, I> Outputs<M, I> for Envelope {}
+
+const ATTACK_KNOB_ADDR: KnobAddr = 0;
+const DECAY_KNOB_ADDR: KnobAddr = 1;
+const SUSTAIN_KNOB_ADDR: KnobAddr = 2;
+const RELEASE_KNOB_ADDR: KnobAddr = 3;
+const MODE_KNOB_ADDR: KnobAddr = 4;
+const GATE_LENGTH_KNOB_ADDR: KnobAddr = 5;
+const TRIGGER_KNOB_ADDR: KnobAddr = 6;
+
+fn mode_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(MODES.clone())
+}
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        vec!(
+            (ATTACK_KNOB_ADDR, desc("attack", KnobDatatype::UFloat)),
+            (DECAY_KNOB_ADDR, desc("decay", KnobDatatype::UFloat)),
+            (SUSTAIN_KNOB_ADDR, desc("sustain", KnobDatatype::Wiggle(Datatype::Unipolar))),
+            (RELEASE_KNOB_ADDR, desc("release", KnobDatatype::UFloat)),
+            (MODE_KNOB_ADDR, desc("mode", mode_knob_datatype())),
+            (GATE_LENGTH_KNOB_ADDR, desc("gate length", KnobDatatype::Wiggle(Datatype::Unipolar))),
+            (TRIGGER_KNOB_ADDR, desc("trigger", KnobDatatype::Button)),
+        )
+    };
+}
+
+impl Knobs<KnobAddr> for Envelope {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            ATTACK_KNOB_ADDR | DECAY_KNOB_ADDR | RELEASE_KNOB_ADDR => Ok(KnobDatatype::UFloat),
+            SUSTAIN_KNOB_ADDR |
+            GATE_LENGTH_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            MODE_KNOB_ADDR => Ok(mode_knob_datatype()),
+            TRIGGER_KNOB_ADDR => Ok(KnobDatatype::Button),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            ATTACK_KNOB_ADDR => Ok(KnobData::UFloat(self.attack)),
+            DECAY_KNOB_ADDR => Ok(KnobData::UFloat(self.decay)),
+            SUSTAIN_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.sustain))),
+            RELEASE_KNOB_ADDR => Ok(KnobData::UFloat(self.release)),
+            MODE_KNOB_ADDR => Ok(KnobData::Picker(self.mode.to_picker().to_string())),
+            GATE_LENGTH_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.gate_length))),
+            TRIGGER_KNOB_ADDR => Ok(KnobData::Button(self.state.button_gate)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            ATTACK_KNOB_ADDR => self.attack = value.as_ufloat()?,
+            DECAY_KNOB_ADDR => self.decay = value.as_ufloat()?,
+            SUSTAIN_KNOB_ADDR => self.sustain = value.as_unipolar()?,
+            RELEASE_KNOB_ADDR => self.release = value.as_ufloat()?,
+            MODE_KNOB_ADDR => {
+                self.mode =
+                    value.as_picker()
+                        .and_then(|p| Mode::from_picker(&p))
+                        .map_err(|()| badtype(mode_knob_datatype(), value))?;
+            }
+            GATE_LENGTH_KNOB_ADDR => self.gate_length = value.as_unipolar()?,
+            TRIGGER_KNOB_ADDR => {
+                let pressed = value.as_button()?;
+                // Trigger on the next update, so every trigger is handled in the same place.
+                if pressed && !self.state.button_gate {
+                    self.state.button_pressed = true;
+                }
+                self.state.button_gate = pressed;
+            }
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Envelope {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Check for triggers, then run the envelope forward.
+    fn update(&mut self, dt: Duration, clocks: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        let mut triggered = self.state.button_pressed;
+        self.state.button_pressed = false;
+        match self.clock {
+            Some(cid) => {
+                let clock_val = clocks.get_value(cid);
+                if clock_val.ticked {
+                    triggered = true;
+                }
+                self.state.clock_gate = clock_val.phase() < self.gate_length;
+            }
+            None => self.state.clock_gate = false,
+        }
+        if triggered {
+            self.trigger();
+        }
+        // Closing the gate cuts straight to the release.
+        if self.mode != Mode::OneShot && !self.state.gate() {
+            match self.state.stage {
+                Stage::Attack | Stage::Decay | Stage::Sustain => self.state.stage = Stage::Release,
+                _ => (),
+            }
+        }
+        self.advance(secs(dt));
+        Messages::none()
+    }
+
+    /// The envelope is always positive, whichever type is requested.
+    fn render(
+        &self,
+        _: f64,
+        type_hint: Option<Datatype>,
+        _: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        _: &WiggleProvider,
+        _: &ClockProvider)
+        -> Data
+    {
+        match type_hint {
+            Some(t) if t.is_centered() => Data::bipolar(self.state.level).coerce(),
+            _ => Data::unipolar(self.state.level).coerce(),
+        }
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Ok(self.clock)
+    }
+
+    fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()> {
+        self.clock = source;
+        Ok(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,183 @@
+//! A wiggle that evaluates a user-written expression over its inputs and clock.
+//! The expression is compiled whenever it is edited, so rendering only has to walk the tree.
+//! See the expression module for what the language supports.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider, ClockValue};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleKnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Bipolar, Datatype, Data};
+use expression::{Program, Env};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Expression {
+    name: String,
+    clock: Option<ClockId>,
+    expression: Program,
+}
+
+impl Expression {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Expression {
+            name: name.into(),
+            clock: None,
+            // Pass the first input through unchanged.
+            expression: Program::compile("in1").unwrap(),
+        }
+    }
+}
+
+pub const KIND: &'static str = "expression";
+
+// Expression starts with one input, and can have as many as we like.
+// The expression refers to inputs by number, so adding or removing one needs no other changes.
+impl Inputs<KnobResponse<WiggleKnobAddr>, WiggleId> for Expression {
+    fn default_input_count(&self) -> u32 {
+        1
+    }
+    fn try_push_input(
+            &mut self, _: WiggleId) -> Result<Messages<KnobResponse<WiggleKnobAddr>>, ()> {
+        Ok(Messages::none())
+    }
+    fn try_pop_input(
+            &mut self, _: WiggleId) -> Result<Messages<KnobResponse<WiggleKnobAddr>>, ()> {
+        Ok(Messages::none())
+    }
+}
+
+// Expression has one output.
+impl<M, I> Outputs<M, I> for Expression {}
+
+const EXPRESSION_KNOB_ADDR: KnobAddr = 0;
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = vec!(
+        (EXPRESSION_KNOB_ADDR, KnobDescription {
+            name: Arc::new("expression".to_string()),
+            datatype: KnobDatatype::Text,
+        }),
+    );
+}
+
+impl Knobs<KnobAddr> for Expression {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            EXPRESSION_KNOB_ADDR => Ok(KnobDatatype::Text),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            EXPRESSION_KNOB_ADDR => Ok(KnobData::Text(self.expression.source().to_string())),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Compile a new expression.
+    /// If it doesn't compile, keep the old one and report why.
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            EXPRESSION_KNOB_ADDR => {
+                self.expression = Program::compile(value.as_text()?).map_err(|e|
+                    KnobError::InvalidValue{addr: addr, message: e.to_string()})?;
+            }
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Expression {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Expression is stateless, update does nothing.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        Messages::none()
+    }
+
+    /// Evaluate every input, then the expression.
+    /// The result is clipped into the range of the requested datatype.
+    fn render(
+        &self,
+        phase_offset: f64,
+        type_hint: Option<Datatype>,
+        inputs: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        wiggles: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let clock_val = match self.clock {
+            Some(cid) => clocks.get_value(cid),
+            None => ClockValue::default(),
+        };
+        let input_vals: Vec<f64> = inputs.iter().map(|input| {
+            match *input {
+                Some((id, output)) => {
+                    match wiggles.get_value(id, output, phase_offset, type_hint, clocks) {
+                        Data::Unipolar(Unipolar(v)) => v,
+                        Data::Bipolar(Bipolar(v)) => v,
+                        other => Unipolar::from(other).0,
+                    }
+                }
+                None => 0.0,
+            }
+        }).collect();
+        let env = Env {
+            phase: clock_val.phase_shift(phase_offset).0,
+            inputs: &input_vals,
+            type_hint: type_hint,
+        };
+        let mut val = self.expression.eval(&env);
+        // Dividing by zero and the like shouldn't send garbage downstream.
+        if !val.is_finite() {
+            val = 0.0;
+        }
+        match type_hint {
+            Some(t) if t.is_centered() => Data::bipolar(val).coerce(),
+            _ => Data::unipolar(val).coerce(),
+        }
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Ok(self.clock)
+    }
+
+    fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()> {
+        self.clock = source;
+        Ok(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
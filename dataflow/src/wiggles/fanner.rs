@@ -6,6 +6,7 @@ use std::ops::{Add, Mul};
 use std::cmp::max;
 use std::fmt;
 use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
 use ::network::{OutputId, Inputs, Outputs};
 use wiggles_value::knob::{
     Knobs,
@@ -123,7 +124,7 @@ impl Wiggle for Fanner {
     }
 
     /// Fanner is stateless, update does nothing.
-    fn update(&mut self, _: Duration) -> Messages<KnobResponse<KnobAddr>> {
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
         Messages::none()
     }
 
@@ -176,7 +177,7 @@ impl Wiggle for Fanner {
         Err(())
     }
 
-    fn as_json(&self) -> Result<String, SerdeJsonError> {
-        serde_json::to_string(self)
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
     }
 }
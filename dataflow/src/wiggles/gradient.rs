@@ -0,0 +1,291 @@
+//! A wiggle that maps a unipolar input through a gradient of user-defined color stops.
+//! Each stop has a color and a position on the unit range; colors are interpolated linearly
+//! between neighboring stops, and inputs beyond the outermost stops take the color of the nearest
+//! one.  Stops may be set in any order.
+//! If nothing is connected to the input, the position knob is used instead.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeserializeError;
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Datatype, Data, Color};
+
+/// The fewest stops that make a gradient.
+const MIN_STOPS: usize = 2;
+/// The most stops we support.
+const MAX_STOPS: usize = 16;
+
+lazy_static! {
+    static ref STOP_COUNTS: Vec<String> =
+        (MIN_STOPS..MAX_STOPS+1).map(|n| n.to_string()).collect();
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+struct Stop {
+    color: Color,
+    position: Unipolar,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gradient {
+    name: String,
+    /// Color stops, each controlled by a pair of knobs.
+    /// Knob addresses for the stops start from FIRST_STOP_KNOB_ADDR.
+    #[serde(deserialize_with="deserialize_stops")]
+    stops: Vec<Stop>,
+    /// Position in the gradient to render if nothing is connected to the input.
+    position: Unipolar,
+    /// Knob changes caused by changing the number of stops, to be sent out on the next update.
+    #[serde(skip)]
+    pending: Vec<KnobResponse<KnobAddr>>,
+}
+
+impl Gradient {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Gradient {
+            name: name.into(),
+            stops: vec!(
+                Stop { color: Color::rgb(0.0, 0.0, 0.0), position: Unipolar(0.0) },
+                Stop { color: Color::rgb(1.0, 1.0, 1.0), position: Unipolar(1.0) },
+            ),
+            position: Unipolar(0.0),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Change the number of stops, and queue up messages about the knobs added or removed.
+    /// New stops are white at the top of the gradient.
+    fn set_stop_count(&mut self, count: usize) {
+        while self.stops.len() < count {
+            self.stops.push(Stop { color: Color::rgb(1.0, 1.0, 1.0), position: Unipolar(1.0) });
+            let stop = self.stops.len() - 1;
+            for &(addr, ref desc) in &stop_knob_descs(stop) {
+                self.pending.push(KnobResponse::Added(addr, desc.clone()));
+            }
+        }
+        while self.stops.len() > count {
+            self.stops.pop();
+            let stop = self.stops.len();
+            for &(addr, _) in &stop_knob_descs(stop) {
+                self.pending.push(KnobResponse::Removed(addr));
+            }
+        }
+    }
+
+    /// Interpolate the color at this position in the gradient.
+    fn color_at(&self, Unipolar(position): Unipolar) -> Color {
+        // A NaN position can't be ordered, so treat it as the bottom of the gradient.
+        let mut stops = self.stops.iter().map(|stop| {
+            if stop.position.0.is_nan() {
+                Stop { color: stop.color, position: Unipolar(0.0) }
+            }
+            else {
+                *stop
+            }
+        }).collect::<Vec<_>>();
+        stops.sort_by(|a, b| a.position.partial_cmp(&b.position).unwrap());
+        let first = stops[0];
+        if position <= first.position.0 {
+            return first.color;
+        }
+        for pair in stops.windows(2) {
+            let (lower, upper) = (pair[0], pair[1]);
+            if position <= upper.position.0 {
+                let span = upper.position.0 - lower.position.0;
+                if span <= 0.0 {
+                    return upper.color;
+                }
+                let frac = (position - lower.position.0) / span;
+                return lower.color * Unipolar(1.0 - frac) + upper.color * Unipolar(frac);
+            }
+        }
+        stops[stops.len() - 1].color
+    }
+}
+
+/// Load the stops, rejecting a number of them that we couldn't render or provide knobs for.
+fn deserialize_stops<'de, D>(deserializer: D) -> Result<Vec<Stop>, D::Error>
+    where D: Deserializer<'de>
+{
+    let stops = Vec::<Stop>::deserialize(deserializer)?;
+    if stops.len() < MIN_STOPS || stops.len() > MAX_STOPS {
+        return Err(D::Error::custom(format!(
+            "a gradient needs {} to {} stops, not {}", MIN_STOPS, MAX_STOPS, stops.len())));
+    }
+    Ok(stops)
+}
+
+pub const KIND: &'static str = "gradient";
+
+// Gradient has a single input, the default.
+impl<M, I> Inputs<M, I> for Gradient {}
+
+// Gradient has one output.
+impl<M, I> Outputs<M, I> for Gradient {}
+
+const STOP_COUNT_KNOB_ADDR: KnobAddr = 0;
+const POSITION_KNOB_ADDR: KnobAddr = 1;
+const FIRST_STOP_KNOB_ADDR: KnobAddr = 2;
+
+/// Every stop has a color knob followed by a position knob.
+fn stop_color_knob_addr(stop: usize) -> KnobAddr {
+    FIRST_STOP_KNOB_ADDR + 2 * stop as KnobAddr
+}
+
+fn stop_position_knob_addr(stop: usize) -> KnobAddr {
+    stop_color_knob_addr(stop) + 1
+}
+
+fn stop_knob_descs(stop: usize) -> Vec<(KnobAddr, KnobDescription)> {
+    let desc = |param: &str, datatype| KnobDescription {
+        name: Arc::new(format!("stop {} {}", stop + 1, param)),
+        datatype: KnobDatatype::Wiggle(datatype),
+    };
+    vec!(
+        (stop_color_knob_addr(stop), desc("color", Datatype::Color)),
+        (stop_position_knob_addr(stop), desc("position", Datatype::Unipolar)),
+    )
+}
+
+fn stop_count_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(STOP_COUNTS.clone())
+}
+
+impl Knobs<KnobAddr> for Gradient {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        let mut descs = vec!(
+            (STOP_COUNT_KNOB_ADDR, desc("stops", stop_count_knob_datatype())),
+            (POSITION_KNOB_ADDR, desc("position", KnobDatatype::Wiggle(Datatype::Unipolar))),
+        );
+        for stop in 0..self.stops.len() {
+            descs.extend(stop_knob_descs(stop));
+        }
+        descs
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            STOP_COUNT_KNOB_ADDR => Ok(stop_count_knob_datatype()),
+            POSITION_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            a if a < stop_color_knob_addr(self.stops.len()) => {
+                if (a - FIRST_STOP_KNOB_ADDR) % 2 == 0 {
+                    Ok(KnobDatatype::Wiggle(Datatype::Color))
+                }
+                else {
+                    Ok(KnobDatatype::Wiggle(Datatype::Unipolar))
+                }
+            }
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            STOP_COUNT_KNOB_ADDR => Ok(KnobData::Picker(self.stops.len().to_string())),
+            POSITION_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.position))),
+            _ => {
+                let offset = (addr - FIRST_STOP_KNOB_ADDR) as usize;
+                match self.stops.get(offset / 2) {
+                    Some(stop) if offset % 2 == 0 => Ok(KnobData::Wiggle(Data::Color(stop.color))),
+                    Some(stop) => Ok(KnobData::Wiggle(Data::Unipolar(stop.position))),
+                    None => Err(badaddr(addr)),
+                }
+            }
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            STOP_COUNT_KNOB_ADDR => {
+                let count =
+                    value.as_picker()
+                        .and_then(|p| p.parse::<usize>().map_err(|_| ()))
+                        .and_then(|n| {
+                            if n >= MIN_STOPS && n <= MAX_STOPS { Ok(n) } else { Err(()) }
+                        })
+                        .map_err(|()| badtype(stop_count_knob_datatype(), value))?;
+                self.set_stop_count(count);
+            }
+            POSITION_KNOB_ADDR => self.position = value.as_unipolar()?,
+            _ => {
+                let offset = (addr - FIRST_STOP_KNOB_ADDR) as usize;
+                match self.stops.get_mut(offset / 2) {
+                    Some(stop) if offset % 2 == 0 => stop.color = value.as_color()?,
+                    Some(stop) => stop.position = value.as_unipolar()?,
+                    None => return Err(badaddr(addr)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Gradient {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Gradient is stateless; update only announces knob changes.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        self.pending.drain(..).collect()
+    }
+
+    /// Always render a color; the network converts it if another type was asked for.
+    fn render(
+        &self,
+        phase_offset: f64,
+        _: Option<Datatype>,
+        inputs: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        wiggles: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let position = match inputs.first() {
+            Some(&Some((id, output))) => Unipolar::from(
+                wiggles.get_value(id, output, phase_offset, Some(Datatype::Unipolar), clocks)),
+            _ => self.position,
+        };
+        Data::Color(self.color_at(position).coerce())
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Err(())
+    }
+
+    fn set_clock(&mut self, _: Option<ClockId>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
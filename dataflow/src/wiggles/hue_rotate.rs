@@ -0,0 +1,157 @@
+//! A wiggle that rotates the hue of a color, driven by a clock.
+//! The hue makes one full turn around the color wheel on every cycle of the clock, starting from
+//! the hue of the input color plus the hue offset.  Saturation and value pass through unchanged.
+//! If nothing is connected to the input, the color knob is used as the source color.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider, ClockValue};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Datatype, Data, Color};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct HueRotate {
+    name: String,
+    clock: Option<ClockId>,
+    /// The color to rotate if nothing is connected to the input.
+    color: Color,
+    /// Constant hue shift, in turns around the color wheel.
+    hue_offset: Unipolar,
+}
+
+impl HueRotate {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        HueRotate {
+            name: name.into(),
+            clock: None,
+            color: Color::rgb(1.0, 0.0, 0.0),
+            hue_offset: Unipolar(0.0),
+        }
+    }
+}
+
+pub const KIND: &'static str = "hue rotate";
+
+// HueRotate has a single input, the default.
+impl<M, I> Inputs<M, I> for HueRotate {}
+
+// HueRotate has one output.
+impl<M, I> Outputs<M, I> for HueRotate {}
+
+const COLOR_KNOB_ADDR: KnobAddr = 0;
+const HUE_OFFSET_KNOB_ADDR: KnobAddr = 1;
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        vec!(
+            (COLOR_KNOB_ADDR, desc("color", KnobDatatype::Wiggle(Datatype::Color))),
+            (HUE_OFFSET_KNOB_ADDR, desc("hue offset", KnobDatatype::Wiggle(Datatype::Unipolar))),
+        )
+    };
+}
+
+impl Knobs<KnobAddr> for HueRotate {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            COLOR_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Color)),
+            HUE_OFFSET_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            COLOR_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Color(self.color))),
+            HUE_OFFSET_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.hue_offset))),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            COLOR_KNOB_ADDR => self.color = value.as_color()?,
+            HUE_OFFSET_KNOB_ADDR => self.hue_offset = value.as_unipolar()?,
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for HueRotate {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// HueRotate is driven entirely by its clock, update does nothing.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        Messages::none()
+    }
+
+    /// Always render a color; the network converts it if another type was asked for.
+    fn render(
+        &self,
+        phase_offset: f64,
+        _: Option<Datatype>,
+        inputs: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        wiggles: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let color = match inputs.first() {
+            Some(&Some((id, output))) => Color::from(
+                wiggles.get_value(id, output, phase_offset, Some(Datatype::Color), clocks)),
+            _ => self.color,
+        };
+        let clock_val = match self.clock {
+            Some(cid) => clocks.get_value(cid),
+            None => ClockValue::default(),
+        };
+        let (hue, saturation, value) = color.to_hsv();
+        let shift = clock_val.float_value() + phase_offset + self.hue_offset.0;
+        Data::Color(Color::hsv(hue + shift, saturation, value))
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Ok(self.clock)
+    }
+
+    fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()> {
+        self.clock = source;
+        Ok(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,245 @@
+//! A wiggle that follows its input slowly, to tame sources that move too fast or flicker.
+//! In slew mode, the output moves towards the input at a limited rate: rise and fall are the
+//! time in seconds to sweep across the full range.  In low pass mode, the output follows the
+//! input through a one-pole low pass filter, using rise and fall as the time constants.
+//!
+//! The filter runs in update, so it advances with the fixed update interval and behaves the same
+//! however often we render.  Both the unipolar and bipolar forms of the input are followed, so
+//! downstream wiggles can ask for either.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider, InputValue};
+use wiggles_value::{Datatype, Data};
+use util::secs;
+
+lazy_static! {
+    static ref MODES: Vec<String> = vec!(
+        "slew".to_string(),
+        "low pass".to_string());
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+enum Mode {
+    Slew,
+    LowPass,
+}
+
+impl Mode {
+    fn to_picker(&self) -> &'static str {
+        match *self {
+            Mode::Slew => "slew",
+            Mode::LowPass => "low pass",
+        }
+    }
+
+    fn from_picker(s: &str) -> Result<Self, ()> {
+        match s {
+            "slew" => Ok(Mode::Slew),
+            "low pass" => Ok(Mode::LowPass),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// The running state of the filter, which is not saved with the show.
+struct State {
+    /// The most recent value of the input.
+    input: Option<InputValue>,
+    unipolar: f64,
+    bipolar: f64,
+}
+
+// Two lags with the same settings are the same, wherever their filters happen to be.
+impl PartialEq for State {
+    fn eq(&self, _: &State) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lag {
+    name: String,
+    mode: Mode,
+    /// Rise and fall times, in seconds.
+    rise: f64,
+    fall: f64,
+    #[serde(skip)]
+    state: State,
+}
+
+impl Lag {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Lag {
+            name: name.into(),
+            mode: Mode::Slew,
+            rise: 0.5,
+            fall: 0.5,
+            state: State::default(),
+        }
+    }
+
+    /// Move a value towards its target over the interval dt.
+    /// Range is the size of the full range of the value.
+    fn follow(&self, current: f64, target: f64, dt: f64, range: f64) -> f64 {
+        let time = if target > current { self.rise } else { self.fall };
+        if time <= 0.0 {
+            return target;
+        }
+        match self.mode {
+            Mode::Slew => {
+                let max_step = range * dt / time;
+                current + (target - current).max(-max_step).min(max_step)
+            }
+            Mode::LowPass => current + (target - current) * (1.0 - (-dt / time).exp()),
+        }
+    }
+}
+
+pub const KIND: &'static str = "lag";
+
+// Lag has a single input, the default.
+impl<M, I> Inputs<M, I> for Lag {}
+
+// Lag has one output.
+impl<M, I> Outputs<M, I> for Lag {}
+
+const MODE_KNOB_ADDR: KnobAddr = 0;
+const RISE_KNOB_ADDR: KnobAddr = 1;
+const FALL_KNOB_ADDR: KnobAddr = 2;
+
+fn mode_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(MODES.clone())
+}
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        vec!(
+            (MODE_KNOB_ADDR, desc("mode", mode_knob_datatype())),
+            (RISE_KNOB_ADDR, desc("rise", KnobDatatype::UFloat)),
+            (FALL_KNOB_ADDR, desc("fall", KnobDatatype::UFloat)),
+        )
+    };
+}
+
+impl Knobs<KnobAddr> for Lag {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            MODE_KNOB_ADDR => Ok(mode_knob_datatype()),
+            RISE_KNOB_ADDR | FALL_KNOB_ADDR => Ok(KnobDatatype::UFloat),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            MODE_KNOB_ADDR => Ok(KnobData::Picker(self.mode.to_picker().to_string())),
+            RISE_KNOB_ADDR => Ok(KnobData::UFloat(self.rise)),
+            FALL_KNOB_ADDR => Ok(KnobData::UFloat(self.fall)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            MODE_KNOB_ADDR => {
+                self.mode =
+                    value.as_picker()
+                        .and_then(|p| Mode::from_picker(&p))
+                        .map_err(|()| badtype(mode_knob_datatype(), value))?;
+            }
+            RISE_KNOB_ADDR => self.rise = value.as_ufloat()?,
+            FALL_KNOB_ADDR => self.fall = value.as_ufloat()?,
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Lag {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    fn samples_inputs(&self) -> bool {
+        true
+    }
+
+    fn sample_inputs(&mut self, values: &[Option<InputValue>]) {
+        self.state.input = values.first().and_then(|v| *v);
+    }
+
+    /// Move the output towards the most recent value of the input.
+    /// A disconnected input is treated as 0.
+    fn update(&mut self, dt: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        let dt = secs(dt);
+        let (unipolar, bipolar) = match self.state.input {
+            Some(input) => (input.unipolar.0, input.bipolar.0),
+            None => (0.0, 0.0),
+        };
+        self.state.unipolar = self.follow(self.state.unipolar, unipolar, dt, 1.0);
+        self.state.bipolar = self.follow(self.state.bipolar, bipolar, dt, 2.0);
+        Messages::none()
+    }
+
+    fn render(
+        &self,
+        _: f64,
+        type_hint: Option<Datatype>,
+        _: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        _: &WiggleProvider,
+        _: &ClockProvider)
+        -> Data
+    {
+        match type_hint {
+            Some(t) if t.is_centered() => Data::bipolar(self.state.bipolar),
+            _ => Data::unipolar(self.state.unipolar),
+        }
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Err(())
+    }
+
+    fn set_clock(&mut self, _: Option<ClockId>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,286 @@
+//! A low-frequency oscillator, the basic clock-driven generator wiggle.
+//! Renders one of the standard waveforms from its assigned clock, with controls for duty cycle,
+//! edge smoothing, amplitude, offset, phase offset, and pulse mode.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider, ClockValue};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Bipolar, Datatype, Data};
+use waveforms;
+
+lazy_static! {
+    static ref WAVEFORMS: Vec<String> = vec!(
+        "sine".to_string(),
+        "triangle".to_string(),
+        "sawtooth".to_string(),
+        "square".to_string(),
+        "exp ramp up".to_string(),
+        "exp ramp down".to_string());
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+enum Waveform {
+    Sine,
+    Triangle,
+    Sawtooth,
+    Square,
+    ExpRampUp,
+    ExpRampDown,
+}
+
+impl Waveform {
+    fn to_picker(&self) -> &'static str {
+        match *self {
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+            Waveform::Square => "square",
+            Waveform::ExpRampUp => "exp ramp up",
+            Waveform::ExpRampDown => "exp ramp down",
+        }
+    }
+
+    fn from_picker(s: &str) -> Result<Self, ()> {
+        match s {
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" => Ok(Waveform::Sawtooth),
+            "square" => Ok(Waveform::Square),
+            "exp ramp up" => Ok(Waveform::ExpRampUp),
+            "exp ramp down" => Ok(Waveform::ExpRampDown),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        Waveform::Sine
+    }
+}
+
+// Defaults for parameters that may be missing from older saved shows.
+fn default_duty_cycle() -> Unipolar { Unipolar(1.0) }
+fn default_amplitude() -> Unipolar { Unipolar(1.0) }
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lfo {
+    name: String,
+    clock: Option<ClockId>,
+    #[serde(default)]
+    waveform: Waveform,
+    #[serde(default = "default_duty_cycle")]
+    duty_cycle: Unipolar,
+    /// Edge smoothing, only applied to the square wave.
+    #[serde(default)]
+    smoothing: Unipolar,
+    #[serde(default = "default_amplitude")]
+    amplitude: Unipolar,
+    /// DC offset added after scaling by amplitude.
+    #[serde(default)]
+    offset: Bipolar,
+    /// Constant phase offset, added to any offset applied by downstream wiggles.
+    #[serde(default)]
+    phase_offset: Unipolar,
+    #[serde(default)]
+    pulse: bool,
+}
+
+impl Lfo {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Lfo {
+            name: name.into(),
+            clock: None,
+            waveform: Waveform::Sine,
+            duty_cycle: default_duty_cycle(),
+            smoothing: Unipolar(0.0),
+            amplitude: default_amplitude(),
+            offset: Bipolar(0.0),
+            phase_offset: Unipolar(0.0),
+            pulse: false,
+        }
+    }
+
+    /// Evaluate the selected waveform at this angle.
+    fn waveform_value(&self, angle: Unipolar, type_hint: Option<Datatype>) -> Data {
+        let duty = self.duty_cycle;
+        let pulse = self.pulse;
+        match self.waveform {
+            Waveform::Sine => waveforms::sine(angle, duty, pulse, type_hint),
+            Waveform::Triangle => waveforms::triangle(angle, duty, pulse, type_hint),
+            Waveform::Sawtooth => waveforms::sawtooth(angle, duty, pulse, type_hint),
+            Waveform::Square =>
+                waveforms::smooth_square(angle, self.smoothing, duty, pulse, type_hint),
+            Waveform::ExpRampUp => waveforms::exp_ramp_up(angle, duty, pulse, type_hint),
+            Waveform::ExpRampDown => waveforms::exp_ramp_down(angle, duty, pulse, type_hint),
+        }
+    }
+}
+
+pub const KIND: &'static str = "lfo";
+
+// Lfo has no inputs.
+impl<M, I> Inputs<M, I> for Lfo {
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+// Lfo has one output.
+impl<M, I> Outputs<M, I> for Lfo {}
+
+const WAVEFORM_KNOB_ADDR: KnobAddr = 0;
+const DUTY_KNOB_ADDR: KnobAddr = 1;
+const SMOOTHING_KNOB_ADDR: KnobAddr = 2;
+const AMPLITUDE_KNOB_ADDR: KnobAddr = 3;
+const OFFSET_KNOB_ADDR: KnobAddr = 4;
+const PHASE_OFFSET_KNOB_ADDR: KnobAddr = 5;
+const PULSE_KNOB_ADDR: KnobAddr = 6;
+
+fn waveform_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(WAVEFORMS.clone())
+}
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        vec!(
+            (WAVEFORM_KNOB_ADDR, desc("waveform", waveform_knob_datatype())),
+            (DUTY_KNOB_ADDR, desc("duty cycle", KnobDatatype::Wiggle(Datatype::Unipolar))),
+            (SMOOTHING_KNOB_ADDR, desc("smoothing", KnobDatatype::Wiggle(Datatype::Unipolar))),
+            (AMPLITUDE_KNOB_ADDR, desc("amplitude", KnobDatatype::Wiggle(Datatype::Unipolar))),
+            (OFFSET_KNOB_ADDR, desc("offset", KnobDatatype::Wiggle(Datatype::Bipolar))),
+            (PHASE_OFFSET_KNOB_ADDR, desc("phase offset", KnobDatatype::Wiggle(Datatype::Unipolar))),
+            (PULSE_KNOB_ADDR, desc("pulse", KnobDatatype::Button)),
+        )
+    };
+}
+
+impl Knobs<KnobAddr> for Lfo {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            WAVEFORM_KNOB_ADDR => Ok(waveform_knob_datatype()),
+            DUTY_KNOB_ADDR |
+            SMOOTHING_KNOB_ADDR |
+            AMPLITUDE_KNOB_ADDR |
+            PHASE_OFFSET_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            OFFSET_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Bipolar)),
+            PULSE_KNOB_ADDR => Ok(KnobDatatype::Button),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            WAVEFORM_KNOB_ADDR => Ok(KnobData::Picker(self.waveform.to_picker().to_string())),
+            DUTY_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.duty_cycle))),
+            SMOOTHING_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.smoothing))),
+            AMPLITUDE_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.amplitude))),
+            OFFSET_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Bipolar(self.offset))),
+            PHASE_OFFSET_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.phase_offset))),
+            PULSE_KNOB_ADDR => Ok(KnobData::Button(self.pulse)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            WAVEFORM_KNOB_ADDR => {
+                self.waveform =
+                    value.as_picker()
+                        .and_then(|p| Waveform::from_picker(&p))
+                        .map_err(|()| badtype(waveform_knob_datatype(), value))?;
+            }
+            DUTY_KNOB_ADDR => self.duty_cycle = value.as_unipolar()?,
+            SMOOTHING_KNOB_ADDR => self.smoothing = value.as_unipolar()?,
+            AMPLITUDE_KNOB_ADDR => self.amplitude = value.as_unipolar()?,
+            OFFSET_KNOB_ADDR => self.offset = value.as_bipolar()?,
+            PHASE_OFFSET_KNOB_ADDR => self.phase_offset = value.as_unipolar()?,
+            PULSE_KNOB_ADDR => self.pulse = value.as_button()?,
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Lfo {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Lfo is driven entirely by its clock, update does nothing.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        Messages::none()
+    }
+
+    fn render(
+        &self,
+        phase_offset: f64,
+        type_hint: Option<Datatype>,
+        _: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        _: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let clock_val = match self.clock {
+            Some(cid) => clocks.get_value(cid),
+            None => ClockValue::default(),
+        };
+        let angle = clock_val.phase_shift(phase_offset + self.phase_offset.0);
+        let scaled = self.waveform_value(angle, type_hint) * self.amplitude;
+        match scaled {
+            Data::Unipolar(Unipolar(v)) => Data::unipolar(v + self.offset.0).coerce(),
+            Data::Bipolar(Bipolar(v)) => Data::bipolar(v + self.offset.0).coerce(),
+            other => (other + Data::Bipolar(self.offset)).coerce(),
+        }
+    }
+
+    /// Return Ok if this wiggle uses a clock input, and return the current value of it.
+    /// If it doesn't use a clock, return Err.
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Ok(self.clock)
+    }
+
+    /// Set the clock source for this wiggle.
+    /// If this wiggle doesn't use a clock, return Err.
+    fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()> {
+        self.clock = source;
+        Ok(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
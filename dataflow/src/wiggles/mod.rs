@@ -1,14 +1,28 @@
-use std::collections::HashMap;
-use serde::Deserializer;
+use std::sync::RwLock;
+use serde::de::DeserializeOwned;
 use serde_json::{self, Error as SerdeJsonError};
 use self::serde::SerializableWiggle;
-use serde::de::Error;
+use registry::{Registry, RegistryError};
 
 pub mod wiggle;
 mod serde;
+#[cfg(test)]
 pub mod trial;
+pub mod lfo;
 pub mod blender;
 pub mod fanner;
+pub mod expression;
+pub mod envelope;
+pub mod sequencer;
+pub mod noise;
+pub mod sample_hold;
+pub mod lag;
+pub mod crossfader;
+pub mod convert;
+pub mod hue_rotate;
+pub mod gradient;
+pub mod palette;
+pub mod dmx_input;
 
 pub use self::wiggle::{
     Wiggle,
@@ -16,69 +30,79 @@ pub use self::wiggle::{
     CompleteWiggle,
     WiggleKnobAddr,
     WiggleNetwork,
+    LegacyWiggleNetwork,
     KnobAddr,
     WiggleCollection,
     WiggleProvider,
+    InputValue,
 };
 
 // Gather every wiggle declaration up here.
-// We could potentially make this mutable and provide a registration function if we want to be able
-// to load wiggle defintions after compile time.  For now, we'll just keep it static.
-// This collection serves as both a registry to every kind of wiggle and how it is created, and
-// enables serialization and deserialization of those wiggles once they are hidden behind trait
-// objects.
+// This registry knows about every kind of wiggle and how it is created, and enables serialization
+// and deserialization of those wiggles once they are hidden behind trait objects.
+// The built-in wiggles are registered here; other crates can add their own using register.
 
 lazy_static! {
-    pub static ref WIGGLES: Vec<&'static str> = vec!(
-        trial::KIND,
-        blender::KIND,
-        fanner::KIND,
-    );
+    static ref REGISTRY: RwLock<Registry<CompleteWiggle>> = {
+        let mut registry = Registry::new("wiggle");
+        add(&mut registry, lfo::KIND, lfo::Lfo::new).unwrap();
+        add(&mut registry, blender::KIND, blender::Blender::new).unwrap();
+        add(&mut registry, fanner::KIND, fanner::Fanner::new).unwrap();
+        add(&mut registry, expression::KIND, expression::Expression::new).unwrap();
+        add(&mut registry, envelope::KIND, envelope::Envelope::new).unwrap();
+        add(&mut registry, sequencer::KIND, sequencer::Sequencer::new).unwrap();
+        add(&mut registry, noise::STEPPED_KIND, noise::Noise::stepped).unwrap();
+        add(&mut registry, noise::SMOOTH_KIND, noise::Noise::smooth).unwrap();
+        add(&mut registry, sample_hold::KIND, sample_hold::SampleHold::new).unwrap();
+        add(&mut registry, lag::KIND, lag::Lag::new).unwrap();
+        add(&mut registry, crossfader::KIND, crossfader::Crossfader::new).unwrap();
+        add(&mut registry, convert::KIND, convert::Convert::new).unwrap();
+        add(&mut registry, hue_rotate::KIND, hue_rotate::HueRotate::new).unwrap();
+        add(&mut registry, gradient::KIND, gradient::Gradient::new).unwrap();
+        add(&mut registry, palette::KIND, palette::Palette::new).unwrap();
+        add(&mut registry, dmx_input::KIND, dmx_input::DmxInput::new).unwrap();
+        RwLock::new(registry)
+    };
+}
+
+fn add<W, F>(
+    registry: &mut Registry<CompleteWiggle>,
+    kind: &'static str,
+    constructor: F)
+    -> Result<(), RegistryError>
+    where W: 'static + CompleteWiggle + DeserializeOwned,
+          F: 'static + Fn(String) -> W + Send + Sync
+{
+    registry.register(
+        kind,
+        Box::new(move |name| Box::new(constructor(name))),
+        Box::new(|data| {
+            let wiggle: W = serde_json::from_value(data)?;
+            Ok(Box::new(wiggle))
+        }))
+}
+
+/// Register a new kind of wiggle, using a function that creates a new wiggle given a name.
+/// The kind must match the one the wiggle reports, as that is how it will be deserialized.
+pub fn register<W, F>(kind: &'static str, constructor: F) -> Result<(), RegistryError>
+    where W: 'static + CompleteWiggle + DeserializeOwned,
+          F: 'static + Fn(String) -> W + Send + Sync
+{
+    add(&mut REGISTRY.write().unwrap(), kind, constructor)
+}
+
+/// List every registered kind of wiggle.
+pub fn kinds() -> Vec<&'static str> {
+    REGISTRY.read().unwrap().kinds().to_vec()
 }
 
 /// Return an initialized wiggle with the provided name, if the kind matches a registered one.
 /// Return None if the kind is unknown.
 pub fn new_wiggle<N: Into<String>>(kind: &str, name: N) -> Option<Box<CompleteWiggle>> {
-    match kind {
-        trial::KIND => {
-            Some(Box::new(trial::TestWiggle::new(name)))
-        }
-        blender::KIND => {
-            Some(Box::new(blender::Blender::new(name)))
-        }
-        fanner::KIND => {
-            Some(Box::new(fanner::Fanner::new(name)))
-        }
-        _ => None,
-    }
+    REGISTRY.read().unwrap().create(kind, name.into())
 }
 
-/// Deserialize a wiggle that has been serialized using our janky mechanism.
-pub fn deserialize(wiggle: SerializableWiggle) -> Result<Box<CompleteWiggle>, SerdeJsonError>
-{
-    match wiggle.kind.as_str() {
-        trial::KIND => {
-            let result: Result<trial::TestWiggle, _> = serde_json::from_str(&wiggle.serialized); 
-            handle_deserialize_result(result)
-        }
-        blender::KIND => {
-            let result: Result<blender::Blender, _> = serde_json::from_str(&wiggle.serialized); 
-            handle_deserialize_result(result)
-        }
-        fanner::KIND => {
-            let result: Result<fanner::Fanner, _> = serde_json::from_str(&wiggle.serialized); 
-            handle_deserialize_result(result)
-        }
-        _ => Err(SerdeJsonError::custom(format!("Unknown wiggle kind: '{}'.", wiggle.kind))),
-    }
+/// Deserialize a wiggle from its kind and data, using whatever was registered for that kind.
+pub fn deserialize(wiggle: SerializableWiggle) -> Result<Box<CompleteWiggle>, SerdeJsonError> {
+    REGISTRY.read().unwrap().deserialize(&wiggle.kind, wiggle.data.0)
 }
-
-fn handle_deserialize_result<T>(
-    result: Result<T, SerdeJsonError>) -> Result<Box<CompleteWiggle>, SerdeJsonError>
-    where T: 'static + CompleteWiggle
-{
-    match result {
-        Ok(deserialized) => Ok(Box::new(deserialized)),
-        Err(e) => Err(SerdeJsonError::custom(e)),
-    }
-}
\ No newline at end of file
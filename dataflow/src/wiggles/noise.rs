@@ -0,0 +1,181 @@
+//! Random wiggles: stepped random values, which jump to a new value on every tick of the clock,
+//! and smooth value noise, which glides from one random value to the next over each tick.
+//! The values are a pure function of a seed and the clock, so the same show renders the same
+//! way on every machine, and phase offsets pick out neighboring values coherently.
+//! The seed is saved with the show; the seed knob picks a new one.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider, ClockValue};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Datatype, Data};
+use util::{random_unit, random_seed};
+
+pub const STEPPED_KIND: &'static str = "random";
+pub const SMOOTH_KIND: &'static str = "noise";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Noise {
+    name: String,
+    clock: Option<ClockId>,
+    seed: u64,
+    /// Glide between values instead of jumping.
+    smooth: bool,
+}
+
+impl Noise {
+    /// Random values that jump on every tick.
+    pub fn stepped<N: Into<String>>(name: N) -> Self {
+        Noise::with_seed(name, false, random_seed())
+    }
+
+    /// Value noise that glides from one random value to the next.
+    pub fn smooth<N: Into<String>>(name: N) -> Self {
+        Noise::with_seed(name, true, random_seed())
+    }
+
+    pub fn with_seed<N: Into<String>>(name: N, smooth: bool, seed: u64) -> Self {
+        Noise {
+            name: name.into(),
+            clock: None,
+            seed: seed,
+            smooth: smooth,
+        }
+    }
+
+    /// The value of the noise at a position along the clock, on [0.0, 1.0).
+    fn value_at(&self, position: f64) -> f64 {
+        let tick = position.floor();
+        let current = random_unit(self.seed, tick as i64);
+        if !self.smooth {
+            return current;
+        }
+        let next = random_unit(self.seed, tick as i64 + 1);
+        // Ease in and out of each value so the noise has no corners.
+        let t = position - tick;
+        let t = t * t * (3.0 - 2.0 * t);
+        current + (next - current) * t
+    }
+}
+
+// Noise has no inputs.
+impl<M, I> Inputs<M, I> for Noise {
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+// Noise has one output.
+impl<M, I> Outputs<M, I> for Noise {}
+
+const NEW_SEED_KNOB_ADDR: KnobAddr = 0;
+
+// Unchanging collection of knobs.
+lazy_static! {
+    static ref KNOB_DESC: Vec<(KnobAddr, KnobDescription)> = vec!(
+        (NEW_SEED_KNOB_ADDR, KnobDescription {
+            name: Arc::new("new seed".to_string()),
+            datatype: KnobDatatype::Button,
+        }),
+    );
+}
+
+impl Knobs<KnobAddr> for Noise {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        KNOB_DESC.clone()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            NEW_SEED_KNOB_ADDR => Ok(KnobDatatype::Button),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            NEW_SEED_KNOB_ADDR => Ok(KnobData::Button(false)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Pressing the seed button picks a new seed.
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            NEW_SEED_KNOB_ADDR => {
+                if value.as_button()? {
+                    self.seed = random_seed();
+                }
+            }
+            _ => return Err(badaddr(addr)),
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Noise {
+    fn kind(&self) -> &'static str {
+        if self.smooth { SMOOTH_KIND } else { STEPPED_KIND }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Noise is driven entirely by its clock, update does nothing.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        Messages::none()
+    }
+
+    /// Bipolar noise covers the whole bipolar range.
+    fn render(
+        &self,
+        phase_offset: f64,
+        type_hint: Option<Datatype>,
+        _: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        _: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let clock_val = match self.clock {
+            Some(cid) => clocks.get_value(cid),
+            None => ClockValue::default(),
+        };
+        let val = self.value_at(clock_val.float_value() + phase_offset);
+        match type_hint {
+            Some(t) if t.is_centered() => Data::bipolar(2.0 * val - 1.0),
+            _ => Data::unipolar(val),
+        }
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Ok(self.clock)
+    }
+
+    fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()> {
+        self.clock = source;
+        Ok(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
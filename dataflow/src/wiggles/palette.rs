@@ -0,0 +1,255 @@
+//! A wiggle that picks one color out of a user-defined palette.
+//! The selected knob picks the color; if something is connected to the input, it does the
+//! picking instead, read as an index into the palette.  A unipolar source such as an LFO or a
+//! sequencer will sweep across the whole palette.
+use std::cmp::min;
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeserializeError;
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Datatype, Data, Color, Index};
+
+/// The largest palette we support.
+const MAX_COLORS: usize = 16;
+
+lazy_static! {
+    static ref COLOR_COUNTS: Vec<String> = (1..MAX_COLORS+1).map(|n| n.to_string()).collect();
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    name: String,
+    /// Palette colors, each controlled by a knob.
+    /// Knob addresses for the colors start from FIRST_COLOR_KNOB_ADDR.
+    #[serde(deserialize_with="deserialize_colors")]
+    colors: Vec<Color>,
+    /// Index of the color to render if nothing is connected to the input.
+    selected: usize,
+    /// Knob changes caused by changing the number of colors, to be sent out on the next update.
+    #[serde(skip)]
+    pending: Vec<KnobResponse<KnobAddr>>,
+}
+
+impl Palette {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Palette {
+            name: name.into(),
+            colors: vec!(
+                Color::rgb(1.0, 0.0, 0.0),
+                Color::rgb(0.0, 1.0, 0.0),
+                Color::rgb(0.0, 0.0, 1.0),
+                Color::rgb(1.0, 1.0, 1.0),
+            ),
+            selected: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Change the number of colors, and queue up messages about the knobs added or removed.
+    /// If the selected color is removed, select the last remaining one.
+    fn set_color_count(&mut self, count: usize) {
+        while self.colors.len() < count {
+            self.colors.push(Color::rgb(1.0, 1.0, 1.0));
+            let addr = color_knob_addr(self.colors.len() - 1);
+            self.pending.push(KnobResponse::Added(addr, color_knob_desc(addr)));
+        }
+        while self.colors.len() > count {
+            self.colors.pop();
+            self.pending.push(KnobResponse::Removed(color_knob_addr(self.colors.len())));
+        }
+        if self.selected >= count {
+            self.selected = count - 1;
+            self.pending.push(KnobResponse::ValueChange(
+                SELECTED_KNOB_ADDR, KnobData::Picker(selected_to_picker(self.selected))));
+        }
+    }
+}
+
+/// Load the colors, rejecting a number of them that we couldn't render or provide knobs for.
+fn deserialize_colors<'de, D>(deserializer: D) -> Result<Vec<Color>, D::Error>
+    where D: Deserializer<'de>
+{
+    let colors = Vec::<Color>::deserialize(deserializer)?;
+    if colors.is_empty() || colors.len() > MAX_COLORS {
+        return Err(D::Error::custom(format!(
+            "a palette needs 1 to {} colors, not {}", MAX_COLORS, colors.len())));
+    }
+    Ok(colors)
+}
+
+pub const KIND: &'static str = "palette";
+
+// Palette has a single input, the default.
+impl<M, I> Inputs<M, I> for Palette {}
+
+// Palette has one output.
+impl<M, I> Outputs<M, I> for Palette {}
+
+const COLOR_COUNT_KNOB_ADDR: KnobAddr = 0;
+const SELECTED_KNOB_ADDR: KnobAddr = 1;
+const FIRST_COLOR_KNOB_ADDR: KnobAddr = 2;
+
+fn color_knob_addr(color: usize) -> KnobAddr {
+    FIRST_COLOR_KNOB_ADDR + color as KnobAddr
+}
+
+fn color_knob_desc(addr: KnobAddr) -> KnobDescription {
+    KnobDescription {
+        name: Arc::new(format!("color {}", addr - FIRST_COLOR_KNOB_ADDR + 1)),
+        datatype: KnobDatatype::Wiggle(Datatype::Color),
+    }
+}
+
+/// Both the color count and the selected color are picked from the same list of numbers.
+/// Selections outside of the current palette are rejected.
+fn count_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(COLOR_COUNTS.clone())
+}
+
+/// Selections are shown to the user counting from 1.
+fn selected_to_picker(selected: usize) -> String {
+    (selected + 1).to_string()
+}
+
+/// Parse a number from a picker, ensuring it is on the range [1, max].
+fn parse_picker(value: &KnobData, max: usize) -> Result<usize, ()> {
+    value.as_picker()
+        .and_then(|p| p.parse::<usize>().map_err(|_| ()))
+        .and_then(|n| if n >= 1 && n <= max { Ok(n) } else { Err(()) })
+}
+
+impl Knobs<KnobAddr> for Palette {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        let desc = |name: &str| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: count_knob_datatype(),
+        };
+        let mut descs = vec!(
+            (COLOR_COUNT_KNOB_ADDR, desc("colors")),
+            (SELECTED_KNOB_ADDR, desc("selected")),
+        );
+        for color in 0..self.colors.len() {
+            let addr = color_knob_addr(color);
+            descs.push((addr, color_knob_desc(addr)));
+        }
+        descs
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            COLOR_COUNT_KNOB_ADDR | SELECTED_KNOB_ADDR => Ok(count_knob_datatype()),
+            a if a < color_knob_addr(self.colors.len()) =>
+                Ok(KnobDatatype::Wiggle(Datatype::Color)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            COLOR_COUNT_KNOB_ADDR => Ok(KnobData::Picker(self.colors.len().to_string())),
+            SELECTED_KNOB_ADDR => Ok(KnobData::Picker(selected_to_picker(self.selected))),
+            _ => {
+                match self.colors.get((addr - FIRST_COLOR_KNOB_ADDR) as usize) {
+                    Some(color) => Ok(KnobData::Wiggle(Data::Color(*color))),
+                    None => Err(badaddr(addr)),
+                }
+            }
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            COLOR_COUNT_KNOB_ADDR => {
+                let count =
+                    parse_picker(&value, MAX_COLORS)
+                        .map_err(|()| badtype(count_knob_datatype(), value))?;
+                self.set_color_count(count);
+            }
+            SELECTED_KNOB_ADDR => {
+                let selected =
+                    parse_picker(&value, self.colors.len())
+                        .map_err(|()| badtype(count_knob_datatype(), value))?;
+                self.selected = selected - 1;
+            }
+            _ => {
+                match self.colors.get_mut((addr - FIRST_COLOR_KNOB_ADDR) as usize) {
+                    Some(color) => *color = value.as_color()?,
+                    None => return Err(badaddr(addr)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Palette {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Palette is stateless; update only announces knob changes.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        self.pending.drain(..).collect()
+    }
+
+    /// Always render a color; the network converts it if another type was asked for.
+    fn render(
+        &self,
+        phase_offset: f64,
+        _: Option<Datatype>,
+        inputs: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        wiggles: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let count = self.colors.len() as u32;
+        let selected = match inputs.first() {
+            Some(&Some((id, output))) => {
+                let hint = Some(Datatype::Index(count));
+                let input = wiggles.get_value(id, output, phase_offset, hint, clocks);
+                Index::from_data(input, count).index as usize
+            }
+            // a saved selection may be past the end of the palette; use the last color
+            _ => min(self.selected, self.colors.len() - 1),
+        };
+        Data::Color(self.colors[selected])
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Err(())
+    }
+
+    fn set_clock(&mut self, _: Option<ClockId>) -> Result<(), ()> {
+        Err(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
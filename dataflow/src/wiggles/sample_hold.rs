@@ -0,0 +1,122 @@
+//! Sample and hold: on every tick of the clock, take the value of the input and hold it until
+//! the next tick.
+//! Rather than remembering the sampled value, we ask the input for its value at the moment of
+//! the last tick by rendering it with the phase offset wound back to the tick.  This keeps the
+//! output a pure function of the clock, so phase offsets stay coherent.  The sample is exact for
+//! inputs driven by the same clock.
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Datatype, Data};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SampleHold {
+    name: String,
+    clock: Option<ClockId>,
+}
+
+impl SampleHold {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        SampleHold {
+            name: name.into(),
+            clock: None,
+        }
+    }
+}
+
+pub const KIND: &'static str = "sample and hold";
+
+// Sample and hold has a single input, the default.
+impl<M, I> Inputs<M, I> for SampleHold {}
+
+// Sample and hold has one output.
+impl<M, I> Outputs<M, I> for SampleHold {}
+
+// Sample and hold has no knobs.
+impl Knobs<KnobAddr> for SampleHold {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        Vec::new()
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        Err(badaddr(addr))
+    }
+
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        Err(badaddr(addr))
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, _: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        Err(badaddr(addr))
+    }
+}
+
+impl Wiggle for SampleHold {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Sample and hold is driven entirely by its clock, update does nothing.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        Messages::none()
+    }
+
+    /// Render the input as it was at the last tick.
+    /// Without a clock, the input passes straight through.
+    fn render(
+        &self,
+        phase_offset: f64,
+        type_hint: Option<Datatype>,
+        inputs: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        wiggles: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let (id, output) = match inputs.first() {
+            Some(&Some(input)) => input,
+            _ => return Data::default_with_type_hint(type_hint),
+        };
+        let clock_val = match self.clock {
+            Some(cid) => clocks.get_value(cid),
+            None => return wiggles.get_value(id, output, phase_offset, type_hint, clocks),
+        };
+        let position = clock_val.float_value() + phase_offset;
+        let since_tick = position - position.floor();
+        wiggles.get_value(id, output, phase_offset - since_tick, type_hint, clocks)
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Ok(self.clock)
+    }
+
+    fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()> {
+        self.clock = source;
+        Ok(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -0,0 +1,307 @@
+//! A step sequencer, which plays back a list of values, moving to the next one on every tick of
+//! its clock.
+//! The current step is worked out from the clock's tick count rather than being counted up as
+//! the clock ticks, so the sequencer plays back the same way every time and phase offsets pick
+//! out neighboring steps coherently.
+use std::sync::Arc;
+use std::time::Duration;
+use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
+use network::{OutputId, Inputs, Outputs};
+use wiggles_value::knob::{
+    Knobs,
+    Datatype as KnobDatatype,
+    Data as KnobData,
+    KnobDescription,
+    Error as KnobError,
+    badaddr,
+    badtype,
+    Response as KnobResponse,
+};
+use serde_json::{Error as SerdeJsonError, self};
+use clocks::clock::{ClockId, ClockProvider, ClockValue};
+use super::wiggle::{Wiggle, WiggleId, KnobAddr, WiggleProvider};
+use wiggles_value::{Unipolar, Datatype, Data};
+use util::hash;
+
+/// The longest sequence we support.
+const MAX_STEPS: usize = 32;
+
+lazy_static! {
+    static ref PLAYBACK_MODES: Vec<String> = vec!(
+        "forward".to_string(),
+        "reverse".to_string(),
+        "ping pong".to_string(),
+        "random".to_string());
+
+    static ref STEP_COUNTS: Vec<String> = (1..MAX_STEPS+1).map(|n| n.to_string()).collect();
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+enum Playback {
+    Forward,
+    Reverse,
+    /// Play forward then backward, without repeating the end steps.
+    PingPong,
+    /// Pick a step at random on every tick.
+    Random,
+}
+
+impl Playback {
+    fn to_picker(&self) -> &'static str {
+        match *self {
+            Playback::Forward => "forward",
+            Playback::Reverse => "reverse",
+            Playback::PingPong => "ping pong",
+            Playback::Random => "random",
+        }
+    }
+
+    fn from_picker(s: &str) -> Result<Self, ()> {
+        match s {
+            "forward" => Ok(Playback::Forward),
+            "reverse" => Ok(Playback::Reverse),
+            "ping pong" => Ok(Playback::PingPong),
+            "random" => Ok(Playback::Random),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Integer modulus that is always positive.
+fn modulo(a: i64, b: i64) -> i64 {
+    ((a % b) + b) % b
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sequencer {
+    name: String,
+    clock: Option<ClockId>,
+    /// Step values, each controlled by a knob.
+    /// Knob addresses for the steps start from FIRST_STEP_KNOB_ADDR.
+    steps: Vec<Unipolar>,
+    playback: Playback,
+    /// Fraction of each step spent gliding to the next one.
+    glide: Unipolar,
+    /// Knob changes caused by changing the number of steps, to be sent out on the next update.
+    #[serde(skip)]
+    pending: Vec<KnobResponse<KnobAddr>>,
+}
+
+impl Sequencer {
+    pub fn new<N: Into<String>>(name: N) -> Self {
+        Sequencer {
+            name: name.into(),
+            clock: None,
+            steps: vec!(Unipolar(1.0), Unipolar(0.0), Unipolar(0.0), Unipolar(0.0)),
+            playback: Playback::Forward,
+            glide: Unipolar(0.0),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Which step is playing on this tick?
+    fn step_index(&self, tick: i64) -> usize {
+        let n = self.steps.len() as i64;
+        let index = match self.playback {
+            Playback::Forward => modulo(tick, n),
+            Playback::Reverse => n - 1 - modulo(tick, n),
+            Playback::PingPong if n == 1 => 0,
+            Playback::PingPong => {
+                let period = 2 * (n - 1);
+                let t = modulo(tick, period);
+                if t < n { t } else { period - t }
+            }
+            Playback::Random => (hash(tick as u64) % (n as u64)) as i64,
+        };
+        index as usize
+    }
+
+    /// Change the number of steps, and queue up messages about the knobs added or removed.
+    fn set_step_count(&mut self, count: usize) {
+        while self.steps.len() < count {
+            self.steps.push(Unipolar(0.0));
+            let addr = step_knob_addr(self.steps.len() - 1);
+            self.pending.push(KnobResponse::Added(addr, step_knob_desc(addr)));
+        }
+        while self.steps.len() > count {
+            self.steps.pop();
+            self.pending.push(KnobResponse::Removed(step_knob_addr(self.steps.len())));
+        }
+    }
+}
+
+pub const KIND: &'static str = "sequencer";
+
+// Sequencer has no inputs.
+impl<M, I> Inputs<M, I> for Sequencer {
+    fn default_input_count(&self) -> u32 {
+        0
+    }
+}
+
+// Sequencer has one output.
+impl<M, I> Outputs<M, I> for Sequencer {}
+
+const PLAYBACK_KNOB_ADDR: KnobAddr = 0;
+const GLIDE_KNOB_ADDR: KnobAddr = 1;
+const STEP_COUNT_KNOB_ADDR: KnobAddr = 2;
+const FIRST_STEP_KNOB_ADDR: KnobAddr = 3;
+
+fn step_knob_addr(step: usize) -> KnobAddr {
+    FIRST_STEP_KNOB_ADDR + step as KnobAddr
+}
+
+fn step_knob_desc(addr: KnobAddr) -> KnobDescription {
+    KnobDescription {
+        name: Arc::new(format!("step {}", addr - FIRST_STEP_KNOB_ADDR + 1)),
+        datatype: KnobDatatype::Wiggle(Datatype::Unipolar),
+    }
+}
+
+fn playback_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(PLAYBACK_MODES.clone())
+}
+
+fn step_count_knob_datatype() -> KnobDatatype {
+    KnobDatatype::Picker(STEP_COUNTS.clone())
+}
+
+impl Knobs<KnobAddr> for Sequencer {
+    fn knobs(&self) -> Vec<(KnobAddr, KnobDescription)> {
+        let desc = |name: &str, datatype| KnobDescription {
+            name: Arc::new(name.to_string()),
+            datatype: datatype,
+        };
+        let mut descs = vec!(
+            (PLAYBACK_KNOB_ADDR, desc("playback", playback_knob_datatype())),
+            (GLIDE_KNOB_ADDR, desc("glide", KnobDatatype::Wiggle(Datatype::Unipolar))),
+            (STEP_COUNT_KNOB_ADDR, desc("steps", step_count_knob_datatype())),
+        );
+        for step in 0..self.steps.len() {
+            let addr = step_knob_addr(step);
+            descs.push((addr, step_knob_desc(addr)));
+        }
+        descs
+    }
+
+    fn knob_datatype(&self, addr: KnobAddr) -> Result<KnobDatatype, KnobError<KnobAddr>> {
+        match addr {
+            PLAYBACK_KNOB_ADDR => Ok(playback_knob_datatype()),
+            GLIDE_KNOB_ADDR => Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            STEP_COUNT_KNOB_ADDR => Ok(step_count_knob_datatype()),
+            a if a < step_knob_addr(self.steps.len()) =>
+                Ok(KnobDatatype::Wiggle(Datatype::Unipolar)),
+            _ => Err(badaddr(addr)),
+        }
+    }
+
+    /// Return this knob's current data payload or an error if it doesn't exist.
+    fn knob_value(&self, addr: KnobAddr) -> Result<KnobData, KnobError<KnobAddr>> {
+        match addr {
+            PLAYBACK_KNOB_ADDR => Ok(KnobData::Picker(self.playback.to_picker().to_string())),
+            GLIDE_KNOB_ADDR => Ok(KnobData::Wiggle(Data::Unipolar(self.glide))),
+            STEP_COUNT_KNOB_ADDR => Ok(KnobData::Picker(self.steps.len().to_string())),
+            _ => {
+                match self.steps.get((addr - FIRST_STEP_KNOB_ADDR) as usize) {
+                    Some(step) => Ok(KnobData::Wiggle(Data::Unipolar(*step))),
+                    None => Err(badaddr(addr)),
+                }
+            }
+        }
+    }
+
+    fn set_knob(&mut self, addr: KnobAddr, value: KnobData) -> Result<(), KnobError<KnobAddr>> {
+        match addr {
+            PLAYBACK_KNOB_ADDR => {
+                self.playback =
+                    value.as_picker()
+                        .and_then(|p| Playback::from_picker(&p))
+                        .map_err(|()| badtype(playback_knob_datatype(), value))?;
+            }
+            GLIDE_KNOB_ADDR => self.glide = value.as_unipolar()?,
+            STEP_COUNT_KNOB_ADDR => {
+                let count =
+                    value.as_picker()
+                        .and_then(|p| p.parse::<usize>().map_err(|_| ()))
+                        .and_then(|n| if n >= 1 && n <= MAX_STEPS { Ok(n) } else { Err(()) })
+                        .map_err(|()| badtype(step_count_knob_datatype(), value))?;
+                self.set_step_count(count);
+            }
+            _ => {
+                match self.steps.get_mut((addr - FIRST_STEP_KNOB_ADDR) as usize) {
+                    Some(step) => *step = value.as_unipolar()?,
+                    None => return Err(badaddr(addr)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Wiggle for Sequencer {
+    fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    /// Sequencer is driven entirely by its clock; update only announces knob changes.
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
+        self.pending.drain(..).collect()
+    }
+
+    fn render(
+        &self,
+        phase_offset: f64,
+        type_hint: Option<Datatype>,
+        _: &[Option<(WiggleId, OutputId)>],
+        _: OutputId,
+        _: &WiggleProvider,
+        clocks: &ClockProvider)
+        -> Data
+    {
+        let clock_val = match self.clock {
+            Some(cid) => clocks.get_value(cid),
+            None => ClockValue::default(),
+        };
+        let position = clock_val.float_value() + phase_offset;
+        let tick = position.floor();
+        let phase = position - tick;
+        let tick = tick as i64;
+
+        let Unipolar(current) = self.steps[self.step_index(tick)];
+        let Unipolar(glide) = self.glide;
+        let value = if glide > 0.0 && phase > 1.0 - glide {
+            let Unipolar(next) = self.steps[self.step_index(tick + 1)];
+            current + (next - current) * (phase - (1.0 - glide)) / glide
+        }
+        else {
+            current
+        };
+        let data = Data::unipolar(value);
+        match type_hint {
+            Some(datatype) => data.as_type(datatype),
+            None => data,
+        }
+    }
+
+    fn clock_source(&self) -> Result<Option<ClockId>, ()> {
+        Ok(self.clock)
+    }
+
+    fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()> {
+        self.clock = source;
+        Ok(())
+    }
+
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
+    }
+}
@@ -1,17 +1,14 @@
 //! Implement serialization and deserialization of wiggles hidden behind trait objects.
-//! See clocks::serde for notes on this hack.
+//! See clocks::serde for notes on how this works.
 use super::wiggle::{CompleteWiggle, KnobAddr};
 use super::{deserialize as deserialize_wiggle};
+use node_data::SerializedNode;
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde::ser::{Error as SerError};
 use serde::de::{Error as DeError};
 
-#[derive(Debug, Serialize, Deserialize)]
-/// A wiggle in JSON-serialized form.
-pub struct SerializableWiggle {
-    pub kind: String,
-    pub serialized: String,
-}
+/// A wiggle in serialized form.
+pub type SerializableWiggle = SerializedNode;
 
 impl Serialize for Box<CompleteWiggle> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
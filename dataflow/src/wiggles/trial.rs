@@ -1,7 +1,9 @@
 //! A wiggle that just produces a sin wave, as a proof of principle.
+//! It is only built for tests, which register it themselves.
 use std::sync::Arc;
 use std::time::Duration;
 use console_server::reactor::Messages;
+use console_server::value_tree::to_value;
 use network::{OutputId, Inputs, Outputs};
 use wiggles_value::knob::{
     Knobs,
@@ -107,7 +109,7 @@ impl Wiggle for TestWiggle {
     }
 
     /// Update the state of this wiggle using the provided update interval.
-    fn update(&mut self, _: Duration) -> Messages<KnobResponse<KnobAddr>> {
+    fn update(&mut self, _: Duration, _: &ClockProvider) -> Messages<KnobResponse<KnobAddr>> {
         Messages::none()
     }
 
@@ -141,7 +143,7 @@ impl Wiggle for TestWiggle {
         Ok(())
     }
 
-    fn as_json(&self) -> Result<String, SerdeJsonError> {
-        serde_json::to_string(self)
+    fn as_json(&self) -> Result<serde_json::Value, SerdeJsonError> {
+        to_value(self)
     }
 }
\ No newline at end of file
@@ -2,7 +2,7 @@
 use util::{modulo_one, almost_eq, angle_almost_eq};
 use network::{Network, NodeIndex, GenerationId, NodeId, OutputId, Inputs, Outputs};
 use console_server::reactor::Messages;
-use wiggles_value::{Data, Unipolar, Datatype};
+use wiggles_value::{Data, Unipolar, Bipolar, Datatype};
 use wiggles_value::knob::{Knobs, Response as KnobResponse};
 use std::collections::HashMap;
 use std::time::Duration;
@@ -10,8 +10,9 @@ use std::fmt;
 use std::any::Any;
 use serde::{Serialize, Serializer};
 use serde::de::DeserializeOwned;
-use serde_json::{Error as SerdeJsonError, self};
+use serde_json::{Error as SerdeJsonError, Value, self};
 use super::serde::SerializableWiggle;
+use node_data::{NodeData, LegacyNode};
 use clocks::clock::{ClockId, ClockProvider};
 
 pub type KnobAddr = u32;
@@ -30,6 +31,13 @@ pub trait WiggleProvider {
         -> Data;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The current value of one of a wiggle's inputs, rendered as both datatypes.
+pub struct InputValue {
+    pub unipolar: Unipolar,
+    pub bipolar: Bipolar,
+}
+
 pub trait Wiggle {
     /// A string name for this kind of wiggle.
     /// This string will be used during serialization and deserialization to uniquely identify
@@ -42,14 +50,27 @@ pub trait Wiggle {
     /// Rename this wiggle.
     fn set_name(&mut self, name: String);
 
+    /// Return true if this wiggle needs the values of its inputs before every update, such as a
+    /// filter that follows its input over time.
+    fn samples_inputs(&self) -> bool {
+        false
+    }
+
+    /// Receive the current values of this wiggle's inputs, just before it is updated.
+    /// Only called if samples_inputs returns true.  Disconnected inputs are None.
+    fn sample_inputs(&mut self, _: &[Option<InputValue>]) {}
+
     /// Update the state of this wiggle using the provided update interval.
+    /// Clocks have already been updated for this interval, so wiggles can react to their ticks.
     /// Return a message collection of some kind.
-    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<KnobAddr>>;
+    fn update(&mut self, dt: Duration, clocks: &ClockProvider) -> Messages<KnobResponse<KnobAddr>>;
 
     /// Render the state of this wiggle, providing its currently-assigned inputs as well as a
     /// function that can be used to retrieve the current value of one of those inputs.
     /// Specify which output port this wiggle should be rendered for.
     /// Also provide access to the clock network if this node needs it.
+    /// The type hint is only a hint; the network converts whatever is rendered into the type
+    /// asked for, so wiggles that only make scalars can render structured types as scalars.
     fn render(
         &self,
         phase_offset: f64,
@@ -68,15 +89,16 @@ pub trait Wiggle {
     /// If this wiggle doesn't use a clock, return Err.
     fn set_clock(&mut self, source: Option<ClockId>) -> Result<(), ()>;
 
-    /// Serialize yourself into JSON.
+    /// Serialize yourself into a JSON value.
     /// Every wiggle must implement this separately until an erased_serde solution is back in
-    /// action.
-    fn as_json(&self) -> Result<String, SerdeJsonError>;
+    /// action.  Use value_tree::to_value rather than serde_json's, so that NaN and infinite
+    /// values are rejected rather than saved as null.
+    fn as_json(&self) -> Result<Value, SerdeJsonError>;
 
     fn serializable(&self) -> Result<SerializableWiggle, SerdeJsonError> {
         Ok(SerializableWiggle {
             kind: self.kind().to_string(),
-            serialized: self.as_json()?,
+            data: NodeData(self.as_json()?),
         })
     }
 }
@@ -105,6 +127,9 @@ impl NodeId for WiggleId {
 /// Type alias for a network of wiggles.
 pub type WiggleNetwork = Network<Box<CompleteWiggle>, WiggleId, KnobResponse<WiggleKnobAddr>>;
 
+/// A network of wiggles as stored in binary autosaves from before show versioning.
+pub type LegacyWiggleNetwork = Network<LegacyNode, WiggleId, KnobResponse<WiggleKnobAddr>>;
+
 impl WiggleProvider for WiggleNetwork {
     fn get_value(
         &self,
@@ -122,7 +147,12 @@ impl WiggleProvider for WiggleNetwork {
                 Data::default_with_type_hint(type_hint)
             }
             Ok(node) => {
-                node.inner().render(phase_offset, type_hint, node.inputs(), output_id, self, clocks)
+                let data = node.inner().render(
+                    phase_offset, type_hint, node.inputs(), output_id, self, clocks);
+                match type_hint {
+                    Some(datatype) if datatype != data.datatype() => data.as_type(datatype),
+                    _ => data,
+                }
             }
         }
     }
@@ -181,17 +211,42 @@ impl Outputs<KnobResponse<WiggleKnobAddr>, WiggleId> for Box<CompleteWiggle> {
 // TODO: consider generalizing Update and/or Render as traits.
 /// Wrapper trait for a wiggle network.
 pub trait WiggleCollection {
-    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<WiggleKnobAddr>>;
+    fn update(
+        &mut self, dt: Duration, clocks: &ClockProvider) -> Messages<KnobResponse<WiggleKnobAddr>>;
 }
 
 impl WiggleCollection for WiggleNetwork {
-    fn update(&mut self, dt: Duration) -> Messages<KnobResponse<WiggleKnobAddr>> {
+    fn update(
+        &mut self, dt: Duration, clocks: &ClockProvider) -> Messages<KnobResponse<WiggleKnobAddr>>
+    {
+        // Sample the inputs of any wiggle that needs them before anything is updated, so every
+        // wiggle sees the network as it was at the end of the last update.
+        let samples: Vec<(WiggleId, Vec<Option<InputValue>>)> = self.nodes()
+            .filter(|&(_, node)| node.inner().samples_inputs())
+            .map(|(id, node)| {
+                let values = node.inputs().iter().map(|input| {
+                    input.map(|(input_id, output)| InputValue {
+                        unipolar: self.get_value(
+                            input_id, output, 0.0, Some(Datatype::Unipolar), clocks).into(),
+                        bipolar: self.get_value(
+                            input_id, output, 0.0, Some(Datatype::Bipolar), clocks).into(),
+                    })
+                }).collect();
+                (id, values)
+            })
+            .collect();
+        for (id, values) in samples {
+            if let Ok(wiggle) = self.node_inner_mut(id) {
+                wiggle.sample_inputs(&values);
+            }
+        }
+
         let mut update_messages = Messages::none();
         {
             let update = |node_id: WiggleId, wiggle: &mut Box<CompleteWiggle>| {
                 // lift the address of this message up into the network address space
                 let address_lifter = |knob_num| (node_id, knob_num);
-                let mut messages = wiggle.update(dt);
+                let mut messages = wiggle.update(dt, clocks);
                 for message in messages.drain() {
                     let lifted_message = message.lift_address(&address_lifter);
                     (&mut update_messages).push(lifted_message);
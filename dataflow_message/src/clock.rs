@@ -14,7 +14,7 @@ use dataflow::clocks::{
     KnobAddr as ClockNodeKnobAddr,
     ClockKnobAddr,
     new_clock,
-    CLOCKS,
+    kinds as clock_kinds,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,12 +94,13 @@ pub enum ResponseWithKnobs {
     Knob(KnobResponse<ClockKnobAddr>),
 }
 
-lazy_static! {
-    static ref KINDS: Arc<Vec<String>> = Arc::new(CLOCKS.iter().map(|s| s.to_string()).collect());
-}
-
 const o0: OutputId = OutputId(0);
 
+/// List every kind of clock currently registered.
+fn kinds() -> Arc<Vec<String>> {
+    Arc::new(clock_kinds().iter().map(|s| s.to_string()).collect())
+}
+
 /// Apply the action dictated by a clock command to this clock network.
 pub fn handle_message(
     network: &mut ClockNetwork,
@@ -115,7 +116,7 @@ pub fn handle_message(
             Ok((Messages::one(ResponseWithKnobs::Clock(Response::State(state))), None))
         }
         Kinds => Ok((
-            Messages::one(ResponseWithKnobs::Clock(Response::Kinds(KINDS.clone()))),
+            Messages::one(ResponseWithKnobs::Clock(Response::Kinds(kinds()))),
             None)),
         Create{kind, name} => {
             let node = new_clock(&kind, name).ok_or(Error::UnknownKind(kind.clone()))?;
@@ -3,7 +3,6 @@ extern crate console_server;
 extern crate wiggles_value;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
-#[macro_use] extern crate lazy_static;
 
 pub mod clock;
 pub mod wiggle;
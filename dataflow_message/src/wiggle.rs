@@ -14,7 +14,7 @@ use dataflow::wiggles::{
     KnobAddr as WiggleNodeKnobAddr,
     WiggleKnobAddr,
     new_wiggle,
-    WIGGLES};
+    kinds as wiggle_kinds};
 use dataflow::clocks::{ClockId};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -97,8 +97,9 @@ pub enum ResponseWithKnobs {
     Knob(KnobResponse<WiggleKnobAddr>),
 }
 
-lazy_static! {
-    static ref KINDS: Arc<Vec<String>> = Arc::new(WIGGLES.iter().map(|s| s.to_string()).collect());
+/// List every kind of wiggle currently registered.
+fn kinds() -> Arc<Vec<String>> {
+    Arc::new(wiggle_kinds().iter().map(|s| s.to_string()).collect())
 }
 
 /// Apply the action dictated by a wiggle command to this wiggle network.
@@ -110,7 +111,7 @@ pub fn handle_message(
     use self::Command::*;
     match command {
         Kinds => Ok((
-            Messages::one(ResponseWithKnobs::Wiggle(Response::Kinds(KINDS.clone()))),
+            Messages::one(ResponseWithKnobs::Wiggle(Response::Kinds(kinds()))),
             None)),
         State => {
             let state = network.nodes()
@@ -0,0 +1,185 @@
+//! Art-Net packets.
+//! Universes are sent as ArtDmx packets, and nodes on the network are found by sending an ArtPoll
+//! and listening for the ArtPollReply each of them sends back.
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// The UDP port used by Art-Net.
+pub const ARTNET_PORT: u16 = 0x1936;
+
+/// Art-Net protocol revision that we speak.
+const PROTOCOL_VERSION: u16 = 14;
+
+pub const ID: &'static [u8; 8] = b"Art-Net\0";
+
+pub const OP_POLL: u16 = 0x2000;
+pub const OP_POLL_REPLY: u16 = 0x2100;
+pub const OP_DMX: u16 = 0x5000;
+
+/// Size of the ArtDmx header, before the DMX data.
+pub const DMX_HEADER_SIZE: usize = 18;
+const MAX_CHANNELS: usize = 512;
+/// An ArtPollReply is at least this long, up to the end of the output port addresses.
+const MIN_POLL_REPLY_SIZE: usize = 194;
+
+/// Bit in an ArtPollReply port type that indicates the port outputs DMX from Art-Net.
+const PORT_TYPE_OUTPUT: u8 = 0x80;
+
+/// The largest Art-Net universe, written as a single 15-bit port address.
+pub const MAX_PORT_ADDRESS: u16 = 0x7fff;
+
+/// The 15-bit address of an Art-Net universe.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PortAddress {
+    /// 7 bits.
+    pub net: u8,
+    /// 4 bits.
+    pub subnet: u8,
+    /// 4 bits.
+    pub universe: u8,
+}
+
+impl PortAddress {
+    /// Create a port address, or None if any part is out of range.
+    pub fn new(net: u8, subnet: u8, universe: u8) -> Option<Self> {
+        if net < 0x80 && subnet < 0x10 && universe < 0x10 {
+            Some(PortAddress { net: net, subnet: subnet, universe: universe })
+        }
+        else {
+            None
+        }
+    }
+
+    /// The low byte of the address, as sent in the SubUni field of an ArtDmx.
+    fn sub_uni(&self) -> u8 {
+        (self.subnet << 4) | self.universe
+    }
+}
+
+impl fmt::Display for PortAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.net, self.subnet, self.universe)
+    }
+}
+
+impl FromStr for PortAddress {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts =
+            s.split(':').map(u8::from_str).collect::<Result<Vec<_>, _>>().map_err(|_| ())?;
+        match parts.as_slice() {
+            &[net, subnet, universe] => PortAddress::new(net, subnet, universe).ok_or(()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Write the common header shared by every Art-Net packet.
+fn write_header(packet: &mut Vec<u8>, op_code: u16) {
+    packet.extend_from_slice(ID);
+    // Op codes are little endian, but the protocol version is big endian.
+    packet.push((op_code & 0xff) as u8);
+    packet.push((op_code >> 8) as u8);
+    packet.push((PROTOCOL_VERSION >> 8) as u8);
+    packet.push((PROTOCOL_VERSION & 0xff) as u8);
+}
+
+/// Return the op code of this packet, if it is an Art-Net packet.
+pub fn op_code(packet: &[u8]) -> Option<u16> {
+    if packet.len() < 10 || &packet[..8] != ID {
+        return None;
+    }
+    Some(packet[8] as u16 | (packet[9] as u16) << 8)
+}
+
+/// Build an ArtDmx packet.
+/// The data is padded to an even number of channels, as the protocol requires, and truncated to
+/// a single universe.
+pub fn dmx_packet(address: PortAddress, sequence: u8, frame: &[u8]) -> Vec<u8> {
+    let frame = &frame[..frame.len().min(MAX_CHANNELS)];
+    let length = (frame.len() + frame.len() % 2).max(2);
+    let mut packet = Vec::with_capacity(DMX_HEADER_SIZE + length);
+    write_header(&mut packet, OP_DMX);
+    packet.push(sequence);
+    // physical input port, unused
+    packet.push(0);
+    packet.push(address.sub_uni());
+    packet.push(address.net);
+    packet.push((length >> 8) as u8);
+    packet.push((length & 0xff) as u8);
+    packet.extend_from_slice(frame);
+    packet.resize(DMX_HEADER_SIZE + length, 0);
+    packet
+}
+
+/// The contents of an ArtDmx packet.
+#[derive(Debug, PartialEq)]
+pub struct Dmx<'a> {
+    /// The universe, as a single 15-bit port address.
+    pub universe: u16,
+    pub sequence: u8,
+    pub levels: &'a [u8],
+}
+
+/// Parse an ArtDmx packet, returning None for any other kind of packet.
+/// If the packet is shorter than its length field says, the levels are cut short to match.
+pub fn parse_dmx<'a>(packet: &'a [u8]) -> Option<Dmx<'a>> {
+    if op_code(packet) != Some(OP_DMX) || packet.len() < DMX_HEADER_SIZE {
+        return None;
+    }
+    let length = (packet[16] as usize) << 8 | packet[17] as usize;
+    let end = (DMX_HEADER_SIZE + length).min(packet.len());
+    Some(Dmx {
+        universe: (packet[15] as u16 & 0x7f) << 8 | packet[14] as u16,
+        sequence: packet[12],
+        levels: &packet[DMX_HEADER_SIZE..end],
+    })
+}
+
+/// Build an ArtPoll packet, asking every node to reply with an ArtPollReply.
+pub fn poll_packet() -> Vec<u8> {
+    let mut packet = Vec::with_capacity(14);
+    write_header(&mut packet, OP_POLL);
+    // flags and diagnostic priority, none of which we need
+    packet.push(0);
+    packet.push(0);
+    packet
+}
+
+/// An Art-Net node, as described by its ArtPollReply.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Node {
+    pub ip: Ipv4Addr,
+    pub short_name: String,
+    pub long_name: String,
+    /// The addresses of every port on this node that outputs DMX.
+    pub outputs: Vec<PortAddress>,
+}
+
+/// Read a null-terminated string from a fixed-size field.
+fn read_string(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Parse an ArtPollReply packet, returning None if this isn't one.
+pub fn parse_poll_reply(packet: &[u8]) -> Option<Node> {
+    if op_code(packet) != Some(OP_POLL_REPLY) || packet.len() < MIN_POLL_REPLY_SIZE {
+        return None;
+    }
+    let ip = Ipv4Addr::new(packet[10], packet[11], packet[12], packet[13]);
+    let net = packet[18] & 0x7f;
+    let subnet = packet[19] & 0x0f;
+    let port_count = (packet[173] as usize).min(4);
+    let outputs = (0..port_count)
+        .filter(|&port| packet[174 + port] & PORT_TYPE_OUTPUT != 0)
+        .filter_map(|port| PortAddress::new(net, subnet, packet[190 + port] & 0x0f))
+        .collect();
+    Some(Node {
+        ip: ip,
+        short_name: read_string(&packet[26..44]),
+        long_name: read_string(&packet[44..108]),
+        outputs: outputs,
+    })
+}
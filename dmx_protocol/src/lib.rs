@@ -0,0 +1,6 @@
+//! Packet layouts for the network DMX protocols, Art-Net and sACN.
+//! This only knows how to build and parse packets; sending them, and keeping track of what is
+//! on the network, is up to the fixture patch for output and the dataflow DMX receiver for input.
+pub mod artnet;
+pub mod sacn;
+#[cfg(test)] mod test;
@@ -0,0 +1,227 @@
+//! Streaming ACN (ANSI E1.31) packets.
+//! Universes are sent as data packets, each of which identifies its source by a name and a CID.
+//! Sources also announce the universes they are sending with pages of discovery packets.
+use std::net::{SocketAddr, SocketAddrV4, Ipv4Addr};
+
+/// The UDP port used by sACN.
+pub const SACN_PORT: u16 = 5568;
+
+pub const MIN_UNIVERSE: u16 = 1;
+pub const MAX_UNIVERSE: u16 = 63999;
+/// Universe discovery packets are sent to this universe's multicast group.
+pub const DISCOVERY_UNIVERSE: u16 = 64214;
+
+pub const DEFAULT_PRIORITY: u8 = 100;
+pub const MAX_PRIORITY: u8 = 200;
+
+/// The most universes listed in a single discovery packet.
+const UNIVERSES_PER_PAGE: usize = 512;
+
+const MAX_SLOTS: usize = 512;
+const SOURCE_NAME_SIZE: usize = 64;
+
+pub const ACN_PACKET_IDENTIFIER: &'static [u8; 12] = b"ASC-E1.17\0\0\0";
+pub const VECTOR_ROOT_E131_DATA: u32 = 0x4;
+const VECTOR_ROOT_E131_EXTENDED: u32 = 0x8;
+pub const VECTOR_E131_DATA_PACKET: u32 = 0x2;
+const VECTOR_E131_EXTENDED_DISCOVERY: u32 = 0x2;
+const VECTOR_UNIVERSE_DISCOVERY_UNIVERSE_LIST: u32 = 0x1;
+pub const VECTOR_DMP_SET_PROPERTY: u8 = 0x2;
+
+/// Offsets where each layer of a packet begins.
+pub const FRAMING_LAYER: usize = 38;
+pub const DMP_LAYER: usize = 115;
+const DISCOVERY_LAYER: usize = 112;
+
+/// Size of a data packet up to the start code, after which come the slots.
+const DATA_HEADER_SIZE: usize = 125;
+
+/// Framing layer option bits.
+pub const OPTION_PREVIEW: u8 = 0x80;
+pub const OPTION_STREAM_TERMINATED: u8 = 0x40;
+
+/// The identity of an sACN source.
+#[derive(Debug)]
+pub struct Source {
+    pub cid: [u8; 16],
+    pub name: String,
+}
+
+/// The multicast group for a universe.
+pub fn multicast_group(universe: u16) -> Ipv4Addr {
+    Ipv4Addr::new(239, 255, (universe >> 8) as u8, (universe & 0xff) as u8)
+}
+
+/// The address to send a universe to by multicast.
+pub fn multicast_address(universe: u16) -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(multicast_group(universe), SACN_PORT))
+}
+
+fn push_u16(packet: &mut Vec<u8>, value: u16) {
+    packet.push((value >> 8) as u8);
+    packet.push((value & 0xff) as u8);
+}
+
+fn push_u32(packet: &mut Vec<u8>, value: u32) {
+    push_u16(packet, (value >> 16) as u16);
+    push_u16(packet, (value & 0xffff) as u16);
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> u16 {
+    (packet[offset] as u16) << 8 | packet[offset + 1] as u16
+}
+
+fn read_u32(packet: &[u8], offset: usize) -> u32 {
+    (read_u16(packet, offset) as u32) << 16 | read_u16(packet, offset + 2) as u32
+}
+
+/// Write the root layer; its length is filled in by finish_layers.
+fn write_root_layer(packet: &mut Vec<u8>, vector: u32, source: &Source) {
+    push_u16(packet, 0x0010);
+    push_u16(packet, 0x0000);
+    packet.extend_from_slice(ACN_PACKET_IDENTIFIER);
+    push_u16(packet, 0);
+    push_u32(packet, vector);
+    packet.extend_from_slice(&source.cid);
+}
+
+/// Write the source name as a null-terminated, fixed-size field.
+fn write_source_name(packet: &mut Vec<u8>, source: &Source) {
+    let mut end = source.name.len().min(SOURCE_NAME_SIZE - 1);
+    while !source.name.is_char_boundary(end) {
+        end -= 1;
+    }
+    let start = packet.len();
+    packet.extend_from_slice(source.name[..end].as_bytes());
+    packet.resize(start + SOURCE_NAME_SIZE, 0);
+}
+
+/// Fill in the flags and length of every layer, each of which runs to the end of the packet.
+fn finish_layers(packet: &mut [u8], layers: &[usize]) {
+    for &layer in layers {
+        let flags_and_length = 0x7000 | (packet.len() - layer) as u16;
+        packet[layer] = (flags_and_length >> 8) as u8;
+        packet[layer + 1] = (flags_and_length & 0xff) as u8;
+    }
+}
+
+/// Build a data packet carrying a frame of DMX, truncated to a single universe.
+pub fn data_packet(
+    source: &Source,
+    universe: u16,
+    priority: u8,
+    sequence: u8,
+    options: u8,
+    frame: &[u8])
+    -> Vec<u8>
+{
+    let frame = &frame[..frame.len().min(MAX_SLOTS)];
+    let mut packet = Vec::with_capacity(DATA_HEADER_SIZE + 1 + frame.len());
+    write_root_layer(&mut packet, VECTOR_ROOT_E131_DATA, source);
+
+    push_u16(&mut packet, 0);
+    push_u32(&mut packet, VECTOR_E131_DATA_PACKET);
+    write_source_name(&mut packet, source);
+    packet.push(priority);
+    // synchronization address, unused
+    push_u16(&mut packet, 0);
+    packet.push(sequence);
+    packet.push(options);
+    push_u16(&mut packet, universe);
+
+    push_u16(&mut packet, 0);
+    packet.push(VECTOR_DMP_SET_PROPERTY);
+    // address and data type
+    packet.push(0xa1);
+    // first property address and address increment
+    push_u16(&mut packet, 0);
+    push_u16(&mut packet, 1);
+    // property values are the start code followed by the slots
+    push_u16(&mut packet, 1 + frame.len() as u16);
+    packet.push(0);
+    packet.extend_from_slice(frame);
+
+    finish_layers(&mut packet, &[0x10, FRAMING_LAYER, DMP_LAYER]);
+    packet
+}
+
+/// The contents of a data packet.
+#[derive(Debug, PartialEq)]
+pub struct Data<'a> {
+    pub cid: [u8; 16],
+    pub universe: u16,
+    pub priority: u8,
+    pub sequence: u8,
+    pub options: u8,
+    /// The slots, after the start code.
+    pub levels: &'a [u8],
+}
+
+impl<'a> Data<'a> {
+    /// The source has stopped sending this universe.
+    pub fn terminated(&self) -> bool {
+        self.options & OPTION_STREAM_TERMINATED != 0
+    }
+
+    /// This data is only meant for visualizers, not for driving fixtures.
+    pub fn preview(&self) -> bool {
+        self.options & OPTION_PREVIEW != 0
+    }
+}
+
+/// Parse a data packet carrying DMX, returning None for any other kind of packet.
+/// Packets with an alternate start code are ignored.  If the packet is shorter than its property
+/// count says, the levels are cut short to match.
+pub fn parse_data<'a>(packet: &'a [u8]) -> Option<Data<'a>> {
+    if packet.len() < DATA_HEADER_SIZE + 1 || &packet[4..16] != ACN_PACKET_IDENTIFIER {
+        return None;
+    }
+    // Every layer starts with two bytes of flags and length, followed by its vector.
+    if read_u32(packet, 18) != VECTOR_ROOT_E131_DATA
+        || read_u32(packet, FRAMING_LAYER + 2) != VECTOR_E131_DATA_PACKET
+        || packet[DMP_LAYER + 2] != VECTOR_DMP_SET_PROPERTY
+        || packet[DATA_HEADER_SIZE] != 0
+    {
+        return None;
+    }
+    // the property count includes the start code
+    let count = read_u16(packet, 123) as usize;
+    let end = (DATA_HEADER_SIZE + count).min(packet.len());
+    let mut cid = [0; 16];
+    cid.copy_from_slice(&packet[22..38]);
+    Some(Data {
+        cid: cid,
+        universe: read_u16(packet, 113),
+        priority: packet[108],
+        sequence: packet[111],
+        options: packet[112],
+        levels: &packet[DATA_HEADER_SIZE + 1..end],
+    })
+}
+
+/// Build the pages of discovery packets that announce these universes, which must be sorted.
+pub fn discovery_packets(source: &Source, universes: &[u16]) -> Vec<Vec<u8>> {
+    let pages = universes.chunks(UNIVERSES_PER_PAGE).collect::<Vec<_>>();
+    let last_page = pages.len().saturating_sub(1) as u8;
+    pages.iter().enumerate().map(|(page, universes)| {
+        let mut packet = Vec::with_capacity(DISCOVERY_LAYER + 8 + 2 * universes.len());
+        write_root_layer(&mut packet, VECTOR_ROOT_E131_EXTENDED, source);
+
+        push_u16(&mut packet, 0);
+        push_u32(&mut packet, VECTOR_E131_EXTENDED_DISCOVERY);
+        write_source_name(&mut packet, source);
+        // reserved
+        push_u32(&mut packet, 0);
+
+        push_u16(&mut packet, 0);
+        push_u32(&mut packet, VECTOR_UNIVERSE_DISCOVERY_UNIVERSE_LIST);
+        packet.push(page as u8);
+        packet.push(last_page);
+        for &universe in universes.iter() {
+            push_u16(&mut packet, universe);
+        }
+
+        finish_layers(&mut packet, &[0x10, FRAMING_LAYER, DISCOVERY_LAYER]);
+        packet
+    }).collect()
+}
@@ -0,0 +1,69 @@
+//! Tests for building and parsing packets.
+use std::net::Ipv4Addr;
+use super::artnet::{self, PortAddress};
+use super::sacn::{self, Source};
+
+fn source() -> Source {
+    Source { cid: [7; 16], name: "test source".to_string() }
+}
+
+#[test]
+fn test_artnet_dmx_round_trip() {
+    let address = PortAddress::new(3, 2, 1).unwrap();
+    let packet = artnet::dmx_packet(address, 9, &[1, 2, 3]);
+    let dmx = artnet::parse_dmx(&packet).unwrap();
+    assert_eq!(3 << 8 | 0x21, dmx.universe);
+    assert_eq!(9, dmx.sequence);
+    // padded to an even number of channels
+    assert_eq!(&[1, 2, 3, 0], dmx.levels);
+
+    // Packets that claim more data than they carry are cut short.
+    assert_eq!(&[1, 2], artnet::parse_dmx(&packet[..20]).unwrap().levels);
+    assert_eq!(None, artnet::parse_dmx(&artnet::poll_packet()));
+    assert_eq!(None, artnet::parse_dmx(b"not art-net"));
+}
+
+#[test]
+fn test_artnet_poll_reply() {
+    let mut reply = vec!(0; 239);
+    reply[..8].copy_from_slice(artnet::ID);
+    reply[8..10].copy_from_slice(&[0x00, 0x21]);
+    reply[10..14].copy_from_slice(&[10, 0, 0, 12]);
+    reply[18] = 2;
+    reply[19] = 5;
+    reply[26..30].copy_from_slice(b"node");
+    reply[173] = 2;
+    reply[174..176].copy_from_slice(&[0x80, 0x40]);
+    reply[190..192].copy_from_slice(&[4, 1]);
+    let node = artnet::parse_poll_reply(&reply).unwrap();
+    assert_eq!(Ipv4Addr::new(10, 0, 0, 12), node.ip);
+    assert_eq!("node", node.short_name);
+    assert_eq!(vec!(PortAddress::new(2, 5, 4).unwrap()), node.outputs);
+    assert_eq!(None, artnet::parse_poll_reply(&reply[..100]));
+}
+
+#[test]
+fn test_sacn_data_round_trip() {
+    let packet = sacn::data_packet(&source(), 300, 150, 12, 0, &[0, 0, 255]);
+    let data = sacn::parse_data(&packet).unwrap();
+    assert_eq!([7; 16], data.cid);
+    assert_eq!(300, data.universe);
+    assert_eq!(150, data.priority);
+    assert_eq!(12, data.sequence);
+    assert_eq!(&[0, 0, 255], data.levels);
+    assert!(!data.terminated());
+    assert!(!data.preview());
+
+    let packet = sacn::data_packet(
+        &source(), 1, 100, 0, sacn::OPTION_STREAM_TERMINATED | sacn::OPTION_PREVIEW, &[]);
+    let data = sacn::parse_data(&packet).unwrap();
+    assert!(data.terminated());
+    assert!(data.preview());
+
+    // Alternate start codes and other kinds of packet are ignored.
+    let mut packet = sacn::data_packet(&source(), 1, 100, 0, 0, &[1]);
+    packet[125] = 0xdd;
+    assert_eq!(None, sacn::parse_data(&packet));
+    let discovery = sacn::discovery_packets(&source(), &[1, 2]);
+    assert_eq!(None, sacn::parse_data(&discovery[0]));
+}
@@ -0,0 +1,205 @@
+//! Art-Net output, so that universes can be sent to Ethernet nodes.
+//! An Art-Net port sends ArtDmx packets for a single Art-Net universe to a single destination,
+//! which may be a node's own address or a broadcast address.  Ports are identified by strings of
+//! the form "net:subnet:universe@address", where the address is an IPv4 address with an optional
+//! UDP port, such as "0:1:4@10.0.0.12" or "0:0:0@255.255.255.255".
+//! Nodes on the network can be found by polling for them with ArtPoll; once ports have been
+//! listed, this keeps happening in the background.
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::{UdpSocket, SocketAddr, SocketAddrV4, Ipv4Addr};
+use std::str::FromStr;
+use std::sync::{Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+use rust_dmx::{DmxPort, SerializablePort, Error as DmxPortError};
+pub use dmx_protocol::artnet::{
+    ARTNET_PORT,
+    MAX_PORT_ADDRESS,
+    PortAddress,
+    Node,
+    dmx_packet,
+    poll_packet,
+    parse_poll_reply,
+};
+
+pub const NAMESPACE: &'static str = "artnet";
+
+/// A universe address and where to send it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PortId {
+    pub address: PortAddress,
+    pub destination: SocketAddr,
+}
+
+impl fmt::Display for PortId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.destination {
+            SocketAddr::V4(dest) if dest.port() == ARTNET_PORT =>
+                write!(f, "{}@{}", self.address, dest.ip()),
+            dest => write!(f, "{}@{}", self.address, dest),
+        }
+    }
+}
+
+impl FromStr for PortId {
+    type Err = io::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid Art-Net port '{}', expected net:subnet:universe@address.", s));
+        let mut parts = s.splitn(2, '@');
+        let address = parts.next().ok_or_else(&invalid)?.parse().map_err(|_| invalid())?;
+        let dest = parts.next().ok_or_else(&invalid)?;
+        let destination = match Ipv4Addr::from_str(dest) {
+            Ok(ip) => SocketAddr::V4(SocketAddrV4::new(ip, ARTNET_PORT)),
+            Err(_) => SocketAddr::from_str(dest).map_err(|_| invalid())?,
+        };
+        Ok(PortId { address: address, destination: destination })
+    }
+}
+
+/// Send an ArtPoll to the destination, and collect the replies that arrive on this socket before
+/// the timeout expires.  Nodes that reply more than once are only listed once.
+pub fn poll(socket: &UdpSocket, destination: SocketAddr, timeout: Duration)
+        -> io::Result<Vec<Node>> {
+    socket.set_broadcast(true)?;
+    socket.send_to(&poll_packet(), destination)?;
+    let start = Instant::now();
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            break;
+        }
+        socket.set_read_timeout(Some(timeout - elapsed))?;
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                if let Some(node) = parse_poll_reply(&buf[..len]) {
+                    if !nodes.contains(&node) {
+                        nodes.push(node);
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut =>
+                break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(nodes)
+}
+
+/// How long to wait for nodes to reply to each discovery poll.
+const DISCOVERY_TIMEOUT_MS: u64 = 500;
+
+/// How long to wait between discovery polls.
+const DISCOVERY_INTERVAL_SECS: u64 = 10;
+
+lazy_static! {
+    /// The nodes that replied to the most recent discovery poll.
+    static ref NODES: Mutex<Vec<Node>> = Mutex::new(Vec::new());
+}
+
+static START_DISCOVERY: Once = Once::new();
+
+/// Poll for nodes forever, keeping the list of known nodes up to date.
+/// Nodes reply to the Art-Net port, so if something else is already listening there we may not
+/// hear from any of them.  The port is only held while a poll is waiting for replies.
+fn discover(destination: SocketAddr) {
+    loop {
+        let socket =
+            UdpSocket::bind(("0.0.0.0", ARTNET_PORT)).or_else(|_| UdpSocket::bind("0.0.0.0:0"));
+        let nodes = socket.and_then(|socket| {
+            poll(&socket, destination, Duration::from_millis(DISCOVERY_TIMEOUT_MS))
+        });
+        match nodes {
+            Ok(nodes) => *NODES.lock().unwrap() = nodes,
+            Err(e) => warn!("Art-Net node discovery failed: {}", e),
+        }
+        thread::sleep(Duration::from_secs(DISCOVERY_INTERVAL_SECS));
+    }
+}
+
+/// List a port for every output of every node that has replied to a broadcast ArtPoll, as well
+/// as a port that broadcasts the first universe.
+/// Nodes are polled for on a background thread, started by the first call, so this never waits
+/// on the network; nodes found since the last call will show up the next time it is called.
+pub fn available_ports() -> Vec<(String, String)> {
+    let broadcast = PortId {
+        address: PortAddress { net: 0, subnet: 0, universe: 0 },
+        destination: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(255, 255, 255, 255),
+                                                      ARTNET_PORT)),
+    };
+    START_DISCOVERY.call_once(|| {
+        let destination = broadcast.destination;
+        thread::spawn(move || discover(destination));
+    });
+    let mut ports = vec!((NAMESPACE.to_string(), broadcast.to_string()));
+    for node in NODES.lock().unwrap().iter() {
+        for address in node.outputs.iter() {
+            let id = PortId {
+                address: *address,
+                destination: SocketAddr::V4(SocketAddrV4::new(node.ip, ARTNET_PORT)),
+            };
+            ports.push((NAMESPACE.to_string(), id.to_string()));
+        }
+    }
+    ports
+}
+
+/// Sends a single universe as ArtDmx.
+#[derive(Debug)]
+pub struct ArtNetPort {
+    /// The string this port was opened with, which is also how it is identified.
+    name: String,
+    id: PortId,
+    socket: UdpSocket,
+    /// Sequence number of the last packet we sent; 0 means nothing has been sent yet.
+    sequence: u8,
+}
+
+impl ArtNetPort {
+    /// Open a port from a port identifier string.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let id: PortId = name.parse()?;
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_broadcast(true)?;
+        Ok(ArtNetPort {
+            name: name.to_string(),
+            id: id,
+            socket: socket,
+            sequence: 0,
+        })
+    }
+
+    /// Advance the sequence number, skipping 0 which tells receivers not to check ordering.
+    fn next_sequence(&mut self) -> u8 {
+        self.sequence = if self.sequence == 255 { 1 } else { self.sequence + 1 };
+        self.sequence
+    }
+}
+
+impl DmxPort for ArtNetPort {
+    fn namespace(&self) -> &str {
+        NAMESPACE
+    }
+
+    fn port_name(&self) -> &str {
+        &self.name
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), DmxPortError> {
+        let sequence = self.next_sequence();
+        let packet = dmx_packet(self.id.address, sequence, frame);
+        self.socket.send_to(&packet, self.id.destination).map_err(DmxPortError::from)?;
+        Ok(())
+    }
+
+    fn serializable(&self) -> SerializablePort {
+        SerializablePort {
+            namespace: NAMESPACE.to_string(),
+            port_name: self.name.clone(),
+        }
+    }
+}
@@ -6,10 +6,12 @@ use std::fmt;
 use std::marker::PhantomData;
 use std::error::Error;
 use std::slice::{Iter, IterMut};
+use std::sync::Arc;
 use serde::{Serializer, Deserializer};
 use serde::de::{self, Visitor};
-use wiggles_value::{Datatype, Data};
-use profiles::render_func_for_type;
+use wiggles_value::{Datatype, Data, Conversion, Unipolar};
+use profiles::renderer_for_type;
+use profile_file::ProfileDescription;
 
 pub type DmxChannelCount = u16;
 pub type DmxValue = u8;
@@ -31,6 +33,12 @@ pub struct FixtureControl {
     data_type: Datatype,
     /// The current value of this control.
     value: Data,
+    /// If set, the source of this control is read as the other data type, and converted to the
+    /// native type using this strategy.  For example, a dimmer can be driven by a bipolar
+    /// source using Rescale.
+    /// If not set, the source is read as the native type.
+    #[serde(default)]
+    conversion: Option<Conversion>,
 }
 
 impl FixtureControl {
@@ -39,6 +47,7 @@ impl FixtureControl {
             name: name.into(),
             data_type: data_type,
             value: initial_value.as_type(data_type).coerce(),
+            conversion: None,
         }
     }
     /// The name of this control.
@@ -48,8 +57,16 @@ impl FixtureControl {
 
     /// Set this fixture control using value.  The data will be reinterpreted as the native
     /// data type specified by this control, and it will be coerced to be in range.
+    /// The conversion strategy is used if one has been selected.  Half-wave unipolar values are
+    /// only kept from going negative, so that peaks above full are passed on rather than clipped;
+    /// rendering saturates them.
     pub fn set_value(&mut self, value: Data) {
-        self.value = value.as_type(self.data_type).coerce();
+        let conversion = self.conversion.unwrap_or_default();
+        self.value = match (conversion, conversion.convert(value, self.data_type)) {
+            (Conversion::HalfWave, Data::Unipolar(Unipolar(val))) =>
+                Data::Unipolar(Unipolar(val.max(0.0))),
+            (_, converted) => converted.coerce(),
+        };
     }
     /// Get the value of this control.
     pub fn value(&self) -> Data {
@@ -59,6 +76,24 @@ impl FixtureControl {
     pub fn data_type(&self) -> Datatype {
         self.data_type
     }
+    /// Get the data type that this control's source should be read as.
+    /// Conversion strategies only apply to the scalar types.
+    pub fn source_type(&self) -> Datatype {
+        match (self.conversion, self.data_type) {
+            (Some(_), Datatype::Unipolar) => Datatype::Bipolar,
+            (Some(_), Datatype::Bipolar) => Datatype::Unipolar,
+            (_, data_type) => data_type,
+        }
+    }
+    /// Get the conversion strategy used by this control, if any.
+    pub fn conversion(&self) -> Option<Conversion> {
+        self.conversion
+    }
+    /// Select the conversion strategy used by this control, or read its source as its native
+    /// type if None.
+    pub fn set_conversion(&mut self, conversion: Option<Conversion>) {
+        self.conversion = conversion;
+    }
 }
 
 // ------------------
@@ -67,11 +102,29 @@ impl FixtureControl {
 
 pub type RenderFunc = fn(&[FixtureControl], &mut [DmxValue]);
 
+/// The means by which a fixture renders its controls into DMX.
+#[derive(Clone)]
+pub enum Renderer {
+    /// A precompiled render function.
+    Func(RenderFunc),
+    /// A profile loaded from a file, which knows how to render itself.
+    Description(Arc<ProfileDescription>),
+}
+
+impl Renderer {
+    fn render(&self, controls: &[FixtureControl], buffer: &mut [DmxValue]) {
+        match *self {
+            Renderer::Func(func) => func(controls, buffer),
+            Renderer::Description(ref description) => description.render(controls, buffer),
+        }
+    }
+}
+
 struct RenderAction {
     /// The name of this render action, probably the same as the associated fixture type.
     /// Used to round-trip this action through serde.
     name: String,
-    func: RenderFunc,
+    renderer: Renderer,
 }
 
 impl RenderAction {
@@ -99,11 +152,11 @@ impl Eq for RenderAction {}
 
 impl FromStr for RenderAction {
     type Err = String;
-    /// Use the precompiled table of render functions to try to look up this render action.
+    /// Use the table of fixture profiles to try to look up this render action.
     /// Used during deserialization.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        render_func_for_type(s)
-            .map(|func| RenderAction {name: s.to_string(), func: func})
+        renderer_for_type(s)
+            .map(|renderer| RenderAction {name: s.to_string(), renderer: renderer})
             .ok_or(format!("Unknown fixture type: '{}'.", s))
     }
 }
@@ -156,9 +209,9 @@ impl DmxFixture {
             kind: K,
             channel_count: DmxChannelCount,
             controls: Vec<FixtureControl>,
-            render_func: RenderFunc) -> Self {
+            renderer: Renderer) -> Self {
         let kind = kind.into();
-        let render_action = RenderAction {name: kind.clone(), func: render_func};
+        let render_action = RenderAction {name: kind.clone(), renderer: renderer};
         DmxFixture {
             kind: kind,
             channel_count: channel_count,
@@ -176,7 +229,7 @@ impl DmxFixture {
     /// Use this fixture's render func and its controls to render into a DMX buffer.
     pub fn render(&self, buffer: &mut [DmxValue]) {
         debug_assert!(buffer.len() == self.channel_count as usize);
-        (self.render_action.func)(&self.controls, buffer);
+        self.render_action.renderer.render(&self.controls, buffer);
     }
 
     pub fn control_count(&self) -> usize {
@@ -192,4 +245,9 @@ impl DmxFixture {
     pub fn controls_mut(&mut self) -> IterMut<FixtureControl> {
         self.controls.iter_mut()
     }
+
+    /// Get a mutable reference to a single control, if it exists.
+    pub fn control_mut(&mut self, control_id: usize) -> Option<&mut FixtureControl> {
+        self.controls.get_mut(control_id)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+//! The patch as stored in binary autosaves from before show versioning was introduced.
+//! Binary autosaves can only be read using the exact layout they were written with, so these types
+//! mirror the patch as it was then.  They serialize to the same JSON as the patch did, and anything
+//! added to the patch since is filled in with defaults when that JSON is loaded.
+use rust_dmx::SerializablePort;
+use wiggles_value::{Data, Datatype};
+use super::{FixtureId, UniverseId, DmxAddress, DmxChannelCount};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LegacyPatch<S> {
+    universes: Vec<Option<LegacyUniverse>>,
+    items: Vec<LegacyPatchItem<S>>,
+    next_id: FixtureId,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Ports aren't reopened here; that happens when the upgraded patch is loaded.
+struct LegacyUniverse {
+    port: SerializablePort,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyPatchItem<S> {
+    id: FixtureId,
+    name: String,
+    address: Option<(UniverseId, DmxAddress)>,
+    active: bool,
+    fixture: LegacyFixture,
+    control_sources: Vec<Option<S>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LegacyFixture {
+    kind: String,
+    channel_count: DmxChannelCount,
+    controls: Vec<LegacyControl>,
+    /// The name of the render action.
+    render_action: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// Controls gained a conversion strategy.
+struct LegacyControl {
+    name: String,
+    data_type: Datatype,
+    value: Data,
+}
@@ -7,24 +7,34 @@
 //! dream anyway.
 //! All DMX addresses are indexed from 0.  Conversion to index from 1 is left to the client.
 extern crate rust_dmx;
+extern crate dmx_protocol;
 
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
 extern crate wiggles_value;
 #[macro_use] extern crate lazy_static;
-#[cfg(test)] extern crate serde_json;
+#[macro_use] extern crate log;
+extern crate serde_json;
 #[cfg(test)] extern crate bincode;
 
 use std::fmt;
 use std::slice::Iter;
-use wiggles_value::{Data, Datatype};
+use wiggles_value::{Data, Datatype, Conversion};
 use rust_dmx::{DmxPort, Error as DmxPortError, OfflineDmxPort};
-pub use profiles::{Profile, PROFILES};
+pub use profiles::{Profile, ChannelPair, PROFILES};
+pub use profile_file::{ProfileDescription, ProfileError, load_profile, load_profiles};
 pub use fixture::{DmxFixture, DmxValue, DmxChannelCount, FixtureControl};
+pub use port::{open_port, available_ports, take_reopen_errors};
+pub use legacy::LegacyPatch;
 
+pub mod artnet;
 mod fixture;
+mod legacy;
 mod profiles;
+mod profile_file;
+mod port;
+pub mod sacn;
 mod test;
 
 /// DmxAddress, indexed from 1!  When indexing into a buffer, make sure to subtract 1.
@@ -41,6 +51,7 @@ fn valid_address(a: DmxAddress) -> Result<DmxAddress, PatchError> {
 }
 pub type UniverseId = u32;
 pub type FixtureId = u32;
+pub type GroupId = u32;
 
 // -----------------------
 // DMX Universe
@@ -56,7 +67,7 @@ fn empty_buffer() -> [DmxValue; UNIVERSE_SIZE] {
 
 #[derive(Serialize, Deserialize)]
 pub struct Universe {
-    #[serde(with="rust_dmx")]
+    #[serde(with="port")]
     port: Box<DmxPort>,
     #[serde(skip_serializing)]
     #[serde(skip_deserializing)]
@@ -174,7 +185,7 @@ impl<S> PatchItem<S> {
         for (source, control) in self.control_sources.iter().zip(self.fixture.controls_mut()) {
             
             let data = match *source {
-                Some(ref s) => data_source(s, control.data_type()),
+                Some(ref s) => data_source(s, control.source_type()),
                 None => Data::default_with_type_hint(Some(control.data_type())),
             };
             control.set_value(data);
@@ -182,6 +193,31 @@ impl<S> PatchItem<S> {
     }
 }
 
+// -------------------------
+// Fixture groups
+// -------------------------
+
+/// A named, ordered collection of fixtures, so that they can be controlled together.
+/// The order of the members is the order in which they are assigned successive sources.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixtureGroup {
+    id: GroupId,
+    pub name: String,
+    members: Vec<FixtureId>,
+}
+
+impl FixtureGroup {
+    /// Unique group id.
+    pub fn id(&self) -> GroupId {
+        self.id
+    }
+
+    /// The fixtures in this group, in order.
+    pub fn members(&self) -> &[FixtureId] {
+        &self.members
+    }
+}
+
 // -------------------------
 // The whole patch
 // -------------------------
@@ -191,6 +227,10 @@ pub struct Patch<S> {
     universes: Vec<Option<Universe>>,
     items: Vec<PatchItem<S>>,
     next_id: FixtureId,
+    #[serde(default)]
+    groups: Vec<FixtureGroup>,
+    #[serde(default)]
+    next_group_id: GroupId,
 }
 
 impl<S> Patch<S> {
@@ -199,6 +239,8 @@ impl<S> Patch<S> {
             universes: Vec::new(),
             items: Vec::new(),
             next_id: 0,
+            groups: Vec::new(),
+            next_group_id: 0,
         }
     }
 
@@ -307,9 +349,15 @@ impl<S> Patch<S> {
         }
 
     /// Remove a fixture by id, if it exists, and return it.
+    /// The fixture is also removed from any groups it was a member of.
     pub fn remove(&mut self, id: FixtureId) -> Result<PatchItem<S>, PatchError> {
         match self.items.iter().position(|item| item.id == id) {
-            Some(index) => Ok(self.items.swap_remove(index)),
+            Some(index) => {
+                for group in self.groups.iter_mut() {
+                    group.members.retain(|member| *member != id);
+                }
+                Ok(self.items.swap_remove(index))
+            }
             None => Err(PatchError::InvalidFixtureId(id)),
         }
     }
@@ -431,6 +479,29 @@ impl<S> Patch<S> {
         Ok(())
     }
 
+    /// Select the conversion strategy for one of a fixture's controls.
+    pub fn set_control_conversion(
+        &mut self,
+        id: FixtureId,
+        control_id: usize,
+        conversion: Option<Conversion>)
+        -> Result<(), PatchError>
+    {
+        let item = self.item_mut(id)?;
+        let control_count = item.fixture.control_count();
+        match item.fixture.control_mut(control_id) {
+            Some(control) => {
+                control.set_conversion(conversion);
+                Ok(())
+            }
+            None => Err(PatchError::ControlOutOfRange{
+                fixture: id,
+                control_id: control_id,
+                control_count: control_count,
+            }),
+        }
+    }
+
     /// Set the control source for a particular control ID to the provided value.
     pub fn set_control_source(
         &mut self,
@@ -453,6 +524,103 @@ impl<S> Patch<S> {
         }
     }
 
+    /// Get an immutable reference to every group.
+    pub fn groups(&self) -> &Vec<FixtureGroup> {
+        &self.groups
+    }
+
+    /// Get an immutable reference to a group by id, if it exists.
+    pub fn group(&self, id: GroupId) -> Result<&FixtureGroup, PatchError> {
+        self.groups.iter().find(|group| group.id == id).ok_or(PatchError::InvalidGroupId(id))
+    }
+
+    /// Get a mutable reference to a group by id, if it exists.
+    pub fn group_mut(&mut self, id: GroupId) -> Result<&mut FixtureGroup, PatchError> {
+        self.groups.iter_mut().find(|group| group.id == id).ok_or(PatchError::InvalidGroupId(id))
+    }
+
+    /// Check that every member exists, and drop any repeats, keeping the first appearance.
+    fn valid_members(&self, members: Vec<FixtureId>) -> Result<Vec<FixtureId>, PatchError> {
+        let mut valid = Vec::with_capacity(members.len());
+        for id in members {
+            self.item(id)?;
+            if !valid.contains(&id) {
+                valid.push(id);
+            }
+        }
+        Ok(valid)
+    }
+
+    /// Create a new group of fixtures, and return its id.
+    pub fn add_group(&mut self, name: String, members: Vec<FixtureId>)
+            -> Result<GroupId, PatchError> {
+        let members = self.valid_members(members)?;
+        let id = self.next_group_id;
+        self.next_group_id += 1;
+        self.groups.push(FixtureGroup {
+            id: id,
+            name: name,
+            members: members,
+        });
+        Ok(id)
+    }
+
+    /// Remove a group by id, if it exists, and return it.
+    /// The fixtures in the group are left alone.
+    pub fn remove_group(&mut self, id: GroupId) -> Result<FixtureGroup, PatchError> {
+        match self.groups.iter().position(|group| group.id == id) {
+            Some(index) => Ok(self.groups.remove(index)),
+            None => Err(PatchError::InvalidGroupId(id)),
+        }
+    }
+
+    /// Replace the members of a group.
+    /// Return a reference to the group if it exists.
+    pub fn set_group_members(&mut self, id: GroupId, members: Vec<FixtureId>)
+            -> Result<&FixtureGroup, PatchError> {
+        self.group(id)?;
+        let members = self.valid_members(members)?;
+        let group = self.group_mut(id)?;
+        group.members = members;
+        Ok(group)
+    }
+
+    /// Set the source of the control with this name on every member of a group.
+    /// Sources are provided for each member in order, so members can be given different sources.
+    /// Every member must have a control with this name, otherwise nothing is changed.
+    /// Return the ids of the fixtures that were changed.
+    pub fn set_group_control_source(
+        &mut self,
+        id: GroupId,
+        control_name: &str,
+        sources: Vec<Option<S>>)
+        -> Result<Vec<FixtureId>, PatchError>
+    {
+        let members = self.group(id)?.members.clone();
+        if sources.len() != members.len() {
+            return Err(PatchError::GroupSourceCount{
+                group: id,
+                member_count: members.len(),
+                source_count: sources.len(),
+            });
+        }
+        let mut control_ids = Vec::with_capacity(members.len());
+        for &member in &members {
+            let item = self.item(member)?;
+            match item.controls().position(|control| control.name() == control_name) {
+                Some(control_id) => control_ids.push(control_id),
+                None => return Err(PatchError::ControlNotFound{
+                    fixture: member,
+                    name: control_name.to_string(),
+                }),
+            }
+        }
+        for ((&member, control_id), source) in members.iter().zip(control_ids).zip(sources) {
+            self.set_control_source(member, control_id, source)?;
+        }
+        Ok(members)
+    }
+
     /// Set all of the control values of every fixture.
     pub fn set_controls<F>(&mut self, data_source: F)
         where F: Fn(&S, Datatype) -> Data
@@ -518,6 +686,9 @@ pub enum PatchError {
     NonEmptyUniverse(UniverseId),
     PortError(DmxPortError),
     ControlOutOfRange{fixture: FixtureId, control_id: usize, control_count: usize},
+    InvalidGroupId(GroupId),
+    ControlNotFound{fixture: FixtureId, name: String},
+    GroupSourceCount{group: GroupId, member_count: usize, source_count: usize},
 }
 
 impl fmt::Display for PatchError {
@@ -551,6 +722,17 @@ impl fmt::Display for PatchError {
                     control_id,
                     control_count,
                 ),
+            InvalidGroupId(id) => write!(f, "Invalid group id: {}.", id),
+            ControlNotFound{fixture, ref name} =>
+                write!(f, "Fixture {} has no control named '{}'.", fixture, name),
+            GroupSourceCount{group, member_count, source_count} =>
+                write!(
+                    f,
+                    "Group {} has {} members but {} sources were provided.",
+                    group,
+                    member_count,
+                    source_count,
+                ),
         }
     }
 }
@@ -567,6 +749,9 @@ impl std::error::Error for PatchError {
             NonEmptyUniverse(_) => "Universe is not empty.",
             PortError(ref pe) => pe.description(),
             ControlOutOfRange{..} => "Control ID out of range.",
+            InvalidGroupId(_) => "Invalid group id.",
+            ControlNotFound{..} => "Control not found.",
+            GroupSourceCount{..} => "Wrong number of sources for group.",
         }
     }
 
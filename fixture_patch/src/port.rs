@@ -0,0 +1,65 @@
+//! Opening DMX ports by namespace and port id.
+//! Serial DMX interfaces are provided by rust_dmx; network protocols are implemented here.
+//! Everything that opens or lists ports, including reloading a saved patch, should go through
+//! these functions so that every kind of port is available.
+use std::sync::Mutex;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use rust_dmx::{self, DmxPort, SerializablePort, OfflineDmxPort, Error as DmxPortError};
+use artnet::{self, ArtNetPort};
+use sacn::{self, SacnPort};
+
+lazy_static! {
+    /// Ports that couldn't be reopened while loading a patch, waiting to be reported.
+    static ref REOPEN_ERRORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+}
+
+/// Open a port by namespace and id.
+pub fn open_port(namespace: &str, id: &str) -> Result<Box<DmxPort>, DmxPortError> {
+    if namespace == artnet::NAMESPACE {
+        Ok(Box::new(ArtNetPort::open(id)?))
+    }
+    else if namespace == sacn::NAMESPACE {
+        Ok(Box::new(SacnPort::open(id)?))
+    }
+    else {
+        rust_dmx::open_port(namespace, id)
+    }
+}
+
+/// List every port that can currently be opened, as (namespace, id) pairs.
+pub fn available_ports() -> Vec<(String, String)> {
+    let mut ports = rust_dmx::available_ports();
+    ports.extend(artnet::available_ports());
+    ports.extend(sacn::available_ports());
+    ports
+}
+
+/// Serialize a port as its namespace and id, for use with serde's with attribute.
+pub fn serialize<S: Serializer>(port: &Box<DmxPort>, serializer: S) -> Result<S::Ok, S::Error> {
+    port.serializable().serialize(serializer)
+}
+
+/// Describe every port that couldn't be reopened since the last call, so that the problems can be
+/// reported to clients.
+pub fn take_reopen_errors() -> Vec<String> {
+    REOPEN_ERRORS.lock().unwrap().drain(..).collect()
+}
+
+/// Reopen a serialized port.
+/// If the port can't be opened, we record the problem for take_reopen_errors and use an offline
+/// port instead, so that the rest of the patch still loads.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<DmxPort>, D::Error>
+    where D: Deserializer<'de>
+{
+    let port = SerializablePort::deserialize(deserializer)?;
+    match open_port(&port.namespace, &port.port_name) {
+        Ok(opened) => Ok(opened),
+        Err(e) => {
+            let message =
+                format!("Could not reopen port {}/{}: {}", port.namespace, port.port_name, e);
+            error!("{}", message);
+            REOPEN_ERRORS.lock().unwrap().push(message);
+            Ok(Box::new(OfflineDmxPort))
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Fixture profiles described by data rather than code.
+//! A profile file is a JSON description of a fixture's DMX channels and the controls that drive
+//! them.  Every profile file in the profile directory is loaded at startup alongside the
+//! precompiled profiles, so adding a fixture does not need a recompile.  The profile directory is
+//! fixture_profiles next to the executable, unless WIGGLES_PROFILE_PATH says otherwise; the
+//! fixture_profiles directory in this crate holds samples to start from.
+//!
+//! For example, a scanner with a 16-bit pan, an 8-bit tilt, a gobo wheel, and a dimmer that
+//! starts at full, with a mode channel that must be held at 255:
+//!
+//! ```text
+//! {
+//!     "name": "acme:Scanner",
+//!     "description": "Mirror scanner with gobo wheel.",
+//!     "channel_count": 6,
+//!     "controls": [
+//!         {
+//!             "name": "position",
+//!             "datatype": "Position",
+//!             "render": {
+//!                 "kind": "position",
+//!                 "pan": {"channel": 0, "fine": 1},
+//!                 "tilt": {"channel": 2}
+//!             }
+//!         },
+//!         {
+//!             "name": "gobo",
+//!             "datatype": {"Index": 3},
+//!             "render": {"kind": "slots", "channel": 3, "slots": [
+//!                 {"name": "open", "value": 0},
+//!                 {"name": "dots", "value": 16},
+//!                 {"name": "star", "value": 32}
+//!             ]}
+//!         },
+//!         {
+//!             "name": "level",
+//!             "datatype": "Unipolar",
+//!             "default": {"Unipolar": 1.0},
+//!             "render": {"kind": "range", "channel": 4, "min": 8, "max": 255}
+//!         }
+//!     ],
+//!     "fixed": [{"channel": 5, "value": 255}]
+//! }
+//! ```
+//!
+//! Channels are numbered from 0 within the fixture.  Ranges default to the full DMX range, and
+//! controls default to the default value of their datatype; a default must be of the control's
+//! datatype.  Color controls use three or four consecutive channels, in the order named by their
+//! mixing.
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde_json;
+use wiggles_value::{Data, Datatype, Unipolar, Bipolar, Color, Position, Index};
+use fixture::{FixtureControl, DmxValue, DmxChannelCount};
+use profiles::{
+    Profile,
+    ChannelPair,
+    unipolar_as_range,
+    bipolar_as_range,
+    unipolar_as_coarse_fine,
+    bipolar_as_coarse_fine,
+};
+
+/// File extension of profile files; any other files in the profile directory are ignored.
+const PROFILE_EXTENSION: &'static str = "json";
+
+/// A complete fixture profile, as found in a profile file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfileDescription {
+    /// Unique name of this kind of fixture, by convention "maker:model".
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub channel_count: DmxChannelCount,
+    pub controls: Vec<ControlDescription>,
+    /// Channels that always render the same value, such as mode selection channels.
+    #[serde(default)]
+    pub fixed: Vec<FixedChannel>,
+}
+
+/// A single control, and how to render it into DMX.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ControlDescription {
+    pub name: String,
+    pub datatype: Datatype,
+    /// The initial value of this control.  The default for the datatype if not provided.
+    #[serde(default)]
+    pub default: Option<Data>,
+    pub render: ControlRender,
+}
+
+/// The ways a control can be rendered into DMX.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ControlRender {
+    /// Spread a unipolar or bipolar control evenly across a range of DMX values.
+    Range(Channel),
+    /// Render an index control as one of a table of DMX values, one for each item.
+    Slots { channel: DmxChannelCount, slots: Vec<Slot> },
+    /// Mix a color control into consecutive channels starting at channel.
+    Color { channel: DmxChannelCount, mixing: ColorMixing },
+    /// Render the x and y axes of a position control as pan and tilt.
+    Position { pan: Channel, tilt: Channel },
+}
+
+fn min_value() -> DmxValue { 0 }
+fn max_value() -> DmxValue { 255 }
+
+/// A DMX channel, optionally paired with a fine channel for 16-bit resolution, along with the
+/// range of values a control is spread over.
+/// When paired, min and max are the range of the coarse channel.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Channel {
+    pub channel: DmxChannelCount,
+    #[serde(default)]
+    pub fine: Option<DmxChannelCount>,
+    #[serde(default = "min_value")]
+    pub min: DmxValue,
+    #[serde(default = "max_value")]
+    pub max: DmxValue,
+}
+
+impl Channel {
+    /// The channels this occupies.
+    fn channels(&self) -> Vec<DmxChannelCount> {
+        let mut channels = vec!(self.channel);
+        channels.extend(self.fine);
+        channels
+    }
+
+    /// Render a unipolar or bipolar value into this channel, and its fine channel if it has one.
+    /// Bipolar values are spread across the range with -1.0 at the bottom.
+    fn render(&self, value: Data, datatype: Datatype, buffer: &mut [DmxValue]) {
+        let centered = datatype == Datatype::Bipolar;
+        match self.fine {
+            None => {
+                buffer[self.channel as usize] = if centered {
+                    bipolar_as_range(value, self.min, self.max)
+                }
+                else {
+                    unipolar_as_range(value, self.min, self.max)
+                };
+            }
+            Some(fine) => {
+                let (coarse_val, fine_val) = if centered {
+                    bipolar_as_coarse_fine(value, self.min, self.max)
+                }
+                else {
+                    unipolar_as_coarse_fine(value, self.min, self.max)
+                };
+                buffer[self.channel as usize] = coarse_val;
+                buffer[fine as usize] = fine_val;
+            }
+        }
+    }
+}
+
+/// One entry in a table of discrete DMX values, such as a gobo or color wheel slot.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Slot {
+    #[serde(default)]
+    pub name: String,
+    pub value: DmxValue,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMixing {
+    Rgb,
+    Cmy,
+    Rgbw,
+    Rgba,
+}
+
+impl ColorMixing {
+    fn channel_count(&self) -> DmxChannelCount {
+        match *self {
+            ColorMixing::Rgb | ColorMixing::Cmy => 3,
+            ColorMixing::Rgbw | ColorMixing::Rgba => 4,
+        }
+    }
+
+    /// Decompose a color into the levels of each channel.
+    fn levels(&self, color: Color) -> Vec<f64> {
+        match *self {
+            ColorMixing::Rgb => vec!(color.red, color.green, color.blue),
+            ColorMixing::Cmy => {
+                let (c, m, y) = color.to_cmy();
+                vec!(c, m, y)
+            }
+            ColorMixing::Rgbw => {
+                let (r, g, b, w) = color.to_rgbw();
+                vec!(r, g, b, w)
+            }
+            ColorMixing::Rgba => {
+                let (r, g, b, a) = color.to_rgba();
+                vec!(r, g, b, a)
+            }
+        }
+    }
+}
+
+/// A channel that always renders the same value.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FixedChannel {
+    pub channel: DmxChannelCount,
+    pub value: DmxValue,
+}
+
+impl ControlRender {
+    /// The coarse/fine channel pairs this occupies.
+    fn paired_channels(&self) -> Vec<ChannelPair> {
+        let pair = |channel: &Channel| channel.fine.map(|fine| (channel.channel, fine));
+        match *self {
+            ControlRender::Range(ref channel) => pair(channel).into_iter().collect(),
+            ControlRender::Position { ref pan, ref tilt } =>
+                pair(pan).into_iter().chain(pair(tilt)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The channels this occupies.
+    fn channels(&self) -> Result<Vec<DmxChannelCount>, String> {
+        match *self {
+            ControlRender::Range(ref channel) => Ok(channel.channels()),
+            ControlRender::Slots { channel, .. } => Ok(vec!(channel)),
+            ControlRender::Color { channel, mixing } => {
+                let end = channel.checked_add(mixing.channel_count()).ok_or_else(|| format!(
+                    "{} color channels starting at {} run past the last channel",
+                    mixing.channel_count(),
+                    channel))?;
+                Ok((channel..end).collect())
+            }
+            ControlRender::Position { ref pan, ref tilt } => {
+                let mut channels = pan.channels();
+                channels.extend(tilt.channels());
+                Ok(channels)
+            }
+        }
+    }
+
+    /// Check that this can render a control of this datatype.
+    fn check_datatype(&self, datatype: Datatype) -> Result<(), String> {
+        let ok = match (self, datatype) {
+            (&ControlRender::Range(_), Datatype::Unipolar) => true,
+            (&ControlRender::Range(_), Datatype::Bipolar) => true,
+            (&ControlRender::Slots { ref slots, .. }, Datatype::Index(count)) => {
+                if slots.is_empty() {
+                    return Err("an index needs at least one slot".to_string());
+                }
+                if slots.len() != count as usize {
+                    return Err(format!(
+                        "an index of {} needs {} slots, found {}",
+                        count,
+                        count,
+                        slots.len()));
+                }
+                true
+            }
+            (&ControlRender::Color { .. }, Datatype::Color) => true,
+            (&ControlRender::Position { .. }, Datatype::Position) => true,
+            _ => false,
+        };
+        if ok {
+            Ok(())
+        }
+        else {
+            Err(format!("cannot render a {:?} control this way", datatype))
+        }
+    }
+
+    /// Check that every range is not empty.
+    fn check_ranges(&self) -> Result<(), String> {
+        let check = |channel: &Channel| {
+            if channel.max > channel.min {
+                Ok(())
+            }
+            else {
+                Err(format!(
+                    "range of channel {} has max {} not above min {}",
+                    channel.channel,
+                    channel.max,
+                    channel.min))
+            }
+        };
+        match *self {
+            ControlRender::Range(ref channel) => check(channel),
+            ControlRender::Position { ref pan, ref tilt } => check(pan).and(check(tilt)),
+            _ => Ok(()),
+        }
+    }
+
+    fn render(&self, control: &FixtureControl, buffer: &mut [DmxValue]) {
+        let value = control.value();
+        match *self {
+            ControlRender::Range(ref channel) =>
+                channel.render(value, control.data_type(), buffer),
+            ControlRender::Slots { channel, ref slots } => {
+                let Index { index, .. } = Index::from_data(value, slots.len() as u32);
+                buffer[channel as usize] = slots[index as usize].value;
+            }
+            ControlRender::Color { channel, mixing } => {
+                let levels = mixing.levels(Color::from(value));
+                for (offset, level) in levels.iter().enumerate() {
+                    buffer[channel as usize + offset] =
+                        unipolar_as_range(Data::Unipolar(Unipolar(*level)), 0, 255);
+                }
+            }
+            ControlRender::Position { ref pan, ref tilt } => {
+                let Position { x, y } = Position::from(value);
+                pan.render(Data::Bipolar(Bipolar(x)), Datatype::Bipolar, buffer);
+                tilt.render(Data::Bipolar(Bipolar(y)), Datatype::Bipolar, buffer);
+            }
+        }
+    }
+}
+
+impl ProfileDescription {
+    /// Check that this profile makes sense, so that it cannot fail to render.
+    fn validate(&self) -> Result<(), ProfileError> {
+        let invalid = |reason: String| ProfileError::Invalid(self.name.clone(), reason);
+        let mut used = HashSet::new();
+        let mut use_channel = |channel: DmxChannelCount| {
+            if channel >= self.channel_count {
+                Err(invalid(format!(
+                    "channel {} is out of range for {} channels", channel, self.channel_count)))
+            }
+            else if !used.insert(channel) {
+                Err(invalid(format!("channel {} is used more than once", channel)))
+            }
+            else {
+                Ok(())
+            }
+        };
+        for control in &self.controls {
+            let in_control = |reason: String| invalid(format!("{}: {}", control.name, reason));
+            control.render.check_datatype(control.datatype).map_err(&in_control)?;
+            if let Some(default) = control.default {
+                if default.datatype() != control.datatype {
+                    return Err(in_control(format!(
+                        "default is a {:?}, not a {:?}", default.datatype(), control.datatype)));
+                }
+            }
+            control.render.check_ranges().map_err(&in_control)?;
+            for channel in control.render.channels().map_err(&in_control)? {
+                use_channel(channel)?;
+            }
+        }
+        for fixed in &self.fixed {
+            use_channel(fixed.channel)?;
+        }
+        Ok(())
+    }
+
+    /// Every coarse/fine channel pair used by this profile.
+    pub fn paired_channels(&self) -> Vec<ChannelPair> {
+        self.controls.iter().flat_map(|control| control.render.paired_channels()).collect()
+    }
+
+    /// Create a fresh set of controls for a fixture of this kind.
+    pub fn controls(&self) -> Vec<FixtureControl> {
+        self.controls.iter().map(|control| {
+            let initial = control.default.unwrap_or(
+                Data::default_with_type_hint(Some(control.datatype)));
+            FixtureControl::new(control.name.clone(), control.datatype, initial)
+        }).collect()
+    }
+
+    /// Render a fixture of this kind into its DMX buffer.
+    pub fn render(&self, controls: &[FixtureControl], buffer: &mut [DmxValue]) {
+        debug_assert!(controls.len() == self.controls.len());
+        debug_assert!(buffer.len() == self.channel_count as usize);
+        for (description, control) in self.controls.iter().zip(controls) {
+            description.render.render(control, buffer);
+        }
+        for fixed in &self.fixed {
+            buffer[fixed.channel as usize] = fixed.value;
+        }
+    }
+}
+
+impl Profile {
+    /// Create a profile from a description, ensuring that it is valid.
+    pub fn from_description(description: ProfileDescription) -> Result<Self, ProfileError> {
+        description.validate()?;
+        Ok(Profile::described(
+            Cow::Owned(description.name.clone()),
+            Cow::Owned(description.description.clone()),
+            description.channel_count,
+            Arc::new(description)))
+    }
+}
+
+/// Load a single profile file.
+pub fn load_profile<P: AsRef<Path>>(path: P) -> Result<Profile, ProfileError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| ProfileError::Io(path.to_path_buf(), e))?;
+    let description: ProfileDescription =
+        serde_json::from_reader(file).map_err(|e| ProfileError::Parse(path.to_path_buf(), e))?;
+    Profile::from_description(description)
+}
+
+/// Load every profile file in a directory.
+/// Files that fail to load are skipped, and their errors are returned alongside the profiles
+/// that loaded successfully.  A missing directory simply contains no profiles.
+pub fn load_profiles<P: AsRef<Path>>(dir: P) -> (Vec<Profile>, Vec<ProfileError>) {
+    let dir = dir.as_ref();
+    let mut profiles = Vec::new();
+    let mut errors = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return (profiles, errors),
+        Err(e) => {
+            errors.push(ProfileError::Io(dir.to_path_buf(), e));
+            return (profiles, errors);
+        }
+    };
+    let mut paths = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => paths.push(entry.path()),
+            Err(e) => errors.push(ProfileError::Io(dir.to_path_buf(), e)),
+        }
+    }
+    // Load in a consistent order so duplicates are resolved the same way every time.
+    paths.sort();
+    for path in paths {
+        if path.extension().map(|ext| ext != PROFILE_EXTENSION).unwrap_or(true) {
+            continue;
+        }
+        match load_profile(&path) {
+            Ok(profile) => profiles.push(profile),
+            Err(e) => errors.push(e),
+        }
+    }
+    (profiles, errors)
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, serde_json::Error),
+    /// The named profile doesn't make sense, for the given reason.
+    Invalid(String, String),
+    /// A profile with this name already exists.
+    Duplicate(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ProfileError::*;
+        match *self {
+            Io(ref path, ref e) => write!(f, "Could not read {}: {}", path.display(), e),
+            Parse(ref path, ref e) =>
+                write!(f, "Could not parse profile {}: {}", path.display(), e),
+            Invalid(ref name, ref reason) => write!(f, "Invalid profile '{}': {}.", name, reason),
+            Duplicate(ref name) => write!(f, "A profile named '{}' already exists.", name),
+        }
+    }
+}
+
+impl Error for ProfileError {
+    fn description(&self) -> &str {
+        use self::ProfileError::*;
+        match *self {
+            Io(_, ref e) => e.description(),
+            Parse(_, ref e) => e.description(),
+            Invalid(..) => "Invalid profile.",
+            Duplicate(_) => "Duplicate profile name.",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ProfileError::Io(_, ref e) => Some(e),
+            ProfileError::Parse(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -1,11 +1,21 @@
-//! Precompiled DMX fixture types for general use.
-//! Would be good to implement a domain-specific description of these things
-//! so that they can be parsed and created dynamically, allowing for the
-//! creation of a fixture editor.
+//! DMX fixture types for general use.
+//! A handful of precompiled profiles are declared here; the rest are described by profile files
+//! which are loaded from the profile directory at startup.  See profile_file for the format.
+use std::borrow::Cow;
 use std::cmp::min;
 use std::collections::HashMap;
-use wiggles_value::{Data, Datatype, Unipolar, Bipolar};
-use fixture::{DmxFixture, FixtureControl, DmxValue, RenderFunc, DmxChannelCount};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wiggles_value::{Data, Datatype, Unipolar, Bipolar, Color};
+use fixture::{DmxFixture, FixtureControl, DmxValue, RenderFunc, Renderer, DmxChannelCount};
+use profile_file::{ProfileDescription, ProfileError, load_profiles};
+
+/// Directory to load profile files from, unless overridden by PROFILE_PATH_VAR.
+/// This is found next to the executable, so it doesn't matter where we are started from.
+const DEFAULT_PROFILE_DIR: &'static str = "fixture_profiles";
+/// Environment variable that may be set to load profile files from a different directory.
+const PROFILE_PATH_VAR: &'static str = "WIGGLES_PROFILE_PATH";
 
 // Helper functions for converting wiggles values into DMX.
 /// Interpret as unipolar and map directly to dmx values.
@@ -14,7 +24,7 @@ fn as_single_channel(data: Data) -> DmxValue {
 }
 
 /// Spread a unipolar value evenly across a DMX interval.
-fn unipolar_as_range(data: Data, min_val: DmxValue, max_val: DmxValue) -> DmxValue {
+pub fn unipolar_as_range(data: Data, min_val: DmxValue, max_val: DmxValue) -> DmxValue {
     debug_assert!(max_val > min_val);
     let Unipolar(val) = data.into();
     let range_delta = max_val as usize - min_val as usize + 1;
@@ -23,7 +33,7 @@ fn unipolar_as_range(data: Data, min_val: DmxValue, max_val: DmxValue) -> DmxVal
 }
 
 /// Spread a bipolar value evenly across a DMX interval.
-fn bipolar_as_range(data: Data, min_val: DmxValue, max_val: DmxValue) -> DmxValue {
+pub fn bipolar_as_range(data: Data, min_val: DmxValue, max_val: DmxValue) -> DmxValue {
     debug_assert!(max_val > min_val);
     let Bipolar(val) = data.into();
     let val = (val + 1.0) / 2.0;
@@ -46,6 +56,43 @@ fn bipolar_as_unequal_range(
     }
 }
 
+/// Spread a value on the unit range evenly across a 16-bit interval, rounding to the nearest
+/// step.  The interval runs from min_val on the coarse channel and 0 on the fine channel, up to
+/// max_val on the coarse channel and 255 on the fine channel.
+fn fraction_as_coarse_fine(
+        val: f64, min_val: DmxValue, max_val: DmxValue) -> (DmxValue, DmxValue) {
+    debug_assert!(max_val > min_val);
+    let bottom = (min_val as u16) << 8;
+    let top = ((max_val as u16) << 8) | 0xff;
+    let val = val.max(0.0).min(1.0);
+    let scaled = bottom + (val * (top - bottom) as f64).round() as u16;
+    ((scaled >> 8) as DmxValue, (scaled & 0xff) as DmxValue)
+}
+
+/// Spread a unipolar value evenly across a coarse/fine channel pair.
+/// Return the values of the coarse and fine channels.
+pub fn unipolar_as_coarse_fine(
+        data: Data, min_val: DmxValue, max_val: DmxValue) -> (DmxValue, DmxValue) {
+    let Unipolar(val) = data.into();
+    fraction_as_coarse_fine(val, min_val, max_val)
+}
+
+/// Spread a bipolar value evenly across a coarse/fine channel pair.
+/// Return the values of the coarse and fine channels.
+pub fn bipolar_as_coarse_fine(
+        data: Data, min_val: DmxValue, max_val: DmxValue) -> (DmxValue, DmxValue) {
+    let Bipolar(val) = data.into();
+    fraction_as_coarse_fine((val + 1.0) / 2.0, min_val, max_val)
+}
+
+/// Render a list of unipolar levels into consecutive full-range DMX channels.
+fn as_channels(levels: &[f64], buffer: &mut [DmxValue]) {
+    debug_assert!(levels.len() == buffer.len());
+    for (level, channel) in levels.iter().zip(buffer.iter_mut()) {
+        *channel = as_single_channel(Data::Unipolar(Unipolar(*level)));
+    }
+}
+
 mod test_helpers {
     use super::*;
     #[test]
@@ -94,65 +141,170 @@ mod test_helpers {
         check(64, 0.0);
         check(255, 1.0);
     }
+
+    #[test]
+    fn test_unipolar_as_coarse_fine() {
+        fn check_full_range(expected: (DmxValue, DmxValue), unipolar: f64) {
+            assert_eq!(
+                expected, unipolar_as_coarse_fine(Data::Unipolar(Unipolar(unipolar)), 0, 255));
+        }
+        check_full_range((0, 0), 0.0);
+        check_full_range((255, 255), 1.0);
+        check_full_range((128, 0), 0.5);
+        // round to the nearest step, in both directions
+        check_full_range((0, 1), 0.6 / 65535.0);
+        check_full_range((0, 0), 0.4 / 65535.0);
+        check_full_range((255, 254), 1.0 - 1.4 / 65535.0);
+        check_full_range((1, 0), 256.0 / 65535.0);
+
+        fn check_half_range(expected: (DmxValue, DmxValue), unipolar: f64) {
+            assert_eq!(
+                expected, unipolar_as_coarse_fine(Data::Unipolar(Unipolar(unipolar)), 128, 255));
+        }
+        check_half_range((128, 0), 0.0);
+        check_half_range((192, 0), 0.5);
+        check_half_range((255, 255), 1.0);
+    }
+
+    #[test]
+    fn test_bipolar_as_coarse_fine() {
+        fn check_full_range(expected: (DmxValue, DmxValue), bipolar: f64) {
+            assert_eq!(expected, bipolar_as_coarse_fine(Data::Bipolar(Bipolar(bipolar)), 0, 255));
+        }
+        check_full_range((0, 0), -1.0);
+        check_full_range((128, 0), 0.0);
+        check_full_range((255, 255), 1.0);
+        check_full_range((64, 0), -0.5);
+    }
 }
 
 type ControlsCreator = fn() -> Vec<FixtureControl>;
 
+/// A coarse channel and the fine channel that extends it to 16 bits, numbered from 0.
+pub type ChannelPair = (DmxChannelCount, DmxChannelCount);
+
+/// Where a profile's controls and rendering come from.
+enum Source {
+    Compiled(ControlsCreator, RenderFunc),
+    File(Arc<ProfileDescription>),
+}
+
 /// Roll up all the data needed to instantiate a fixture of a particular type.
 pub struct Profile {
-    name: &'static str,
-    description: &'static str,
+    name: Cow<'static, str>,
+    description: Cow<'static, str>,
     channel_count: DmxChannelCount,
-    controls: ControlsCreator,
-    render_func: RenderFunc,
+    /// Channels that are rendered together as coarse/fine pairs.
+    paired_channels: Cow<'static, [ChannelPair]>,
+    source: Source,
 }
 
 impl Profile {
-    pub fn name(&self) -> &'static str {
-        self.name
+    /// A profile described by a profile file.
+    pub fn described(
+            name: Cow<'static, str>,
+            description: Cow<'static, str>,
+            channel_count: DmxChannelCount,
+            profile: Arc<ProfileDescription>) -> Self {
+        Profile {
+            name: name,
+            description: description,
+            channel_count: channel_count,
+            paired_channels: Cow::Owned(profile.paired_channels()),
+            source: Source::File(profile),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
-    pub fn description(&self) -> &'static str {
-        self.description
+    pub fn description(&self) -> &str {
+        &self.description
     }
 
     pub fn channel_count(&self) -> DmxChannelCount {
         self.channel_count
     }
 
+    pub fn paired_channels(&self) -> &[ChannelPair] {
+        &self.paired_channels
+    }
+
+    fn renderer(&self) -> Renderer {
+        match self.source {
+            Source::Compiled(_, render_func) => Renderer::Func(render_func),
+            Source::File(ref description) => Renderer::Description(description.clone()),
+        }
+    }
+
     pub fn create_fixture(&self) -> DmxFixture {
-        DmxFixture::new(self.name.clone(), self.channel_count, (self.controls)(), self.render_func)
+        let controls = match self.source {
+            Source::Compiled(controls, _) => controls(),
+            Source::File(ref description) => description.controls(),
+        };
+        DmxFixture::new(self.name.to_string(), self.channel_count, controls, self.renderer())
     }
 }
 
-type ProfileMap = HashMap<&'static str, Profile>;
+type ProfileMap = HashMap<String, Profile>;
+
+/// The directory to load profile files from.
+fn profile_path() -> PathBuf {
+    env::var_os(PROFILE_PATH_VAR).map(PathBuf::from).unwrap_or_else(|| {
+        let exe_dir = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf));
+        if exe_dir.is_none() {
+            warn!("Could not find the executable; looking for profiles in the working directory.");
+        }
+        exe_dir.unwrap_or_default().join(DEFAULT_PROFILE_DIR)
+    })
+}
 
 // Define all of the available profiles here.
 lazy_static! {
     /// Runtime lookup for every available fixture profile.
+    /// Profile files that fail to load, or that reuse the name of an existing profile, are
+    /// logged and skipped.
     pub static ref PROFILES: ProfileMap = {
         let mut m = HashMap::new();
         {
             let mut add = |profile: Profile| {
-                if m.contains_key(profile.name) {
+                if m.contains_key(profile.name()) {
                     panic!("Duplicate declaration of profile {}", profile.name);
                 }
-                m.insert(profile.name, profile);
+                m.insert(profile.name().to_string(), profile);
             };
             add(dimmer::PROFILE);
+            add(dimmer_16_bit::PROFILE);
             add(apollo_smart_move_dmx::PROFILE);
             add(apollo_roto_q_dmx::PROFILE);
             add(clay_paky_astroraggi_power::PROFILE);
             add(clay_paky_atlas::PROFILE);
+            add(generic_rgb::PROFILE);
+            add(generic_cmy::PROFILE);
+            add(generic_rgbw::PROFILE);
+            add(generic_rgba::PROFILE);
+        }
+        let path = profile_path();
+        let (profiles, errors) = load_profiles(&path);
+        for e in errors {
+            error!("{}", e);
+        }
+        for profile in profiles {
+            if m.contains_key(profile.name()) {
+                error!("{}", ProfileError::Duplicate(profile.name().to_string()));
+                continue;
+            }
+            m.insert(profile.name().to_string(), profile);
         }
         m
     };
 }
 
-/// Match a fixture profile name to a RenderFunc.
+/// Match a fixture profile name to a Renderer.
 /// Used during deserialization of saved states.
-pub fn render_func_for_type(name: &str) -> Option<RenderFunc> {
-    PROFILES.get(name).map(|profile| profile.render_func)
+pub fn renderer_for_type(name: &str) -> Option<Renderer> {
+    PROFILES.get(name).map(Profile::renderer)
 }
 
 // declare profiles in individual modules
@@ -166,11 +318,11 @@ pub mod dimmer {
     /// Basic 1-channel dimmer.
     /// Controlled by a single unipolar.
     pub const PROFILE: Profile = Profile {
-        name: "dimmer",
-        description: "1-channel linear dimmer.",
+        name: Cow::Borrowed("dimmer"),
+        description: Cow::Borrowed("1-channel linear dimmer."),
         channel_count: CHANNEL_COUNT,
-        controls: controls,
-        render_func: render,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
     };
 
     fn controls() -> Vec<FixtureControl> {
@@ -185,6 +337,150 @@ pub mod dimmer {
     }
 }
 
+/// 2-channel dimmer with 16-bit resolution.
+pub mod dimmer_16_bit {
+    use super::*;
+
+    const CHANNEL_COUNT: DmxChannelCount = 2;
+
+    /// 16-bit dimmer, for smooth fades on fixtures with a fine dimmer channel.
+    /// Controlled by a single unipolar; channels are coarse, fine.
+    pub const PROFILE: Profile = Profile {
+        name: Cow::Borrowed("dimmer 16 bit"),
+        description: Cow::Borrowed("2-channel linear dimmer with fine channel."),
+        channel_count: CHANNEL_COUNT,
+        paired_channels: Cow::Borrowed(&[(0, 1)]),
+        source: Source::Compiled(controls, render),
+    };
+
+    fn controls() -> Vec<FixtureControl> {
+        vec!(FixtureControl::new("level", Datatype::Unipolar, Data::Unipolar(Unipolar(0.0))))
+    }
+
+    fn render(controls: &[FixtureControl], buffer: &mut [DmxValue]) {
+        debug_assert!(controls.len() == 1);
+        debug_assert!(buffer.len() == CHANNEL_COUNT as usize);
+        let (coarse, fine) = unipolar_as_coarse_fine(controls[0].value(), 0, 255);
+        buffer[0] = coarse;
+        buffer[1] = fine;
+    }
+}
+
+/// Generic 3-channel RGB color mixing fixture.
+pub mod generic_rgb {
+    use super::*;
+
+    const CHANNEL_COUNT: DmxChannelCount = 3;
+
+    /// Generic RGB fixture, controlled by a single color.
+    /// Channels are red, green, blue.
+    pub const PROFILE: Profile = Profile {
+        name: Cow::Borrowed("generic:RGB"),
+        description: Cow::Borrowed("3-channel RGB color mixing fixture."),
+        channel_count: CHANNEL_COUNT,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
+    };
+
+    fn controls() -> Vec<FixtureControl> {
+        vec!(FixtureControl::new("color", Datatype::Color, Data::Color(Color::default())))
+    }
+
+    fn render(controls: &[FixtureControl], buffer: &mut [DmxValue]) {
+        debug_assert!(controls.len() == 1);
+        debug_assert!(buffer.len() == CHANNEL_COUNT as usize);
+        let color = Color::from(controls[0].value());
+        as_channels(&[color.red, color.green, color.blue], buffer);
+    }
+}
+
+/// Generic 3-channel CMY subtractive color mixing fixture.
+pub mod generic_cmy {
+    use super::*;
+
+    const CHANNEL_COUNT: DmxChannelCount = 3;
+
+    /// Generic CMY fixture, such as a color mixing module with dichroic flags.
+    /// Controlled by a single color; channels are cyan, magenta, yellow.
+    /// Note that a CMY fixture cannot dim, so black renders as full saturation of every flag.
+    pub const PROFILE: Profile = Profile {
+        name: Cow::Borrowed("generic:CMY"),
+        description: Cow::Borrowed("3-channel CMY subtractive color mixing."),
+        channel_count: CHANNEL_COUNT,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
+    };
+
+    fn controls() -> Vec<FixtureControl> {
+        vec!(FixtureControl::new("color", Datatype::Color, Data::Color(Color::rgb(1.0, 1.0, 1.0))))
+    }
+
+    fn render(controls: &[FixtureControl], buffer: &mut [DmxValue]) {
+        debug_assert!(controls.len() == 1);
+        debug_assert!(buffer.len() == CHANNEL_COUNT as usize);
+        let (cyan, magenta, yellow) = Color::from(controls[0].value()).to_cmy();
+        as_channels(&[cyan, magenta, yellow], buffer);
+    }
+}
+
+/// Generic 4-channel RGBW color mixing fixture.
+pub mod generic_rgbw {
+    use super::*;
+
+    const CHANNEL_COUNT: DmxChannelCount = 4;
+
+    /// Generic RGBW fixture, controlled by a single color.
+    /// The white emitter carries as much of the color as possible.
+    /// Channels are red, green, blue, white.
+    pub const PROFILE: Profile = Profile {
+        name: Cow::Borrowed("generic:RGBW"),
+        description: Cow::Borrowed("4-channel RGBW color mixing fixture."),
+        channel_count: CHANNEL_COUNT,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
+    };
+
+    fn controls() -> Vec<FixtureControl> {
+        vec!(FixtureControl::new("color", Datatype::Color, Data::Color(Color::default())))
+    }
+
+    fn render(controls: &[FixtureControl], buffer: &mut [DmxValue]) {
+        debug_assert!(controls.len() == 1);
+        debug_assert!(buffer.len() == CHANNEL_COUNT as usize);
+        let (red, green, blue, white) = Color::from(controls[0].value()).to_rgbw();
+        as_channels(&[red, green, blue, white], buffer);
+    }
+}
+
+/// Generic 4-channel RGBA color mixing fixture.
+pub mod generic_rgba {
+    use super::*;
+
+    const CHANNEL_COUNT: DmxChannelCount = 4;
+
+    /// Generic RGBA fixture, controlled by a single color.
+    /// The amber emitter carries as much of the color as possible.
+    /// Channels are red, green, blue, amber.
+    pub const PROFILE: Profile = Profile {
+        name: Cow::Borrowed("generic:RGBA"),
+        description: Cow::Borrowed("4-channel RGBA color mixing fixture."),
+        channel_count: CHANNEL_COUNT,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
+    };
+
+    fn controls() -> Vec<FixtureControl> {
+        vec!(FixtureControl::new("color", Datatype::Color, Data::Color(Color::default())))
+    }
+
+    fn render(controls: &[FixtureControl], buffer: &mut [DmxValue]) {
+        debug_assert!(controls.len() == 1);
+        debug_assert!(buffer.len() == CHANNEL_COUNT as usize);
+        let (red, green, blue, amber) = Color::from(controls[0].value()).to_rgba();
+        as_channels(&[red, green, blue, amber], buffer);
+    }
+}
+
 /// Astroraggi Power, eh!?
 pub mod clay_paky_astroraggi_power {
     use super::*;
@@ -195,11 +491,11 @@ pub mod clay_paky_astroraggi_power {
     /// No dome indexing.
     /// Breaks out shutter and strobe separately, nonzero strobe takes priority over shutter.
     pub const PROFILE: Profile = Profile {
-        name: "clay paky:Astroraggi Power",
-        description: "The ORIGINAL moonflower.",
+        name: Cow::Borrowed("clay paky:Astroraggi Power"),
+        description: Cow::Borrowed("The ORIGINAL moonflower."),
         channel_count: CHANNEL_COUNT,
-        controls: controls,
-        render_func: render,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
     };
 
     fn controls() -> Vec<FixtureControl> {
@@ -243,11 +539,11 @@ pub mod clay_paky_atlas {
     /// The apertures control is all closed at -1, all open at 0.0, and all closed again at +1,
     /// allowing both directions of fanning action.
     pub const PROFILE: Profile = Profile {
-        name: "clay paky:Atlas",
-        description: "The megaest fan light of them all.",
+        name: Cow::Borrowed("clay paky:Atlas"),
+        description: Cow::Borrowed("The megaest fan light of them all."),
         channel_count: CHANNEL_COUNT,
-        controls: controls,
-        render_func: render,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
     };
 
     fn controls() -> Vec<FixtureControl> {
@@ -285,11 +581,11 @@ pub mod apollo_roto_q_dmx {
 
     /// Apollo Roto-Q DMX, controlled as a single bipolar.
     pub const PROFILE: Profile = Profile {
-        name: "apollo:Roto-Q DMX",
-        description: "Not yet implemented. Apollo Roto-Q DMX, rotating mode only.",
+        name: Cow::Borrowed("apollo:Roto-Q DMX"),
+        description: Cow::Borrowed("Not yet implemented. Apollo Roto-Q DMX, rotating mode only."),
         channel_count: CHANNEL_COUNT,
-        controls: controls,
-        render_func: render,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
     };
 
     fn controls() -> Vec<FixtureControl> {
@@ -314,11 +610,12 @@ pub mod apollo_smart_move_dmx {
 
     /// Apollo smart move DMX, controlled as a single bipolar.
     pub const PROFILE: Profile = Profile {
-        name: "apollo:Smart Move DMX",
-        description: "Not yet implemented. Apollo Smart Move DMX, rotating mode only.",
+        name: Cow::Borrowed("apollo:Smart Move DMX"),
+        description: Cow::Borrowed(
+            "Not yet implemented. Apollo Smart Move DMX, rotating mode only."),
         channel_count: CHANNEL_COUNT,
-        controls: controls,
-        render_func: render,
+        paired_channels: Cow::Borrowed(&[]),
+        source: Source::Compiled(controls, render),
     };
 
     fn controls() -> Vec<FixtureControl> {
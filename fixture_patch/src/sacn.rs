@@ -0,0 +1,311 @@
+//! Streaming ACN (ANSI E1.31) output.
+//! An sACN port sends a single universe, either to the universe's multicast group or to a single
+//! receiver.  Ports are identified by strings of the form "universe[/priority][@address]", such
+//! as "1" to multicast universe 1 at the default priority, or "12/150@10.0.0.5" to send universe
+//! 12 at priority 150 to a single receiver.
+//! Every port in this process belongs to the same source, which is identified by a name and a CID.
+//! The CID should stay the same across restarts so that receivers recognize us; set
+//! WIGGLES_SACN_CID to a UUID to make it persistent, otherwise a random one is used.
+//! The source periodically announces the universes it is sending with discovery packets, and
+//! tells receivers when it stops sending a universe.
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::env;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::{UdpSocket, SocketAddr, SocketAddrV4, Ipv4Addr};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use rust_dmx::{DmxPort, SerializablePort, Error as DmxPortError};
+pub use dmx_protocol::sacn::{
+    SACN_PORT,
+    MIN_UNIVERSE,
+    MAX_UNIVERSE,
+    DISCOVERY_UNIVERSE,
+    DEFAULT_PRIORITY,
+    MAX_PRIORITY,
+    OPTION_STREAM_TERMINATED,
+    Source,
+    multicast_address,
+    data_packet,
+    discovery_packets,
+};
+
+pub const NAMESPACE: &'static str = "sacn";
+
+/// How often to announce our universes.
+const DISCOVERY_INTERVAL_SECS: u64 = 10;
+/// Receivers must see this many terminated packets when a stream ends.
+const TERMINATION_PACKETS: usize = 3;
+
+/// Read the source identity from the environment, falling back to a random CID and a generic name.
+fn source_from_env() -> Source {
+    let cid = match env::var("WIGGLES_SACN_CID") {
+        Ok(uuid) => parse_uuid(&uuid).unwrap_or_else(|()| {
+            error!("WIGGLES_SACN_CID '{}' is not a UUID, using a random CID.", uuid);
+            random_cid()
+        }),
+        Err(_) => random_cid(),
+    };
+    Source {
+        cid: cid,
+        name: env::var("WIGGLES_SACN_SOURCE_NAME").unwrap_or("wiggles".to_string()),
+    }
+}
+
+/// Pick a fresh CID, different every time.
+/// Every RandomState is seeded differently, so two of them give us 128 random bits.
+fn random_cid() -> [u8; 16] {
+    let mut cid = [0; 16];
+    for half in cid.chunks_mut(8) {
+        let mut bits = RandomState::new().build_hasher().finish();
+        for byte in half.iter_mut() {
+            *byte = bits as u8;
+            bits >>= 8;
+        }
+    }
+    cid
+}
+
+/// Parse a UUID written as 32 hex digits, optionally with dashes.
+fn parse_uuid(uuid: &str) -> Result<[u8; 16], ()> {
+    let digits = uuid.chars().filter(|c| *c != '-').collect::<Vec<_>>();
+    if digits.len() != 32 {
+        return Err(());
+    }
+    let mut cid = [0; 16];
+    for (byte, pair) in cid.iter_mut().zip(digits.chunks(2)) {
+        let pair = pair.iter().cloned().collect::<String>();
+        *byte = u8::from_str_radix(&pair, 16).map_err(|_| ())?;
+    }
+    Ok(cid)
+}
+
+/// The universes this source is sending, and when we last told everyone about them.
+struct Discovery {
+    /// How many open ports are sending each universe.
+    universes: BTreeMap<u16, usize>,
+    last_sent: Option<Instant>,
+}
+
+lazy_static! {
+    pub static ref SOURCE: Source = source_from_env();
+    static ref DISCOVERY: Mutex<Discovery> = Mutex::new(Discovery {
+        universes: BTreeMap::new(),
+        last_sent: None,
+    });
+}
+
+fn register_universe(universe: u16) {
+    let mut discovery = DISCOVERY.lock().unwrap();
+    *discovery.universes.entry(universe).or_insert(0) += 1;
+}
+
+/// Stop counting a port as sending a universe, returning how many ports still send it.
+fn unregister_universe(universe: u16) -> usize {
+    let mut discovery = DISCOVERY.lock().unwrap();
+    let remaining = match discovery.universes.get_mut(&universe) {
+        Some(count) => {
+            *count -= 1;
+            *count
+        }
+        None => return 0,
+    };
+    if remaining == 0 {
+        discovery.universes.remove(&universe);
+    }
+    remaining
+}
+
+/// If it's time to announce our universes again, return the discovery packets to send.
+fn discovery_due() -> Option<Vec<Vec<u8>>> {
+    let mut discovery = DISCOVERY.lock().unwrap();
+    let interval = Duration::from_secs(DISCOVERY_INTERVAL_SECS);
+    match discovery.last_sent {
+        Some(last_sent) if last_sent.elapsed() < interval => None,
+        _ => {
+            discovery.last_sent = Some(Instant::now());
+            let universes = discovery.universes.keys().cloned().collect::<Vec<_>>();
+            Some(discovery_packets(&SOURCE, &universes))
+        }
+    }
+}
+
+/// A universe, its priority, and where to send it.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PortId {
+    pub universe: u16,
+    pub priority: u8,
+    /// Send to this receiver, or to the universe's multicast group if None.
+    pub unicast: Option<SocketAddr>,
+}
+
+impl PortId {
+    pub fn destination(&self) -> SocketAddr {
+        self.unicast.unwrap_or_else(|| multicast_address(self.universe))
+    }
+}
+
+impl fmt::Display for PortId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.universe)?;
+        if self.priority != DEFAULT_PRIORITY {
+            write!(f, "/{}", self.priority)?;
+        }
+        match self.unicast {
+            Some(SocketAddr::V4(dest)) if dest.port() == SACN_PORT => write!(f, "@{}", dest.ip()),
+            Some(dest) => write!(f, "@{}", dest),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for PortId {
+    type Err = io::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid sACN port '{}', expected universe[/priority][@address].", s));
+        let mut parts = s.splitn(2, '@');
+        let mut stream = parts.next().ok_or_else(&invalid)?.splitn(2, '/');
+        let universe = u16::from_str(stream.next().ok_or_else(&invalid)?).map_err(|_| invalid())?;
+        if universe < MIN_UNIVERSE || universe > MAX_UNIVERSE {
+            return Err(invalid());
+        }
+        let priority = match stream.next() {
+            Some(p) => u8::from_str(p).map_err(|_| invalid())?,
+            None => DEFAULT_PRIORITY,
+        };
+        if priority > MAX_PRIORITY {
+            return Err(invalid());
+        }
+        let unicast = match parts.next() {
+            Some(dest) => Some(match Ipv4Addr::from_str(dest) {
+                Ok(ip) => SocketAddr::V4(SocketAddrV4::new(ip, SACN_PORT)),
+                Err(_) => SocketAddr::from_str(dest).map_err(|_| invalid())?,
+            }),
+            None => None,
+        };
+        Ok(PortId { universe: universe, priority: priority, unicast: unicast })
+    }
+}
+
+/// sACN universes aren't discoverable without listening to the network for a while, so offer
+/// the first universe at the default priority; any other port id may be attached directly.
+pub fn available_ports() -> Vec<(String, String)> {
+    let id = PortId { universe: MIN_UNIVERSE, priority: DEFAULT_PRIORITY, unicast: None };
+    vec!((NAMESPACE.to_string(), id.to_string()))
+}
+
+/// Sends a single universe as sACN.
+/// When the port is terminated or dropped, receivers are told that the stream has ended.
+#[derive(Debug)]
+pub struct SacnPort {
+    /// The string this port was opened with, which is also how it is identified.
+    name: String,
+    id: PortId,
+    socket: UdpSocket,
+    sequence: u8,
+    /// False once the stream has been terminated, until the next write starts it again.
+    streaming: bool,
+    /// The last frame we sent, repeated in the termination packets.
+    last_frame: Vec<u8>,
+}
+
+impl SacnPort {
+    /// Open a port from a port identifier string.
+    pub fn open(name: &str) -> io::Result<Self> {
+        let id: PortId = name.parse()?;
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        register_universe(id.universe);
+        Ok(SacnPort {
+            name: name.to_string(),
+            id: id,
+            socket: socket,
+            sequence: 0,
+            streaming: true,
+            last_frame: Vec::new(),
+        })
+    }
+
+    /// Tell receivers that this port's stream has ended.
+    /// Every port in this process is the same source, so if another port is still sending this
+    /// universe, its stream carries on and nothing is sent.
+    /// Dropping the port terminates it, but this can be done sooner, such as once a replacement
+    /// port is open.  Writing to the port again starts a new stream.
+    pub fn terminate(&mut self) {
+        if !self.streaming {
+            return;
+        }
+        self.streaming = false;
+        if unregister_universe(self.id.universe) > 0 {
+            return;
+        }
+        let frame = self.last_frame.clone();
+        for _ in 0..TERMINATION_PACKETS {
+            if let Err(e) = self.send(OPTION_STREAM_TERMINATED, &frame) {
+                warn!("Could not terminate sACN universe {}: {}", self.id.universe, e);
+                break;
+            }
+        }
+    }
+
+    fn send(&mut self, options: u8, frame: &[u8]) -> io::Result<()> {
+        let packet =
+            data_packet(&SOURCE, self.id.universe, self.id.priority, self.sequence, options, frame);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.socket.send_to(&packet, self.id.destination())?;
+        Ok(())
+    }
+
+    /// Announce every universe this source is sending if it is time to do so.
+    /// Discovery is a courtesy to receivers, so a failure is only logged.
+    fn send_discovery(&self) {
+        if let Some(packets) = discovery_due() {
+            let destination = multicast_address(DISCOVERY_UNIVERSE);
+            for packet in packets {
+                if let Err(e) = self.socket.send_to(&packet, destination) {
+                    warn!("Could not send sACN universe discovery: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl DmxPort for SacnPort {
+    fn namespace(&self) -> &str {
+        NAMESPACE
+    }
+
+    fn port_name(&self) -> &str {
+        &self.name
+    }
+
+    fn write(&mut self, frame: &[u8]) -> Result<(), DmxPortError> {
+        if !self.streaming {
+            register_universe(self.id.universe);
+            self.streaming = true;
+        }
+        self.send_discovery();
+        self.send(0, frame).map_err(DmxPortError::from)?;
+        self.last_frame.clear();
+        self.last_frame.extend_from_slice(frame);
+        Ok(())
+    }
+
+    fn serializable(&self) -> SerializablePort {
+        SerializablePort {
+            namespace: NAMESPACE.to_string(),
+            port_name: self.name.clone(),
+        }
+    }
+}
+
+impl Drop for SacnPort {
+    fn drop(&mut self) {
+        self.terminate();
+    }
+}
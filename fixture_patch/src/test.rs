@@ -1,7 +1,16 @@
 //! Tests for the fixture patch.
+use std::{env, fs, process};
+use std::fs::File;
+use std::io::Write;
+use std::net::{UdpSocket, Ipv4Addr};
+use std::thread;
+use std::time::Duration;
+use std::path::PathBuf;
 use super::*;
 use super::profiles::dimmer::PROFILE as dimmer_profile;
 use super::profiles::clay_paky_astroraggi_power::PROFILE as astro_profile;
+use super::artnet::{self, PortAddress, PortId};
+use super::sacn;
 use wiggles_value::*;
 
 fn assert_fixture_patched_at<S>(p: &Patch<S>, id: FixtureId, address: Option<(UniverseId, DmxAddress)>) {
@@ -100,9 +109,631 @@ fn test_serde() {
     assert_eq!(patch, json_round_trip_patch);
 
     // serialize to bincode
-    let bincode_patch = bincode::serialize(&patch, bincode::Infinite).unwrap();
+    let bincode_patch = bincode::serialize(&patch).unwrap();
     println!("{}", bincode_patch.len());
     // round-trip through the reader interface to emulate reading directly from a file
-    let bincode_round_trip_patch = bincode::deserialize_from(&mut bincode_patch.as_slice(), bincode::Infinite).unwrap();
+    let bincode_round_trip_patch =
+        bincode::deserialize_from(&mut bincode_patch.as_slice()).unwrap();
     assert_eq!(patch, bincode_round_trip_patch);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_control_conversion() {
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    let fid = patch.add_at_address(&dimmer_profile, None, uid, 1).unwrap();
+    patch.set_control_source(fid, 0, Some(EmptyId)).unwrap();
+    let source_type = |patch: &Patch<EmptyId>| {
+        patch.item(fid).unwrap().controls().next().unwrap().source_type()
+    };
+    // Provide a bipolar source at -0.5, whatever type we're asked for.
+    let set_controls = |patch: &mut Patch<EmptyId>| {
+        patch.item_mut(fid).unwrap().set_controls(|_, _| Data::Bipolar(Bipolar(-0.5)));
+        patch.item(fid).unwrap().controls().next().unwrap().value()
+    };
+    // Without a conversion, the source is read as unipolar and converted using abs.
+    assert_eq!(Datatype::Unipolar, source_type(&patch));
+    assert_eq!(Data::Unipolar(Unipolar(0.5)), set_controls(&mut patch));
+
+    patch.set_control_conversion(fid, 0, Some(Conversion::Rescale)).unwrap();
+    assert_eq!(Datatype::Bipolar, source_type(&patch));
+    assert_eq!(Data::Unipolar(Unipolar(0.25)), set_controls(&mut patch));
+
+    // Half-wave and clip both rest the negative half at zero, but only clip flattens peaks.
+    let set_bipolar = |patch: &mut Patch<EmptyId>, conversion, val| {
+        patch.set_control_conversion(fid, 0, Some(conversion)).unwrap();
+        patch.item_mut(fid).unwrap().set_controls(|_, _| Data::Bipolar(Bipolar(val)));
+        assert!(patch.render().is_empty());
+        let value = patch.item(fid).unwrap().controls().next().unwrap().value();
+        (value, patch.universe(uid).unwrap().buffer[0])
+    };
+    for &conversion in &[Conversion::Clip, Conversion::HalfWave] {
+        assert_eq!((Data::Unipolar(Unipolar(0.0)), 0), set_bipolar(&mut patch, conversion, -0.5));
+        assert_eq!((Data::Unipolar(Unipolar(0.5)), 128), set_bipolar(&mut patch, conversion, 0.5));
+    }
+    assert_eq!(
+        (Data::Unipolar(Unipolar(1.0)), 255),
+        set_bipolar(&mut patch, Conversion::Clip, 1.5));
+    assert_eq!(
+        (Data::Unipolar(Unipolar(1.5)), 255),
+        set_bipolar(&mut patch, Conversion::HalfWave, 1.5));
+
+    assert_eq!(
+        PatchError::ControlOutOfRange{fixture: fid, control_id: 1, control_count: 1},
+        patch.set_control_conversion(fid, 1, None).unwrap_err());
+}
+
+#[test]
+fn test_groups() {
+    let mut patch: Patch<u32> = Patch::new();
+    let dimmers: Vec<_> = (0..3).map(|_| patch.add(&dimmer_profile, None)).collect();
+    let astro = patch.add(&astro_profile, None);
+
+    // Members must exist, and are only listed once.
+    assert_eq!(PatchError::InvalidFixtureId(100), patch.add_group("bad".to_string(), vec!(100))
+        .unwrap_err());
+    let gid = patch.add_group(
+        "dimmers".to_string(), vec!(dimmers[2], dimmers[0], dimmers[2], dimmers[1])).unwrap();
+    assert_eq!(&[dimmers[2], dimmers[0], dimmers[1]], patch.group(gid).unwrap().members());
+
+    // Members get their sources in group order.
+    let changed = patch.set_group_control_source(gid, "level", vec!(Some(0), Some(1), None))
+        .unwrap();
+    assert_eq!(patch.group(gid).unwrap().members(), changed.as_slice());
+    let source = |patch: &Patch<u32>, id| patch.item(id).unwrap().control_sources()[0];
+    assert_eq!(Some(1), source(&patch, dimmers[0]));
+    assert_eq!(None, source(&patch, dimmers[1]));
+    assert_eq!(Some(0), source(&patch, dimmers[2]));
+
+    assert_eq!(
+        PatchError::GroupSourceCount{group: gid, member_count: 3, source_count: 1},
+        patch.set_group_control_source(gid, "level", vec!(None)).unwrap_err());
+
+    // Nothing changes unless every member has the control.
+    patch.set_group_members(gid, vec!(dimmers[0], astro)).unwrap();
+    assert_eq!(
+        PatchError::ControlNotFound{fixture: astro, name: "level".to_string()},
+        patch.set_group_control_source(gid, "level", vec!(Some(5), Some(5))).unwrap_err());
+    assert_eq!(Some(1), source(&patch, dimmers[0]));
+
+    // Removing a fixture takes it out of its groups, removing a group leaves its fixtures alone.
+    patch.remove(dimmers[0]).unwrap();
+    assert_eq!(&[astro], patch.group(gid).unwrap().members());
+    patch.remove_group(gid).unwrap();
+    assert_eq!(PatchError::InvalidGroupId(gid), patch.group(gid).unwrap_err());
+    assert!(patch.item(astro).is_ok());
+    // Group ids aren't reused.
+    assert_eq!(gid + 1, patch.add_group("empty".to_string(), Vec::new()).unwrap());
+}
+
+#[test]
+fn test_groups_serde() {
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let fid = patch.add(&dimmer_profile, None);
+    patch.add_group("one".to_string(), vec!(fid)).unwrap();
+    let json_patch = serde_json::to_string(&patch).unwrap();
+    let round_trip: Patch<EmptyId> = serde_json::from_str(&json_patch).unwrap();
+    assert_eq!(patch.groups(), round_trip.groups());
+
+    // Shows saved before groups existed load without any.
+    let mut old_json: serde_json::Value = serde_json::from_str(&json_patch).unwrap();
+    {
+        let fields = old_json.as_object_mut().unwrap();
+        fields.remove("groups");
+        fields.remove("next_group_id");
+    }
+    let old_patch: Patch<EmptyId> = serde_json::from_value(old_json).unwrap();
+    assert!(old_patch.groups().is_empty());
+    assert!(old_patch.item(fid).is_ok());
+}
+
+#[test]
+fn test_structured_controls() {
+    let mut color = FixtureControl::new("color", Datatype::Color, Data::Unipolar(Unipolar(0.0)));
+    assert_eq!(Data::Color(Color::rgb(0.0, 0.0, 0.0)), color.value());
+    color.set_value(Data::Color(Color::rgb(1.5, 0.5, -0.5)));
+    assert_eq!(Data::Color(Color::rgb(1.0, 0.5, 0.0)), color.value());
+    color.set_value(Data::Unipolar(Unipolar(0.25)));
+    assert_eq!(Data::Color(Color::rgb(0.25, 0.25, 0.25)), color.value());
+
+    let center = Data::Position(Position::new(0.0, 0.0));
+    let mut position = FixtureControl::new("position", Datatype::Position, center);
+    position.set_value(Data::Position(Position::new(0.5, -2.0)));
+    assert_eq!(Data::Position(Position::new(0.5, -1.0)), position.value());
+
+    let mut gobo = FixtureControl::new("gobo", Datatype::Index(6), Data::Index(Index::new(0, 6)));
+    gobo.set_value(Data::Unipolar(Unipolar(1.0)));
+    assert_eq!(Data::Index(Index::new(5, 6)), gobo.value());
+    // Conversion strategies don't apply to structured types.
+    gobo.set_conversion(Some(Conversion::Rescale));
+    assert_eq!(Datatype::Index(6), gobo.source_type());
+}
+
+#[test]
+fn test_color_profiles() {
+    let color = Color::rgb(1.0, 0.75, 0.25);
+    let render = |name: &str| {
+        let mut patch: Patch<EmptyId> = Patch::new();
+        let uid = patch.add_universe(Universe::new_offline());
+        let profile = PROFILES.get(name).unwrap();
+        let fid = patch.add_at_address(profile, None, uid, 1).unwrap();
+        patch.set_control_source(fid, 0, Some(EmptyId)).unwrap();
+        patch.item_mut(fid).unwrap().set_controls(|_, _| Data::Color(color));
+        assert!(patch.render().is_empty());
+        let count = profile.channel_count() as usize;
+        patch.universe(uid).unwrap().buffer[..count].to_vec()
+    };
+    assert_eq!(vec!(255, 192, 64), render("generic:RGB"));
+    assert_eq!(vec!(0, 64, 192), render("generic:CMY"));
+    assert_eq!(vec!(192, 128, 0, 64), render("generic:RGBW"));
+    assert_eq!(vec!(0, 0, 64, 255), render("generic:RGBA"));
+}
+
+/// Write profile files into a fresh temporary directory.
+fn profile_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("wiggles_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for &(file_name, contents) in files {
+        File::create(dir.join(file_name)).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+    dir
+}
+
+const SCANNER_PROFILE: &'static str = r#"{
+    "name": "acme:Scanner",
+    "description": "Mirror scanner with gobo wheel.",
+    "channel_count": 9,
+    "controls": [
+        {
+            "name": "position",
+            "datatype": "Position",
+            "render": {"kind": "position", "pan": {"channel": 0, "fine": 1}, "tilt": {"channel": 2}}
+        },
+        {
+            "name": "gobo",
+            "datatype": {"Index": 3},
+            "render": {"kind": "slots", "channel": 3, "slots": [
+                {"name": "open", "value": 0},
+                {"name": "dots", "value": 16},
+                {"name": "star", "value": 32}
+            ]}
+        },
+        {
+            "name": "level",
+            "datatype": "Unipolar",
+            "default": {"Unipolar": 1.0},
+            "render": {"kind": "range", "channel": 4, "min": 8, "max": 135}
+        },
+        {
+            "name": "color",
+            "datatype": "Color",
+            "render": {"kind": "color", "channel": 5, "mixing": "cmy"}
+        }
+    ],
+    "fixed": [{"channel": 8, "value": 255}]
+}"#;
+
+#[test]
+fn test_profile_files() {
+    let bad_slots = r#"{
+        "name": "bad slots",
+        "channel_count": 1,
+        "controls": [{
+            "name": "gobo",
+            "datatype": {"Index": 3},
+            "render": {"kind": "slots", "channel": 0, "slots": [{"value": 0}]}
+        }]
+    }"#;
+    let empty_slots = r#"{
+        "name": "empty slots",
+        "channel_count": 1,
+        "controls": [{
+            "name": "gobo",
+            "datatype": {"Index": 0},
+            "render": {"kind": "slots", "channel": 0, "slots": []}
+        }]
+    }"#;
+    let overflow = r#"{
+        "name": "overflow",
+        "channel_count": 1,
+        "controls": [{
+            "name": "color",
+            "datatype": "Color",
+            "render": {"kind": "color", "channel": 65534, "mixing": "rgb"}
+        }]
+    }"#;
+    let overlap = r#"{
+        "name": "overlap",
+        "channel_count": 2,
+        "controls": [{
+            "name": "level",
+            "datatype": "Unipolar",
+            "render": {"kind": "range", "channel": 0, "fine": 1}
+        }],
+        "fixed": [{"channel": 1, "value": 0}]
+    }"#;
+    let bad_default = r#"{
+        "name": "bad default",
+        "channel_count": 1,
+        "controls": [{
+            "name": "level",
+            "datatype": "Unipolar",
+            "default": {"Bipolar": -1.0},
+            "render": {"kind": "range", "channel": 0}
+        }]
+    }"#;
+    let dir = profile_dir("profiles", &[
+        ("scanner.json", SCANNER_PROFILE),
+        ("bad_default.json", bad_default),
+        ("bad_slots.json", bad_slots),
+        ("empty_slots.json", empty_slots),
+        ("overflow.json", overflow),
+        ("overlap.json", overlap),
+        ("broken.json", "{\"name\": "),
+        ("notes.txt", "not a profile"),
+    ]);
+    let (mut profiles, errors) = load_profiles(&dir);
+    assert_eq!(1, profiles.len());
+    assert_eq!(6, errors.len());
+    match errors[2] {
+        ProfileError::Parse(..) => (),
+        ref e => panic!("Unexpected error: {}", e),
+    }
+    let expected_invalid =
+        [(0, "bad default"), (1, "bad slots"), (3, "empty slots"), (4, "overflow"), (5, "overlap")];
+    for &(i, expected) in &expected_invalid {
+        match errors[i] {
+            ProfileError::Invalid(ref name, _) => assert_eq!(expected, name),
+            ref e => panic!("Unexpected error: {}", e),
+        }
+    }
+    let profile = profiles.pop().unwrap();
+    assert_eq!("acme:Scanner", profile.name());
+    assert_eq!(9, profile.channel_count());
+    assert_eq!(&[(0, 1)], profile.paired_channels());
+
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    let fid = patch.add_at_address(&profile, None, uid, 1).unwrap();
+    // Controls start at their declared defaults.
+    assert!(patch.render().is_empty());
+    assert_eq!(
+        [128, 0, 128, 0, 135, 255, 255, 255, 255][..],
+        patch.universe(uid).unwrap().buffer[..9]);
+
+    for control_id in 0..4 {
+        patch.set_control_source(fid, control_id, Some(EmptyId)).unwrap();
+    }
+    patch.item_mut(fid).unwrap().set_controls(|_, datatype| {
+        match datatype {
+            Datatype::Position => Data::Position(Position::new(0.5, -1.0)),
+            Datatype::Index(_) => Data::Index(Index::new(2, 3)),
+            Datatype::Color => Data::Color(Color::rgb(1.0, 0.5, 0.0)),
+            _ => Data::Unipolar(Unipolar(0.5)),
+        }
+    });
+    assert!(patch.render().is_empty());
+    assert_eq!(
+        [191, 255, 0, 32, 72, 0, 128, 255, 255][..],
+        patch.universe(uid).unwrap().buffer[..9]);
+
+    // A missing directory just has no profiles in it.
+    fs::remove_dir_all(&dir).unwrap();
+    let (profiles, errors) = load_profiles(&dir);
+    assert!(profiles.is_empty() && errors.is_empty());
+}
+
+#[test]
+fn test_sample_profiles() {
+    let mut dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    dir.push("fixture_profiles");
+    let (profiles, errors) = load_profiles(&dir);
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(1, profiles.len());
+
+    let profile = load_profile(dir.join("acme_scanner.json")).unwrap();
+    assert_eq!("acme:Scanner", profile.name());
+    assert_eq!(6, profile.channel_count());
+    assert_eq!(&[(0, 1)], profile.paired_channels());
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    patch.add_at_address(&profile, None, uid, 1).unwrap();
+    assert!(patch.render().is_empty());
+    assert_eq!([128, 0, 128, 0, 255, 255][..], patch.universe(uid).unwrap().buffer[..6]);
+}
+
+#[test]
+fn test_render_16_bit() {
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    let profile = PROFILES.get("dimmer 16 bit").unwrap();
+    assert_eq!(&[(0, 1)], profile.paired_channels());
+    assert!(dimmer_profile.paired_channels().is_empty());
+    let fid = patch.add_at_address(profile, None, uid, 1).unwrap();
+    patch.set_control_source(fid, 0, Some(EmptyId)).unwrap();
+    let mut render = |level: f64| {
+        patch.item_mut(fid).unwrap().set_controls(|_, _| Data::Unipolar(Unipolar(level)));
+        assert!(patch.render().is_empty());
+        let buffer = &patch.universe(uid).unwrap().buffer;
+        (buffer[0], buffer[1])
+    };
+    assert_eq!((0, 0), render(0.0));
+    assert_eq!((0, 1), render(1.0 / 65535.0));
+    assert_eq!((64, 0), render(0.25));
+    assert_eq!((255, 255), render(1.0));
+}
+
+#[test]
+fn test_render_16_bit_fixture() {
+    let profile = PROFILES.get("dimmer 16 bit").unwrap();
+    let level = Data::Unipolar(Unipolar(0x1234 as f64 / 65535.0));
+    // The fixture puts the coarse byte first and the fine byte second.
+    let mut fixture = profile.create_fixture();
+    fixture.control_mut(0).unwrap().set_value(level);
+    let mut buffer = [0; 2];
+    fixture.render(&mut buffer);
+    assert_eq!([0x12, 0x34], buffer);
+
+    // Both bytes land at the fixture's address in the universe, and nowhere else.
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    let fid = patch.add_at_address(profile, None, uid, 100).unwrap();
+    patch.set_control_source(fid, 0, Some(EmptyId)).unwrap();
+    patch.item_mut(fid).unwrap().set_controls(|_, _| level);
+    assert!(patch.render().is_empty());
+    let buffer = &patch.universe(uid).unwrap().buffer;
+    assert_eq!([0, 0x12, 0x34, 0], buffer[98..102]);
+
+    // Bipolar values span the pair from -1 to 1.
+    let bipolar = |val: f64| profiles::bipolar_as_coarse_fine(Data::Bipolar(Bipolar(val)), 0, 255);
+    assert_eq!((0, 0), bipolar(-1.0));
+    assert_eq!((128, 0), bipolar(0.0));
+    assert_eq!((255, 255), bipolar(1.0));
+}
+
+/// Open a socket on the loopback interface to stand in for an Art-Net node.
+fn loopback_node() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket
+}
+
+#[test]
+fn test_artnet_port_ids() {
+    let id: PortId = "1:2:3@10.0.0.12".parse().unwrap();
+    assert_eq!(PortAddress::new(1, 2, 3).unwrap(), id.address);
+    assert_eq!("10.0.0.12:6454".parse::<::std::net::SocketAddr>().unwrap(), id.destination);
+    assert_eq!("1:2:3@10.0.0.12", id.to_string());
+    let id: PortId = "0:0:15@127.0.0.1:7000".parse().unwrap();
+    assert_eq!("0:0:15@127.0.0.1:7000", id.to_string());
+    for bad in &["", "1:2:3", "1:2@10.0.0.12", "128:0:0@10.0.0.12", "0:16:0@10.0.0.12",
+                 "0:0:16@10.0.0.12", "0:0:0@nowhere"] {
+        assert!(bad.parse::<PortId>().is_err(), "{} should not parse", bad);
+        assert!(open_port(artnet::NAMESPACE, bad).is_err());
+    }
+}
+
+#[test]
+fn test_artnet_output() {
+    let node = loopback_node();
+    let port_id = format!("1:2:3@{}", node.local_addr().unwrap());
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    patch.set_universe_port(uid, open_port(artnet::NAMESPACE, &port_id).unwrap()).unwrap();
+    assert_eq!(artnet::NAMESPACE, patch.universe(uid).unwrap().port_namespace());
+    assert_eq!(port_id, patch.universe(uid).unwrap().port_id());
+
+    let fid = patch.add_at_address(&dimmer_profile, None, uid, 2).unwrap();
+    patch.set_control_source(fid, 0, Some(EmptyId)).unwrap();
+    patch.item_mut(fid).unwrap().set_controls(|_, _| Data::Unipolar(Unipolar(1.0)));
+    let mut buf = [0; 1024];
+    for sequence in 1..3 {
+        assert!(patch.render().is_empty());
+        let len = node.recv(&mut buf).unwrap();
+        assert_eq!(18 + 512, len);
+        assert_eq!(b"Art-Net\0", &buf[..8]);
+        // OpDmx, little endian, then protocol version 14
+        assert_eq!([0x00, 0x50, 0, 14], buf[8..12]);
+        assert_eq!(sequence, buf[12]);
+        // subnet and universe share a byte, followed by the net
+        assert_eq!([0x23, 1], buf[14..16]);
+        // length is big endian
+        assert_eq!([0x02, 0x00], buf[16..18]);
+        assert_eq!([0, 255, 0], buf[18..21]);
+    }
+
+    // Art-Net ports are reopened when a saved patch is loaded.
+    let json_patch = serde_json::to_string(&patch).unwrap();
+    let mut loaded_patch: Patch<EmptyId> = serde_json::from_str(&json_patch).unwrap();
+    assert_eq!(port_id, loaded_patch.universe(uid).unwrap().port_id());
+    loaded_patch.render();
+    let len = node.recv(&mut buf).unwrap();
+    assert_eq!(18 + 512, len);
+    assert_eq!(1, buf[12]);
+}
+
+#[test]
+fn test_port_reopen_failure() {
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    let json_patch = serde_json::to_string(&patch).unwrap().replace(
+        r#"{"namespace":"offline","port_name":"offline"}"#,
+        r#"{"namespace":"artnet","port_name":"0:0:0@nowhere"}"#);
+    // The rest of the patch still loads, and the failure is kept to be reported.
+    let loaded_patch: Patch<EmptyId> = serde_json::from_str(&json_patch).unwrap();
+    assert_eq!("offline", loaded_patch.universe(uid).unwrap().port_namespace());
+    let errors = take_reopen_errors();
+    assert!(errors.iter().any(|e| e.starts_with("Could not reopen port artnet/0:0:0@nowhere")),
+            "{:?}", errors);
+}
+
+#[test]
+fn test_artnet_packets() {
+    // Short frames are padded to an even length, long ones truncated to a universe.
+    let address = PortAddress::new(0, 0, 1).unwrap();
+    assert_eq!(&[0, 4, 1, 2, 3, 0], &artnet::dmx_packet(address, 7, &[1, 2, 3])[16..]);
+    assert_eq!(18 + 512, artnet::dmx_packet(address, 7, &[0; 600]).len());
+    assert_eq!(b"Art-Net\0\x00\x20\x00\x0e\x00\x00", &artnet::poll_packet()[..]);
+}
+
+/// Build an ArtPollReply for a node with two output ports and an input port.
+fn poll_reply(ip: Ipv4Addr) -> Vec<u8> {
+    let mut reply = vec!(0; 239);
+    reply[..8].copy_from_slice(b"Art-Net\0");
+    reply[8..10].copy_from_slice(&[0x00, 0x21]);
+    reply[10..14].copy_from_slice(&ip.octets());
+    reply[14..16].copy_from_slice(&[0x36, 0x19]);
+    // net and subnet switches
+    reply[18] = 2;
+    reply[19] = 5;
+    reply[26..30].copy_from_slice(b"node");
+    reply[44..53].copy_from_slice(b"test node");
+    reply[173] = 3;
+    reply[174..177].copy_from_slice(&[0x80, 0x40, 0x80]);
+    reply[190..193].copy_from_slice(&[0, 1, 7]);
+    reply
+}
+
+#[test]
+fn test_artnet_discovery() {
+    let node = loopback_node();
+    let node_addr = node.local_addr().unwrap();
+    let responder = thread::spawn(move || {
+        let mut buf = [0; 1024];
+        let (len, controller) = node.recv_from(&mut buf).unwrap();
+        assert_eq!(&artnet::poll_packet()[..], &buf[..len]);
+        // Reply twice, and send something that isn't a reply; we should only see one node.
+        let reply = poll_reply(Ipv4Addr::new(10, 0, 0, 12));
+        node.send_to(&reply, controller).unwrap();
+        node.send_to(&reply, controller).unwrap();
+        node.send_to(&artnet::poll_packet(), controller).unwrap();
+    });
+    let controller = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let nodes = artnet::poll(&controller, node_addr, Duration::from_millis(500)).unwrap();
+    responder.join().unwrap();
+    assert_eq!(1, nodes.len());
+    let node = &nodes[0];
+    assert_eq!(Ipv4Addr::new(10, 0, 0, 12), node.ip);
+    assert_eq!("node", node.short_name);
+    assert_eq!("test node", node.long_name);
+    // The input-only port isn't listed.
+    assert_eq!(vec!(PortAddress::new(2, 5, 0).unwrap(), PortAddress::new(2, 5, 7).unwrap()),
+               node.outputs);
+}
+
+#[test]
+fn test_sacn_port_ids() {
+    let parse = |id: &str| id.parse::<sacn::PortId>();
+    let id = parse("7").unwrap();
+    assert_eq!((7, 100, None), (id.universe, id.priority, id.unicast));
+    assert_eq!("239.255.0.7:5568".parse::<::std::net::SocketAddr>().unwrap(), id.destination());
+    assert_eq!("7", id.to_string());
+    let id = parse("300/150@10.0.0.5").unwrap();
+    assert_eq!((300, 150), (id.universe, id.priority));
+    assert_eq!("10.0.0.5:5568".parse::<::std::net::SocketAddr>().unwrap(), id.destination());
+    assert_eq!("300/150@10.0.0.5", id.to_string());
+    assert_eq!("239.255.1.44:5568".parse::<::std::net::SocketAddr>().unwrap(),
+               sacn::multicast_address(300));
+    assert_eq!("1@127.0.0.1:7000", parse("1/100@127.0.0.1:7000").unwrap().to_string());
+    for bad in &["", "0", "64000", "1/201", "1/", "x", "1@nowhere"] {
+        assert!(parse(bad).is_err(), "{} should not parse", bad);
+        assert!(open_port(sacn::NAMESPACE, bad).is_err());
+    }
+}
+
+#[test]
+fn test_sacn_output() {
+    let node = loopback_node();
+    let port_id = format!("3/150@{}", node.local_addr().unwrap());
+    let mut patch: Patch<EmptyId> = Patch::new();
+    let uid = patch.add_universe(Universe::new_offline());
+    patch.set_universe_port(uid, open_port(sacn::NAMESPACE, &port_id).unwrap()).unwrap();
+    assert_eq!(sacn::NAMESPACE, patch.universe(uid).unwrap().port_namespace());
+
+    let fid = patch.add_at_address(&dimmer_profile, None, uid, 2).unwrap();
+    patch.set_control_source(fid, 0, Some(EmptyId)).unwrap();
+    patch.item_mut(fid).unwrap().set_controls(|_, _| Data::Unipolar(Unipolar(1.0)));
+    let mut buf = [0; 1024];
+    let mut check_packet = |sequence: u8, options: u8| {
+        let len = node.recv(&mut buf).unwrap();
+        assert_eq!(638, len);
+        assert_eq!(b"\x00\x10\x00\x00ASC-E1.17\x00\x00\x00", &buf[..16]);
+        // root layer flags and length, and the data vector
+        assert_eq!([0x72, 0x6e, 0, 0, 0, 4], buf[16..22]);
+        assert_eq!(sacn::SOURCE.cid, buf[22..38]);
+        // framing layer
+        assert_eq!([0x72, 0x58, 0, 0, 0, 2], buf[38..44]);
+        let name = sacn::SOURCE.name.as_bytes();
+        assert_eq!(name, &buf[44..44 + name.len()]);
+        assert_eq!(0, buf[44 + name.len()]);
+        assert_eq!(150, buf[108]);
+        assert_eq!(sequence, buf[111]);
+        assert_eq!(options, buf[112]);
+        assert_eq!([0, 3], buf[113..115]);
+        // DMP layer, then the start code and the slots
+        assert_eq!([0x72, 0x0b, 0x02, 0xa1, 0, 0, 0, 1, 0x02, 0x01], buf[115..125]);
+        assert_eq!([0, 0, 255, 0], buf[125..129]);
+    };
+    for sequence in 0..2 {
+        assert!(patch.render().is_empty());
+        check_packet(sequence, 0);
+    }
+    // Replacing the port ends the stream.
+    patch.set_universe_port(uid, Box::new(::rust_dmx::OfflineDmxPort)).unwrap();
+    for sequence in 2..5 {
+        check_packet(sequence, sacn::OPTION_STREAM_TERMINATED);
+    }
+}
+
+#[test]
+fn test_sacn_termination() {
+    let node = loopback_node();
+    let port_id = format!("7@{}", node.local_addr().unwrap());
+    let mut port = sacn::SacnPort::open(&port_id).unwrap();
+    let mut replacement = sacn::SacnPort::open(&port_id).unwrap();
+    let mut buf = [0; 1024];
+    let mut next_packet = || {
+        node.recv(&mut buf).unwrap();
+        (buf[111], buf[112])
+    };
+    port.write(&[0; 512]).unwrap();
+    assert_eq!((0, 0), next_packet());
+    // The replacement is still sending the universe, so its stream carries on.
+    port.terminate();
+    replacement.write(&[0; 512]).unwrap();
+    assert_eq!((0, 0), next_packet());
+    // Writing again starts a new stream, which is only terminated once.
+    port.write(&[0; 512]).unwrap();
+    assert_eq!((1, 0), next_packet());
+    replacement.terminate();
+    port.terminate();
+    port.terminate();
+    for sequence in 2..5 {
+        assert_eq!((sequence, sacn::OPTION_STREAM_TERMINATED), next_packet());
+    }
+    drop(port);
+    replacement.write(&[0; 512]).unwrap();
+    assert_eq!((1, 0), next_packet());
+}
+
+#[test]
+fn test_sacn_discovery_packets() {
+    let universes = (1..601).collect::<Vec<u16>>();
+    let packets = sacn::discovery_packets(&sacn::SOURCE, &universes);
+    assert_eq!(2, packets.len());
+    for (page, packet) in packets.iter().enumerate() {
+        let count = if page == 0 { 512 } else { 88 };
+        assert_eq!(120 + 2 * count, packet.len());
+        // extended root vector, discovery framing vector, universe list vector
+        assert_eq!([0, 0, 0, 8], packet[18..22]);
+        assert_eq!([0, 0, 0, 2], packet[40..44]);
+        assert_eq!([0, 0, 0, 1], packet[114..118]);
+        let layer_length = (packet[112] as usize & 0x0f) << 8 | packet[113] as usize;
+        assert_eq!(packet.len() - 112, layer_length);
+        assert_eq!([page as u8, 1], packet[118..120]);
+        let first = 1 + 512 * page as u16;
+        assert_eq!([(first >> 8) as u8, (first & 0xff) as u8], packet[120..122]);
+    }
+    assert!(sacn::discovery_packets(&sacn::SOURCE, &[]).is_empty());
+}
@@ -10,8 +10,8 @@ use std::fmt;
 use fixture_patch::*;
 use console_server::reactor::Messages;
 use console_server::clients::ResponseFilter;
-use rust_dmx::{open_port, available_ports, Error as DmxPortError};
-use wiggles_value::Datatype;
+use rust_dmx::Error as DmxPortError;
+use wiggles_value::{Datatype, Conversion};
 
 type GlobalAddress = (UniverseId, DmxAddress);
 
@@ -28,6 +28,7 @@ pub struct ControlSourceDescription<S> {
     name: String,
     data_type: Datatype,
     source: Option<S>,
+    conversion: Option<Conversion>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -50,6 +51,7 @@ impl<'a, S: Clone> From<&'a PatchItem<S>> for PatchItemDescription<S> {
                         name: control.name().to_string(),
                         data_type: control.data_type(),
                         source: source.clone(),
+                        conversion: control.conversion(),
                     }
                 })
                 .collect();
@@ -70,6 +72,8 @@ impl<'a, S: Clone> From<&'a PatchItem<S>> for PatchItemDescription<S> {
 pub struct FixtureKindDescription {
     name: String,
     channel_count: DmxChannelCount,
+    /// Coarse/fine channel pairs, numbered from 0 within the fixture.
+    paired_channels: Vec<ChannelPair>,
 }
 
 impl<'a> From<&'a Profile> for FixtureKindDescription {
@@ -77,6 +81,7 @@ impl<'a> From<&'a Profile> for FixtureKindDescription {
         FixtureKindDescription {
             name: profile.name().to_string(),
             channel_count: profile.channel_count(),
+            paired_channels: profile.paired_channels().to_vec(),
         }
     }
 }
@@ -107,6 +112,15 @@ impl<'a> From<(UniverseId, &'a Universe)> for UnivWithPort {
     }
 }
 
+/// How to pick the control source for each member of a group.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum GroupSource<S> {
+    /// Give every member the same source, or clear it.
+    Same(Option<S>),
+    /// Give the members successive outputs of a multi-output source, starting with this one.
+    Spread(S),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum PatchServerRequest<S> {
     PatchState,
@@ -120,6 +134,13 @@ pub enum PatchServerRequest<S> {
     AttachPort(UnivWithPort),
     AvailablePorts,
     SetControlSource(FixtureId, usize, Option<S>),
+    SetControlConversion(FixtureId, usize, Option<Conversion>),
+    AddGroup(String, Vec<FixtureId>),
+    RemoveGroup(GroupId),
+    RenameGroup(GroupId, String),
+    SetGroupMembers(GroupId, Vec<FixtureId>),
+    /// Set the source of the control with this name on every member of a group.
+    SetGroupControlSource(GroupId, String, GroupSource<S>),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -132,16 +153,23 @@ pub enum PatchServerResponse<S> {
     UpdateUniverse(UnivWithPort),
     UniverseRemoved(UniverseId),
     AvailablePorts(Vec<(String, String)>),
+    Groups(Vec<FixtureGroup>),
+    UpdateGroup(FixtureGroup),
+    GroupRemoved(GroupId),
 }
 
 /// Handle a command to the fixture patch, producing either a response message or forwarding an
 /// error to be lifted into a global generic error type.
 /// In the successful case, optionally also provide a override that will be applied to the outgoing
 /// responses.
-pub fn handle_message<S: Clone>(
+/// Spreading a source over a group needs to know about the sources themselves, so the caller
+/// provides spread, which returns the source n outputs after the given one, if there is one.
+pub fn handle_message<S, F>(
         patch: &mut Patch<S>,
-        command: PatchServerRequest<S>)
+        command: PatchServerRequest<S>,
+        spread: F)
         -> Result<(Messages<PatchServerResponse<S>>, Option<ResponseFilter>), PatchRequestError>
+    where S: Clone, F: Fn(&S, usize) -> Option<S>
 {
     use PatchServerRequest::*;
     use ResponseFilter::All;
@@ -149,7 +177,10 @@ pub fn handle_message<S: Clone>(
         PatchState => {
             let descriptions = patch.items().iter().map(Into::into).collect();
             let universes = patch.universes().iter().map(|item| (*item).into()).collect();
-            Ok((Messages::one(PatchServerResponse::PatchState(descriptions, universes)), None))
+            let mut messages =
+                Messages::one(PatchServerResponse::PatchState(descriptions, universes));
+            messages.push(PatchServerResponse::Groups(patch.groups().clone()));
+            Ok((messages, None))
         }
         NewPatches(mut reqs) => {
             // Keep track of fixture IDs that we've added so we can remove them if any patch action
@@ -215,8 +246,18 @@ pub fn handle_message<S: Clone>(
             Ok((Messages::one(PatchServerResponse::Update(item.into())), Some(All)))
         }
         Remove(id) => {
+            let affected_groups: Vec<GroupId> = patch.groups().iter()
+                .filter(|group| group.members().contains(&id))
+                .map(FixtureGroup::id)
+                .collect();
             let item = patch.remove(id)?;
-            Ok((Messages::one(PatchServerResponse::Remove(item.id())), Some(All)))
+            let mut messages = Messages::one(PatchServerResponse::Remove(item.id()));
+            for group_id in affected_groups {
+                if let Ok(group) = patch.group(group_id) {
+                    messages.push(PatchServerResponse::UpdateGroup(group.clone()));
+                }
+            }
+            Ok((messages, Some(All)))
         }
         GetKinds => {
             let kinds = PROFILES.values().map(Into::into).collect();
@@ -255,6 +296,53 @@ pub fn handle_message<S: Clone>(
             let item = patch.item(id)?;
             Ok((Messages::one(PatchServerResponse::Update(item.into())), Some(All)))
         }
+        SetControlConversion(id, control_id, conversion) => {
+            patch.set_control_conversion(id, control_id, conversion)?;
+            let item = patch.item(id)?;
+            Ok((Messages::one(PatchServerResponse::Update(item.into())), Some(All)))
+        }
+        AddGroup(name, members) => {
+            let id = patch.add_group(name, members)?;
+            let group = patch.group(id)?;
+            Ok((Messages::one(PatchServerResponse::UpdateGroup(group.clone())), Some(All)))
+        }
+        RemoveGroup(id) => {
+            patch.remove_group(id)?;
+            Ok((Messages::one(PatchServerResponse::GroupRemoved(id)), Some(All)))
+        }
+        RenameGroup(id, name) => {
+            let group = patch.group_mut(id)?;
+            group.name = name;
+            Ok((Messages::one(PatchServerResponse::UpdateGroup(group.clone())), Some(All)))
+        }
+        SetGroupMembers(id, members) => {
+            let group = patch.set_group_members(id, members)?;
+            Ok((Messages::one(PatchServerResponse::UpdateGroup(group.clone())), Some(All)))
+        }
+        SetGroupControlSource(id, control_name, source) => {
+            let member_count = patch.group(id)?.members().len();
+            let sources = match source {
+                GroupSource::Same(source) => vec!(source; member_count),
+                GroupSource::Spread(first) => {
+                    let mut sources = Vec::with_capacity(member_count);
+                    for n in 0..member_count {
+                        match spread(&first, n) {
+                            Some(source) => sources.push(Some(source)),
+                            None => return Err(PatchRequestError::NotEnoughOutputs(member_count)),
+                        }
+                    }
+                    sources
+                }
+            };
+            let changed = patch.set_group_control_source(id, &control_name, sources)?;
+            let mut messages = Messages::none();
+            for fixture_id in changed {
+                if let Ok(item) = patch.item(fixture_id) {
+                    messages.push(PatchServerResponse::Update(item.into()));
+                }
+            }
+            Ok((messages, Some(All)))
+        }
     }
 }
 
@@ -262,7 +350,8 @@ pub fn handle_message<S: Clone>(
 pub enum PatchRequestError {
     PatchError(PatchError),
     ProfileNotFound(String),
-
+    /// A source was spread over a group with more members than it has outputs.
+    NotEnoughOutputs(usize),
 }
 
 impl fmt::Display for PatchRequestError {
@@ -270,6 +359,8 @@ impl fmt::Display for PatchRequestError {
         match *self {
             PatchRequestError::PatchError(ref pe) => pe.fmt(f),
             PatchRequestError::ProfileNotFound(ref name) => write!(f, "Profile not found for fixture '{}'.", name),
+            PatchRequestError::NotEnoughOutputs(count) =>
+                write!(f, "Source doesn't have enough outputs for {} fixtures.", count),
         }
     }
 }
@@ -291,6 +382,7 @@ impl std::error::Error for PatchRequestError {
         match *self {
             PatchRequestError::PatchError(ref pe) => pe.description(),
             PatchRequestError::ProfileNotFound(_) => "Fixture profile not found.",
+            PatchRequestError::NotEnoughOutputs(_) => "Not enough source outputs for group.",
         }
     }
 
@@ -298,6 +390,7 @@ impl std::error::Error for PatchRequestError {
         match *self {
             PatchRequestError::PatchError(ref pe) => Some(pe),
             PatchRequestError::ProfileNotFound(_) => None,
+            PatchRequestError::NotEnoughOutputs(_) => None,
         }
     }
 }
\ No newline at end of file
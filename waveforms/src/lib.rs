@@ -1,14 +1,68 @@
 //! Basic waveform generation functions, wrapped into a Wiggles-compatible interface.
 //! All of these waveform generators are pure functions.
+//!
+//! Every waveform takes the same basic set of parameters:
+//! - angle: the phase at which to evaluate the waveform, on [0.0, 1.0).
+//! - duty_cycle: the waveform is compressed into the interval [0.0, duty_cycle), and rests at 0
+//!   for the remainder of the period.
+//! - pulse: if true, bipolar output uses the unipolar shape, so the waveform rests at 0 and only
+//!   ever swings positive.  Unipolar output always uses the unipolar shape.
+//! - type_hint: the datatype to produce.  Unipolar is produced if no hint is provided.
+//!
+//! Unipolar shapes all start at 0.0, and the periodic ones peak in the middle of the period.
+//! Bipolar shapes start at 0.0 and swing positive during the first half of the period, with the
+//! exception of the exponential ramps which sweep across the entire range.
 extern crate wiggles_value;
 
-use std::f64::consts::{PI, FRAC_PI_2 as HALF_PI};
+use std::f64::consts::PI;
 use wiggles_value::*;
 
+#[cfg(test)]
+mod test;
+
 const TWOPI: f64 = 2.0 * PI;
 
+/// Curvature of the exponential ramps.  Larger values produce a sharper curve.
+const EXP_CURVATURE: f64 = 4.0;
+
 // Helper functions to avoid having to call math functions as methods.
 fn sin(x: f64) -> f64 { x.sin() }
+fn cos(x: f64) -> f64 { x.cos() }
+fn exp(x: f64) -> f64 { x.exp() }
+
+/// Rescale an angle into the active portion of the duty cycle.
+/// Return None if the angle falls in the resting portion of the period.
+fn duty_cycle_angle(angle: f64, duty_cycle: f64) -> Option<f64> {
+    if angle >= duty_cycle || duty_cycle == 0.0 {
+        None
+    }
+    else {
+        Some(angle / duty_cycle)
+    }
+}
+
+/// Evaluate a waveform described by a unipolar and bipolar shape, handling duty cycle, pulse mode,
+/// and the type hint.
+fn render<U, B>(
+        angle: f64,
+        duty_cycle: f64,
+        pulse: bool,
+        type_hint: Option<Datatype>,
+        unipolar: U,
+        bipolar: B)
+        -> Data
+    where U: Fn(f64) -> f64, B: Fn(f64) -> f64
+{
+    let angle = match duty_cycle_angle(angle, duty_cycle) {
+        Some(a) => a,
+        None => return Data::default_with_type_hint(type_hint),
+    };
+    match type_hint {
+        Some(t) if t.is_centered() && pulse => Data::bipolar(unipolar(angle)),
+        Some(t) if t.is_centered() => Data::bipolar(bipolar(angle)),
+        _ => Data::unipolar(unipolar(angle)),
+    }
+}
 
 /// Generate a unit-amplitude sine wave on the interval [-1.0, 1.0].
 pub fn sine(
@@ -18,64 +72,195 @@ pub fn sine(
         type_hint: Option<Datatype>)
         -> Data
 {
-    match type_hint {
-        Some(Datatype::Unipolar) | None => Data::Unipolar(sine_unipolar(angle, duty_cycle)),
-        Some(Datatype::Bipolar) => Data::Bipolar(sine_bipolar(angle, duty_cycle, pulse))
-    }
+    render(angle, duty_cycle, pulse, type_hint, sine_unipolar, sine_bipolar)
 }
 
-/// Bipolar output specialized impl of sine function.
-fn sine_bipolar(
-        angle: f64,
-        duty_cycle: f64,
-        pulse: bool)
-        -> Bipolar
+/// Unipolar shape of the sine function.
+fn sine_unipolar(angle: f64) -> f64 {
+    (1.0 - cos(TWOPI * angle)) / 2.0
+}
+
+/// Bipolar shape of the sine function.
+fn sine_bipolar(angle: f64) -> f64 {
+    sin(TWOPI * angle)
+}
+
+/// Generate a unit-amplitude triangle wave.
+/// The triangle wave traces the same path as the sine wave, but with linear segments.
+pub fn triangle(
+        Unipolar(angle): Unipolar,
+        Unipolar(duty_cycle): Unipolar,
+        pulse: bool,
+        type_hint: Option<Datatype>)
+        -> Data
 {
-    if angle > duty_cycle || duty_cycle == 0.0 {
-        return Bipolar(0.0);
+    render(angle, duty_cycle, pulse, type_hint, triangle_unipolar, triangle_bipolar)
+}
+
+/// Unipolar shape of the triangle function.
+fn triangle_unipolar(angle: f64) -> f64 {
+    if angle < 0.5 {
+        2.0 * angle
     }
+    else {
+        2.0 * (1.0 - angle)
+    }
+}
 
-    let angle = angle / duty_cycle;
-    if pulse {
-        Bipolar((sin(TWOPI * angle - HALF_PI) + 1.0) / 2.0)
+/// Bipolar shape of the triangle function.
+fn triangle_bipolar(angle: f64) -> f64 {
+    if angle < 0.25 {
+        4.0 * angle
+    }
+    else if angle < 0.75 {
+        2.0 - 4.0 * angle
     }
     else {
-        Bipolar(sin(TWOPI + angle))
+        4.0 * angle - 4.0
     }
 }
 
-/// Unipolar output specialized impl of sine function.
-fn sine_unipolar(angle: f64, duty_cycle: f64) -> Unipolar {
-    if angle > duty_cycle || duty_cycle == 0.0 {
-        return Unipolar(0.0);
+/// Generate a unit-amplitude rising sawtooth wave.
+/// The bipolar sawtooth is phase-aligned with the bipolar sine, so it jumps from 1.0 to -1.0 in
+/// the middle of the period.
+pub fn sawtooth(
+        Unipolar(angle): Unipolar,
+        Unipolar(duty_cycle): Unipolar,
+        pulse: bool,
+        type_hint: Option<Datatype>)
+        -> Data
+{
+    render(angle, duty_cycle, pulse, type_hint, sawtooth_unipolar, sawtooth_bipolar)
+}
+
+/// Unipolar shape of the sawtooth function.
+fn sawtooth_unipolar(angle: f64) -> f64 {
+    angle
+}
+
+/// Bipolar shape of the sawtooth function.
+fn sawtooth_bipolar(angle: f64) -> f64 {
+    if angle < 0.5 {
+        2.0 * angle
+    }
+    else {
+        2.0 * angle - 2.0
     }
+}
 
-    let angle = angle / duty_cycle;
-    Unipolar((sin(TWOPI * angle - HALF_PI) + 1.0) / 2.0)
-}
-
-// fn sawtooth(
-//         Unipolar(angle): Unipolar,
-//         Unipolar(smoothing): Unipolar,
-//         Unipolar(duty_cycle): Unipolar,
-//         pulse: bool)
-//         -> Bipolar
-// {
-//     if angle > duty_cycle || duty_cycle == 0.0 {
-//         return Bipolar(0.0);
-//     }
-
-//     let angle = angle / duty_cycle;
-//     if pulse {
-//         if angle < 0.5 {
-//             Bipolar(2.0 * angle)
-//         }
-//         else {
-//             Bipolar(2.0 * (1.0 - angle))
-//         }
-//     }
-//     else {
-//         if angle < 0.25 {
-//             Bipolar(4.0 * angle)
-//         }
-//     }
\ No newline at end of file
+/// Generate a unit-amplitude square wave.
+/// Use the duty cycle to produce a pulse wave of arbitrary width.
+pub fn square(
+        Unipolar(angle): Unipolar,
+        Unipolar(duty_cycle): Unipolar,
+        pulse: bool,
+        type_hint: Option<Datatype>)
+        -> Data
+{
+    render(angle, duty_cycle, pulse, type_hint, square_unipolar, square_bipolar)
+}
+
+/// Unipolar shape of the square function.
+/// High for the middle half of the period.
+fn square_unipolar(angle: f64) -> f64 {
+    if angle >= 0.25 && angle < 0.75 { 1.0 } else { 0.0 }
+}
+
+/// Bipolar shape of the square function.
+fn square_bipolar(angle: f64) -> f64 {
+    if angle < 0.5 { 1.0 } else { -1.0 }
+}
+
+/// Generate a unit-amplitude square wave with smoothed edges.
+/// The smoothing parameter sets how much of each half-period is spent transitioning between the
+/// high and low state.  A smoothing of 0.0 produces a square wave, and a smoothing of 1.0
+/// produces a sine wave.
+pub fn smooth_square(
+        Unipolar(angle): Unipolar,
+        Unipolar(smoothing): Unipolar,
+        Unipolar(duty_cycle): Unipolar,
+        pulse: bool,
+        type_hint: Option<Datatype>)
+        -> Data
+{
+    let smoothing = smoothing.min(1.0).max(0.0);
+    render(
+        angle,
+        duty_cycle,
+        pulse,
+        type_hint,
+        |a| smooth_square_unipolar(a, smoothing),
+        |a| smooth_square_bipolar(a, smoothing))
+}
+
+/// Unipolar shape of the smoothed square function.
+fn smooth_square_unipolar(angle: f64, smoothing: f64) -> f64 {
+    // The unipolar shape is the bipolar shape delayed by a quarter period and rescaled.
+    let shifted = angle - 0.25;
+    let shifted = if shifted < 0.0 { shifted + 1.0 } else { shifted };
+    (smooth_square_bipolar(shifted, smoothing) + 1.0) / 2.0
+}
+
+/// Bipolar shape of the smoothed square function.
+/// The transitions are half-cosines centered on 0.0 (rising) and 0.5 (falling).
+fn smooth_square_bipolar(angle: f64, smoothing: f64) -> f64 {
+    // width of each transition
+    let width = smoothing / 2.0;
+    let half_width = width / 2.0;
+    // distance from the rising edge, wrapped to be in [-0.5, 0.5)
+    let from_rising = if angle < 0.5 { angle } else { angle - 1.0 };
+    let from_falling = angle - 0.5;
+    if from_rising.abs() < half_width {
+        -cos(PI * (from_rising + half_width) / width)
+    }
+    else if from_falling.abs() < half_width {
+        cos(PI * (from_falling + half_width) / width)
+    }
+    else {
+        square_bipolar(angle)
+    }
+}
+
+/// Generate an exponentially-curved ramp from 0.0 up to 1.0.
+/// The bipolar ramp sweeps from -1.0 up to 1.0.
+pub fn exp_ramp_up(
+        Unipolar(angle): Unipolar,
+        Unipolar(duty_cycle): Unipolar,
+        pulse: bool,
+        type_hint: Option<Datatype>)
+        -> Data
+{
+    render(angle, duty_cycle, pulse, type_hint, exp_ramp_up_unipolar, exp_ramp_up_bipolar)
+}
+
+/// Unipolar shape of the exponential up ramp.
+fn exp_ramp_up_unipolar(angle: f64) -> f64 {
+    (exp(EXP_CURVATURE * angle) - 1.0) / (exp(EXP_CURVATURE) - 1.0)
+}
+
+/// Bipolar shape of the exponential up ramp.
+fn exp_ramp_up_bipolar(angle: f64) -> f64 {
+    2.0 * exp_ramp_up_unipolar(angle) - 1.0
+}
+
+/// Generate an exponentially-decaying ramp from 1.0 down to 0.0.
+/// The bipolar ramp sweeps from 1.0 down to -1.0.
+pub fn exp_ramp_down(
+        Unipolar(angle): Unipolar,
+        Unipolar(duty_cycle): Unipolar,
+        pulse: bool,
+        type_hint: Option<Datatype>)
+        -> Data
+{
+    render(angle, duty_cycle, pulse, type_hint, exp_ramp_down_unipolar, exp_ramp_down_bipolar)
+}
+
+/// Unipolar shape of the exponential down ramp.
+fn exp_ramp_down_unipolar(angle: f64) -> f64 {
+    exp_ramp_up_unipolar(1.0 - angle)
+}
+
+/// Bipolar shape of the exponential down ramp.
+fn exp_ramp_down_bipolar(angle: f64) -> f64 {
+    2.0 * exp_ramp_down_unipolar(angle) - 1.0
+}
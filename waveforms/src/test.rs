@@ -0,0 +1,222 @@
+//! Tests for the waveform generators.
+use super::*;
+
+/// Number of points to sample across a single period.
+const SAMPLES: usize = 10000;
+
+/// Signature shared by all of the waveforms, with smoothing bound in for smooth_square.
+type Waveform = Box<Fn(Unipolar, Unipolar, bool, Option<Datatype>) -> Data>;
+
+/// Every waveform, along with the angles at which it is allowed to be discontinuous when
+/// rendered as unipolar and as bipolar.
+fn waveforms() -> Vec<(&'static str, Waveform, Vec<f64>, Vec<f64>)> {
+    vec!(
+        ("sine", Box::new(sine), vec!(), vec!()),
+        ("triangle", Box::new(triangle), vec!(), vec!()),
+        ("sawtooth", Box::new(sawtooth), vec!(0.0), vec!(0.5)),
+        ("square", Box::new(square), vec!(0.25, 0.75), vec!(0.0, 0.5)),
+        ("smooth square 0.5", Box::new(|a, d, p, t| smooth_square(a, Unipolar(0.5), d, p, t)),
+            vec!(), vec!()),
+        ("smooth square 0.0", Box::new(|a, d, p, t| smooth_square(a, Unipolar(0.0), d, p, t)),
+            vec!(0.25, 0.75), vec!(0.0, 0.5)),
+        ("exp ramp up", Box::new(exp_ramp_up), vec!(0.0), vec!(0.0)),
+        ("exp ramp down", Box::new(exp_ramp_down), vec!(0.0), vec!(0.0)),
+    )
+}
+
+fn sample_angle(i: usize) -> f64 {
+    i as f64 / SAMPLES as f64
+}
+
+fn value(d: Data) -> f64 {
+    match d {
+        Data::Unipolar(Unipolar(v)) => v,
+        Data::Bipolar(Bipolar(v)) => v,
+        x => panic!("Unexpected waveform output: {:?}", x),
+    }
+}
+
+/// Return true if angle is within a sample of any of the allowed discontinuities, accounting for
+/// wrap-around at the end of the period.
+fn near_any(angle: f64, points: &[f64]) -> bool {
+    let tolerance = 2.0 / SAMPLES as f64;
+    points.iter().any(|p| {
+        let d = (angle - p).abs();
+        d < tolerance || (1.0 - d) < tolerance
+    })
+}
+
+#[test]
+fn test_range() {
+    for (name, wave, _, _) in waveforms() {
+        for i in 0..SAMPLES {
+            let angle = Unipolar(sample_angle(i));
+            for &pulse in &[false, true] {
+                match wave(angle, Unipolar(1.0), pulse, Some(Datatype::Unipolar)) {
+                    Data::Unipolar(Unipolar(v)) =>
+                        assert!(v >= 0.0 && v <= 1.0, "{} out of unipolar range: {}", name, v),
+                    x => panic!("{} returned the wrong datatype: {:?}", name, x),
+                }
+                match wave(angle, Unipolar(1.0), pulse, Some(Datatype::Bipolar)) {
+                    Data::Bipolar(Bipolar(v)) => {
+                        let lower = if pulse { 0.0 } else { -1.0 };
+                        assert!(v >= lower && v <= 1.0, "{} out of bipolar range: {}", name, v);
+                    }
+                    x => panic!("{} returned the wrong datatype: {:?}", name, x),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_default_type_hint_is_unipolar() {
+    for (name, wave, _, _) in waveforms() {
+        for i in 0..100 {
+            let angle = Unipolar(i as f64 / 100.0);
+            assert_eq!(
+                wave(angle, Unipolar(1.0), false, Some(Datatype::Unipolar)),
+                wave(angle, Unipolar(1.0), false, None),
+                "{} at angle {:?}",
+                name,
+                angle);
+        }
+    }
+}
+
+#[test]
+fn test_continuity() {
+    // The steepest continuous waveform is the bipolar smooth square with 0.5 smoothing, whose
+    // slope peaks at 4*pi.
+    let max_step = 15.0 / SAMPLES as f64;
+    for (name, wave, unipolar_breaks, bipolar_breaks) in waveforms() {
+        for &(type_hint, ref breaks) in
+                &[(Datatype::Unipolar, unipolar_breaks), (Datatype::Bipolar, bipolar_breaks)] {
+            let mut prev = value(wave(Unipolar(0.0), Unipolar(1.0), false, Some(type_hint)));
+            // Include the wrap-around back to the start of the period.
+            for i in 1..SAMPLES+1 {
+                let angle = sample_angle(i % SAMPLES);
+                let current = value(wave(Unipolar(angle), Unipolar(1.0), false, Some(type_hint)));
+                if !near_any(angle, breaks) {
+                    assert!(
+                        (current - prev).abs() < max_step,
+                        "{} as {:?} is discontinuous at angle {}: {} -> {}",
+                        name,
+                        type_hint,
+                        angle,
+                        prev,
+                        current);
+                }
+                prev = current;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_duty_cycle() {
+    for (name, wave, _, _) in waveforms() {
+        for &duty_cycle in &[0.0, 0.25, 0.5, 0.8] {
+            for &type_hint in &[Datatype::Unipolar, Datatype::Bipolar] {
+                for i in 0..SAMPLES {
+                    let angle = sample_angle(i);
+                    let val = wave(Unipolar(angle), Unipolar(duty_cycle), false, Some(type_hint));
+                    if angle >= duty_cycle {
+                        // Resting portion of the duty cycle.
+                        assert_eq!(
+                            Data::default_with_type_hint(Some(type_hint)),
+                            val,
+                            "{} with duty cycle {} not at rest at angle {}",
+                            name,
+                            duty_cycle,
+                            angle);
+                    }
+                    else {
+                        // The active portion should be the full waveform, compressed.
+                        let expected = wave(
+                            Unipolar(angle / duty_cycle), Unipolar(1.0), false, Some(type_hint));
+                        assert_eq!(
+                            expected,
+                            val,
+                            "{} with duty cycle {} is not compressed at angle {}",
+                            name,
+                            duty_cycle,
+                            angle);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pulse() {
+    for (name, wave, _, _) in waveforms() {
+        for i in 0..100 {
+            let angle = Unipolar(i as f64 / 100.0);
+            let unipolar = value(wave(angle, Unipolar(1.0), false, Some(Datatype::Unipolar)));
+            assert_eq!(
+                Data::bipolar(unipolar),
+                wave(angle, Unipolar(1.0), true, Some(Datatype::Bipolar)),
+                "{} pulse mode does not match unipolar shape",
+                name);
+            // Pulse mode has no effect on unipolar output.
+            assert_eq!(
+                wave(angle, Unipolar(1.0), false, Some(Datatype::Unipolar)),
+                wave(angle, Unipolar(1.0), true, Some(Datatype::Unipolar)),
+                "{} pulse mode changed unipolar output",
+                name);
+        }
+    }
+}
+
+#[test]
+fn test_smooth_square_limits() {
+    for i in 0..SAMPLES {
+        let angle = Unipolar(sample_angle(i));
+        for &type_hint in &[Datatype::Unipolar, Datatype::Bipolar] {
+            assert_eq!(
+                sine(angle, Unipolar(1.0), false, Some(type_hint)),
+                smooth_square(angle, Unipolar(1.0), Unipolar(1.0), false, Some(type_hint)));
+            assert_eq!(
+                square(angle, Unipolar(1.0), false, Some(type_hint)),
+                smooth_square(angle, Unipolar(0.0), Unipolar(1.0), false, Some(type_hint)));
+        }
+    }
+}
+
+#[test]
+fn test_landmarks() {
+    fn check<F>(wave: F, angle: f64, unipolar: f64, bipolar: f64)
+        where F: Fn(Unipolar, Unipolar, bool, Option<Datatype>) -> Data
+    {
+        assert_eq!(
+            Data::unipolar(unipolar),
+            wave(Unipolar(angle), Unipolar(1.0), false, Some(Datatype::Unipolar)));
+        assert_eq!(
+            Data::bipolar(bipolar),
+            wave(Unipolar(angle), Unipolar(1.0), false, Some(Datatype::Bipolar)));
+    }
+    check(sine, 0.0, 0.0, 0.0);
+    check(sine, 0.25, 0.5, 1.0);
+    check(sine, 0.5, 1.0, 0.0);
+    check(sine, 0.75, 0.5, -1.0);
+
+    check(triangle, 0.0, 0.0, 0.0);
+    check(triangle, 0.25, 0.5, 1.0);
+    check(triangle, 0.5, 1.0, 0.0);
+    check(triangle, 0.75, 0.5, -1.0);
+
+    check(sawtooth, 0.0, 0.0, 0.0);
+    check(sawtooth, 0.25, 0.25, 0.5);
+    check(sawtooth, 0.5, 0.5, -1.0);
+    check(sawtooth, 0.75, 0.75, -0.5);
+
+    check(square, 0.0, 0.0, 1.0);
+    check(square, 0.25, 1.0, 1.0);
+    check(square, 0.5, 1.0, -1.0);
+    check(square, 0.75, 0.0, -1.0);
+
+    check(exp_ramp_up, 0.0, 0.0, -1.0);
+    check(exp_ramp_down, 0.0, 1.0, 1.0);
+}
@@ -3,7 +3,20 @@
 //! Note that blending performs no coercions; we allow infinite headroom inside the dataflow
 //! networks and avoid clipping until it is absolutely necessary.
 use std::f64;
-use super::{Data, Unipolar, Bipolar};
+use super::{Data, Unipolar, Bipolar, Color, Position, Index};
+
+/// A layer of data along with its priority, for priority-aware blending.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Prioritized {
+    pub data: Data,
+    pub priority: f64,
+}
+
+impl Prioritized {
+    pub fn new(data: Data, priority: f64) -> Self {
+        Prioritized { data: data, priority: priority }
+    }
+}
 
 pub trait Blend {
     /// Perform additive blending.
@@ -14,6 +27,42 @@ pub trait Blend {
 
     /// Perform max value blending.
     fn max(base: Data, top: Data) -> Data;
+
+    /// Perform min value blending.
+    fn min(base: Data, top: Data) -> Data;
+
+    /// Subtract the top layer from the base.
+    fn subtract(base: Data, top: Data) -> Data;
+
+    /// Take the absolute difference between the two layers.
+    fn difference(base: Data, top: Data) -> Data;
+
+    /// Perform screen blending, the inverse of multiplying the inverses.
+    fn screen(base: Data, top: Data) -> Data;
+
+    /// Highest takes precedence, with priority.
+    /// The layer with the higher priority wins outright; layers of equal priority are blended
+    /// using max.
+    fn htp(base: Prioritized, top: Prioritized) -> Prioritized;
+
+    /// Latest takes precedence.
+    /// The top layer was applied last, so it replaces the base.
+    fn ltp(base: Data, top: Data) -> Data;
+}
+
+/// Shared implementation of priority blending, given the max blend to use for ties.
+fn htp_with<F>(base: Prioritized, top: Prioritized, max: F) -> Prioritized
+    where F: Fn(Data, Data) -> Data
+{
+    if top.priority > base.priority {
+        top
+    }
+    else if top.priority < base.priority {
+        base
+    }
+    else {
+        Prioritized::new(max(base.data, top.data), base.priority)
+    }
 }
 
 /// Interpret a base layer and top layer as Unipolar and blend them.
@@ -36,6 +85,44 @@ impl Blend for Unipolar {
         let Unipolar(top) = top.into();
         Data::Unipolar(Unipolar(f64::max(base, top)))
     }
+
+    /// Perform min value blending.
+    fn min(base: Data, top: Data) -> Data {
+        let Unipolar(base) = base.into();
+        let Unipolar(top) = top.into();
+        Data::Unipolar(Unipolar(f64::min(base, top)))
+    }
+
+    /// Subtract the top layer from the base.
+    fn subtract(base: Data, top: Data) -> Data {
+        let Unipolar(base) = base.into();
+        let Unipolar(top) = top.into();
+        Data::Unipolar(Unipolar(base - top))
+    }
+
+    /// Take the absolute difference between the two layers.
+    fn difference(base: Data, top: Data) -> Data {
+        let Unipolar(base) = base.into();
+        let Unipolar(top) = top.into();
+        Data::Unipolar(Unipolar((base - top).abs()))
+    }
+
+    /// Perform screen blending.
+    fn screen(base: Data, top: Data) -> Data {
+        let Unipolar(base) = base.into();
+        let Unipolar(top) = top.into();
+        Data::Unipolar(Unipolar(1.0 - (1.0 - base) * (1.0 - top)))
+    }
+
+    /// Highest takes precedence, with priority.
+    fn htp(base: Prioritized, top: Prioritized) -> Prioritized {
+        htp_with(base, top, <Unipolar as Blend>::max)
+    }
+
+    /// Latest takes precedence.
+    fn ltp(_: Data, top: Data) -> Data {
+        Data::Unipolar(top.into())
+    }
 }
 
 /// Interpret a base layer and top layer as Bipolar and blend them.
@@ -61,4 +148,311 @@ impl Blend for Bipolar {
         let out = if base.0.abs() < top.0.abs() { top } else { base };
         Data::Bipolar(out)
     }
-}
\ No newline at end of file
+
+    /// Perform min value blending.
+    /// As for max, we compare the absolute values, so the value closest to zero wins.
+    fn min(base: Data, top: Data) -> Data {
+        let base: Bipolar = base.into();
+        let top: Bipolar = top.into();
+        let out = if top.0.abs() < base.0.abs() { top } else { base };
+        Data::Bipolar(out)
+    }
+
+    /// Subtract the top layer from the base.
+    fn subtract(base: Data, top: Data) -> Data {
+        let Bipolar(base) = base.into();
+        let Bipolar(top) = top.into();
+        Data::Bipolar(Bipolar(base - top))
+    }
+
+    /// Take the absolute difference between the two layers.
+    fn difference(base: Data, top: Data) -> Data {
+        let Bipolar(base) = base.into();
+        let Bipolar(top) = top.into();
+        Data::Bipolar(Bipolar((base - top).abs()))
+    }
+
+    /// Perform screen blending.
+    /// We screen the absolute values, and keep the sign of whichever layer is larger, in the
+    /// same spirit as max.
+    fn screen(base: Data, top: Data) -> Data {
+        let Bipolar(base) = base.into();
+        let Bipolar(top) = top.into();
+        let magnitude = 1.0 - (1.0 - base.abs()) * (1.0 - top.abs());
+        let sign = if base.abs() < top.abs() { top.signum() } else { base.signum() };
+        Data::Bipolar(Bipolar(sign * magnitude))
+    }
+
+    /// Highest takes precedence, with priority.
+    fn htp(base: Prioritized, top: Prioritized) -> Prioritized {
+        htp_with(base, top, <Bipolar as Blend>::max)
+    }
+
+    /// Latest takes precedence.
+    fn ltp(_: Data, top: Data) -> Data {
+        Data::Bipolar(top.into())
+    }
+}
+
+/// Blend two colors channel by channel, using a unipolar blend function.
+fn channelwise<F>(base: Data, top: Data, blend: F) -> Data
+    where F: Fn(Data, Data) -> Data
+{
+    let base: Color = base.into();
+    let top: Color = top.into();
+    let channel = |b, t| Unipolar::from(blend(Data::unipolar(b), Data::unipolar(t))).0;
+    Data::Color(Color::rgb(
+        channel(base.red, top.red),
+        channel(base.green, top.green),
+        channel(base.blue, top.blue)))
+}
+
+/// Interpret a base layer and top layer as Color and blend them channel by channel, the same
+/// way as Unipolar.
+impl Blend for Color {
+    fn add(base: Data, top: Data) -> Data {
+        channelwise(base, top, <Unipolar as Blend>::add)
+    }
+
+    fn mult(base: Data, top: Data) -> Data {
+        channelwise(base, top, <Unipolar as Blend>::mult)
+    }
+
+    fn max(base: Data, top: Data) -> Data {
+        channelwise(base, top, <Unipolar as Blend>::max)
+    }
+
+    fn min(base: Data, top: Data) -> Data {
+        channelwise(base, top, <Unipolar as Blend>::min)
+    }
+
+    fn subtract(base: Data, top: Data) -> Data {
+        channelwise(base, top, <Unipolar as Blend>::subtract)
+    }
+
+    fn difference(base: Data, top: Data) -> Data {
+        channelwise(base, top, <Unipolar as Blend>::difference)
+    }
+
+    fn screen(base: Data, top: Data) -> Data {
+        channelwise(base, top, <Unipolar as Blend>::screen)
+    }
+
+    /// Layers of equal priority take the highest value of each channel, as a lighting console
+    /// would.
+    fn htp(base: Prioritized, top: Prioritized) -> Prioritized {
+        htp_with(base, top, <Color as Blend>::max)
+    }
+
+    fn ltp(_: Data, top: Data) -> Data {
+        Data::Color(top.into())
+    }
+}
+
+/// Blend two positions axis by axis, using a bipolar blend function.
+fn axiswise<F>(base: Data, top: Data, blend: F) -> Data
+    where F: Fn(Data, Data) -> Data
+{
+    let base: Position = base.into();
+    let top: Position = top.into();
+    let axis = |b, t| Bipolar::from(blend(Data::bipolar(b), Data::bipolar(t))).0;
+    Data::Position(Position::new(axis(base.x, top.x), axis(base.y, top.y)))
+}
+
+/// Interpret a base layer and top layer as Position and blend them axis by axis, the same way
+/// as Bipolar.
+impl Blend for Position {
+    fn add(base: Data, top: Data) -> Data {
+        axiswise(base, top, <Bipolar as Blend>::add)
+    }
+
+    fn mult(base: Data, top: Data) -> Data {
+        axiswise(base, top, <Bipolar as Blend>::mult)
+    }
+
+    fn max(base: Data, top: Data) -> Data {
+        axiswise(base, top, <Bipolar as Blend>::max)
+    }
+
+    fn min(base: Data, top: Data) -> Data {
+        axiswise(base, top, <Bipolar as Blend>::min)
+    }
+
+    fn subtract(base: Data, top: Data) -> Data {
+        axiswise(base, top, <Bipolar as Blend>::subtract)
+    }
+
+    fn difference(base: Data, top: Data) -> Data {
+        axiswise(base, top, <Bipolar as Blend>::difference)
+    }
+
+    fn screen(base: Data, top: Data) -> Data {
+        axiswise(base, top, <Bipolar as Blend>::screen)
+    }
+
+    fn htp(base: Prioritized, top: Prioritized) -> Prioritized {
+        htp_with(base, top, <Position as Blend>::max)
+    }
+
+    fn ltp(_: Data, top: Data) -> Data {
+        Data::Position(top.into())
+    }
+}
+
+/// Blend two indices as unipolar values, and pick the item nearest the result.
+/// The number of items is taken from whichever layer is an index, preferring the base.
+fn as_index<F>(base: Data, top: Data, blend: F) -> Data
+    where F: Fn(Data, Data) -> Data
+{
+    let count = match (base, top) {
+        (Data::Index(i), _) | (_, Data::Index(i)) => i.count,
+        _ => 1,
+    };
+    let base = Index::from_data(base, count).to_unipolar();
+    let top = Index::from_data(top, count).to_unipolar();
+    let blended = blend(Data::Unipolar(base), Data::Unipolar(top));
+    Data::Index(Index::from_unipolar(blended.into(), count))
+}
+
+/// Interpret a base layer and top layer as Index and blend them as unipolar values.
+impl Blend for Index {
+    fn add(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::add)
+    }
+
+    fn mult(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::mult)
+    }
+
+    fn max(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::max)
+    }
+
+    fn min(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::min)
+    }
+
+    fn subtract(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::subtract)
+    }
+
+    fn difference(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::difference)
+    }
+
+    fn screen(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::screen)
+    }
+
+    fn htp(base: Prioritized, top: Prioritized) -> Prioritized {
+        htp_with(base, top, <Index as Blend>::max)
+    }
+
+    fn ltp(base: Data, top: Data) -> Data {
+        as_index(base, top, <Unipolar as Blend>::ltp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn up(v: f64) -> Data {
+        Data::unipolar(v)
+    }
+
+    fn bp(v: f64) -> Data {
+        Data::bipolar(v)
+    }
+
+    #[test]
+    fn test_min() {
+        assert_eq!(up(0.2), <Unipolar as Blend>::min(up(0.2), up(0.7)));
+        assert_eq!(up(0.2), <Unipolar as Blend>::min(up(0.7), up(0.2)));
+        assert_eq!(bp(0.2), <Bipolar as Blend>::min(bp(-0.7), bp(0.2)));
+        assert_eq!(bp(-0.2), <Bipolar as Blend>::min(bp(0.7), bp(-0.2)));
+    }
+
+    #[test]
+    fn test_subtract() {
+        assert_eq!(up(0.5), <Unipolar as Blend>::subtract(up(0.75), up(0.25)));
+        // No clipping inside the network.
+        assert_eq!(up(-0.5), <Unipolar as Blend>::subtract(up(0.25), up(0.75)));
+        assert_eq!(bp(1.5), <Bipolar as Blend>::subtract(bp(0.75), bp(-0.75)));
+    }
+
+    #[test]
+    fn test_difference() {
+        assert_eq!(up(0.5), <Unipolar as Blend>::difference(up(0.25), up(0.75)));
+        assert_eq!(up(0.5), <Unipolar as Blend>::difference(up(0.75), up(0.25)));
+        assert_eq!(bp(1.5), <Bipolar as Blend>::difference(bp(-0.75), bp(0.75)));
+    }
+
+    #[test]
+    fn test_screen() {
+        assert_eq!(up(0.75), <Unipolar as Blend>::screen(up(0.5), up(0.5)));
+        assert_eq!(up(0.3), <Unipolar as Blend>::screen(up(0.3), up(0.0)));
+        assert_eq!(up(1.0), <Unipolar as Blend>::screen(up(1.0), up(0.4)));
+        assert_eq!(bp(-0.625), <Bipolar as Blend>::screen(bp(0.25), bp(-0.5)));
+        assert_eq!(bp(0.8), <Bipolar as Blend>::screen(bp(0.6), bp(-0.5)));
+        assert_eq!(bp(0.0), <Bipolar as Blend>::screen(bp(0.0), bp(0.0)));
+    }
+
+    #[test]
+    fn test_htp() {
+        let p = Prioritized::new;
+        // Higher priority wins even with a lower value.
+        assert_eq!(
+            p(up(0.2), 2.0),
+            <Unipolar as Blend>::htp(p(up(0.9), 1.0), p(up(0.2), 2.0)));
+        assert_eq!(
+            p(up(0.2), 2.0),
+            <Unipolar as Blend>::htp(p(up(0.2), 2.0), p(up(0.9), 1.0)));
+        // Equal priority falls back to max.
+        assert_eq!(
+            p(up(0.9), 1.0),
+            <Unipolar as Blend>::htp(p(up(0.2), 1.0), p(up(0.9), 1.0)));
+        assert_eq!(
+            p(bp(-0.9), 0.0),
+            <Bipolar as Blend>::htp(p(bp(0.2), 0.0), p(bp(-0.9), 0.0)));
+    }
+
+    #[test]
+    fn test_ltp() {
+        assert_eq!(up(0.1), <Unipolar as Blend>::ltp(up(0.9), up(0.1)));
+        assert_eq!(bp(-0.1), <Bipolar as Blend>::ltp(bp(0.9), bp(-0.1)));
+        // The result takes the type we blend as.
+        assert_eq!(bp(0.5), <Bipolar as Blend>::ltp(up(0.9), up(0.5)));
+    }
+
+    #[test]
+    fn test_color() {
+        let red = Data::Color(Color::rgb(1.0, 0.0, 0.0));
+        let blue = Data::Color(Color::rgb(0.0, 0.25, 1.0));
+        assert_eq!(Data::Color(Color::rgb(1.0, 0.25, 1.0)), <Color as Blend>::add(red, blue));
+        assert_eq!(Data::Color(Color::rgb(0.0, 0.0, 0.0)), <Color as Blend>::min(red, blue));
+        // Scalars blend as greys.
+        assert_eq!(Data::Color(Color::rgb(1.0, 0.5, 0.5)), <Color as Blend>::max(red, up(0.5)));
+    }
+
+    #[test]
+    fn test_position() {
+        let a = Data::Position(Position::new(0.5, -0.5));
+        let b = Data::Position(Position::new(-0.25, 0.75));
+        assert_eq!(Data::Position(Position::new(0.25, 0.25)), <Position as Blend>::add(a, b));
+        assert_eq!(Data::Position(Position::new(0.5, 0.75)), <Position as Blend>::max(a, b));
+        assert_eq!(b, <Position as Blend>::ltp(a, b));
+    }
+
+    #[test]
+    fn test_index() {
+        let a = Data::Index(Index::new(1, 5));
+        let b = Data::Index(Index::new(2, 5));
+        assert_eq!(Data::Index(Index::new(3, 5)), <Index as Blend>::add(a, b));
+        assert_eq!(Data::Index(Index::new(1, 5)), <Index as Blend>::subtract(b, a));
+        assert_eq!(Data::Index(Index::new(2, 5)), <Index as Blend>::max(a, b));
+        assert_eq!(b, <Index as Blend>::ltp(a, b));
+        // Adding past the end stays on the last item.
+        assert_eq!(Data::Index(Index::new(4, 5)), <Index as Blend>::add(b, b));
+    }
+}
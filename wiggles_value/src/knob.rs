@@ -6,7 +6,7 @@
 //! a value.
 use std::sync::Arc;
 use std::{error, fmt};
-use super::{Datatype as WiggleDatatype, Data as WiggleData, Unipolar};
+use super::{Datatype as WiggleDatatype, Data as WiggleData, Unipolar, Bipolar, Color};
 use super::knob_types::Rate;
 use console_server::reactor::Messages;
 
@@ -22,6 +22,9 @@ pub enum Datatype {
     // Pick a value from a finite set of named items.
     // Would be better if this were a simpler impl but we'll roll with it and see how it pans out.
     Picker(Vec<String>),
+    // Free-form text, such as an expression.
+    Text,
+    UInt, // whole number >= 0
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -31,6 +34,8 @@ pub enum Data {
     Button(bool),
     UFloat(f64),
     Picker(String),
+    Text(String),
+    UInt(u32),
 }
 
 // Helper conversion functions for standard allowed conversions.
@@ -75,6 +80,24 @@ impl Data {
             _ => Err(badtype(Datatype::Wiggle(WiggleDatatype::Unipolar), self)),
         }
     }
+    /// Unpack this knob data as a bipolar.
+    /// Convert a Wiggle and ensure it is coerced.
+    /// All other types are an error.
+    pub fn as_bipolar<A>(self) -> Result<Bipolar, Error<A>> {
+        match self {
+            Data::Wiggle(d) => Ok(d.coerce().into()),
+            _ => Err(badtype(Datatype::Wiggle(WiggleDatatype::Bipolar), self)),
+        }
+    }
+    /// Unpack this knob data as a color.
+    /// Convert a Wiggle and ensure it is coerced.
+    /// All other types are an error.
+    pub fn as_color<A>(self) -> Result<Color, Error<A>> {
+        match self {
+            Data::Wiggle(d) => Ok(Color::from(d).coerce()),
+            _ => Err(badtype(Datatype::Wiggle(WiggleDatatype::Color), self)),
+        }
+    }
     /// Unpack this knob data as a picker variant.
     /// Since we don't have access to the expected variants here, return an empty error and allow
     /// the client to decide what to do.
@@ -84,6 +107,22 @@ impl Data {
             _ => Err(()),
         }
     }
+    /// Unpack this knob data as a whole number.
+    /// Do not convert any other datatype into a whole number.
+    pub fn as_uint<A>(self) -> Result<u32, Error<A>> {
+        match self {
+            Data::UInt(u) => Ok(u),
+            _ => Err(badtype(Datatype::UInt, self)),
+        }
+    }
+    /// Unpack this knob data as text.
+    /// Do not convert any other datatype into text.
+    pub fn as_text<A>(self) -> Result<String, Error<A>> {
+        match self {
+            Data::Text(t) => Ok(t),
+            _ => Err(badtype(Datatype::Text, self)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -171,6 +210,8 @@ impl<A> Response<A> {
 pub enum Error<A> {
     InvalidAddress(A),
     InvalidDatatype{expected: Datatype, provided: Data},
+    /// The data was the right type, but the knob couldn't use it.
+    InvalidValue{addr: A, message: String},
 }
 
 impl<A> Error<A> {
@@ -182,6 +223,7 @@ impl<A> Error<A> {
         match self {
             InvalidAddress(a) => InvalidAddress(lifter(a)),
             InvalidDatatype{expected: e, provided: p} => InvalidDatatype{expected: e, provided: p},
+            InvalidValue{addr, message} => InvalidValue{addr: lifter(addr), message: message},
         }
     }
 }
@@ -192,6 +234,8 @@ impl<A: fmt::Debug> fmt::Display for Error<A> {
             Error::InvalidAddress(ref a) => write!(f, "Invalid knob address: {:?}.", a),
             Error::InvalidDatatype{ref expected, ref provided} =>
                 write!(f, "Knob expected datatype {:?} but received the data {:?}.", expected, provided),
+            Error::InvalidValue{ref addr, ref message} =>
+                write!(f, "Invalid value for knob {:?}: {}", addr, message),
         }
     }
 }
@@ -201,6 +245,7 @@ impl<A: fmt::Debug> error::Error for Error<A> {
         match *self {
             Error::InvalidAddress(_) => "Invalid knob address.",
             Error::InvalidDatatype{..} => "Invalid datatype for knob.",
+            Error::InvalidValue{..} => "Invalid value for knob.",
         }
     }
 
@@ -1,7 +1,10 @@
 //! Types for dataflow.
-//! TODO: consider how to allow injection of different conversion behaviors for
-//! flexibility.  We may want to use abs() or rescale to go from bipolar to
-//! unipolar, for example.
+//! The plain conversions between unipolar and bipolar use abs() to go from bipolar to unipolar,
+//! and reinterpret unipolar values as bipolar as they are.  Where a different behavior is needed,
+//! pick one of the strategies in Conversion.
+//! Besides the scalar types, there are structured types for colors, positions, and discrete
+//! indices, so that one wiggle can drive a whole color or a whole position.  These convert to and
+//! from the scalar types so that any wiggle can drive any input, if not always usefully.
 use std::cmp::{min, max};
 use std::ops::{Deref, Mul, Add};
 
@@ -26,6 +29,77 @@ fn almost_eq(a: f64, b: f64) -> bool {
 pub enum Datatype {
     Unipolar,
     Bipolar,
+    Color,
+    Position,
+    /// An index into a collection of this many items.
+    Index(u32),
+}
+
+impl Datatype {
+    /// Return true if this type is centered on zero, like bipolar.
+    /// Wiggles that only make scalars should render these types as bipolar.
+    pub fn is_centered(&self) -> bool {
+        match *self {
+            Datatype::Bipolar | Datatype::Position => true,
+            Datatype::Unipolar | Datatype::Color | Datatype::Index(_) => false,
+        }
+    }
+}
+
+/// Strategies for converting between unipolar and bipolar values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Conversion {
+    /// Take the absolute value of bipolar values.  This doubles the frequency of a bipolar
+    /// waveform.  Unipolar values are reinterpreted as bipolar as they are.
+    /// This is the behavior of the plain conversions.
+    Abs,
+    /// Rescale the whole range of one type onto the whole range of the other, so bipolar -1.0
+    /// becomes unipolar 0.0.  This keeps the shape of a waveform.
+    Rescale,
+    /// Clip bipolar values into the unipolar range, so the negative half rests at 0.0.
+    /// Unipolar values are reinterpreted as bipolar as they are.
+    Clip,
+    /// Pass only the positive half-wave of bipolar values, so [0.0, 1.0] maps onto itself and the
+    /// negative half rests at 0.0.  Unlike clip, nothing is clipped at the top: peaks above 1.0
+    /// are passed through, and fixture controls keep them.
+    /// Unipolar values are reinterpreted as bipolar as they are.
+    HalfWave,
+}
+
+impl Default for Conversion {
+    fn default() -> Self {
+        Conversion::Abs
+    }
+}
+
+impl Conversion {
+    /// Convert a bipolar value to unipolar using this strategy.
+    pub fn to_unipolar(&self, Bipolar(val): Bipolar) -> Unipolar {
+        match *self {
+            Conversion::Abs => Unipolar(val.abs()),
+            Conversion::Rescale => Unipolar((val + 1.0) / 2.0),
+            Conversion::Clip => Unipolar(val.min(1.0).max(0.0)),
+            Conversion::HalfWave => Unipolar(val.max(0.0)),
+        }
+    }
+
+    /// Convert a unipolar value to bipolar using this strategy.
+    pub fn to_bipolar(&self, Unipolar(val): Unipolar) -> Bipolar {
+        match *self {
+            Conversion::Rescale => Bipolar(2.0 * val - 1.0),
+            Conversion::Abs | Conversion::Clip | Conversion::HalfWave => Bipolar(val),
+        }
+    }
+
+    /// Convert data to the provided type using this strategy.
+    /// Only conversions between the scalar types use the strategy; others are plain conversions.
+    pub fn convert(&self, data: Data, datatype: Datatype) -> Data {
+        match (data, datatype) {
+            (Data::Bipolar(bp), Datatype::Unipolar) => Data::Unipolar(self.to_unipolar(bp)),
+            (Data::Unipolar(up), Datatype::Bipolar) => Data::Bipolar(self.to_bipolar(up)),
+            _ => data.as_type(datatype),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialOrd, Serialize, Deserialize)]
@@ -49,7 +123,7 @@ impl Unipolar {
 impl From<Bipolar> for Unipolar {
     /// Convert to unipolar by taking the absolute value.
     fn from(bp: Bipolar) -> Self {
-        Unipolar(bp.0.abs())
+        Conversion::Abs.to_unipolar(bp)
     }
 }
 
@@ -58,6 +132,9 @@ impl From<Data> for Unipolar {
         match d {
             Data::Unipolar(up) => up,
             Data::Bipolar(bp) => bp.into(),
+            Data::Color(c) => Unipolar(c.brightness()),
+            Data::Position(p) => Bipolar(p.x).into(),
+            Data::Index(i) => i.to_unipolar(),
         }
     }
 }
@@ -109,7 +186,7 @@ impl Bipolar {
 impl From<Unipolar> for Bipolar {
     /// We just take the value and interpret it as bipolar.
     fn from(up: Unipolar) -> Self {
-        Bipolar(up.0)
+        Conversion::Abs.to_bipolar(up)
     }
 }
 
@@ -118,6 +195,9 @@ impl From<Data> for Bipolar {
         match d {
             Data::Unipolar(up) => up.into(),
             Data::Bipolar(bp) => bp,
+            Data::Color(c) => Unipolar(c.brightness()).into(),
+            Data::Position(p) => Bipolar(p.x),
+            Data::Index(i) => i.to_unipolar().into(),
         }
     }
 }
@@ -157,12 +237,268 @@ impl Add for Bipolar {
     }
 }
 
+/// The green component of an amber emitter, relative to its red.
+const AMBER_GREEN: f64 = 0.75;
+
+/// A color, as red, green, and blue components each on the range [0.0, 1.0].
+/// Colors can also be constructed from and decomposed into hue, saturation, and value.
+#[derive(Clone, Copy, Debug, PartialOrd, Serialize, Deserialize)]
+pub struct Color {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+impl PartialEq for Color {
+    fn eq(&self, other: &Color) -> bool {
+        almost_eq(self.red, other.red)
+            && almost_eq(self.green, other.green)
+            && almost_eq(self.blue, other.blue)
+    }
+}
+
+impl Eq for Color {}
+
+impl Color {
+    pub fn rgb(red: f64, green: f64, blue: f64) -> Self {
+        Color { red: red, green: green, blue: blue }
+    }
+
+    /// Construct a color from hue, saturation, and value.
+    /// Hue is measured in turns, so 0.0 and 1.0 are both red.
+    pub fn hsv(hue: f64, saturation: f64, value: f64) -> Self {
+        let hue = (hue - hue.floor()) * 6.0;
+        let sector = hue.floor();
+        let f = hue - sector;
+        let p = value * (1.0 - saturation);
+        let q = value * (1.0 - saturation * f);
+        let t = value * (1.0 - saturation * (1.0 - f));
+        match sector as u8 {
+            0 => Color::rgb(value, t, p),
+            1 => Color::rgb(q, value, p),
+            2 => Color::rgb(p, value, t),
+            3 => Color::rgb(p, q, value),
+            4 => Color::rgb(t, p, value),
+            _ => Color::rgb(value, p, q),
+        }
+    }
+
+    /// Decompose this color into hue, saturation, and value.
+    /// Greys have no hue; we call it 0.0.
+    pub fn to_hsv(&self) -> (f64, f64, f64) {
+        let max = self.red.max(self.green).max(self.blue);
+        let min = self.red.min(self.green).min(self.blue);
+        let delta = max - min;
+        let saturation = if max > 0.0 { delta / max } else { 0.0 };
+        if delta <= 0.0 {
+            return (0.0, saturation, max);
+        }
+        let sector = if max == self.red {
+            (self.green - self.blue) / delta
+        }
+        else if max == self.green {
+            (self.blue - self.red) / delta + 2.0
+        }
+        else {
+            (self.red - self.green) / delta + 4.0
+        };
+        let hue = sector / 6.0;
+        (hue - hue.floor(), saturation, max)
+    }
+
+    /// Decompose this color into cyan, magenta, and yellow, for subtractive color mixing.
+    pub fn to_cmy(&self) -> (f64, f64, f64) {
+        (1.0 - self.red, 1.0 - self.green, 1.0 - self.blue)
+    }
+
+    /// Decompose this color into red, green, blue, and white.
+    /// As much of the color as possible is moved into the white.
+    pub fn to_rgbw(&self) -> (f64, f64, f64, f64) {
+        let white = self.red.min(self.green).min(self.blue).max(0.0);
+        (self.red - white, self.green - white, self.blue - white, white)
+    }
+
+    /// Decompose this color into red, green, blue, and amber.
+    /// As much of the color as possible is moved into the amber, which we take to be full red
+    /// with AMBER_GREEN green.
+    pub fn to_rgba(&self) -> (f64, f64, f64, f64) {
+        let amber = self.red.min(self.green / AMBER_GREEN).max(0.0);
+        (self.red - amber, self.green - amber * AMBER_GREEN, self.blue, amber)
+    }
+
+    /// The brightness of this color, the value in HSV terms.
+    pub fn brightness(&self) -> f64 {
+        self.red.max(self.green).max(self.blue)
+    }
+
+    pub fn coerce(self) -> Self {
+        let clip = |v: f64| v.min(1.0).max(0.0);
+        Color::rgb(clip(self.red), clip(self.green), clip(self.blue))
+    }
+}
+
+impl From<Unipolar> for Color {
+    /// A scalar becomes a grey of that brightness.
+    fn from(up: Unipolar) -> Self {
+        Color::rgb(up.0, up.0, up.0)
+    }
+}
+
+impl From<Data> for Color {
+    fn from(d: Data) -> Self {
+        match d {
+            Data::Color(c) => c,
+            d => Unipolar::from(d).into(),
+        }
+    }
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Color::rgb(0.0, 0.0, 0.0)
+    }
+}
+
+impl Mul<Unipolar> for Color {
+    type Output = Color;
+    fn mul(self, rhs: Unipolar) -> Self::Output {
+        Color::rgb(self.red * rhs.0, self.green * rhs.0, self.blue * rhs.0)
+    }
+}
+
+impl Add for Color {
+    type Output = Color;
+    fn add(self, rhs: Color) -> Self::Output {
+        Color::rgb(self.red + rhs.red, self.green + rhs.green, self.blue + rhs.blue)
+    }
+}
+
+/// A position in two dimensions, such as pan and tilt.
+/// Each axis is on the range [-1.0, 1.0], with the center at 0.0.
+#[derive(Clone, Copy, Debug, PartialOrd, Serialize, Deserialize)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl PartialEq for Position {
+    fn eq(&self, other: &Position) -> bool {
+        almost_eq(self.x, other.x) && almost_eq(self.y, other.y)
+    }
+}
+
+impl Eq for Position {}
+
+impl Position {
+    pub fn new(x: f64, y: f64) -> Self {
+        Position { x: x, y: y }
+    }
+
+    pub fn coerce(self) -> Self {
+        let clip = |v: f64| v.min(1.0).max(-1.0);
+        Position::new(clip(self.x), clip(self.y))
+    }
+}
+
+impl From<Bipolar> for Position {
+    /// A scalar drives the x axis, leaving y at the center.
+    fn from(bp: Bipolar) -> Self {
+        Position::new(bp.0, 0.0)
+    }
+}
+
+impl From<Data> for Position {
+    fn from(d: Data) -> Self {
+        match d {
+            Data::Position(p) => p,
+            d => Bipolar::from(d).into(),
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::new(0.0, 0.0)
+    }
+}
+
+impl Mul<Unipolar> for Position {
+    type Output = Position;
+    /// Scaling a position draws it in towards the center.
+    fn mul(self, rhs: Unipolar) -> Self::Output {
+        Position::new(self.x * rhs.0, self.y * rhs.0)
+    }
+}
+
+impl Add for Position {
+    type Output = Position;
+    fn add(self, rhs: Position) -> Self::Output {
+        Position::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+/// One of a finite number of discrete items, such as a slot on a gobo wheel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+pub struct Index {
+    pub index: u32,
+    /// How many items there are to choose from.
+    pub count: u32,
+}
+
+impl Index {
+    pub fn new(index: u32, count: u32) -> Self {
+        Index { index: index, count: count }
+    }
+
+    /// Spread the unipolar range evenly over the items, so that every item gets an equal share.
+    pub fn from_unipolar(Unipolar(val): Unipolar, count: u32) -> Self {
+        if count == 0 {
+            return Index::new(0, 0);
+        }
+        let index = (val.max(0.0) * count as f64) as u32;
+        Index::new(min(index, count - 1), count)
+    }
+
+    /// Convert any data into an index into this many items.
+    pub fn from_data(d: Data, count: u32) -> Self {
+        match d {
+            Data::Index(i) if i.count == count => i,
+            d => Index::from_unipolar(d.into(), count),
+        }
+    }
+
+    /// The first item is 0.0 and the last is 1.0.
+    /// Converting back with from_unipolar gives the same index.
+    pub fn to_unipolar(&self) -> Unipolar {
+        if self.count <= 1 {
+            Unipolar(0.0)
+        }
+        else {
+            Unipolar(self.index as f64 / (self.count - 1) as f64)
+        }
+    }
+
+    pub fn coerce(self) -> Self {
+        Index::new(min(self.index, max(self.count, 1) - 1), self.count)
+    }
+}
+
+impl Mul<Unipolar> for Index {
+    type Output = Index;
+    fn mul(self, rhs: Unipolar) -> Self::Output {
+        Index::from_unipolar(self.to_unipolar() * rhs, self.count)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Data {
     /// A float on the range [0.0, 1.0].
     Unipolar(Unipolar),
     /// A float on the range [-1.0, 1.0].
     Bipolar(Bipolar),
+    Color(Color),
+    Position(Position),
+    Index(Index),
 }
 
 impl Data {
@@ -171,6 +507,9 @@ impl Data {
         match *self {
             Data::Unipolar(up) => Data::Unipolar(up.coerce()),
             Data::Bipolar(bp) => Data::Bipolar(bp.coerce()),
+            Data::Color(c) => Data::Color(c.coerce()),
+            Data::Position(p) => Data::Position(p.coerce()),
+            Data::Index(i) => Data::Index(i.coerce()),
         }
     }
 
@@ -178,6 +517,9 @@ impl Data {
         match *self {
             Data::Unipolar(_) => Datatype::Unipolar,
             Data::Bipolar(_) => Datatype::Bipolar,
+            Data::Color(_) => Datatype::Color,
+            Data::Position(_) => Datatype::Position,
+            Data::Index(i) => Datatype::Index(i.count),
         }
     }
 
@@ -185,6 +527,9 @@ impl Data {
         match datatype {
             Datatype::Bipolar => Data::Bipolar((*self).into()),
             Datatype::Unipolar => Data::Unipolar((*self).into()),
+            Datatype::Color => Data::Color((*self).into()),
+            Datatype::Position => Data::Position((*self).into()),
+            Datatype::Index(count) => Data::Index(Index::from_data(*self, count)),
         }
     }
 
@@ -194,6 +539,9 @@ impl Data {
         match type_hint {
             Some(Datatype::Unipolar) | None => Data::Unipolar(Unipolar::default()),
             Some(Datatype::Bipolar) => Data::Bipolar(Bipolar::default()),
+            Some(Datatype::Color) => Data::Color(Color::default()),
+            Some(Datatype::Position) => Data::Position(Position::default()),
+            Some(Datatype::Index(count)) => Data::Index(Index::new(0, count)),
         }
     }
 
@@ -215,6 +563,126 @@ impl Mul<Unipolar> for Data {
         match self {
             Data::Unipolar(u) => Data::Unipolar(u * rhs),
             Data::Bipolar(b) => Data::Bipolar(b * rhs),
+            Data::Color(c) => Data::Color(c * rhs),
+            Data::Position(p) => Data::Position(p * rhs),
+            Data::Index(i) => Data::Index(i * rhs),
         }
     }
-}
\ No newline at end of file
+}
+
+impl Add for Data {
+    type Output = Self;
+    /// Add the right hand side to the left, in the type of the left hand side.
+    fn add(self, rhs: Data) -> Self::Output {
+        match self {
+            Data::Unipolar(u) => Data::Unipolar(u + rhs.into()),
+            Data::Bipolar(b) => Data::Bipolar(b + rhs.into()),
+            Data::Color(c) => Data::Color(c + rhs.into()),
+            Data::Position(p) => Data::Position(p + rhs.into()),
+            Data::Index(i) => {
+                let sum = i.to_unipolar() + Index::from_data(rhs, i.count).to_unipolar();
+                Data::Index(Index::from_unipolar(sum, i.count))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        let to_unipolar = |conversion: Conversion, val| conversion.to_unipolar(Bipolar(val)).0;
+        let check = |conversion, expected: [f64; 4]| {
+            let actual = [
+                to_unipolar(conversion, -1.0),
+                to_unipolar(conversion, -0.5),
+                to_unipolar(conversion, 0.5),
+                to_unipolar(conversion, 1.5)];
+            for (e, a) in expected.iter().zip(actual.iter()) {
+                assert!(
+                    almost_eq(*e, *a),
+                    "{:?}: expected {:?}, got {:?}", conversion, expected, actual);
+            }
+        };
+        check(Conversion::Abs, [1.0, 0.5, 0.5, 1.5]);
+        check(Conversion::Rescale, [0.0, 0.25, 0.75, 1.25]);
+        check(Conversion::Clip, [0.0, 0.0, 0.5, 1.0]);
+        check(Conversion::HalfWave, [0.0, 0.0, 0.5, 1.5]);
+
+        assert_eq!(Bipolar(-0.5), Conversion::Rescale.to_bipolar(Unipolar(0.25)));
+        assert_eq!(Bipolar(0.25), Conversion::Clip.to_bipolar(Unipolar(0.25)));
+
+        // The plain conversions use abs.
+        assert_eq!(Unipolar(0.5), Bipolar(-0.5).into());
+        assert_eq!(Data::unipolar(0.5), Data::bipolar(-0.5).as_type(Datatype::Unipolar));
+        // Converting to the same type does nothing.
+        assert_eq!(
+            Data::bipolar(-0.5),
+            Conversion::Rescale.convert(Data::bipolar(-0.5), Datatype::Bipolar));
+        assert_eq!(
+            Data::unipolar(0.25),
+            Conversion::Rescale.convert(Data::bipolar(-0.5), Datatype::Unipolar));
+    }
+
+    #[test]
+    fn test_hsv() {
+        let colors = [
+            (Color::rgb(1.0, 0.0, 0.0), (0.0, 1.0, 1.0)),
+            (Color::rgb(0.0, 0.5, 0.0), (1.0 / 3.0, 1.0, 0.5)),
+            (Color::rgb(0.25, 0.25, 1.0), (2.0 / 3.0, 0.75, 1.0)),
+            (Color::rgb(1.0, 0.0, 0.5), (11.0 / 12.0, 1.0, 1.0)),
+        ];
+        for &(color, (h, s, v)) in colors.iter() {
+            assert_eq!(color, Color::hsv(h, s, v));
+            let (h2, s2, v2) = color.to_hsv();
+            assert!(almost_eq(h, h2) && almost_eq(s, s2) && almost_eq(v, v2), "{:?}", color);
+        }
+        // Hue wraps around.
+        assert_eq!(Color::hsv(0.25, 1.0, 1.0), Color::hsv(1.25, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_structured_conversions() {
+        let color = Data::Color(Color::rgb(0.2, 0.8, 0.4));
+        assert_eq!(Data::unipolar(0.8), color.as_type(Datatype::Unipolar));
+        assert_eq!(
+            Data::Color(Color::rgb(0.3, 0.3, 0.3)),
+            Data::unipolar(0.3).as_type(Datatype::Color));
+
+        let position = Data::Position(Position::new(-0.5, 0.7));
+        assert_eq!(Data::bipolar(-0.5), position.as_type(Datatype::Bipolar));
+        assert_eq!(Data::unipolar(0.5), position.as_type(Datatype::Unipolar));
+        assert_eq!(
+            Data::Position(Position::new(-0.25, 0.0)),
+            Data::bipolar(-0.25).as_type(Datatype::Position));
+
+        // Every index survives a round trip through unipolar.
+        for i in 0..8 {
+            let index = Data::Index(Index::new(i, 8));
+            assert_eq!(index, index.as_type(Datatype::Unipolar).as_type(Datatype::Index(8)));
+        }
+        assert_eq!(Data::Index(Index::new(3, 4)), Data::unipolar(1.0).as_type(Datatype::Index(4)));
+        assert_eq!(Data::Index(Index::new(1, 4)), Data::unipolar(0.3).as_type(Datatype::Index(4)));
+        assert_eq!(Data::Index(Index::new(3, 4)), Data::Index(Index::new(9, 4)).coerce());
+        let default_index = Data::default_with_type_hint(Some(Datatype::Index(4)));
+        assert_eq!(Datatype::Index(4), default_index.datatype());
+    }
+
+    #[test]
+    fn test_color_decompositions() {
+        let close = |a: (f64, f64, f64, f64), b: (f64, f64, f64, f64)| {
+            almost_eq(a.0, b.0) && almost_eq(a.1, b.1) && almost_eq(a.2, b.2) && almost_eq(a.3, b.3)
+        };
+        let color = Color::rgb(1.0, 0.75, 0.25);
+        let (c, m, y) = color.to_cmy();
+        assert!(almost_eq(c, 0.0) && almost_eq(m, 0.25) && almost_eq(y, 0.75));
+        assert!(close((0.75, 0.5, 0.0, 0.25), color.to_rgbw()));
+        assert!(close((0.0, 0.0, 0.25, 1.0), color.to_rgba()));
+        // Colors without any white or amber in them are left alone.
+        let blue = Color::rgb(0.0, 0.5, 1.0);
+        assert!(close((0.0, 0.5, 1.0, 0.0), blue.to_rgbw()));
+        assert!(close((0.0, 0.5, 1.0, 0.0), blue.to_rgba()));
+    }
+}